@@ -400,6 +400,14 @@ async function resolveToken(): Promise<{ token: string; source: "env" | "file" }
   throw new Error("missing token; set RELAY_TOKEN or run `relay auth login --save`");
 }
 
+/** `-H x-relay-user-token: ...` from the file hostd writes next to its socket; hostd wants it from callers outside a run while runs are active. */
+async function userTokenArgs(sock: string): Promise<string[]> {
+  const file = Bun.file(`${sock}.token`);
+  if (!(await file.exists())) return [];
+  const token = (await file.text()).trim();
+  return token ? ["-H", `x-relay-user-token: ${token}`] : [];
+}
+
 function requireBinaryInPath(bin: string): void {
   const r = Bun.spawnSync(["bash", "-lc", `command -v ${JSON.stringify(bin)} >/dev/null 2>&1`], {
     stdout: "ignore",
@@ -829,6 +837,7 @@ async function localStartRun(sock: string, tool: string, runCmd: string, cwd?: s
     "--show-error",
     "--unix-socket",
     sock,
+    ...(await userTokenArgs(sock)),
     "-X",
     "POST",
     "http://localhost/runs",
@@ -857,7 +866,7 @@ function requireSupportedTool(tool: string | undefined): "opencode" {
 
 async function localGetJson(sock: string, url: string): Promise<Record<string, JsonValue>> {
   requireBinaryInPath("curl");
-  const curlArgs = ["--silent", "--show-error", "--unix-socket", sock, "-X", "GET", url];
+  const curlArgs = ["--silent", "--show-error", "--unix-socket", sock, ...(await userTokenArgs(sock)), "-X", "GET", url];
   const p = Bun.spawn(["curl", ...curlArgs], { stdout: "pipe", stderr: "pipe" });
   const out = await new Response(p.stdout).text();
   const err = await new Response(p.stderr).text();
@@ -873,6 +882,7 @@ async function localPostJson(sock: string, url: string, body: Record<string, Jso
     "--show-error",
     "--unix-socket",
     sock,
+    ...(await userTokenArgs(sock)),
     "-X",
     "POST",
     url,
@@ -1457,7 +1467,7 @@ async function main() {
       // Best-effort: local API check.
       if (sockOk && have("curl")) {
         const r = Bun.spawnSync(
-          ["curl", "--silent", "--show-error", "--unix-socket", state.sock, "http://localhost/runs"],
+          ["curl", "--silent", "--show-error", "--unix-socket", state.sock, ...(await userTokenArgs(state.sock)), "http://localhost/runs"],
           { stdout: "pipe", stderr: "pipe" },
         );
         checks.push({ name: "hostd.api", ok: r.exitCode === 0, detail: r.exitCode === 0 ? "ok" : "curl failed" });
@@ -1610,6 +1620,7 @@ async function main() {
         "--show-error",
         "--unix-socket",
         sock,
+        ...(await userTokenArgs(sock)),
         "-X",
         "POST",
         `http://localhost/runs/${encodeURIComponent(runId)}/input`,
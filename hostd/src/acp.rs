@@ -0,0 +1,360 @@
+//! Client side of the Agent Client Protocol (ACP): JSON-RPC 2.0, one message per line, over the
+//! agent's stdio.
+//!
+//! hostd opens one ACP session per run. Run input becomes `session/prompt`, `session/update`
+//! notifications are mapped onto relay events, and the agent's `session/request_permission` and
+//! `fs/*` requests are answered on its behalf. The process plumbing lives in `run_manager`.
+
+use serde_json::{Value as JsonValue, json};
+use std::collections::HashMap;
+use std::sync::Mutex as StdMutex;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+pub const PROTOCOL_VERSION: i64 = 1;
+
+/// Agents are often launched through `npx` and friends, so allow a slow first start.
+pub const INIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest file served to `fs/read_text_file` / accepted from `fs/write_text_file`.
+const MAX_FILE_BYTES: usize = 8 * 1024 * 1024;
+
+/// Per-run ACP connection state.
+pub struct AcpSession {
+    next_id: AtomicI64,
+    waiters: StdMutex<HashMap<i64, oneshot::Sender<JsonValue>>>,
+    session_id: StdMutex<Option<String>>,
+    // toolCallId -> (relay tool name, first seen), so updates can be reported with a duration.
+    tool_calls: StdMutex<HashMap<String, (String, Instant)>>,
+    // Whether the last `run.output` chunk left a line open.
+    mid_line: AtomicBool,
+    /// ACP allows one prompt turn at a time per session.
+    pub prompt_lock: tokio::sync::Mutex<()>,
+}
+
+impl Default for AcpSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AcpSession {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicI64::new(1),
+            waiters: StdMutex::new(HashMap::new()),
+            session_id: StdMutex::new(None),
+            tool_calls: StdMutex::new(HashMap::new()),
+            mid_line: AtomicBool::new(false),
+            prompt_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Allocates a request id and registers a waiter for its response.
+    pub fn register(&self) -> (i64, oneshot::Receiver<JsonValue>) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = oneshot::channel();
+        if let Ok(mut waiters) = self.waiters.lock() {
+            waiters.insert(id, tx);
+        }
+        (id, rx)
+    }
+
+    pub fn forget(&self, id: i64) {
+        if let Ok(mut waiters) = self.waiters.lock() {
+            waiters.remove(&id);
+        }
+    }
+
+    /// Hands a response to its waiter; returns false when nobody is waiting for `id`.
+    pub fn resolve(&self, id: i64, msg: JsonValue) -> bool {
+        let tx = self.waiters.lock().ok().and_then(|mut w| w.remove(&id));
+        match tx {
+            Some(tx) => {
+                let _ = tx.send(msg);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drops every waiter (the agent went away), failing in-flight requests.
+    pub fn fail_all(&self) {
+        if let Ok(mut waiters) = self.waiters.lock() {
+            waiters.clear();
+        }
+    }
+
+    pub fn session_id(&self) -> Option<String> {
+        self.session_id.lock().ok().and_then(|s| s.clone())
+    }
+
+    pub fn set_session_id(&self, id: &str) {
+        if let Ok(mut s) = self.session_id.lock() {
+            *s = Some(id.to_string());
+        }
+    }
+
+    /// Returns true (once) if the agent's output stopped mid-line.
+    pub fn take_mid_line(&self) -> bool {
+        self.mid_line.swap(false, Ordering::SeqCst)
+    }
+
+    /// Maps one `session/update` payload onto relay events (`run.output`, `tool.call`,
+    /// `tool.result`). Thoughts and plans are not surfaced.
+    pub fn map_update(&self, actor: &str, update: &JsonValue) -> Vec<(&'static str, JsonValue)> {
+        let kind = update
+            .get("sessionUpdate")
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+        let mut out = Vec::new();
+        match kind {
+            "agent_message_chunk" => {
+                let text = content_text(update.get("content").unwrap_or(&JsonValue::Null));
+                if !text.is_empty() {
+                    self.mid_line.store(!text.ends_with('\n'), Ordering::SeqCst);
+                    out.push(("run.output", json!({ "stream": "stdout", "text": text })));
+                }
+            }
+            "tool_call" | "tool_call_update" => {
+                let Some(id) = update.get("toolCallId").and_then(|v| v.as_str()) else {
+                    return out;
+                };
+                let is_new = {
+                    let Ok(mut calls) = self.tool_calls.lock() else {
+                        return out;
+                    };
+                    let is_new = !calls.contains_key(id);
+                    if is_new {
+                        let tool = op_tool_for_kind(
+                            update.get("kind").and_then(|v| v.as_str()).unwrap_or(""),
+                        );
+                        calls.insert(id.to_string(), (tool.to_string(), Instant::now()));
+                    }
+                    is_new
+                };
+                let (tool, started) = self
+                    .tool_calls
+                    .lock()
+                    .ok()
+                    .and_then(|calls| calls.get(id).cloned())
+                    .unwrap_or_else(|| ("other".to_string(), Instant::now()));
+                if is_new {
+                    out.push((
+                        "tool.call",
+                        json!({
+                            "request_id": id,
+                            "tool": tool,
+                            "actor": actor,
+                            "title": update.get("title"),
+                            "args": update.get("rawInput").cloned().unwrap_or(JsonValue::Null),
+                        }),
+                    ));
+                }
+                let status = update.get("status").and_then(|v| v.as_str()).unwrap_or("");
+                if matches!(status, "completed" | "failed") {
+                    if let Ok(mut calls) = self.tool_calls.lock() {
+                        calls.remove(id);
+                    }
+                    let ok = status == "completed";
+                    let mut data = json!({
+                        "request_id": id,
+                        "tool": tool,
+                        "actor": actor,
+                        "ok": ok,
+                        "duration_ms": started.elapsed().as_millis() as i64,
+                        "result": {
+                            "title": update.get("title"),
+                            "output": update.get("rawOutput"),
+                            "content": update.get("content"),
+                        },
+                    });
+                    if !ok {
+                        data["error"] = JsonValue::from(tool_call_text(update));
+                    }
+                    out.push(("tool.result", data));
+                }
+            }
+            _ => {}
+        }
+        out
+    }
+}
+
+fn content_text(content: &JsonValue) -> String {
+    match content.get("type").and_then(|v| v.as_str()) {
+        Some("text") => content
+            .get("text")
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string(),
+        Some("resource_link") => content
+            .get("uri")
+            .and_then(|v| v.as_str())
+            .map(|uri| format!("[{uri}]"))
+            .unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+fn tool_call_text(update: &JsonValue) -> String {
+    let mut parts = Vec::new();
+    for item in update
+        .get("content")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+    {
+        if item.get("type").and_then(|v| v.as_str()) == Some("content") {
+            let text = content_text(item.get("content").unwrap_or(&JsonValue::Null));
+            if !text.is_empty() {
+                parts.push(text);
+            }
+        }
+    }
+    if parts.is_empty() {
+        "tool call failed".to_string()
+    } else {
+        parts.join("\n")
+    }
+}
+
+/// Relay tool name for an ACP tool kind, so session allow-lists (`bash`, `edit`, ...) apply.
+pub fn op_tool_for_kind(kind: &str) -> &str {
+    match kind {
+        "execute" => "bash",
+        "edit" | "delete" | "move" => "edit",
+        "" => "other",
+        other => other,
+    }
+}
+
+pub fn initialize_params() -> JsonValue {
+    json!({
+        "protocolVersion": PROTOCOL_VERSION,
+        "clientCapabilities": {
+            "fs": { "readTextFile": true, "writeTextFile": true },
+            "terminal": false
+        },
+        "clientInfo": { "name": "relay-hostd", "version": env!("CARGO_PKG_VERSION") }
+    })
+}
+
+pub fn request(id: i64, method: &str, params: JsonValue) -> JsonValue {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+pub fn notification(method: &str, params: JsonValue) -> JsonValue {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+pub fn response(id: &JsonValue, result: JsonValue) -> JsonValue {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+pub fn error_response(id: &JsonValue, code: i64, message: &str) -> JsonValue {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Human-readable prompt for a `session/request_permission` request.
+pub fn permission_prompt(params: &JsonValue) -> String {
+    params
+        .get("toolCall")
+        .and_then(|t| t.get("title"))
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.trim().is_empty())
+        .unwrap_or("permission requested")
+        .to_string()
+}
+
+/// Relay op tool for a `session/request_permission` request.
+pub fn permission_op_tool(params: &JsonValue) -> String {
+    let kind = params
+        .get("toolCall")
+        .and_then(|t| t.get("kind"))
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+    op_tool_for_kind(kind).to_string()
+}
+
+/// `session/request_permission` result for a relay decision. Prefers the one-shot option of
+/// the matching polarity; a request without a matching option is answered as cancelled.
+pub fn permission_outcome(options: &JsonValue, approve: bool) -> JsonValue {
+    let (once, always) = if approve {
+        ("allow_once", "allow_always")
+    } else {
+        ("reject_once", "reject_always")
+    };
+    let options = options.as_array().cloned().unwrap_or_default();
+    let pick = |kind: &str| {
+        options
+            .iter()
+            .find(|o| o.get("kind").and_then(|v| v.as_str()) == Some(kind))
+            .and_then(|o| o.get("optionId").cloned())
+    };
+    match pick(once).or_else(|| pick(always)) {
+        Some(option_id) => json!({ "outcome": { "outcome": "selected", "optionId": option_id } }),
+        None => cancelled_outcome(),
+    }
+}
+
+pub fn cancelled_outcome() -> JsonValue {
+    json!({ "outcome": { "outcome": "cancelled" } })
+}
+
+/// ACP paths are absolute; relay's fs helpers want them relative to the run cwd.
+fn run_relative_path(cwd: &str, path: &str) -> Result<String, String> {
+    let p = std::path::Path::new(path);
+    if !p.is_absolute() {
+        return Ok(path.to_string());
+    }
+    let rel = p
+        .strip_prefix(cwd)
+        .ok()
+        .map(|r| r.to_path_buf())
+        .or_else(|| {
+            let base = std::fs::canonicalize(cwd).ok()?;
+            p.strip_prefix(base).ok().map(|r| r.to_path_buf())
+        })
+        .ok_or_else(|| format!("path escapes run cwd: {path}"))?;
+    Ok(rel.to_string_lossy().to_string())
+}
+
+/// Serves `fs/read_text_file` / `fs/write_text_file` inside the run cwd.
+pub fn handle_fs_request(cwd: &str, method: &str, params: &JsonValue) -> Result<JsonValue, String> {
+    let path = params.get("path").and_then(|v| v.as_str()).unwrap_or("");
+    let rel = run_relative_path(cwd, path)?;
+    match method {
+        "fs/read_text_file" => {
+            let (content, truncated) =
+                crate::fs_git::read_utf8_file(cwd, &rel, MAX_FILE_BYTES).map_err(|(_, e)| e)?;
+            if truncated {
+                return Err(format!("file too large (max {MAX_FILE_BYTES} bytes)"));
+            }
+            let line = params.get("line").and_then(|v| v.as_u64());
+            let limit = params.get("limit").and_then(|v| v.as_u64());
+            if line.is_none() && limit.is_none() {
+                return Ok(json!({ "content": content }));
+            }
+            let skip = line.unwrap_or(1).saturating_sub(1) as usize;
+            let take = limit.map(|l| l as usize).unwrap_or(usize::MAX);
+            let content: String = content
+                .split_inclusive('\n')
+                .skip(skip)
+                .take(take)
+                .collect();
+            Ok(json!({ "content": content }))
+        }
+        "fs/write_text_file" => {
+            let content = params.get("content").and_then(|v| v.as_str()).unwrap_or("");
+            if content.len() > MAX_FILE_BYTES {
+                return Err(format!("content too large (max {MAX_FILE_BYTES} bytes)"));
+            }
+            crate::fs_git::write_utf8_file(cwd, &rel, content, MAX_FILE_BYTES)
+                .map_err(|(_, e)| e)?;
+            Ok(JsonValue::Null)
+        }
+        _ => Err(format!("unsupported method {method}")),
+    }
+}
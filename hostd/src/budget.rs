@@ -0,0 +1,456 @@
+//! Token/cost budgets enforced on `run.usage`.
+//!
+//! Limits come from `~/.relay/budgets.json` (or `RELAY_BUDGETS_CONFIG`) for three scopes:
+//! `run` (one run, overridable per `rpc.run.start`), `host` (every run since hostd started) and
+//! `day` (every run on this host during the current UTC day, kept in `~/.relay/budget-state.json`
+//! so restarts do not reset it). Crossing a soft limit holds the run until someone approves
+//! continuation; crossing a hard limit stops it.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value as JsonValue, json};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Limit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+}
+
+impl Limit {
+    fn reached(&self, spend: &Spend) -> bool {
+        self.tokens.is_some_and(|t| spend.tokens >= t)
+            || self.cost_usd.is_some_and(|c| spend.cost_usd >= c)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    #[serde(default)]
+    pub soft: Limit,
+    #[serde(default)]
+    pub hard: Limit,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BudgetConfig {
+    #[serde(default)]
+    pub run: Limits,
+    #[serde(default)]
+    pub host: Limits,
+    #[serde(default)]
+    pub day: Limits,
+}
+
+fn relay_dir_file(env: &str, name: &str) -> Option<PathBuf> {
+    if let Ok(v) = std::env::var(env) {
+        let v = v.trim().to_string();
+        if !v.is_empty() {
+            return Some(PathBuf::from(v));
+        }
+    }
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".relay").join(name))
+}
+
+impl BudgetConfig {
+    /// A missing file means no limits; an invalid one is logged and ignored.
+    pub fn load() -> Self {
+        let Some(path) = relay_dir_file("RELAY_BUDGETS_CONFIG", "budgets.json") else {
+            return Self::default();
+        };
+        let Ok(raw) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&raw).unwrap_or_else(|e| {
+            tracing::warn!(path=%path.display(), error=%e, "invalid budgets config");
+            Self::default()
+        })
+    }
+}
+
+/// What counts against a budget: every token except cache reads, plus reported cost.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Spend {
+    pub tokens: i64,
+    pub cost_usd: f64,
+}
+
+impl Spend {
+    /// From a `run.usage` payload.
+    pub fn from_usage(data: &JsonValue) -> Self {
+        let int = |k: &str| data.get(k).and_then(|v| v.as_i64()).unwrap_or(0).max(0);
+        Self {
+            tokens: int("input_tokens")
+                + int("output_tokens")
+                + int("reasoning_tokens")
+                + int("cache_write_tokens"),
+            cost_usd: data
+                .get("cost_usd")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(0.0)
+                .max(0.0),
+        }
+    }
+
+    fn add(&mut self, other: &Spend) {
+        self.tokens += other.tokens;
+        self.cost_usd += other.cost_usd;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Run,
+    Host,
+    Day,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Run => "run",
+            Scope::Host => "host",
+            Scope::Day => "day",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// A soft limit was crossed: hold the run under `request_id` until a decision arrives.
+    Soft {
+        request_id: String,
+        scope: Scope,
+        spend: Spend,
+        limit: Limit,
+    },
+    Hard {
+        scope: Scope,
+        spend: Spend,
+        limit: Limit,
+    },
+}
+
+impl Verdict {
+    /// `op_args` for the permission card / details for the stop message.
+    pub fn details(&self) -> JsonValue {
+        let (kind, scope, spend, limit) = match self {
+            Verdict::Soft {
+                scope,
+                spend,
+                limit,
+                ..
+            } => ("soft", scope, spend, limit),
+            Verdict::Hard {
+                scope,
+                spend,
+                limit,
+            } => ("hard", scope, spend, limit),
+        };
+        json!({ "limit_kind": kind, "scope": scope.as_str(), "spend": spend, "limit": limit })
+    }
+
+    pub fn summary(&self) -> String {
+        let (scope, spend, limit) = match self {
+            Verdict::Soft {
+                scope,
+                spend,
+                limit,
+                ..
+            }
+            | Verdict::Hard {
+                scope,
+                spend,
+                limit,
+                ..
+            } => (scope, spend, limit),
+        };
+        let mut s = format!(
+            "{} spend {} tokens / ${:.2}",
+            scope.as_str(),
+            spend.tokens,
+            spend.cost_usd
+        );
+        if let Some(t) = limit.tokens {
+            s.push_str(&format!(", limit {t} tokens"));
+        }
+        if let Some(c) = limit.cost_usd {
+            s.push_str(&format!(", limit ${c:.2}"));
+        }
+        s
+    }
+}
+
+#[derive(Debug, Default)]
+struct RunBudget {
+    limits: Limits,
+    spend: Spend,
+    /// Scopes whose soft limit was already approved for this run.
+    acknowledged: HashSet<Scope>,
+    hold: Option<(String, Scope)>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DayState {
+    day: String,
+    spend: Spend,
+}
+
+#[derive(Debug, Default)]
+pub struct Budgets {
+    config: BudgetConfig,
+    runs: HashMap<String, RunBudget>,
+    host: Spend,
+    day: DayState,
+    state_path: Option<PathBuf>,
+}
+
+fn today() -> String {
+    chrono::Utc::now().format("%Y-%m-%d").to_string()
+}
+
+impl Budgets {
+    pub fn load() -> Self {
+        let state_path = relay_dir_file("RELAY_BUDGET_STATE", "budget-state.json");
+        let day = state_path
+            .as_ref()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|raw| serde_json::from_str::<DayState>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            config: BudgetConfig::load(),
+            day,
+            state_path,
+            ..Self::default()
+        }
+    }
+
+    #[cfg(test)]
+    fn with_config(config: BudgetConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    fn roll_day(&mut self) {
+        let today = today();
+        if self.day.day != today {
+            self.day = DayState {
+                day: today,
+                spend: Spend::default(),
+            };
+        }
+    }
+
+    fn save_day(&self) -> anyhow::Result<()> {
+        let Some(path) = self.state_path.as_ref() else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context("create budget state dir")?;
+        }
+        std::fs::write(path, serde_json::to_vec(&self.day)?).context("write budget state")
+    }
+
+    /// Refuses new runs while a host or day hard limit is exhausted.
+    pub fn check_start(&mut self) -> anyhow::Result<()> {
+        self.roll_day();
+        for (scope, limits, spend) in [
+            (Scope::Host, self.config.host, self.host),
+            (Scope::Day, self.config.day, self.day.spend),
+        ] {
+            anyhow::ensure!(
+                !limits.hard.reached(&spend),
+                "budget exhausted: {} hard limit reached ({} tokens / ${:.2})",
+                scope.as_str(),
+                spend.tokens,
+                spend.cost_usd
+            );
+        }
+        Ok(())
+    }
+
+    pub fn start_run(&mut self, run_id: &str, limits: Option<Limits>) {
+        self.runs.insert(
+            run_id.to_string(),
+            RunBudget {
+                limits: limits.unwrap_or(self.config.run),
+                ..RunBudget::default()
+            },
+        );
+    }
+
+    pub fn end_run(&mut self, run_id: &str) {
+        self.runs.remove(run_id);
+    }
+
+    /// Adds one `run.usage` event. Hard limits win over soft ones; a run already on hold is not
+    /// asked twice.
+    pub fn record(&mut self, run_id: &str, usage: &JsonValue) -> Option<Verdict> {
+        let add = Spend::from_usage(usage);
+        self.roll_day();
+        self.host.add(&add);
+        self.day.spend.add(&add);
+        if let Err(e) = self.save_day() {
+            tracing::warn!(error=%e, "save budget state failed");
+        }
+        let run = self.runs.get_mut(run_id)?;
+        run.spend.add(&add);
+
+        let scopes = [
+            (Scope::Run, run.limits, run.spend),
+            (Scope::Host, self.config.host, self.host),
+            (Scope::Day, self.config.day, self.day.spend),
+        ];
+        if let Some((scope, limits, spend)) = scopes.iter().find(|(_, l, s)| l.hard.reached(s)) {
+            return Some(Verdict::Hard {
+                scope: *scope,
+                spend: *spend,
+                limit: limits.hard,
+            });
+        }
+        if run.hold.is_some() {
+            return None;
+        }
+        let (scope, limits, spend) = scopes
+            .iter()
+            .find(|(scope, l, s)| l.soft.reached(s) && !run.acknowledged.contains(scope))?;
+        let request_id = uuid::Uuid::new_v4().to_string();
+        run.hold = Some((request_id.clone(), *scope));
+        Some(Verdict::Soft {
+            request_id,
+            scope: *scope,
+            spend: *spend,
+            limit: limits.soft,
+        })
+    }
+
+    /// Whether `request_id` is the soft-limit approval `run_id` is waiting on.
+    pub fn is_holding(&self, run_id: &str, request_id: &str) -> bool {
+        self.runs
+            .get(run_id)
+            .and_then(|r| r.hold.as_ref())
+            .is_some_and(|(id, _)| id == request_id)
+    }
+
+    /// Why prompts to `run_id` are refused right now, if they are.
+    pub fn hold_reason(&self, run_id: &str) -> Option<String> {
+        let (_, scope) = self.runs.get(run_id)?.hold.as_ref()?;
+        Some(format!(
+            "run paused: {} budget soft limit reached; approve continuation first",
+            scope.as_str()
+        ))
+    }
+
+    /// Clears the hold matching `request_id`; approving also stops asking for that scope.
+    pub fn resolve_hold(&mut self, run_id: &str, request_id: &str, approved: bool) -> bool {
+        let Some(run) = self.runs.get_mut(run_id) else {
+            return false;
+        };
+        match run.hold.take() {
+            Some((id, scope)) if id == request_id => {
+                if approved {
+                    run.acknowledged.insert(scope);
+                }
+                true
+            }
+            other => {
+                run.hold = other;
+                false
+            }
+        }
+    }
+}
+
+/// Parses the optional `budget` field of `rpc.run.start` (same shape as a scope in budgets.json).
+pub fn limits_from_request(v: Option<&JsonValue>) -> anyhow::Result<Option<Limits>> {
+    match v {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(v) => serde_json::from_value(v.clone())
+            .map(Some)
+            .context("budget must be { soft: { tokens, cost_usd }, hard: { tokens, cost_usd } }"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soft_holds_once_and_hard_stops() {
+        let mut b = Budgets::with_config(BudgetConfig {
+            run: Limits {
+                soft: Limit {
+                    tokens: Some(100),
+                    cost_usd: None,
+                },
+                hard: Limit {
+                    tokens: None,
+                    cost_usd: Some(1.0),
+                },
+            },
+            ..BudgetConfig::default()
+        });
+        b.start_run("r1", None);
+        let step = json!({ "input_tokens": 60, "output_tokens": 10, "cache_read_tokens": 999 });
+        assert_eq!(b.record("r1", &step), None);
+
+        let Some(Verdict::Soft {
+            request_id, scope, ..
+        }) = b.record("r1", &step)
+        else {
+            panic!("expected soft verdict");
+        };
+        assert_eq!(scope, Scope::Run);
+        assert!(b.hold_reason("r1").is_some());
+        // Still held: no second request.
+        assert_eq!(b.record("r1", &step), None);
+        assert!(!b.resolve_hold("r1", "other", true));
+        assert!(b.resolve_hold("r1", &request_id, true));
+        assert!(b.hold_reason("r1").is_none());
+        assert_eq!(b.record("r1", &step), None);
+
+        let costly = json!({ "output_tokens": 1, "cost_usd": 1.5 });
+        assert!(matches!(
+            b.record("r1", &costly),
+            Some(Verdict::Hard {
+                scope: Scope::Run,
+                ..
+            })
+        ));
+        assert!(b.check_start().is_ok());
+    }
+
+    #[test]
+    fn day_hard_limit_blocks_new_runs() {
+        let mut b = Budgets::with_config(BudgetConfig {
+            day: Limits {
+                hard: Limit {
+                    tokens: Some(10),
+                    cost_usd: None,
+                },
+                ..Limits::default()
+            },
+            ..BudgetConfig::default()
+        });
+        b.start_run("r1", None);
+        assert!(matches!(
+            b.record("r1", &json!({ "input_tokens": 20 })),
+            Some(Verdict::Hard {
+                scope: Scope::Day,
+                ..
+            })
+        ));
+        assert!(b.check_start().is_err());
+        let limits = limits_from_request(Some(&json!({ "hard": { "cost_usd": 2 } })))
+            .unwrap()
+            .unwrap();
+        assert_eq!(limits.hard.cost_usd, Some(2.0));
+        assert!(limits_from_request(Some(&json!({ "hard": 3 }))).is_err());
+    }
+}
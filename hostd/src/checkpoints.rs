@@ -0,0 +1,375 @@
+use axum::http::StatusCode;
+use serde_json::json;
+use std::path::Path;
+
+/// Snapshots of a run's working tree stored as commit objects under the hidden ref namespace
+/// `refs/relay/checkpoints/<run_id>/<checkpoint_id>`. They never touch the user's index,
+/// branches or stash.
+const REF_PREFIX: &str = "refs/relay/checkpoints";
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Checkpoint {
+    pub checkpoint_id: String,
+    pub run_id: String,
+    /// `request_id` of the `tool.call` (or prompt `input_id`) that triggered the snapshot.
+    pub request_id: String,
+    /// Operation that was about to run (`rpc.fs.write`, `rpc.bash`, `prompt`, `restore`, ...).
+    pub tool: String,
+    pub commit: String,
+    pub created_at: String,
+}
+
+/// `RELAY_CHECKPOINTS=0` disables automatic snapshots before approved writes/bash.
+pub fn enabled() -> bool {
+    !matches!(
+        std::env::var("RELAY_CHECKPOINTS")
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+            .as_str(),
+        "0" | "false" | "no" | "off"
+    )
+}
+
+/// `RELAY_CHECKPOINT_PROMPTS=1` also snapshots before each structured opencode prompt.
+pub fn prompts_enabled() -> bool {
+    matches!(
+        std::env::var("RELAY_CHECKPOINT_PROMPTS")
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+            .as_str(),
+        "1" | "true" | "yes" | "on"
+    )
+}
+
+fn valid_ref_part(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with('.')
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && !s.contains("..")
+}
+
+fn git(cwd: &Path, args: &[&str], index: Option<&Path>) -> Result<String, (StatusCode, String)> {
+    let mut cmd = std::process::Command::new("git");
+    cmd.current_dir(cwd).args(args);
+    if let Some(index) = index {
+        cmd.env("GIT_INDEX_FILE", index);
+    }
+    // commit-tree needs an identity even when the user has none configured.
+    cmd.env("GIT_AUTHOR_NAME", "relay")
+        .env("GIT_AUTHOR_EMAIL", "relay@localhost")
+        .env("GIT_COMMITTER_NAME", "relay")
+        .env("GIT_COMMITTER_EMAIL", "relay@localhost");
+    let out = cmd
+        .output()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !out.status.success() {
+        let err = String::from_utf8_lossy(&out.stderr).trim().to_string();
+        return Err((StatusCode::BAD_REQUEST, err));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+fn is_git_worktree(cwd: &Path) -> bool {
+    git(cwd, &["rev-parse", "--is-inside-work-tree"], None)
+        .map(|s| s.trim() == "true")
+        .unwrap_or(false)
+}
+
+/// How often `create` retries when another snapshot claims the same id first.
+const CREATE_ATTEMPTS: usize = 16;
+
+fn ref_exists(cwd: &Path, refname: &str) -> bool {
+    git(cwd, &["rev-parse", "--verify", "--quiet", refname], None).is_ok()
+}
+
+fn run_ref_prefix(run_id: &str) -> Result<String, (StatusCode, String)> {
+    if !valid_ref_part(run_id) {
+        return Err((StatusCode::BAD_REQUEST, "invalid run_id".into()));
+    }
+    Ok(format!("{REF_PREFIX}/{run_id}/"))
+}
+
+/// Snapshots everything under `run_cwd` that git would see (tracked + untracked, minus ignored
+/// files). Returns `None` when `run_cwd` is not inside a git work tree.
+pub fn create(
+    run_cwd: &str,
+    run_id: &str,
+    request_id: &str,
+    tool: &str,
+) -> Result<Option<Checkpoint>, (StatusCode, String)> {
+    let cwd = Path::new(run_cwd);
+    if !is_git_worktree(cwd) {
+        return Ok(None);
+    }
+    let prefix = run_ref_prefix(run_id)?;
+
+    let git_dir = git(cwd, &["rev-parse", "--absolute-git-dir"], None)?;
+    let index = Path::new(git_dir.trim()).join(format!(
+        "relay-checkpoint-{}.index",
+        uuid::Uuid::new_v4().simple()
+    ));
+    let head = git(cwd, &["rev-parse", "--verify", "--quiet", "HEAD"], None)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+
+    let tree = (|| {
+        if let Some(head) = head.as_deref() {
+            git(cwd, &["read-tree", head], Some(&index))?;
+        }
+        git(cwd, &["add", "-A", "--", "."], Some(&index))?;
+        git(cwd, &["write-tree"], Some(&index))
+    })();
+    let _ = std::fs::remove_file(&index);
+    let tree = tree?.trim().to_string();
+
+    let message =
+        format!("relay checkpoint\n\nrun_id: {run_id}\nrequest_id: {request_id}\ntool: {tool}\n");
+    let mut args = vec!["commit-tree", tree.as_str(), "-m", message.as_str()];
+    if let Some(head) = head.as_deref() {
+        args.push("-p");
+        args.push(head);
+    }
+    let commit = git(cwd, &args, None)?.trim().to_string();
+
+    // Ids are allocated after the highest existing one and claimed with a create-only
+    // `update-ref` (empty old value), so concurrent snapshots of the same run never overwrite
+    // each other: the loser re-reads the refs and takes the next id.
+    let mut checkpoint_id = String::new();
+    for _ in 0..CREATE_ATTEMPTS {
+        let existing = git(cwd, &["for-each-ref", "--format=%(refname)", &prefix], None)?;
+        let seq = existing
+            .lines()
+            .filter_map(|l| l.trim().strip_prefix(&prefix)?.parse::<u64>().ok())
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let id = format!("{seq:04}");
+        let refname = format!("{prefix}{id}");
+        match git(cwd, &["update-ref", &refname, &commit, ""], None) {
+            Ok(_) => {
+                checkpoint_id = id;
+                break;
+            }
+            Err(err) if !ref_exists(cwd, &refname) => return Err(err),
+            Err(_) => continue,
+        }
+    }
+    if checkpoint_id.is_empty() {
+        return Err((
+            StatusCode::CONFLICT,
+            "could not allocate a checkpoint id".into(),
+        ));
+    }
+
+    Ok(Some(Checkpoint {
+        checkpoint_id,
+        run_id: run_id.to_string(),
+        request_id: request_id.to_string(),
+        tool: tool.to_string(),
+        commit,
+        created_at: chrono::Utc::now().to_rfc3339(),
+    }))
+}
+
+pub fn list(run_cwd: &str, run_id: &str) -> Result<Vec<Checkpoint>, (StatusCode, String)> {
+    let cwd = Path::new(run_cwd);
+    if !is_git_worktree(cwd) {
+        return Ok(Vec::new());
+    }
+    let prefix = run_ref_prefix(run_id)?;
+    let out = git(
+        cwd,
+        &[
+            "for-each-ref",
+            "--sort=refname",
+            "--format=%(refname)%00%(objectname)%00%(creatordate:iso-strict)%00%(contents:body)%00",
+            &prefix,
+        ],
+        None,
+    )?;
+
+    let fields: Vec<&str> = out.split('\0').collect();
+    let mut checkpoints = Vec::new();
+    for rec in fields.chunks(4) {
+        let [refname, commit, created_at, body] = rec else {
+            continue;
+        };
+        let refname = refname.trim_start_matches('\n');
+        let Some(checkpoint_id) = refname.strip_prefix(&prefix) else {
+            continue;
+        };
+        let field = |key: &str| {
+            body.lines()
+                .find_map(|l| l.strip_prefix(key).map(|v| v.trim().to_string()))
+                .unwrap_or_default()
+        };
+        checkpoints.push(Checkpoint {
+            checkpoint_id: checkpoint_id.to_string(),
+            run_id: run_id.to_string(),
+            request_id: field("request_id:"),
+            tool: field("tool:"),
+            commit: commit.to_string(),
+            created_at: created_at.to_string(),
+        });
+    }
+    Ok(checkpoints)
+}
+
+fn nul_paths(s: &str) -> std::collections::HashSet<String> {
+    s.split('\0')
+        .filter(|p| !p.is_empty())
+        .map(|p| p.to_string())
+        .collect()
+}
+
+/// Resets everything under `run_cwd` to the checkpoint: files created since are removed and the
+/// rest is checked out from the snapshot. The current state is snapshotted first (tool
+/// `restore`) so the restore itself can be undone.
+pub fn restore(
+    run_cwd: &str,
+    run_id: &str,
+    checkpoint_id: &str,
+    request_id: &str,
+) -> Result<serde_json::Value, (StatusCode, String)> {
+    let cwd = Path::new(run_cwd);
+    if !is_git_worktree(cwd) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "run cwd is not a git work tree".into(),
+        ));
+    }
+    if !valid_ref_part(checkpoint_id) {
+        return Err((StatusCode::BAD_REQUEST, "invalid checkpoint_id".into()));
+    }
+    let refname = format!("{}{checkpoint_id}", run_ref_prefix(run_id)?);
+    let commit = git(
+        cwd,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{refname}^{{commit}}"),
+        ],
+        None,
+    )
+    .map_err(|_| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("unknown checkpoint {checkpoint_id}"),
+        )
+    })?
+    .trim()
+    .to_string();
+
+    let safety = create(run_cwd, run_id, request_id, "restore")?;
+
+    let current = nul_paths(&git(
+        cwd,
+        &["ls-files", "-z", "-co", "--exclude-standard", "--", "."],
+        None,
+    )?);
+    let target = nul_paths(&git(
+        cwd,
+        &["ls-tree", "-r", "-z", "--name-only", &commit, "--", "."],
+        None,
+    )?);
+    let mut removed = 0;
+    for rel in current.difference(&target) {
+        if std::fs::remove_file(cwd.join(rel)).is_ok() {
+            removed += 1;
+        }
+    }
+    if !target.is_empty() {
+        git(
+            cwd,
+            &["restore", "--source", &commit, "--worktree", "--", "."],
+            None,
+        )?;
+    }
+
+    Ok(json!({
+        "checkpoint_id": checkpoint_id,
+        "commit": commit,
+        "files_removed": removed,
+        "safety_checkpoint": safety,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_list_and_restore_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("relay-ckpt-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let root = dir.to_str().unwrap();
+        git(&dir, &["init", "-q"], None).unwrap();
+        std::fs::write(dir.join("a.txt"), "one\n").unwrap();
+        std::fs::write(dir.join(".gitignore"), "ignored.log\n").unwrap();
+        git(&dir, &["add", "-A"], None).unwrap();
+        git(&dir, &["commit", "-q", "-m", "init"], None).unwrap();
+
+        std::fs::write(dir.join("untracked.txt"), "keep me\n").unwrap();
+        let ckpt = create(root, "run-1", "req-1", "rpc.bash").unwrap().unwrap();
+        assert_eq!(ckpt.checkpoint_id, "0001");
+
+        // Simulate an agent trashing the tree.
+        std::fs::write(dir.join("a.txt"), "broken\n").unwrap();
+        std::fs::remove_file(dir.join("untracked.txt")).unwrap();
+        std::fs::write(dir.join("new.txt"), "junk\n").unwrap();
+        std::fs::write(dir.join("ignored.log"), "log\n").unwrap();
+
+        let res = restore(root, "run-1", "0001", "req-2").unwrap();
+        assert_eq!(res["files_removed"], 1);
+        assert_eq!(std::fs::read_to_string(dir.join("a.txt")).unwrap(), "one\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("untracked.txt")).unwrap(),
+            "keep me\n"
+        );
+        assert!(!dir.join("new.txt").exists());
+        assert!(dir.join("ignored.log").exists());
+        // The user's index is untouched: untracked.txt is still untracked.
+        let status = git(&dir, &["status", "--porcelain"], None).unwrap();
+        assert!(status.contains("?? untracked.txt"));
+
+        let all = list(root, "run-1").unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].request_id, "req-1");
+        assert_eq!(all[0].tool, "rpc.bash");
+        assert_eq!(all[1].tool, "restore");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn concurrent_creates_get_distinct_ids() {
+        let dir = std::env::temp_dir().join(format!("relay-ckpt-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        git(&dir, &["init", "-q"], None).unwrap();
+        std::fs::write(dir.join("a.txt"), "one\n").unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let root = dir.to_str().unwrap().to_string();
+                std::thread::spawn(move || {
+                    create(&root, "run-1", &format!("req-{i}"), "rpc.fs.write")
+                        .unwrap()
+                        .unwrap()
+                        .checkpoint_id
+                })
+            })
+            .collect();
+        let mut ids: Vec<String> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 8);
+        assert_eq!(list(dir.to_str().unwrap(), "run-1").unwrap().len(), 8);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
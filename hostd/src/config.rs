@@ -7,8 +7,25 @@ pub struct Config {
     pub host_id: String,
     pub host_token: String,
     pub local_unix_socket: String,
+    /// File mode of the local socket (default `0o600`).
+    pub local_socket_mode: u32,
+    /// UIDs allowed on the local socket besides hostd's own (checked with `SO_PEERCRED`).
+    pub local_allowed_uids: Vec<u32>,
+    /// Optional TCP listener for the local API (`127.0.0.1:8788`); other than loopback needs mTLS.
+    pub local_tcp_listen: Option<String>,
+    /// Bearer token required on the TCP listener (`LOCAL_TCP_TOKEN`, or read from the 0600 file
+    /// `LOCAL_TCP_TOKEN_FILE`).
+    pub local_tcp_token: Option<String>,
+    /// PEM server certificate/key for TLS on the TCP listener.
+    pub local_tls_cert: Option<String>,
+    pub local_tls_key: Option<String>,
+    /// PEM CA that client certificates must chain to (mTLS).
+    pub local_tls_client_ca: Option<String>,
     pub redaction_extra_regex: Vec<String>,
     pub spool_db_path: String,
+    /// Hours that events already acked by the server stay in the spool for local replay
+    /// (`GET /events?since_seq=`); unacked events are kept for 3 days.
+    pub spool_replay_hours: u32,
     pub log_path: Option<String>,
 }
 
@@ -18,8 +35,27 @@ struct FileConfig {
     host_id: Option<String>,
     host_token: Option<String>,
     local_unix_socket: Option<String>,
+    /// Octal string, e.g. `"660"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    local_socket_mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    local_allowed_uids: Option<Vec<u32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    local_tcp_listen: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    local_tcp_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    local_tcp_token_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    local_tls_cert: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    local_tls_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    local_tls_client_ca: Option<String>,
     redaction_extra_regex: Option<Vec<String>>,
     spool_db_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spool_replay_hours: Option<u32>,
     log_path: Option<String>,
 }
 
@@ -146,8 +182,17 @@ fn default_file_config() -> FileConfig {
         host_id: Some(host_id),
         host_token: Some(host_token),
         local_unix_socket: Some(local_unix_socket),
+        local_socket_mode: None,
+        local_allowed_uids: None,
+        local_tcp_listen: None,
+        local_tcp_token: None,
+        local_tcp_token_file: None,
+        local_tls_cert: None,
+        local_tls_key: None,
+        local_tls_client_ca: None,
         redaction_extra_regex: Some(Vec::new()),
         spool_db_path: Some(spool_db_path),
+        spool_replay_hours: None,
         log_path,
     }
 }
@@ -199,6 +244,79 @@ fn ensure_default_config_file(path: &std::path::Path) -> anyhow::Result<bool> {
     Ok(true)
 }
 
+fn parse_socket_mode(raw: &str) -> Option<u32> {
+    let raw = raw.trim();
+    let raw = raw.strip_prefix("0o").unwrap_or(raw);
+    u32::from_str_radix(raw, 8).ok().filter(|m| *m <= 0o777)
+}
+
+fn parse_uids(raw: &str) -> Vec<u32> {
+    raw.split(',')
+        .filter_map(|s| s.trim().parse::<u32>().ok())
+        .collect()
+}
+
+/// Default of `spool_replay_hours`; values are capped at the 3-day spool retention.
+const DEFAULT_SPOOL_REPLAY_HOURS: u32 = 6;
+const MAX_SPOOL_REPLAY_HOURS: u32 = 72;
+
+fn parse_replay_hours(raw: &str) -> Option<u32> {
+    raw.trim()
+        .parse::<u32>()
+        .ok()
+        .map(|h| h.min(MAX_SPOOL_REPLAY_HOURS))
+}
+
+/// Environment variables holding hostd's credentials. They are removed from hostd's own
+/// environment once the config is loaded and from every agent process, so a run cannot pick up
+/// the host token or reach the TCP listener as a remote client.
+pub const SECRET_ENV_VARS: &[&str] = &[
+    "HOST_TOKEN",
+    "LOCAL_TCP_TOKEN",
+    "LOCAL_TCP_TOKEN_FILE",
+    "LOCAL_TLS_CERT",
+    "LOCAL_TLS_KEY",
+    "LOCAL_TLS_CLIENT_CA",
+];
+
+/// Removes [`SECRET_ENV_VARS`] from `cmd`'s environment.
+pub fn strip_secret_env(cmd: &mut std::process::Command) -> &mut std::process::Command {
+    for name in SECRET_ENV_VARS {
+        cmd.env_remove(name);
+    }
+    cmd
+}
+
+/// Removes [`SECRET_ENV_VARS`] from this process, so that nothing spawned later inherits them.
+/// Call once, right after loading the config and before anything else reads the environment.
+pub fn scrub_secret_env() {
+    for name in SECRET_ENV_VARS {
+        // SAFETY: called at startup before any task that reads or writes the environment runs.
+        unsafe { std::env::remove_var(name) };
+    }
+}
+
+/// Reads the TCP bearer token from `path`, which must not be readable by group or others.
+fn read_token_file(path: &str) -> anyhow::Result<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let meta = std::fs::metadata(path).with_context(|| format!("stat token file: {path}"))?;
+    anyhow::ensure!(
+        meta.permissions().mode() & 0o077 == 0,
+        "token file {path} must not be accessible by group or others (chmod 600)"
+    );
+    let token =
+        std::fs::read_to_string(path).with_context(|| format!("read token file: {path}"))?;
+    Ok(token.trim().to_string())
+}
+
+fn env_nonempty(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
 fn read_file_config(path: &std::path::Path) -> anyhow::Result<Option<FileConfig>> {
     let raw = match std::fs::read_to_string(path) {
         Ok(s) => s,
@@ -255,13 +373,32 @@ impl Config {
             })
             .unwrap_or_default();
 
+        let local_socket_mode = std::env::var("LOCAL_SOCKET_MODE")
+            .ok()
+            .and_then(|v| parse_socket_mode(&v))
+            .unwrap_or(0o600);
+        let local_allowed_uids = std::env::var("LOCAL_ALLOWED_UIDS")
+            .map(|v| parse_uids(&v))
+            .unwrap_or_default();
+
         Self {
             server_base_url,
             host_id,
             host_token,
             local_unix_socket,
+            local_socket_mode,
+            local_allowed_uids,
+            local_tcp_listen: env_nonempty("LOCAL_TCP_LISTEN"),
+            local_tcp_token: env_nonempty("LOCAL_TCP_TOKEN"),
+            local_tls_cert: env_nonempty("LOCAL_TLS_CERT"),
+            local_tls_key: env_nonempty("LOCAL_TLS_KEY"),
+            local_tls_client_ca: env_nonempty("LOCAL_TLS_CLIENT_CA"),
             redaction_extra_regex,
             spool_db_path,
+            spool_replay_hours: std::env::var("SPOOL_REPLAY_HOURS")
+                .ok()
+                .and_then(|v| parse_replay_hours(&v))
+                .unwrap_or(DEFAULT_SPOOL_REPLAY_HOURS),
             log_path,
         }
     }
@@ -379,13 +516,79 @@ impl Config {
             })
             .unwrap_or_default();
 
+        let local_socket_mode = std::env::var("LOCAL_SOCKET_MODE")
+            .ok()
+            .and_then(|v| parse_socket_mode(&v))
+            .or_else(|| {
+                file_cfg
+                    .as_ref()
+                    .and_then(|c| c.local_socket_mode.as_deref())
+                    .and_then(parse_socket_mode)
+            })
+            .unwrap_or(0o600);
+
+        let local_allowed_uids = std::env::var("LOCAL_ALLOWED_UIDS")
+            .ok()
+            .map(|v| parse_uids(&v))
+            .filter(|v| !v.is_empty())
+            .or_else(|| file_cfg.as_ref().and_then(|c| c.local_allowed_uids.clone()))
+            .unwrap_or_default();
+
+        let spool_replay_hours = std::env::var("SPOOL_REPLAY_HOURS")
+            .ok()
+            .and_then(|v| parse_replay_hours(&v))
+            .or_else(|| {
+                file_cfg
+                    .as_ref()
+                    .and_then(|c| c.spool_replay_hours)
+                    .map(|h| h.min(MAX_SPOOL_REPLAY_HOURS))
+            })
+            .unwrap_or(DEFAULT_SPOOL_REPLAY_HOURS);
+
+        let from_file = |get: fn(&FileConfig) -> Option<&String>| {
+            file_cfg
+                .as_ref()
+                .and_then(get)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let local_tcp_listen =
+            env_nonempty("LOCAL_TCP_LISTEN").or_else(|| from_file(|c| c.local_tcp_listen.as_ref()));
+        let local_tcp_token = match env_nonempty("LOCAL_TCP_TOKEN") {
+            Some(token) => Some(token),
+            None => match env_nonempty("LOCAL_TCP_TOKEN_FILE") {
+                Some(path) => Some(read_token_file(&path)?),
+                None => match from_file(|c| c.local_tcp_token.as_ref()) {
+                    Some(token) => Some(token),
+                    None => from_file(|c| c.local_tcp_token_file.as_ref())
+                        .map(|path| read_token_file(&path))
+                        .transpose()?,
+                },
+            },
+        }
+        .filter(|t| !t.is_empty());
+        let local_tls_cert =
+            env_nonempty("LOCAL_TLS_CERT").or_else(|| from_file(|c| c.local_tls_cert.as_ref()));
+        let local_tls_key =
+            env_nonempty("LOCAL_TLS_KEY").or_else(|| from_file(|c| c.local_tls_key.as_ref()));
+        let local_tls_client_ca = env_nonempty("LOCAL_TLS_CLIENT_CA")
+            .or_else(|| from_file(|c| c.local_tls_client_ca.as_ref()));
+
         let cfg = Self {
             server_base_url,
             host_id,
             host_token,
             local_unix_socket,
+            local_socket_mode,
+            local_allowed_uids,
+            local_tcp_listen,
+            local_tcp_token,
+            local_tls_cert,
+            local_tls_key,
+            local_tls_client_ca,
             redaction_extra_regex,
             spool_db_path,
+            spool_replay_hours,
             log_path,
         };
 
@@ -409,7 +612,10 @@ mod tests {
   "host_id": "host-1",
   "host_token": "t-1",
   "local_unix_socket": "/tmp/x.sock",
+  "local_socket_mode": "660",
+  "local_allowed_uids": [1001],
   "spool_db_path": "/tmp/spool.db",
+  "spool_replay_hours": 100,
   "log_path": "/tmp/hostd.log",
   "redaction_extra_regex": ["foo", "bar"]
 }"#,
@@ -420,5 +626,43 @@ mod tests {
         assert_eq!(cfg.server_base_url.as_deref(), Some("https://example.com"));
         assert_eq!(cfg.host_id.as_deref(), Some("host-1"));
         assert_eq!(cfg.host_token.as_deref(), Some("t-1"));
+        assert_eq!(
+            cfg.local_socket_mode.as_deref().and_then(parse_socket_mode),
+            Some(0o660)
+        );
+        assert_eq!(cfg.local_allowed_uids, Some(vec![1001]));
+        assert_eq!(cfg.spool_replay_hours, Some(100));
+        assert_eq!(parse_replay_hours("100"), Some(MAX_SPOOL_REPLAY_HOURS));
+        assert_eq!(parse_socket_mode("0o600"), Some(0o600));
+        assert_eq!(parse_socket_mode("999"), None);
+    }
+
+    #[test]
+    fn token_file_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("abrelay-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tcp.token");
+        std::fs::write(&path, "0123456789abcdef\n").unwrap();
+        let path_str = path.to_str().unwrap();
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let err = read_token_file(path_str).unwrap_err();
+        assert!(err.to_string().contains("chmod 600"), "{err}");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(read_token_file(path_str).unwrap(), "0123456789abcdef");
+
+        let mut cmd = std::process::Command::new("env");
+        cmd.env("LOCAL_TCP_TOKEN", "secret").env("KEEP", "1");
+        strip_secret_env(&mut cmd);
+        let envs: Vec<_> = cmd.get_envs().collect();
+        assert!(envs.contains(&(std::ffi::OsStr::new("LOCAL_TCP_TOKEN"), None)));
+        assert!(envs.contains(&(
+            std::ffi::OsStr::new("KEEP"),
+            Some(std::ffi::OsStr::new("1"))
+        )));
+
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
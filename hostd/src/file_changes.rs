@@ -0,0 +1,748 @@
+//! Per-run file-change tracking.
+//!
+//! When a run starts, the files under its cwd (minus `.git` and anything matched by `.gitignore`)
+//! are snapshotted in memory. On Linux an inotify watch on every directory records which paths
+//! changed and which `tool.call` was open at the time; elsewhere, or once the watch can no longer
+//! be trusted (queue overflow, watch limit), the tree is rescanned on demand. `rpc.run.changes`
+//! diffs the snapshot against the current contents, so it also works outside git repositories.
+
+use crate::tree_watch::{Ignore, MAX_FILES, scan};
+use serde_json::{Value as JsonValue, json};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Larger files are tracked by size/mtime only (no diff).
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+/// Snapshot memory per run; files past it are tracked by size/mtime only.
+const MAX_SNAPSHOT_BYTES: u64 = 16 * 1024 * 1024;
+const MAX_WATCHES: usize = 8_192;
+const MAX_DIFF_BYTES: usize = 400_000;
+/// Past this edit distance a file is shown as fully replaced instead of diffed line by line.
+const MAX_EDIT_DISTANCE: usize = 2_000;
+/// How far a change may lie outside a tool call and still be attributed to it. Covers watcher
+/// latency and runners that report `tool.call` only once the tool has finished.
+const TOOL_SLACK: Duration = Duration::from_secs(1);
+const MAX_TOOL_SPANS: usize = 64;
+/// Finished runs whose snapshot is kept for `rpc.run.changes`.
+const MAX_FINISHED_RUNS: usize = 4;
+
+/// `RELAY_FILE_TRACKING=0` disables snapshots, watches and `run.files_changed`.
+pub fn enabled() -> bool {
+    !matches!(
+        std::env::var("RELAY_FILE_TRACKING")
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+            .as_str(),
+        "0" | "false" | "no" | "off"
+    )
+}
+
+enum Baseline {
+    Content(Vec<u8>),
+    /// Too large (or past the snapshot budget): only size and mtime are known.
+    Skipped {
+        len: u64,
+        mtime: Option<SystemTime>,
+    },
+}
+
+struct ToolSpan {
+    request_id: String,
+    started: Instant,
+    ended: Option<Instant>,
+}
+
+struct PendingChange {
+    path: String,
+    change: &'static str,
+    at: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    /// Every path seen changing since start -> request_ids of the tool calls that touched it.
+    touched: BTreeMap<String, Vec<String>>,
+    /// Changes not yet attributed and reported in `run.files_changed`.
+    pending: Vec<PendingChange>,
+    tools: VecDeque<ToolSpan>,
+    /// Set when the watch may have missed changes; `changes()` then rescans the whole tree.
+    rescan: bool,
+}
+
+impl State {
+    fn tool_at(&self, at: Instant) -> Option<String> {
+        self.tools
+            .iter()
+            .rev()
+            .find(|t| {
+                t.started <= at + TOOL_SLACK && t.ended.is_none_or(|end| end + TOOL_SLACK >= at)
+            })
+            .map(|t| t.request_id.clone())
+    }
+}
+
+pub struct Tracker {
+    root: PathBuf,
+    ignore: Ignore,
+    baseline: HashMap<String, Baseline>,
+    started_at: String,
+    state: Mutex<State>,
+    stop: AtomicBool,
+}
+
+impl Tracker {
+    /// Snapshots `cwd` and, on Linux, starts watching it. Blocking; call from `spawn_blocking`.
+    pub fn start(cwd: &str) -> anyhow::Result<Arc<Self>> {
+        let root = PathBuf::from(cwd);
+        // Watches go in before each directory is read, so changes made right after `start`
+        // returns cannot slip through.
+        #[cfg(target_os = "linux")]
+        let (tree, ignore, watch) =
+            match crate::tree_watch::TreeWatch::new(&root, "", usize::MAX, MAX_WATCHES, true) {
+                Ok((w, tree)) => (tree, w.ignore().clone(), Some(w)),
+                Err(_) => {
+                    let mut ignore = Ignore::default();
+                    (scan(&root, "", &mut ignore, true), ignore, None)
+                }
+            };
+        #[cfg(not(target_os = "linux"))]
+        let (tree, ignore) = {
+            let mut ignore = Ignore::default();
+            (scan(&root, "", &mut ignore, true), ignore)
+        };
+        anyhow::ensure!(
+            !tree.truncated,
+            "more than {MAX_FILES} files under {cwd}; file tracking disabled"
+        );
+        let mut baseline = HashMap::with_capacity(tree.files.len());
+        let mut budget = MAX_SNAPSHOT_BYTES;
+        for (rel, md) in tree.files {
+            let content = (md.len() <= MAX_FILE_BYTES && md.len() <= budget)
+                .then(|| std::fs::read(root.join(&rel)).ok())
+                .flatten();
+            let entry = match content {
+                Some(bytes) => {
+                    budget -= (bytes.len() as u64).min(budget);
+                    Baseline::Content(bytes)
+                }
+                None => Baseline::Skipped {
+                    len: md.len(),
+                    mtime: md.modified().ok(),
+                },
+            };
+            baseline.insert(rel, entry);
+        }
+        let tracker = Arc::new(Self {
+            root,
+            ignore,
+            baseline,
+            started_at: chrono::Utc::now().to_rfc3339(),
+            state: Mutex::new(State::default()),
+            stop: AtomicBool::new(false),
+        });
+        #[cfg(target_os = "linux")]
+        match watch {
+            Some(w) => {
+                if w.is_truncated() {
+                    tracker.force_rescan();
+                }
+                let t = tracker.clone();
+                std::thread::spawn(move || watch_tree(t, w));
+            }
+            None => tracker.force_rescan(),
+        }
+        #[cfg(not(target_os = "linux"))]
+        tracker.force_rescan();
+        Ok(tracker)
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    fn force_rescan(&self) {
+        if let Ok(mut st) = self.state.lock() {
+            st.rescan = true;
+        }
+    }
+
+    fn record(&self, path: String, change: &'static str) {
+        let Ok(mut st) = self.state.lock() else {
+            return;
+        };
+        match st.pending.iter_mut().find(|p| p.path == path) {
+            // A file created and then written is still "created".
+            Some(p) if p.change == "created" && change == "modified" => {}
+            Some(p) => p.change = change,
+            None => st.pending.push(PendingChange {
+                path,
+                change,
+                at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Notes a `tool.call` (`done = false`) or its `tool.result` (`done = true`).
+    pub fn note_tool(&self, request_id: &str, done: bool) {
+        let Ok(mut st) = self.state.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if done {
+            if let Some(t) = st.tools.iter_mut().find(|t| t.request_id == request_id) {
+                t.ended = Some(now);
+            }
+            return;
+        }
+        if st.tools.len() >= MAX_TOOL_SPANS {
+            st.tools.pop_front();
+        }
+        st.tools.push_back(ToolSpan {
+            request_id: request_id.to_string(),
+            started: now,
+            ended: None,
+        });
+    }
+
+    /// Attributes and returns the changes old enough to be matched to tool calls (all of them
+    /// with `all`), as `run.files_changed` entries.
+    pub fn take_pending(&self, all: bool) -> Vec<JsonValue> {
+        let Ok(mut st) = self.state.lock() else {
+            return Vec::new();
+        };
+        let now = Instant::now();
+        let (ready, keep): (Vec<_>, Vec<_>) = std::mem::take(&mut st.pending)
+            .into_iter()
+            .partition(|p| all || p.at + TOOL_SLACK <= now);
+        st.pending = keep;
+        ready
+            .into_iter()
+            .map(|p| {
+                let request_id = st.tool_at(p.at);
+                let ids = st.touched.entry(p.path.clone()).or_default();
+                if let Some(id) = &request_id
+                    && !ids.contains(id)
+                {
+                    ids.push(id.clone());
+                }
+                json!({ "path": p.path, "change": p.change, "request_id": request_id })
+            })
+            .collect()
+    }
+
+    /// Net changes since the run started: per-file status, added/removed line counts and a
+    /// unified diff (unless `with_diff` is false). Blocking.
+    pub fn changes(&self, path: Option<&str>, with_diff: bool) -> JsonValue {
+        let (touched, rescan) = match self.state.lock() {
+            Ok(st) => {
+                // Not-yet-reported changes count too; they stay queued for `run.files_changed`.
+                let mut touched = st.touched.clone();
+                for p in &st.pending {
+                    let ids = touched.entry(p.path.clone()).or_default();
+                    if let Some(id) = st.tool_at(p.at)
+                        && !ids.contains(&id)
+                    {
+                        ids.push(id);
+                    }
+                }
+                (touched, st.rescan)
+            }
+            Err(_) => (BTreeMap::new(), true),
+        };
+        let mut candidates: BTreeSet<String> = touched.keys().cloned().collect();
+        if rescan {
+            let tree = scan(&self.root, "", &mut self.ignore.clone(), false);
+            candidates.extend(self.baseline.keys().cloned());
+            candidates.extend(tree.files.into_iter().map(|(rel, _)| rel));
+        }
+
+        let mut files = Vec::new();
+        let (mut added_total, mut removed_total) = (0usize, 0usize);
+        let mut diff_bytes = 0usize;
+        let mut truncated = false;
+        for rel in candidates
+            .iter()
+            .filter(|rel| path.is_none_or(|p| p == rel.as_str()))
+        {
+            let before = self.baseline.get(rel.as_str());
+            let current = std::fs::symlink_metadata(self.root.join(rel))
+                .ok()
+                .filter(|md| md.is_file());
+            let new_bytes = current
+                .as_ref()
+                .filter(|md| md.len() <= MAX_FILE_BYTES)
+                .and_then(|_| std::fs::read(self.root.join(rel)).ok());
+            let status = match (before, &current) {
+                (None, None) => continue,
+                (None, Some(_)) => "added",
+                (Some(_), None) => "deleted",
+                (Some(Baseline::Content(old)), Some(_)) => {
+                    if new_bytes.as_ref() == Some(old) {
+                        continue;
+                    }
+                    "modified"
+                }
+                (Some(Baseline::Skipped { len, mtime }), Some(md)) => {
+                    if md.len() == *len && md.modified().ok() == *mtime {
+                        continue;
+                    }
+                    "modified"
+                }
+            };
+            let old = match before {
+                None => Some(""),
+                Some(Baseline::Content(b)) => as_text(b),
+                Some(Baseline::Skipped { .. }) => None,
+            };
+            let new = match (&current, &new_bytes) {
+                (None, _) => Some(""),
+                (Some(_), Some(b)) => as_text(b),
+                (Some(_), None) => None,
+            };
+            let mut entry = json!({
+                "path": rel,
+                "status": status,
+                "request_ids": touched.get(rel).cloned().unwrap_or_default(),
+            });
+            match (old, new) {
+                (Some(old), Some(new)) => {
+                    let d = unified_diff(rel, status, old, new);
+                    added_total += d.added;
+                    removed_total += d.removed;
+                    entry["added"] = json!(d.added);
+                    entry["removed"] = json!(d.removed);
+                    if with_diff {
+                        if diff_bytes + d.text.len() <= MAX_DIFF_BYTES {
+                            diff_bytes += d.text.len();
+                            entry["diff"] = JsonValue::String(d.text);
+                        } else {
+                            truncated = true;
+                            entry["diff"] = JsonValue::Null;
+                        }
+                    }
+                }
+                _ => {
+                    entry["binary"] = JsonValue::Bool(true);
+                    entry["added"] = JsonValue::Null;
+                    entry["removed"] = JsonValue::Null;
+                }
+            }
+            files.push(entry);
+        }
+        json!({
+            "since": self.started_at,
+            "tracking": if rescan { "rescan" } else { "inotify" },
+            "files": files,
+            "added": added_total,
+            "removed": removed_total,
+            "truncated": truncated,
+        })
+    }
+}
+
+fn as_text(bytes: &[u8]) -> Option<&str> {
+    if bytes.iter().take(8000).any(|b| *b == 0) {
+        return None;
+    }
+    std::str::from_utf8(bytes).ok()
+}
+
+#[cfg(target_os = "linux")]
+fn watch_tree(tracker: Arc<Tracker>, mut watch: crate::tree_watch::TreeWatch) {
+    use crate::tree_watch::{Change, TreeEvent};
+
+    while !tracker.is_stopped() {
+        let events = match watch.read() {
+            Ok(events) if events.is_empty() => {
+                std::thread::sleep(Duration::from_millis(200));
+                continue;
+            }
+            Ok(events) => events,
+            Err(_) => {
+                tracker.force_rescan();
+                return;
+            }
+        };
+        for ev in events {
+            let (path, change, is_dir, found) = match ev {
+                TreeEvent::Overflow => {
+                    tracker.force_rescan();
+                    continue;
+                }
+                TreeEvent::RootGone => continue,
+                TreeEvent::Entry { ignored: true, .. } => continue,
+                TreeEvent::Entry {
+                    path,
+                    change,
+                    is_dir,
+                    found,
+                    ..
+                } => (path, change, is_dir, found),
+            };
+            let gone = matches!(change, Change::Deleted | Change::MovedFrom(_));
+            if !is_dir {
+                let change = match change {
+                    _ if gone => "deleted",
+                    Change::Created | Change::MovedTo(_) => "created",
+                    _ => "modified",
+                };
+                tracker.record(path, change);
+                continue;
+            }
+            // Files may land in a new directory before its watch exists.
+            for file in found {
+                tracker.record(file, "created");
+            }
+            if gone {
+                let prefix = format!("{path}/");
+                let known: Vec<String> = tracker
+                    .baseline
+                    .keys()
+                    .filter(|k| k.starts_with(&prefix))
+                    .cloned()
+                    .collect();
+                for file in known {
+                    tracker.record(file, "deleted");
+                }
+            }
+        }
+        if watch.is_truncated() {
+            tracker.force_rescan();
+        }
+    }
+}
+
+/// Trackers of live runs, plus the last few finished ones so `rpc.run.changes` still answers
+/// after a run exits.
+#[derive(Default)]
+pub struct Trackers {
+    by_run: HashMap<String, Arc<Tracker>>,
+    finished: VecDeque<String>,
+}
+
+impl Trackers {
+    pub fn insert(&mut self, run_id: &str, tracker: Arc<Tracker>) {
+        self.by_run.insert(run_id.to_string(), tracker);
+    }
+
+    pub fn get(&self, run_id: &str) -> Option<Arc<Tracker>> {
+        self.by_run.get(run_id).cloned()
+    }
+
+    pub fn finish(&mut self, run_id: &str) {
+        let Some(t) = self.by_run.get(run_id) else {
+            return;
+        };
+        t.stop();
+        self.finished.push_back(run_id.to_string());
+        while self.finished.len() > MAX_FINISHED_RUNS {
+            if let Some(old) = self.finished.pop_front() {
+                self.by_run.remove(&old);
+            }
+        }
+    }
+}
+
+pub struct Diff {
+    pub text: String,
+    pub added: usize,
+    pub removed: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Myers' O(ND) line diff. Falls back to "delete everything, insert everything" past
+/// [`MAX_EDIT_DISTANCE`].
+fn diff_ops(a: &[&str], b: &[&str]) -> Vec<Op> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (ma, mb) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut ops: Vec<Op> = (0..prefix).map(|i| Op::Equal(i, i)).collect();
+    let middle = myers(ma, mb).unwrap_or_else(|| {
+        (0..ma.len())
+            .map(Op::Delete)
+            .chain((0..mb.len()).map(Op::Insert))
+            .collect()
+    });
+    ops.extend(middle.into_iter().map(|op| match op {
+        Op::Equal(i, j) => Op::Equal(i + prefix, j + prefix),
+        Op::Delete(i) => Op::Delete(i + prefix),
+        Op::Insert(j) => Op::Insert(j + prefix),
+    }));
+    let (a_tail, b_tail) = (a.len() - suffix, b.len() - suffix);
+    ops.extend((0..suffix).map(|k| Op::Equal(a_tail + k, b_tail + k)));
+    ops
+}
+
+fn myers(a: &[&str], b: &[&str]) -> Option<Vec<Op>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(MAX_EDIT_DISTANCE) as isize;
+    let off = max + 1;
+    let mut v = vec![0isize; (2 * max + 3) as usize];
+    // trace[d] holds v[-d-1..=d+1] as it was before step d.
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    for d in 0..=max {
+        trace.push(v[(off - d - 1) as usize..=(off + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let i = (off + k) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+        }
+    }
+    None
+}
+
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<Op> {
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..trace.len() as isize).rev() {
+        let v = &trace[d as usize];
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 { 0 } else { at(prev_k) };
+        let prev_y = if d == 0 { 0 } else { prev_x - prev_k };
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ops.push(Op::Equal(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                ops.push(Op::Insert((y - 1) as usize));
+            } else {
+                ops.push(Op::Delete((x - 1) as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.reverse();
+    ops
+}
+
+fn push_line(out: &mut String, tag: char, line: &str) {
+    out.push(tag);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// `git diff`-style unified diff with three lines of context. `status` is `added`, `deleted` or
+/// anything else for a modification.
+pub fn unified_diff(path: &str, status: &str, old: &str, new: &str) -> Diff {
+    const CONTEXT: usize = 3;
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let ops = diff_ops(&a, &b);
+    let added = ops.iter().filter(|o| matches!(o, Op::Insert(_))).count();
+    let removed = ops.iter().filter(|o| matches!(o, Op::Delete(_))).count();
+
+    let mut text = String::new();
+    if added + removed == 0 {
+        return Diff {
+            text,
+            added,
+            removed,
+        };
+    }
+    let from = if status == "added" {
+        "/dev/null".to_string()
+    } else {
+        format!("a/{path}")
+    };
+    let to = if status == "deleted" {
+        "/dev/null".to_string()
+    } else {
+        format!("b/{path}")
+    };
+    text.push_str(&format!("--- {from}\n+++ {to}\n"));
+
+    let changed: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, o)| !matches!(o, Op::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+    let mut idx = 0;
+    while idx < changed.len() {
+        let start = changed[idx].saturating_sub(CONTEXT);
+        let mut end = changed[idx];
+        while idx + 1 < changed.len() && changed[idx + 1] <= end + 2 * CONTEXT + 1 {
+            idx += 1;
+            end = changed[idx];
+        }
+        let end = (end + CONTEXT + 1).min(ops.len());
+        idx += 1;
+
+        // Hunk start lines: position of the first line on each side (1-based; 0 if empty).
+        let (mut a_start, mut b_start) = (None, None);
+        let (mut a_len, mut b_len) = (0, 0);
+        let mut body = String::new();
+        for op in &ops[start..end] {
+            match *op {
+                Op::Equal(i, j) => {
+                    a_start.get_or_insert(i);
+                    b_start.get_or_insert(j);
+                    a_len += 1;
+                    b_len += 1;
+                    push_line(&mut body, ' ', a[i]);
+                }
+                Op::Delete(i) => {
+                    a_start.get_or_insert(i);
+                    a_len += 1;
+                    push_line(&mut body, '-', a[i]);
+                }
+                Op::Insert(j) => {
+                    b_start.get_or_insert(j);
+                    b_len += 1;
+                    push_line(&mut body, '+', b[j]);
+                }
+            }
+        }
+        let a_pos = a_start.map(|i| i + 1).unwrap_or_else(|| {
+            ops[..start]
+                .iter()
+                .filter(|o| !matches!(o, Op::Insert(_)))
+                .count()
+        });
+        let b_pos = b_start.map(|j| j + 1).unwrap_or_else(|| {
+            ops[..start]
+                .iter()
+                .filter(|o| !matches!(o, Op::Delete(_)))
+                .count()
+        });
+        text.push_str(&format!("@@ -{a_pos},{a_len} +{b_pos},{b_len} @@\n"));
+        text.push_str(&body);
+    }
+    Diff {
+        text,
+        added,
+        removed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unified_diff_hunks() {
+        let d = unified_diff("f.txt", "modified", "a\nb\nc\nd\n", "a\nB\nc\nd\ne");
+        assert_eq!((d.added, d.removed), (2, 1));
+        assert_eq!(
+            d.text,
+            "--- a/f.txt\n+++ b/f.txt\n@@ -1,4 +1,5 @@\n a\n-b\n+B\n c\n d\n+e\n\\ No newline at end of file\n"
+        );
+        let d = unified_diff("n.txt", "added", "", "x\n");
+        assert!(
+            d.text
+                .starts_with("--- /dev/null\n+++ b/n.txt\n@@ -0,0 +1,1 @@\n+x\n")
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sees_writes_made_right_after_start() {
+        let dir = std::env::temp_dir().join(format!("relay-fc-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("deep/er")).unwrap();
+        std::fs::write(dir.join("deep/er/a.txt"), "a\n").unwrap();
+
+        let t = Tracker::start(dir.to_str().unwrap()).unwrap();
+        // No pause: the watches must already be in place when `start` returns.
+        std::fs::write(dir.join("deep/er/a.txt"), "b\n").unwrap();
+        std::fs::write(dir.join("top.txt"), "t\n").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut seen = BTreeSet::new();
+        while seen.len() < 2 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+            for e in t.take_pending(true) {
+                seen.insert(e["path"].as_str().unwrap().to_string());
+            }
+        }
+        assert!(seen.contains("deep/er/a.txt"), "{seen:?}");
+        assert!(seen.contains("top.txt"), "{seen:?}");
+        assert_eq!(t.changes(None, false)["tracking"], "inotify");
+        t.stop();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tracks_changes_and_attributes_tool_calls() {
+        let dir = std::env::temp_dir().join(format!("relay-fc-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        std::fs::write(dir.join("src/a.txt"), "one\ntwo\n").unwrap();
+        std::fs::write(dir.join("gone.txt"), "bye\n").unwrap();
+
+        let t = Tracker::start(dir.to_str().unwrap()).unwrap();
+        t.note_tool("call-1", false);
+        std::fs::write(dir.join("src/a.txt"), "one\n2\n").unwrap();
+        std::fs::create_dir_all(dir.join("src/new")).unwrap();
+        std::fs::write(dir.join("src/new/b.txt"), "b\n").unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join("target/out.bin"), "x").unwrap();
+        std::fs::remove_file(dir.join("gone.txt")).unwrap();
+        t.note_tool("call-1", true);
+        std::thread::sleep(Duration::from_millis(600));
+
+        let events = t.take_pending(true);
+        assert!(
+            events
+                .iter()
+                .any(|e| e["path"] == "src/a.txt" && e["request_id"] == "call-1")
+        );
+        assert!(events.iter().all(|e| e["path"] != "target/out.bin"));
+
+        let v = t.changes(None, true);
+        assert_eq!(v["tracking"], "inotify");
+        let files = v["files"].as_array().unwrap();
+        let by_path = |p: &str| files.iter().find(|f| f["path"] == p).unwrap().clone();
+        assert_eq!(by_path("src/a.txt")["status"], "modified");
+        assert_eq!(by_path("src/a.txt")["added"], 1);
+        assert_eq!(by_path("src/a.txt")["request_ids"][0], "call-1");
+        assert_eq!(by_path("src/new/b.txt")["status"], "added");
+        assert_eq!(by_path("gone.txt")["status"], "deleted");
+        assert_eq!(files.len(), 3);
+        t.stop();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
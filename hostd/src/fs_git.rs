@@ -1,6 +1,6 @@
 use axum::http::StatusCode;
 
-fn reject_unsafe_rel_path(rel: &str) -> Result<(), (StatusCode, String)> {
+pub fn reject_unsafe_rel_path(rel: &str) -> Result<(), (StatusCode, String)> {
     if rel.trim().is_empty() {
         return Err((StatusCode::BAD_REQUEST, "missing path".into()));
     }
@@ -112,66 +112,177 @@ pub fn write_utf8_file(
     Ok((bytes_to_write.len() as i64, truncated))
 }
 
-pub fn has_cmd(cmd: &str) -> bool {
-    std::process::Command::new(cmd)
-        .arg("--version")
-        .output()
-        .is_ok()
+/// Largest chunk (decoded bytes) `rpc.fs.download` returns or `rpc.fs.upload` accepts.
+pub const TRANSFER_MAX_CHUNK: usize = 1024 * 1024;
+pub const TRANSFER_DEFAULT_CHUNK: usize = 256 * 1024;
+/// Uploads larger than this are rejected.
+const TRANSFER_MAX_FILE: u64 = 4 * 1024 * 1024 * 1024;
+
+pub fn sha256_file(path: &std::path::Path) -> Result<String, (StatusCode, String)> {
+    use sha2::Digest;
+    use std::io::Read;
+    let mut f = std::fs::File::open(path).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let mut hasher = sha2::Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = f
+            .read(&mut buf)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[derive(Debug)]
+pub struct FileChunk {
+    pub size: u64,
+    pub bytes: Vec<u8>,
+    pub eof: bool,
+    /// sha256 of the whole file, only on the last chunk.
+    pub sha256: Option<String>,
 }
 
-pub fn rg_search(
+/// Reads up to `len` bytes at `offset` of any file (binary-safe) under the run cwd.
+pub fn read_file_chunk(
     run_cwd: &str,
-    q: &str,
-    max_matches: usize,
-) -> Result<(Vec<(String, i64, i64, String)>, bool), (StatusCode, String)> {
-    if q.trim().is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "missing q".into()));
+    rel_path: &str,
+    offset: u64,
+    len: usize,
+) -> Result<FileChunk, (StatusCode, String)> {
+    use std::io::{Read, Seek};
+    let path = safe_join_run_path(run_cwd, rel_path)?;
+    let mut f = std::fs::File::open(&path).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let md = f
+        .metadata()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    if !md.is_file() {
+        return Err((StatusCode::BAD_REQUEST, "path is not a file".into()));
     }
-    if !has_cmd("rg") {
-        return Err((StatusCode::NOT_IMPLEMENTED, "missing dependency: rg".into()));
+    let size = md.len();
+    if offset > size {
+        return Err((
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            format!("offset {offset} is past the end of the file ({size} bytes)"),
+        ));
     }
-
-    let out = std::process::Command::new("rg")
-        .current_dir(run_cwd)
-        .args([
-            "--line-number",
-            "--column",
-            "--no-heading",
-            "--color",
-            "never",
-            "--max-count",
-            &max_matches.to_string(),
-        ])
-        .arg(q)
-        .output()
+    f.seek(std::io::SeekFrom::Start(offset))
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut bytes = Vec::with_capacity(len.min(TRANSFER_MAX_CHUNK));
+    f.take(len.min(TRANSFER_MAX_CHUNK) as u64)
+        .read_to_end(&mut bytes)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let eof = offset + bytes.len() as u64 >= size;
+    let sha256 = if eof { Some(sha256_file(&path)?) } else { None };
+    Ok(FileChunk {
+        size,
+        bytes,
+        eof,
+        sha256,
+    })
+}
 
-    if !out.status.success() && out.status.code() != Some(1) {
-        let err = String::from_utf8_lossy(&out.stderr).to_string();
-        return Err((StatusCode::BAD_REQUEST, err));
+/// Uploads are staged next to the target as `.<name>.relay-upload` and renamed into place once the
+/// final chunk's sha256 matches, so an interrupted upload never leaves a half-written file.
+fn upload_partial_path(path: &std::path::Path) -> std::path::PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.relay-upload"))
+}
+
+#[derive(Debug)]
+pub struct UploadStatus {
+    /// Bytes staged so far; the next chunk must start at this offset.
+    pub received: u64,
+    pub done: bool,
+}
+
+/// Appends one upload chunk at `offset`. `offset == 0` with data restarts the upload; an empty
+/// chunk without `finish_sha256` only reports progress. With `finish_sha256` the staged file is
+/// verified and moved to `rel_path`.
+pub fn write_file_chunk(
+    run_cwd: &str,
+    rel_path: &str,
+    offset: u64,
+    bytes: &[u8],
+    finish_sha256: Option<&str>,
+) -> Result<UploadStatus, (StatusCode, String)> {
+    use std::io::Write;
+    if bytes.len() > TRANSFER_MAX_CHUNK {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("chunk exceeds {TRANSFER_MAX_CHUNK} bytes"),
+        ));
+    }
+    let path = safe_join_run_path_allow_create(run_cwd, rel_path)?;
+    if path.is_dir() {
+        return Err((StatusCode::BAD_REQUEST, "path is a directory".into()));
+    }
+    let partial = upload_partial_path(&path);
+    let received = std::fs::metadata(&partial).map(|m| m.len()).unwrap_or(0);
+    if bytes.is_empty() && finish_sha256.is_none() {
+        return Ok(UploadStatus {
+            received,
+            done: false,
+        });
+    }
+    let restart = offset == 0 && !bytes.is_empty();
+    if !restart && offset != received {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("offset mismatch: {received} bytes received so far"),
+        ));
     }
+    let received = offset + bytes.len() as u64;
+    if received > TRANSFER_MAX_FILE {
+        let _ = std::fs::remove_file(&partial);
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("upload exceeds {TRANSFER_MAX_FILE} bytes"),
+        ));
+    }
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(!restart)
+        .write(true)
+        .truncate(restart)
+        .open(&partial)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    f.write_all(bytes)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    drop(f);
 
-    let stdout = String::from_utf8_lossy(&out.stdout).to_string();
-    let mut matches = Vec::new();
-    for line in stdout.lines() {
-        let Some((path, rest)) = line.split_once(':') else {
-            continue;
-        };
-        let Some((line_no, rest)) = rest.split_once(':') else {
-            continue;
-        };
-        let Some((col_no, text)) = rest.split_once(':') else {
-            continue;
-        };
-        let line = line_no.parse::<i64>().unwrap_or(0);
-        let column = col_no.parse::<i64>().unwrap_or(0);
-        matches.push((path.to_string(), line, column, text.to_string()));
-        if matches.len() >= max_matches {
-            break;
-        }
+    let Some(expected) = finish_sha256 else {
+        return Ok(UploadStatus {
+            received,
+            done: false,
+        });
+    };
+    let actual = sha256_file(&partial)?;
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        let _ = std::fs::remove_file(&partial);
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("sha256 mismatch: uploaded data hashes to {actual}; upload discarded"),
+        ));
     }
-    let truncated = matches.len() >= max_matches;
-    Ok((matches, truncated))
+    std::fs::rename(&partial, &path)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(UploadStatus {
+        received,
+        done: true,
+    })
+}
+
+pub fn has_cmd(cmd: &str) -> bool {
+    std::process::Command::new(cmd)
+        .arg("--version")
+        .output()
+        .is_ok()
 }
 
 pub fn git_status(run_cwd: &str, max_chars: usize) -> Result<(String, bool), (StatusCode, String)> {
@@ -269,49 +380,628 @@ pub fn list_dir(
     Ok((out, truncated))
 }
 
-pub fn bash_exec(
+/// Outcome of a `bash -lc` run; the output fields keep only the tail past the size limits.
+#[derive(Debug, Default)]
+pub struct BashOutcome {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: Option<i64>,
+    /// Name of the signal that ended the process (e.g. `SIGKILL`).
+    pub signal: Option<String>,
+    pub timed_out: bool,
+    pub cancelled: bool,
+    pub truncated: bool,
+}
+
+impl BashOutcome {
+    /// The RPC error for anything but a clean zero exit.
+    pub fn error(&self) -> Option<String> {
+        let mut msg = if self.cancelled {
+            "bash cancelled".to_string()
+        } else if self.timed_out {
+            "bash timed out".to_string()
+        } else if let Some(sig) = self.signal.as_deref() {
+            format!("bash killed by {sig}")
+        } else if self.exit_code == Some(0) {
+            return None;
+        } else {
+            format!("bash exited with code {}", self.exit_code.unwrap_or(-1))
+        };
+        if !self.stderr.trim().is_empty() {
+            msg.push_str(": ");
+            msg.push_str(&self.stderr);
+        }
+        Some(msg)
+    }
+}
+
+/// Output tail of one stream plus the undecoded bytes of a UTF-8 sequence split across reads.
+#[derive(Default)]
+struct StreamTail {
+    tail: Vec<u8>,
+    total: usize,
+    partial: Vec<u8>,
+}
+
+impl StreamTail {
+    fn push(&mut self, bytes: &[u8], max: usize) -> String {
+        self.total += bytes.len();
+        self.tail.extend_from_slice(bytes);
+        if self.tail.len() > max.saturating_mul(2).max(8192) {
+            let cut = self.tail.len() - max;
+            self.tail.drain(..cut);
+        }
+        self.partial.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(_) => self.partial.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.partial.len(),
+        };
+        let rest = self.partial.split_off(valid);
+        let text = String::from_utf8_lossy(&self.partial).to_string();
+        self.partial = rest;
+        text
+    }
+
+    fn finish(&self, max: usize) -> (String, bool) {
+        let start = self.tail.len().saturating_sub(max);
+        // Skip continuation bytes so the kept tail starts on a character boundary.
+        let start = (start..self.tail.len())
+            .find(|&i| (self.tail[i] & 0xC0) != 0x80)
+            .unwrap_or(self.tail.len());
+        let truncated = self.total > self.tail.len() - start;
+        (
+            String::from_utf8_lossy(&self.tail[start..]).to_string(),
+            truncated,
+        )
+    }
+}
+
+/// Runs `bash -lc <cmd>` under the run's cwd (inside `sandbox` when set) in its own process
+/// group. Output is handed to `on_output(stream, text)` as it arrives; `cancel` and `timeout`
+/// terminate the whole group (SIGTERM, then SIGKILL after a short grace period). Each stream
+/// keeps at most `max_output_chars` bytes of tail.
+pub fn bash_stream(
     run_cwd: &str,
     cmd: &str,
-    max_stdout_chars: usize,
-    max_stderr_chars: usize,
-) -> Result<(String, String, i64, bool), (StatusCode, String)> {
+    max_output_chars: usize,
+    sandbox: Option<&crate::sandbox::SandboxProfile>,
+    timeout: std::time::Duration,
+    cancel: &std::sync::atomic::AtomicBool,
+    mut on_output: impl FnMut(&'static str, String),
+) -> Result<BashOutcome, (StatusCode, String)> {
+    use std::io::Read;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::time::{Duration, Instant};
+
     if cmd.trim().is_empty() {
         return Err((StatusCode::BAD_REQUEST, "missing cmd".into()));
     }
 
-    let out = std::process::Command::new("bash")
-        .current_dir(run_cwd)
+    let mut command = match sandbox {
+        Some(profile) => profile
+            .command(run_cwd, "bash")
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")))?,
+        None => {
+            let mut c = std::process::Command::new("bash");
+            c.current_dir(run_cwd);
+            c
+        }
+    };
+    let mut child = crate::config::strip_secret_env(&mut command)
         .arg("-lc")
         .arg(cmd)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let pgid = nix::unistd::Pid::from_raw(child.id() as i32);
+
+    let (tx, rx) = std::sync::mpsc::channel::<(&'static str, Vec<u8>)>();
+    let pipes: [(&'static str, Option<Box<dyn Read + Send>>); 2] = [
+        (
+            "stdout",
+            child
+                .stdout
+                .take()
+                .map(|p| Box::new(p) as Box<dyn Read + Send>),
+        ),
+        (
+            "stderr",
+            child
+                .stderr
+                .take()
+                .map(|p| Box::new(p) as Box<dyn Read + Send>),
+        ),
+    ];
+    for (stream, pipe) in pipes {
+        let Some(mut pipe) = pipe else { continue };
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 8192];
+            loop {
+                match pipe.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if tx.send((stream, buf[..n].to_vec())).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+    drop(tx);
+
+    let started = Instant::now();
+    let mut out = BashOutcome::default();
+    let (mut stdout, mut stderr) = (StreamTail::default(), StreamTail::default());
+    let mut kill_at: Option<Instant> = None;
+    let mut exited: Option<(std::process::ExitStatus, Instant)> = None;
+    let mut pipes_open = true;
+    loop {
+        match rx.recv_timeout(Duration::from_millis(50)) {
+            Ok((stream, bytes)) => {
+                let text = if stream == "stdout" {
+                    stdout.push(&bytes, max_output_chars)
+                } else {
+                    stderr.push(&bytes, max_output_chars)
+                };
+                if !text.is_empty() {
+                    on_output(stream, text);
+                }
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => pipes_open = false,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+        }
+
+        if exited.is_none() {
+            match child.try_wait() {
+                Ok(Some(status)) => exited = Some((status, Instant::now())),
+                Ok(None) => {}
+                Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+            }
+        }
+        // Background jobs may keep the pipes open after bash itself exited; stop waiting shortly after.
+        if let Some((_, at)) = exited
+            && (!pipes_open || at.elapsed() > Duration::from_millis(500))
+        {
+            break;
+        }
+
+        if kill_at.is_none() {
+            if cancel.load(std::sync::atomic::Ordering::Relaxed) {
+                out.cancelled = true;
+            } else if started.elapsed() >= timeout {
+                out.timed_out = true;
+            }
+            if out.cancelled || out.timed_out {
+                let _ = nix::sys::signal::killpg(pgid, nix::sys::signal::Signal::SIGTERM);
+                kill_at = Some(Instant::now() + Duration::from_secs(2));
+            }
+        } else if let Some(at) = kill_at
+            && Instant::now() >= at
+        {
+            let _ = nix::sys::signal::killpg(pgid, nix::sys::signal::Signal::SIGKILL);
+            kill_at = Some(Instant::now() + Duration::from_secs(3600));
+        }
+    }
+    if kill_at.is_some() {
+        let _ = nix::sys::signal::killpg(pgid, nix::sys::signal::Signal::SIGKILL);
+    }
+
+    if let Some((status, _)) = exited {
+        out.exit_code = status.code().map(|c| c as i64);
+        out.signal = status.signal().map(|sig| {
+            nix::sys::signal::Signal::try_from(sig)
+                .map(|s| s.as_str().to_string())
+                .unwrap_or_else(|_| format!("signal {sig}"))
+        });
+    }
+    let (stdout_tail, stdout_truncated) = stdout.finish(max_output_chars);
+    let (stderr_tail, stderr_truncated) = stderr.finish(max_output_chars);
+    out.stdout = stdout_tail;
+    out.stderr = stderr_tail;
+    out.truncated = stdout_truncated || stderr_truncated;
+    Ok(out)
+}
+
+/// Runs git in `cwd` and returns stdout. Repository hooks are disabled: the agent can write
+/// `.git/hooks`, and these commands run on the host outside the run's sandbox. Paths are printed
+/// unquoted and pathspecs are taken literally.
+pub(crate) fn git_stdout<I, S>(
+    cwd: impl AsRef<std::path::Path>,
+    args: I,
+) -> Result<String, (StatusCode, String)>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    let out = std::process::Command::new("git")
+        .current_dir(cwd)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_LITERAL_PATHSPECS", "1")
+        .args(["-c", "core.hooksPath=/dev/null"])
+        .args(["-c", "core.quotepath=off", "-c", "color.ui=never"])
+        .args(args)
+        .stdin(std::process::Stdio::null())
         .output()
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !out.status.success() {
+        // `git commit` reports "nothing to commit" on stdout.
+        let mut err = String::from_utf8_lossy(&out.stderr).trim().to_string();
+        if err.is_empty() {
+            err = String::from_utf8_lossy(&out.stdout).trim().to_string();
+        }
+        return Err((StatusCode::BAD_REQUEST, err));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
 
-    let exit_code = out.status.code().unwrap_or(-1) as i64;
+/// A git worktree created for a single run (`isolation: "worktree"`).
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RunWorktree {
+    /// Top-level dir of the checkout the run was requested in.
+    pub repo_root: String,
+    /// Worktree checkout dir (under the repo's git common dir).
+    pub path: String,
+    pub branch: String,
+    /// Branch checked out in `repo_root` when the worktree was created (None when detached).
+    pub base_branch: Option<String>,
+    pub base_commit: String,
+    /// Run cwd relative to the repo root (empty when the run starts at the top level).
+    pub subdir: String,
+    /// Absolute git common dir (shared objects/refs); sandboxed runs need it writable to commit.
+    pub git_common_dir: String,
+}
 
-    let stdout_raw = String::from_utf8_lossy(&out.stdout).to_string();
-    let stderr_raw = String::from_utf8_lossy(&out.stderr).to_string();
-    let stdout_truncated = stdout_raw.len() > max_stdout_chars;
-    let stderr_truncated = stderr_raw.len() > max_stderr_chars;
-    let stdout = if stdout_truncated {
-        stdout_raw[stdout_raw.len() - max_stdout_chars..].to_string()
-    } else {
-        stdout_raw
-    };
-    let stderr = if stderr_truncated {
-        stderr_raw[stderr_raw.len() - max_stderr_chars..].to_string()
+impl RunWorktree {
+    pub fn run_cwd(&self) -> String {
+        let p = std::path::Path::new(&self.path);
+        if self.subdir.is_empty() {
+            p.to_string_lossy().to_string()
+        } else {
+            p.join(&self.subdir).to_string_lossy().to_string()
+        }
+    }
+}
+
+pub fn worktree_branch_name(run_id: &str) -> String {
+    format!("relay/{run_id}")
+}
+
+fn worktree_locate(run_cwd: &str, run_id: &str) -> Result<RunWorktree, (StatusCode, String)> {
+    let cwd = std::path::Path::new(run_cwd);
+    let repo_root = git_stdout(cwd, ["rev-parse", "--show-toplevel"])
+        .map_err(|(_, e)| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("not a git repository: {e}"),
+            )
+        })?
+        .trim()
+        .to_string();
+    let subdir = git_stdout(cwd, ["rev-parse", "--show-prefix"])?
+        .trim()
+        .trim_end_matches('/')
+        .to_string();
+    let common_dir = git_stdout(cwd, ["rev-parse", "--git-common-dir"])?
+        .trim()
+        .to_string();
+    let common_dir = std::fs::canonicalize(cwd.join(&common_dir))
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("bad git dir: {e}")))?;
+    let path = common_dir.join("relay-worktrees").join(run_id);
+    Ok(RunWorktree {
+        repo_root,
+        path: path.to_string_lossy().to_string(),
+        branch: worktree_branch_name(run_id),
+        base_branch: None,
+        base_commit: String::new(),
+        subdir,
+        git_common_dir: common_dir.to_string_lossy().to_string(),
+    })
+}
+
+/// Creates `<git-common-dir>/relay-worktrees/<run_id>` on a fresh `relay/<run_id>` branch
+/// starting at the current `HEAD` of `run_cwd`.
+pub fn worktree_create(run_cwd: &str, run_id: &str) -> Result<RunWorktree, (StatusCode, String)> {
+    let mut wt = worktree_locate(run_cwd, run_id)?;
+    let root = std::path::Path::new(&wt.repo_root);
+    wt.base_commit = git_stdout(root, ["rev-parse", "--verify", "HEAD"])
+        .map_err(|(_, e)| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("worktree isolation needs at least one commit: {e}"),
+            )
+        })?
+        .trim()
+        .to_string();
+    wt.base_branch = git_stdout(root, ["symbolic-ref", "--quiet", "--short", "HEAD"])
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    git_stdout(
+        root,
+        [
+            "worktree",
+            "add",
+            "-b",
+            wt.branch.as_str(),
+            wt.path.as_str(),
+            wt.base_commit.as_str(),
+        ],
+    )?;
+    Ok(wt)
+}
+
+/// Rebuilds the worktree description for a run whose in-memory record is gone (e.g. hostd
+/// restarted), from the repo it was created in.
+pub fn worktree_find(repo_cwd: &str, run_id: &str) -> Result<RunWorktree, (StatusCode, String)> {
+    let mut wt = worktree_locate(repo_cwd, run_id)?;
+    if !std::path::Path::new(&wt.path).is_dir() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("no relay worktree for {run_id}"),
+        ));
+    }
+    wt.subdir = String::new();
+    wt.base_branch = git_stdout(
+        std::path::Path::new(&wt.repo_root),
+        ["symbolic-ref", "--quiet", "--short", "HEAD"],
+    )
+    .ok()
+    .map(|s| s.trim().to_string())
+    .filter(|s| !s.is_empty());
+    Ok(wt)
+}
+
+pub fn worktree_is_dirty(wt: &RunWorktree) -> Result<bool, (StatusCode, String)> {
+    let out = git_stdout(std::path::Path::new(&wt.path), ["status", "--porcelain"])?;
+    Ok(!out.trim().is_empty())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorktreeCleanup {
+    /// Commit pending changes on the run branch, merge it into the base branch, drop both.
+    Merge,
+    /// Drop the worktree and the run branch, losing any unmerged work.
+    Discard,
+    /// Drop the worktree checkout but keep the run branch for later review.
+    KeepBranch,
+}
+
+impl WorktreeCleanup {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "merge" => Some(Self::Merge),
+            "discard" => Some(Self::Discard),
+            "" | "keep_branch" => Some(Self::KeepBranch),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Merge => "merge",
+            Self::Discard => "discard",
+            Self::KeepBranch => "keep_branch",
+        }
+    }
+}
+
+/// Finishes a worktree run. Returns `{ action, committed, merged_commit, branch_deleted }`.
+pub fn worktree_cleanup(
+    wt: &RunWorktree,
+    action: WorktreeCleanup,
+) -> Result<serde_json::Value, (StatusCode, String)> {
+    let root = std::path::Path::new(&wt.repo_root);
+    let wt_path = std::path::Path::new(&wt.path);
+    let mut committed = None;
+    let mut merged_commit = None;
+
+    if action == WorktreeCleanup::Merge {
+        let current = git_stdout(root, ["symbolic-ref", "--quiet", "--short", "HEAD"])
+            .ok()
+            .map(|s| s.trim().to_string());
+        let Some(current) = current.filter(|s| !s.is_empty()) else {
+            return Err((
+                StatusCode::CONFLICT,
+                format!(
+                    "{} has a detached HEAD; check out a branch to merge into",
+                    wt.repo_root
+                ),
+            ));
+        };
+        if let Some(base) = wt.base_branch.as_deref()
+            && base != current
+        {
+            return Err((
+                StatusCode::CONFLICT,
+                format!(
+                    "{} is on `{current}`, expected base branch `{base}`",
+                    wt.repo_root
+                ),
+            ));
+        }
+
+        if worktree_is_dirty(wt)? {
+            git_stdout(wt_path, ["add", "-A"])?;
+            let msg = format!("relay: changes from {}", wt.branch);
+            git_stdout(wt_path, ["commit", "-q", "-m", msg.as_str()])?;
+            committed = Some(
+                git_stdout(wt_path, ["rev-parse", "HEAD"])?
+                    .trim()
+                    .to_string(),
+            );
+        }
+
+        if let Err((_, err)) = git_stdout(root, ["merge", "--no-edit", wt.branch.as_str()]) {
+            let _ = git_stdout(root, ["merge", "--abort"]);
+            return Err((
+                StatusCode::CONFLICT,
+                format!("merge of {} into {current} failed: {err}", wt.branch),
+            ));
+        }
+        merged_commit = Some(git_stdout(root, ["rev-parse", "HEAD"])?.trim().to_string());
+    }
+
+    if wt_path.exists() {
+        git_stdout(root, ["worktree", "remove", "--force", wt.path.as_str()])?;
     } else {
-        stderr_raw
-    };
-    let truncated = stdout_truncated || stderr_truncated;
+        let _ = git_stdout(root, ["worktree", "prune"]);
+    }
 
-    if !out.status.success() {
-        let mut msg = format!("bash exited with code {exit_code}");
-        if !stderr.trim().is_empty() {
-            msg.push_str(": ");
-            msg.push_str(&stderr);
+    let branch_deleted = match action {
+        WorktreeCleanup::KeepBranch => false,
+        WorktreeCleanup::Merge => git_stdout(root, ["branch", "-d", wt.branch.as_str()]).is_ok(),
+        WorktreeCleanup::Discard => {
+            git_stdout(root, ["branch", "-D", wt.branch.as_str()])?;
+            true
         }
-        return Err((StatusCode::BAD_REQUEST, msg));
+    };
+
+    Ok(serde_json::json!({
+        "action": action.as_str(),
+        "branch": wt.branch,
+        "committed": committed,
+        "merged_commit": merged_commit,
+        "branch_deleted": branch_deleted,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(dir: &std::path::Path, args: &[&str]) {
+        git_stdout(dir, args).unwrap();
     }
 
-    Ok((stdout, stderr, exit_code, truncated))
+    #[test]
+    fn chunked_upload_and_download_roundtrip() {
+        use sha2::Digest;
+        let dir = std::env::temp_dir().join(format!("relay-xfer-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cwd = dir.to_str().unwrap();
+        let data: Vec<u8> = (0..=255u8).cycle().take(5000).collect();
+        let sha = hex::encode(sha2::Sha256::digest(&data));
+
+        write_file_chunk(cwd, "b.bin", 0, &data[..3000], None).unwrap();
+        let err = write_file_chunk(cwd, "b.bin", 1000, &data[1000..], None).unwrap_err();
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        // A resumed upload asks where to continue from.
+        let st = write_file_chunk(cwd, "b.bin", 0, &[], None).unwrap();
+        assert_eq!(st.received, 3000);
+        assert!(!dir.join("b.bin").exists());
+        let bad = write_file_chunk(cwd, "b.bin", 3000, &data[3000..], Some("00")).unwrap_err();
+        assert_eq!(bad.0, StatusCode::UNPROCESSABLE_ENTITY);
+
+        write_file_chunk(cwd, "b.bin", 0, &data[..3000], None).unwrap();
+        let st = write_file_chunk(cwd, "b.bin", 3000, &data[3000..], Some(&sha)).unwrap();
+        assert!(st.done);
+        assert_eq!(std::fs::read(dir.join("b.bin")).unwrap(), data);
+
+        let first = read_file_chunk(cwd, "b.bin", 0, 4096).unwrap();
+        assert_eq!(
+            (first.size, first.bytes.len(), first.eof),
+            (5000, 4096, false)
+        );
+        assert!(first.sha256.is_none());
+        let last = read_file_chunk(cwd, "b.bin", 4096, 4096).unwrap();
+        assert_eq!(last.bytes, data[4096..]);
+        assert_eq!(last.sha256.as_deref(), Some(sha.as_str()));
+        assert!(read_file_chunk(cwd, "b.bin", 6000, 10).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn bash_stream_reports_output_exit_and_termination() {
+        use std::sync::atomic::AtomicBool;
+        use std::time::Duration;
+        let cwd = std::env::temp_dir();
+        let cwd = cwd.to_str().unwrap();
+        let no_cancel = AtomicBool::new(false);
+        let long = Duration::from_secs(30);
+
+        let mut streamed = Vec::new();
+        let out = bash_stream(
+            cwd,
+            "echo out; echo err >&2; exit 3",
+            1000,
+            None,
+            long,
+            &no_cancel,
+            |s, t| streamed.push((s, t)),
+        )
+        .unwrap();
+        assert_eq!(out.exit_code, Some(3));
+        assert_eq!(out.stdout, "out\n");
+        // `bash -l` may print profile noise first.
+        assert!(out.stderr.ends_with("err\n"));
+        assert!(streamed.contains(&("stdout", "out\n".to_string())));
+        assert!(out.error().unwrap().starts_with("bash exited with code 3"));
+
+        let out = bash_stream(cwd, "seq 1 5000", 100, None, long, &no_cancel, |_, _| {}).unwrap();
+        assert!(out.truncated && out.stdout.ends_with("4999\n5000\n") && out.stdout.len() <= 100);
+        assert_eq!(out.error(), None);
+
+        let out =
+            bash_stream(cwd, "kill -KILL $$", 100, None, long, &no_cancel, |_, _| {}).unwrap();
+        assert_eq!(
+            (out.exit_code, out.signal.as_deref()),
+            (None, Some("SIGKILL"))
+        );
+
+        let started = std::time::Instant::now();
+        let out = bash_stream(
+            cwd,
+            "sleep 30",
+            100,
+            None,
+            Duration::from_millis(300),
+            &no_cancel,
+            |_, _| {},
+        )
+        .unwrap();
+        assert!(out.timed_out && started.elapsed() < Duration::from_secs(10));
+        assert_eq!(out.error().as_deref(), Some("bash timed out"));
+
+        let cancel = AtomicBool::new(true);
+        let out = bash_stream(cwd, "sleep 30", 100, None, long, &cancel, |_, _| {}).unwrap();
+        assert!(out.cancelled && out.signal.is_some());
+    }
+
+    #[test]
+    fn worktree_create_and_merge_back() {
+        let dir = std::env::temp_dir().join(format!("relay-wt-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        git(&dir, &["init", "-q", "-b", "main"]);
+        git(&dir, &["config", "user.name", "relay"]);
+        git(&dir, &["config", "user.email", "relay@example.invalid"]);
+        std::fs::write(dir.join("sub/a.txt"), "one\n").unwrap();
+        git(&dir, &["add", "-A"]);
+        git(&dir, &["commit", "-q", "-m", "init"]);
+
+        let wt = worktree_create(dir.join("sub").to_str().unwrap(), "run-1").unwrap();
+        assert_eq!(wt.branch, "relay/run-1");
+        assert_eq!(wt.base_branch.as_deref(), Some("main"));
+        assert_eq!(wt.subdir, "sub");
+        assert!(wt.run_cwd().ends_with("relay-worktrees/run-1/sub"));
+
+        std::fs::write(std::path::Path::new(&wt.run_cwd()).join("a.txt"), "two\n").unwrap();
+        assert!(worktree_is_dirty(&wt).unwrap());
+
+        let res = worktree_cleanup(&wt, WorktreeCleanup::Merge).unwrap();
+        assert!(res["committed"].is_string());
+        assert_eq!(res["branch_deleted"], true);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("sub/a.txt")).unwrap(),
+            "two\n"
+        );
+        assert!(!std::path::Path::new(&wt.path).exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,430 @@
+//! `rpc.fs.watch` / `rpc.fs.unwatch`: live change notifications for remote file explorers.
+//!
+//! A watch covers one directory under the run cwd and its sub-directories up to a depth; each has
+//! its own [`crate::tree_watch::TreeWatch`] and thread. Events are debounced into batches of
+//! `{ path, change: created|modified|deleted|renamed, is_dir, old_path? }` with paths relative to
+//! the run cwd; a batch that grows too large (or an inotify queue overflow) is replaced by
+//! `overflow: true`, telling the client to list again. `.git` and directories matched by
+//! `.gitignore` are not descended into, though their own creation or removal is reported. Files
+//! already inside a directory that appears are reported as created.
+
+use axum::http::StatusCode;
+use serde_json::{Value as JsonValue, json};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Changes per batch before it collapses into `overflow: true`.
+const MAX_BATCH: usize = 1_000;
+
+/// Configurable through `RELAY_FS_WATCH_MAX_DEPTH`, `RELAY_FS_WATCH_MAX_DIRS`,
+/// `RELAY_FS_WATCH_MAX_PER_RUN` and `RELAY_FS_WATCH_DEBOUNCE_MS`.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Deepest sub-directory level a watch may request (0 = only the directory itself).
+    pub max_depth: usize,
+    /// inotify watches (directories) per subscription.
+    pub max_dirs: usize,
+    /// Subscriptions per run.
+    pub max_per_run: usize,
+    pub debounce: Duration,
+}
+
+impl Limits {
+    pub fn from_env() -> Self {
+        let num = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            max_depth: num("RELAY_FS_WATCH_MAX_DEPTH", 8) as usize,
+            max_dirs: num("RELAY_FS_WATCH_MAX_DIRS", 2_048).max(1) as usize,
+            max_per_run: num("RELAY_FS_WATCH_MAX_PER_RUN", 16).max(1) as usize,
+            debounce: Duration::from_millis(
+                num("RELAY_FS_WATCH_DEBOUNCE_MS", 200).clamp(10, 10_000),
+            ),
+        }
+    }
+}
+
+pub struct Watch {
+    pub path: String,
+    pub depth: usize,
+    stop: AtomicBool,
+    dirs: AtomicUsize,
+    truncated: AtomicBool,
+}
+
+impl Watch {
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    /// `{ path, depth, watched_dirs, truncated }`; `truncated` means `max_dirs` was hit and some
+    /// directories are not watched.
+    pub fn info(&self) -> JsonValue {
+        json!({
+            "path": self.path,
+            "depth": self.depth,
+            "watched_dirs": self.dirs.load(Ordering::Relaxed),
+            "truncated": self.truncated.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Starts watching `rel` (a directory relative to `run_cwd`, `""` / `"."` for the cwd itself).
+/// `on_batch` receives `{ changes }` or `{ changes: [], overflow: true }` from the watch thread
+/// until the watch is stopped or its directory disappears.
+#[cfg(target_os = "linux")]
+pub fn start(
+    run_cwd: &str,
+    rel: &str,
+    depth: Option<usize>,
+    limits: &Limits,
+    on_batch: impl FnMut(JsonValue) + Send + 'static,
+) -> Result<Arc<Watch>, (StatusCode, String)> {
+    let base = std::fs::canonicalize(run_cwd)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("bad run cwd: {e}")))?;
+    let rel = rel.trim().trim_end_matches('/');
+    let root = if rel.is_empty() || rel == "." {
+        base.clone()
+    } else {
+        crate::fs_git::safe_join_run_path(run_cwd, rel)?
+    };
+    if !root.is_dir() {
+        return Err((StatusCode::BAD_REQUEST, "not a directory".into()));
+    }
+    let depth = depth.unwrap_or(0);
+    if depth > limits.max_depth {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("depth {depth} exceeds the limit of {}", limits.max_depth),
+        ));
+    }
+    let path = relative(&base, &root);
+    let (tree, _) = crate::tree_watch::TreeWatch::new(&base, &path, depth, limits.max_dirs, false)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("inotify: {e}")))?;
+    if tree.watched_dirs() == 0 {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "could not watch directory".into(),
+        ));
+    }
+
+    let watch = Arc::new(Watch {
+        path,
+        depth,
+        stop: AtomicBool::new(false),
+        dirs: AtomicUsize::new(tree.watched_dirs()),
+        truncated: AtomicBool::new(tree.is_truncated()),
+    });
+    let state = Watcher {
+        tree,
+        debounce: limits.debounce,
+        pending: Pending::default(),
+        watch: watch.clone(),
+    };
+    std::thread::spawn(move || state.run(on_batch));
+    Ok(watch)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn start(
+    _run_cwd: &str,
+    _rel: &str,
+    _depth: Option<usize>,
+    _limits: &Limits,
+    _on_batch: impl FnMut(JsonValue) + Send + 'static,
+) -> Result<Arc<Watch>, (StatusCode, String)> {
+    Err((
+        StatusCode::NOT_IMPLEMENTED,
+        "fs watch requires Linux (inotify)".into(),
+    ))
+}
+
+fn relative(base: &Path, path: &Path) -> String {
+    path.strip_prefix(base)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+struct Change {
+    path: String,
+    change: &'static str,
+    is_dir: bool,
+    old_path: Option<String>,
+}
+
+/// Changes since the last batch, merged per path.
+#[derive(Default)]
+struct Pending {
+    changes: Vec<Option<Change>>,
+    by_path: std::collections::HashMap<String, usize>,
+    overflow: bool,
+    first: Option<std::time::Instant>,
+    last: Option<std::time::Instant>,
+}
+
+impl Pending {
+    fn touch(&mut self) {
+        let now = std::time::Instant::now();
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+    }
+
+    fn set_overflow(&mut self) {
+        self.touch();
+        self.overflow = true;
+        self.changes.clear();
+        self.by_path.clear();
+    }
+
+    fn push(&mut self, change: Change) {
+        self.touch();
+        if self.overflow {
+            return;
+        }
+        if let Some(&i) = self.by_path.get(&change.path)
+            && let Some(prev) = self.changes[i].as_mut()
+        {
+            let merged = match (prev.change, change.change) {
+                ("created", "modified") => Some("created"),
+                // Created and gone again within one batch: nothing to report.
+                ("created", "deleted") => None,
+                ("deleted", "created") => Some("modified"),
+                (_, c) => Some(c),
+            };
+            match merged {
+                Some(c) if change.old_path.is_none() => prev.change = c,
+                Some(_) => *prev = change,
+                None => {
+                    self.changes[i] = None;
+                    self.by_path.remove(&change.path);
+                }
+            }
+            return;
+        }
+        if self.by_path.len() >= MAX_BATCH {
+            self.set_overflow();
+            return;
+        }
+        self.by_path.insert(change.path.clone(), self.changes.len());
+        self.changes.push(Some(change));
+    }
+
+    fn take(&mut self) -> JsonValue {
+        let changes: Vec<JsonValue> = self
+            .changes
+            .drain(..)
+            .flatten()
+            .map(|c| {
+                let mut v = json!({ "path": c.path, "change": c.change, "is_dir": c.is_dir });
+                if let Some(old) = c.old_path {
+                    v["old_path"] = json!(old);
+                }
+                v
+            })
+            .collect();
+        let mut batch = json!({ "changes": changes });
+        if std::mem::take(&mut self.overflow) {
+            batch["overflow"] = json!(true);
+        }
+        self.by_path.clear();
+        self.first = None;
+        self.last = None;
+        batch
+    }
+}
+
+#[cfg(target_os = "linux")]
+struct Watcher {
+    tree: crate::tree_watch::TreeWatch,
+    debounce: Duration,
+    pending: Pending,
+    watch: Arc<Watch>,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    fn run(mut self, mut on_batch: impl FnMut(JsonValue)) {
+        use crate::tree_watch::{Change as C, TreeEvent};
+
+        let poll = (self.debounce / 4).max(Duration::from_millis(10));
+        while !self.watch.is_stopped() {
+            let Ok(events) = self.tree.read() else {
+                break;
+            };
+            // Renames arrive as a MOVED_FROM / MOVED_TO pair sharing a cookie.
+            let mut moved_from: Vec<(u32, String, bool)> = Vec::new();
+            for ev in events {
+                let (path, change, is_dir, found) = match ev {
+                    TreeEvent::Overflow => {
+                        self.pending.set_overflow();
+                        continue;
+                    }
+                    TreeEvent::RootGone => {
+                        self.pending.push(Change {
+                            path: self.watch.path.clone(),
+                            change: "deleted",
+                            is_dir: true,
+                            old_path: None,
+                        });
+                        self.watch.stop();
+                        continue;
+                    }
+                    TreeEvent::Entry {
+                        path,
+                        change,
+                        is_dir,
+                        found,
+                        ..
+                    } => (path, change, is_dir, found),
+                };
+                let change = match change {
+                    C::MovedFrom(cookie) => {
+                        moved_from.push((cookie, path, is_dir));
+                        continue;
+                    }
+                    C::MovedTo(cookie) => {
+                        match moved_from.iter().position(|(c, _, _)| *c == cookie) {
+                            Some(i) => {
+                                let (_, from, _) = moved_from.remove(i);
+                                self.pending.push(Change {
+                                    path,
+                                    change: "renamed",
+                                    is_dir,
+                                    old_path: Some(from),
+                                });
+                                continue;
+                            }
+                            None => "created",
+                        }
+                    }
+                    C::Created => "created",
+                    C::Deleted => "deleted",
+                    C::Modified => "modified",
+                };
+                self.pending.push(Change {
+                    path,
+                    change,
+                    is_dir,
+                    old_path: None,
+                });
+                for file in found {
+                    self.pending.push(Change {
+                        path: file,
+                        change: "created",
+                        is_dir: false,
+                        old_path: None,
+                    });
+                }
+            }
+            // Moved out of the watched tree.
+            for (_, from, is_dir) in moved_from {
+                self.pending.push(Change {
+                    path: from,
+                    change: "deleted",
+                    is_dir,
+                    old_path: None,
+                });
+            }
+            self.watch
+                .dirs
+                .store(self.tree.watched_dirs(), Ordering::Relaxed);
+            self.watch
+                .truncated
+                .store(self.tree.is_truncated(), Ordering::Relaxed);
+
+            let now = std::time::Instant::now();
+            let due = match (self.pending.first, self.pending.last) {
+                (Some(first), Some(last)) => {
+                    now >= last + self.debounce || now >= first + self.debounce * 5
+                }
+                _ => false,
+            };
+            if due || (self.watch.is_stopped() && self.pending.first.is_some()) {
+                on_batch(self.pending.take());
+            }
+            std::thread::sleep(poll);
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_debounced_changes_within_depth() {
+        let dir = std::env::temp_dir().join(format!("relay-fs-watch-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("src/deep/deeper")).unwrap();
+        std::fs::create_dir_all(dir.join("ignored")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "ignored/\n").unwrap();
+        std::fs::write(dir.join("src/a.txt"), "a").unwrap();
+        let cwd = dir.to_str().unwrap();
+        let limits = Limits {
+            max_depth: 3,
+            max_dirs: 100,
+            max_per_run: 4,
+            debounce: Duration::from_millis(50),
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        let watch = start(cwd, ".", Some(1), &limits, move |b| {
+            let _ = tx.send(b);
+        })
+        .unwrap();
+        // ".", "src" (depth 1); not "src/deep" (depth 2) or the ignored dir.
+        assert_eq!(watch.info()["watched_dirs"], 2);
+        assert!(start(cwd, ".", Some(4), &limits, |_| {}).is_err());
+        assert!(start(cwd, "../", None, &limits, |_| {}).is_err());
+
+        std::fs::write(dir.join("src/a.txt"), "b").unwrap();
+        std::fs::write(dir.join("src/tmp.txt"), "x").unwrap();
+        std::fs::remove_file(dir.join("src/tmp.txt")).unwrap();
+        std::fs::rename(dir.join("src/a.txt"), dir.join("src/b.txt")).unwrap();
+        std::fs::write(dir.join("src/deep/deeper/x.txt"), "x").unwrap();
+        std::fs::write(dir.join("ignored/y.txt"), "y").unwrap();
+        std::fs::create_dir(dir.join("new")).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        std::fs::write(dir.join("new/n.txt"), "n").unwrap();
+
+        let mut changes = Vec::new();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while changes.len() < 4 && std::time::Instant::now() < deadline {
+            if let Ok(b) = rx.recv_timeout(Duration::from_millis(200)) {
+                changes.extend(b["changes"].as_array().unwrap().clone());
+            }
+        }
+        let summary: Vec<(String, String)> = changes
+            .iter()
+            .map(|c| {
+                (
+                    c["path"].as_str().unwrap().to_string(),
+                    c["change"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        let has = |p: &str, c: &str| summary.iter().any(|(sp, sc)| sp == p && sc == c);
+        assert!(has("src/a.txt", "modified"), "{summary:?}");
+        assert!(has("src/b.txt", "renamed"), "{summary:?}");
+        assert!(has("new", "created"), "{summary:?}");
+        assert!(has("new/n.txt", "created"), "{summary:?}");
+        assert!(
+            !summary.iter().any(|(p, _)| p == "src/tmp.txt"
+                || p.contains("deeper")
+                || p.starts_with("ignored/"))
+        );
+        let renamed = changes.iter().find(|c| c["change"] == "renamed").unwrap();
+        assert_eq!(renamed["old_path"], "src/a.txt");
+
+        watch.stop();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,757 @@
+//! Structured git RPCs run in the run cwd: `rpc.git.log`, `rpc.git.show`, `rpc.git.blame` and
+//! `rpc.git.branches`, plus the permission-gated `rpc.git.stage`, `rpc.git.unstage`,
+//! `rpc.git.commit` and `rpc.git.checkout`.
+//!
+//! Results are parsed from git's machine formats (`--format` with separator bytes, `--numstat`,
+//! `blame --porcelain`, `status -z`) rather than returned as text. Paths in results are relative
+//! to the repository root; path arguments are relative to the run cwd and taken literally.
+
+use crate::fs_git::git_stdout as git;
+use axum::http::StatusCode;
+use serde_json::{Map, Value as JsonValue, json};
+
+type GitError = (StatusCode, String);
+
+const DEFAULT_LOG_LIMIT: u64 = 50;
+const MAX_LOG_LIMIT: u64 = 500;
+/// Diff lines returned by `rpc.git.show` before the rest is cut (stats still cover everything).
+const MAX_SHOW_LINES: usize = 10_000;
+const MAX_BLAME_LINES: usize = 5_000;
+/// `--format` of one commit: fields separated by 0x1f.
+const COMMIT_FORMAT: &str = "%H%x1f%h%x1f%P%x1f%an%x1f%ae%x1f%aI%x1f%cn%x1f%ce%x1f%cI%x1f%s";
+
+fn str_arg<'a>(data: &'a JsonValue, key: &str) -> Option<&'a str> {
+    data.get(key)
+        .and_then(|v| v.as_str())
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+}
+
+/// Revisions and branch names go straight onto the git command line; never let them be options.
+fn rev_arg<'a>(data: &'a JsonValue, key: &str) -> Result<Option<&'a str>, GitError> {
+    match str_arg(data, key) {
+        Some(rev) if rev.starts_with('-') => {
+            Err((StatusCode::BAD_REQUEST, format!("invalid {key}: {rev}")))
+        }
+        rev => Ok(rev),
+    }
+}
+
+/// `paths` (array) or `path` (string), each relative to the run cwd.
+fn paths_arg(data: &JsonValue) -> Result<Vec<String>, GitError> {
+    let mut out = Vec::new();
+    if let Some(p) = str_arg(data, "path") {
+        out.push(p.to_string());
+    }
+    if let Some(arr) = data.get("paths").and_then(|v| v.as_array()) {
+        out.extend(arr.iter().filter_map(|v| v.as_str()).map(str::to_string));
+    }
+    for p in &out {
+        crate::fs_git::reject_unsafe_rel_path(p)?;
+    }
+    Ok(out)
+}
+
+pub fn read(cwd: &str, rpc_type: &str, data: &JsonValue) -> Result<JsonValue, GitError> {
+    match rpc_type {
+        "rpc.git.log" => log(cwd, data),
+        "rpc.git.show" => show(cwd, data),
+        "rpc.git.blame" => blame(cwd, data),
+        "rpc.git.branches" => branches(cwd),
+        _ => Err((StatusCode::NOT_IMPLEMENTED, "unknown rpc type".into())),
+    }
+}
+
+/// Mutating operations; callers run these only after approval.
+pub fn write(cwd: &str, rpc_type: &str, data: &JsonValue) -> Result<JsonValue, GitError> {
+    match rpc_type {
+        "rpc.git.stage" | "rpc.git.unstage" => {
+            let paths = paths_arg(data)?;
+            let all = data.get("all").and_then(|v| v.as_bool()).unwrap_or(false);
+            if paths.is_empty() && !all {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "expected `paths` or `all: true`".into(),
+                ));
+            }
+            let mut args: Vec<&str> = if rpc_type == "rpc.git.stage" {
+                vec!["add", if all && paths.is_empty() { "-A" } else { "--" }]
+            } else {
+                vec!["reset", "-q", "--"]
+            };
+            args.extend(paths.iter().map(String::as_str));
+            git(cwd, &args)?;
+            Ok(json!({ "status": status(cwd)? }))
+        }
+        "rpc.git.commit" => {
+            let message = data
+                .get("message")
+                .and_then(|v| v.as_str())
+                .filter(|m| !m.trim().is_empty())
+                .ok_or((StatusCode::BAD_REQUEST, "missing message".into()))?;
+            let mut args = vec!["commit", "-q", "-m", message];
+            if data.get("all").and_then(|v| v.as_bool()).unwrap_or(false) {
+                args.push("-a");
+            }
+            if data.get("amend").and_then(|v| v.as_bool()).unwrap_or(false) {
+                args.push("--amend");
+            }
+            git(cwd, &args)?;
+            let mut commits = log_commits(cwd, &["-n", "1", "HEAD"])?;
+            Ok(json!({ "commit": commits.pop(), "status": status(cwd)? }))
+        }
+        "rpc.git.checkout" => {
+            let branch = rev_arg(data, "branch")?;
+            let rev = rev_arg(data, "rev")?;
+            let start_point = rev_arg(data, "start_point")?;
+            let create = data
+                .get("create")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false);
+            let mut args = vec!["switch", "-q"];
+            match (branch, rev) {
+                (Some(branch), _) if create => {
+                    args.extend(["-c", branch]);
+                    args.extend(start_point);
+                }
+                (Some(branch), _) => args.push(branch),
+                (None, Some(rev)) => args.extend(["--detach", rev]),
+                (None, None) => {
+                    return Err((StatusCode::BAD_REQUEST, "expected `branch` or `rev`".into()));
+                }
+            }
+            git(cwd, &args)?;
+            let head = git(cwd, ["rev-parse", "HEAD"])?.trim().to_string();
+            let current = git(cwd, ["symbolic-ref", "-q", "--short", "HEAD"])
+                .ok()
+                .map(|s| s.trim().to_string());
+            Ok(json!({ "head": head, "branch": current, "detached": current.is_none() }))
+        }
+        _ => Err((StatusCode::NOT_IMPLEMENTED, "unknown rpc type".into())),
+    }
+}
+
+/// One-line description for `run.permission_requested.op_args_summary`.
+pub fn summary(rpc_type: &str, data: &JsonValue) -> String {
+    match rpc_type {
+        "rpc.git.stage" | "rpc.git.unstage" => match paths_arg(data) {
+            Ok(paths) if !paths.is_empty() => format!("paths={}", paths.join(", ")),
+            _ => "all".to_string(),
+        },
+        "rpc.git.commit" => {
+            let message = data.get("message").and_then(|v| v.as_str()).unwrap_or("");
+            let mut s = format!("message={}", message.lines().next().unwrap_or(""));
+            for flag in ["all", "amend"] {
+                if data.get(flag).and_then(|v| v.as_bool()).unwrap_or(false) {
+                    s.push_str(&format!(" {flag}"));
+                }
+            }
+            s
+        }
+        "rpc.git.checkout" => match (str_arg(data, "branch"), str_arg(data, "rev")) {
+            (Some(b), _)
+                if data
+                    .get("create")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false) =>
+            {
+                format!("create branch={b}")
+            }
+            (Some(b), _) => format!("branch={b}"),
+            (None, Some(r)) => format!("rev={r}"),
+            (None, None) => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+fn person(name: &str, email: &str, date: &str) -> JsonValue {
+    json!({ "name": name, "email": email, "date": date })
+}
+
+/// Parses [`COMMIT_FORMAT`] output (optionally followed by `%x1f%b`).
+fn parse_commit(header: &str) -> JsonValue {
+    let f: Vec<&str> = header.splitn(11, '\x1f').collect();
+    let get = |i: usize| f.get(i).copied().unwrap_or("").trim();
+    let mut v = json!({
+        "sha": get(0),
+        "short_sha": get(1),
+        "parents": get(2).split_whitespace().collect::<Vec<_>>(),
+        "author": person(get(3), get(4), get(5)),
+        "committer": person(get(6), get(7), get(8)),
+        "subject": get(9),
+    });
+    if f.len() > 10 {
+        v["body"] = json!(get(10));
+    }
+    v
+}
+
+/// `added\tremoved\tpath` (`-` counts for binary files).
+fn parse_numstat(line: &str) -> Option<JsonValue> {
+    let mut it = line.splitn(3, '\t');
+    let (added, removed, path) = (it.next()?, it.next()?, it.next()?);
+    let binary = added == "-" && removed == "-";
+    Some(json!({
+        "path": path,
+        "added": added.parse::<u64>().unwrap_or(0),
+        "removed": removed.parse::<u64>().unwrap_or(0),
+        "binary": binary,
+    }))
+}
+
+/// Commits with per-file stats for `git log <extra>`.
+fn log_commits(cwd: &str, extra: &[&str]) -> Result<Vec<JsonValue>, GitError> {
+    let format = format!("--format=%x1e{COMMIT_FORMAT}%x1d");
+    let mut args = vec!["log", format.as_str(), "--numstat", "--no-renames"];
+    args.extend(extra);
+    let out = git(cwd, &args)?;
+    Ok(out
+        .split('\x1e')
+        .filter(|r| !r.trim().is_empty())
+        .map(|record| {
+            let (header, stats) = record.split_once('\x1d').unwrap_or((record, ""));
+            let files: Vec<JsonValue> = stats.lines().filter_map(parse_numstat).collect();
+            let sum = |k: &str| files.iter().filter_map(|f| f[k].as_u64()).sum::<u64>();
+            let mut commit = parse_commit(header);
+            commit["added"] = json!(sum("added"));
+            commit["removed"] = json!(sum("removed"));
+            commit["files"] = json!(files);
+            commit
+        })
+        .collect())
+}
+
+fn log(cwd: &str, data: &JsonValue) -> Result<JsonValue, GitError> {
+    let limit = data
+        .get("limit")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(DEFAULT_LOG_LIMIT)
+        .clamp(1, MAX_LOG_LIMIT);
+    let skip = data.get("skip").and_then(|v| v.as_u64()).unwrap_or(0);
+    let rev = rev_arg(data, "rev")?.unwrap_or("HEAD");
+    let paths = paths_arg(data)?;
+    let (n, skip) = ((limit + 1).to_string(), skip.to_string());
+    let mut args = vec!["-n", n.as_str(), "--skip", skip.as_str(), rev, "--"];
+    args.extend(paths.iter().map(String::as_str));
+    let mut commits = log_commits(cwd, &args)?;
+    let has_more = commits.len() as u64 > limit;
+    commits.truncate(limit as usize);
+    Ok(json!({ "commits": commits, "has_more": has_more }))
+}
+
+fn show(cwd: &str, data: &JsonValue) -> Result<JsonValue, GitError> {
+    let rev = rev_arg(data, "rev")?.unwrap_or("HEAD");
+    let paths = paths_arg(data)?;
+    let context = data
+        .get("context")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(3)
+        .min(50);
+    let header = git(
+        cwd,
+        [
+            "show",
+            "-s",
+            &format!("--format={COMMIT_FORMAT}%x1f%b"),
+            rev,
+            "--",
+        ],
+    )?;
+    let commit = parse_commit(header.trim_end());
+    let sha = commit["sha"].as_str().unwrap_or(rev).to_string();
+
+    // Diff against the first parent (merges included); root commits diff against the empty tree.
+    let unified = format!("-U{context}");
+    let mut args = match commit["parents"].get(0).and_then(|v| v.as_str()) {
+        Some(parent) => vec![
+            "diff",
+            "-M",
+            "--no-ext-diff",
+            unified.as_str(),
+            parent,
+            sha.as_str(),
+        ],
+        None => vec![
+            "diff-tree",
+            "-p",
+            "-M",
+            "--root",
+            "--no-commit-id",
+            unified.as_str(),
+            sha.as_str(),
+        ],
+    };
+    args.push("--");
+    args.extend(paths.iter().map(String::as_str));
+    let (files, truncated) = parse_diff(&git(cwd, &args)?, MAX_SHOW_LINES);
+    let sum = |k: &str| files.iter().filter_map(|f| f[k].as_u64()).sum::<u64>();
+    Ok(json!({
+        "commit": commit,
+        "added": sum("added"),
+        "removed": sum("removed"),
+        "files": files,
+        "truncated": truncated,
+    }))
+}
+
+/// Unified diff → `[{ path, old_path?, status, binary, added, removed, hunks }]`. Past `max_lines`
+/// hunk lines are dropped (counts stay exact) and the flag is set.
+fn parse_diff(text: &str, max_lines: usize) -> (Vec<JsonValue>, bool) {
+    let mut files: Vec<Map<String, JsonValue>> = Vec::new();
+    let mut hunks: Vec<JsonValue> = Vec::new();
+    let (mut added, mut removed) = (0u64, 0u64);
+    let (mut old_left, mut new_left) = (0u64, 0u64);
+    let (mut old_no, mut new_no) = (0u64, 0u64);
+    let mut kept = 0usize;
+    let mut truncated = false;
+
+    let finish = |files: &mut Vec<Map<String, JsonValue>>,
+                  hunks: &mut Vec<JsonValue>,
+                  added: &mut u64,
+                  removed: &mut u64| {
+        if let Some(f) = files.last_mut() {
+            f.insert("added".into(), json!(*added));
+            f.insert("removed".into(), json!(*removed));
+            f.insert("hunks".into(), json!(std::mem::take(hunks)));
+        }
+        (*added, *removed) = (0, 0);
+    };
+
+    for line in text.split('\n') {
+        if old_left > 0 || new_left > 0 {
+            let (kind, body) = match line.chars().next() {
+                Some('+') => ("add", &line[1..]),
+                Some('-') => ("del", &line[1..]),
+                Some('\\') => continue,
+                _ => ("context", line.get(1..).unwrap_or("")),
+            };
+            let mut entry = json!({ "kind": kind, "text": body });
+            match kind {
+                "add" => {
+                    added += 1;
+                    new_left = new_left.saturating_sub(1);
+                    entry["new_line"] = json!(new_no);
+                    new_no += 1;
+                }
+                "del" => {
+                    removed += 1;
+                    old_left = old_left.saturating_sub(1);
+                    entry["old_line"] = json!(old_no);
+                    old_no += 1;
+                }
+                _ => {
+                    old_left = old_left.saturating_sub(1);
+                    new_left = new_left.saturating_sub(1);
+                    entry["old_line"] = json!(old_no);
+                    entry["new_line"] = json!(new_no);
+                    old_no += 1;
+                    new_no += 1;
+                }
+            }
+            if kept < max_lines {
+                kept += 1;
+                if let Some(h) = hunks.last_mut().and_then(|h| h["lines"].as_array_mut()) {
+                    h.push(entry);
+                }
+            } else {
+                truncated = true;
+            }
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            finish(&mut files, &mut hunks, &mut added, &mut removed);
+            let (old, new) = match rest.find(" b/") {
+                Some(i) => (rest[..i].trim_start_matches("a/"), &rest[i + 3..]),
+                None => (rest, rest),
+            };
+            let mut f = Map::new();
+            f.insert("path".into(), json!(new));
+            f.insert("old_path".into(), json!(old));
+            f.insert("status".into(), json!("modified"));
+            f.insert("binary".into(), json!(false));
+            files.push(f);
+        } else if let Some(f) = files.last_mut() {
+            let mut set = |k: &str, v: JsonValue| {
+                f.insert(k.into(), v);
+            };
+            if line.starts_with("new file mode") {
+                set("status", json!("added"));
+            } else if line.starts_with("deleted file mode") {
+                set("status", json!("deleted"));
+            } else if let Some(p) = line.strip_prefix("rename from ") {
+                set("status", json!("renamed"));
+                set("old_path", json!(p));
+            } else if let Some(p) = line.strip_prefix("rename to ") {
+                set("path", json!(p));
+            } else if let Some(p) = line.strip_prefix("copy from ") {
+                set("status", json!("copied"));
+                set("old_path", json!(p));
+            } else if let Some(p) = line.strip_prefix("copy to ") {
+                set("path", json!(p));
+            } else if line.starts_with("Binary files ") || line.starts_with("GIT binary patch") {
+                set("binary", json!(true));
+            } else if let Some(p) = line.strip_prefix("+++ b/") {
+                set("path", json!(p));
+            } else if let Some(p) = line.strip_prefix("--- a/") {
+                set("old_path", json!(p));
+            } else if line.starts_with("@@ ") {
+                let spec = line.split("@@").nth(1).unwrap_or("");
+                let range = |side: char| {
+                    spec.split_whitespace()
+                        .find_map(|s| s.strip_prefix(side))
+                        .map(parse_range)
+                        .unwrap_or((0, 0))
+                };
+                let ((old_start, old_lines), (new_start, new_lines)) = (range('-'), range('+'));
+                (old_left, new_left) = (old_lines, new_lines);
+                (old_no, new_no) = (old_start, new_start);
+                hunks.push(json!({
+                    "header": line,
+                    "old_start": old_start,
+                    "old_lines": old_lines,
+                    "new_start": new_start,
+                    "new_lines": new_lines,
+                    "lines": [],
+                }));
+            }
+        }
+    }
+    finish(&mut files, &mut hunks, &mut added, &mut removed);
+
+    let files = files
+        .into_iter()
+        .map(|mut f| {
+            if f.get("old_path") == f.get("path") {
+                f.remove("old_path");
+            }
+            JsonValue::Object(f)
+        })
+        .collect();
+    (files, truncated)
+}
+
+/// `start[,count]` from a hunk header; a missing count means 1.
+fn parse_range(s: &str) -> (u64, u64) {
+    match s.split_once(',') {
+        Some((start, count)) => (start.parse().unwrap_or(0), count.parse().unwrap_or(0)),
+        None => (s.parse().unwrap_or(0), 1),
+    }
+}
+
+fn blame(cwd: &str, data: &JsonValue) -> Result<JsonValue, GitError> {
+    let path = str_arg(data, "path").ok_or((StatusCode::BAD_REQUEST, "missing path".into()))?;
+    crate::fs_git::reject_unsafe_rel_path(path)?;
+    let rev = rev_arg(data, "rev")?;
+    let start = data.get("start_line").and_then(|v| v.as_u64());
+    let end = data.get("end_line").and_then(|v| v.as_u64());
+    let range = match (start, end) {
+        (Some(s), Some(e)) => Some(format!("{},{}", s.max(1), e.max(s.max(1)))),
+        (Some(s), None) => Some(format!("{},", s.max(1))),
+        (None, Some(e)) => Some(format!("1,{}", e.max(1))),
+        (None, None) => None,
+    };
+    let mut args = vec!["blame", "--porcelain"];
+    if let Some(range) = range.as_deref() {
+        args.extend(["-L", range]);
+    }
+    args.extend(rev);
+    args.extend(["--", path]);
+    let out = git(cwd, &args)?;
+
+    let mut commits = Map::new();
+    let mut lines = Vec::new();
+    let mut truncated = false;
+    let mut current: Option<(String, u64, u64)> = None;
+    for line in out.split('\n') {
+        if let Some(text) = line.strip_prefix('\t') {
+            if let Some((sha, orig, fin)) = current.take() {
+                if lines.len() < MAX_BLAME_LINES {
+                    lines.push(json!({ "line": fin, "orig_line": orig, "sha": sha, "text": text }));
+                } else {
+                    truncated = true;
+                }
+            }
+            continue;
+        }
+        if current.is_none() {
+            let mut it = line.split(' ');
+            let (Some(sha), Some(orig), Some(fin)) = (it.next(), it.next(), it.next()) else {
+                continue;
+            };
+            current = Some((
+                sha.to_string(),
+                orig.parse().unwrap_or(0),
+                fin.parse().unwrap_or(0),
+            ));
+            commits.entry(sha.to_string()).or_insert_with(|| json!({}));
+            continue;
+        }
+        let (Some((sha, _, _)), Some((key, value))) = (&current, line.split_once(' ')) else {
+            continue;
+        };
+        let Some(c) = commits.get_mut(sha) else {
+            continue;
+        };
+        match key {
+            "author" => c["author"] = json!(value),
+            "author-mail" => c["email"] = json!(value.trim_matches(['<', '>'])),
+            "author-time" => {
+                c["date"] = json!(
+                    value
+                        .parse::<i64>()
+                        .ok()
+                        .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+                        .map(|d| d.to_rfc3339())
+                )
+            }
+            "summary" => c["summary"] = json!(value),
+            "previous" => c["previous"] = json!(value.split(' ').next()),
+            _ => {}
+        }
+    }
+    Ok(json!({ "path": path, "commits": commits, "lines": lines, "truncated": truncated }))
+}
+
+fn branches(cwd: &str) -> Result<JsonValue, GitError> {
+    let format = "--format=%(HEAD)%1f%(refname)%1f%(refname:short)%1f%(objectname)%1f%(upstream:short)%1f%(upstream:track,nobracket)%1f%(committerdate:iso-strict)%1f%(contents:subject)";
+    let out = git(
+        cwd,
+        [
+            "for-each-ref",
+            "--sort=-committerdate",
+            format,
+            "refs/heads",
+            "refs/remotes",
+        ],
+    )?;
+    let mut current = None;
+    let mut list = Vec::new();
+    for line in out.lines() {
+        let f: Vec<&str> = line.split('\x1f').collect();
+        let get = |i: usize| f.get(i).copied().unwrap_or("");
+        let refname = get(1);
+        let remote = refname.starts_with("refs/remotes/");
+        if remote && refname.ends_with("/HEAD") {
+            continue;
+        }
+        let track = get(5);
+        let count = |what: &str| {
+            track
+                .split(", ")
+                .find_map(|p| p.strip_prefix(what))
+                .and_then(|n| n.trim().parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+        let is_current = get(0) == "*";
+        if is_current {
+            current = Some(get(2).to_string());
+        }
+        list.push(json!({
+            "name": get(2),
+            "remote": remote,
+            "current": is_current,
+            "sha": get(3),
+            "upstream": Some(get(4)).filter(|s| !s.is_empty()),
+            "ahead": count("ahead "),
+            "behind": count("behind "),
+            "upstream_gone": track == "gone",
+            "date": get(6),
+            "subject": get(7),
+        }));
+    }
+    let head = git(cwd, ["rev-parse", "-q", "--verify", "HEAD"])
+        .ok()
+        .map(|s| s.trim().to_string());
+    Ok(json!({
+        "current": current,
+        "head": head,
+        "detached": current.is_none() && head.is_some(),
+        "branches": list,
+    }))
+}
+
+/// Branch and per-file index/worktree state (`git status --porcelain=v1 -z`).
+fn status(cwd: &str) -> Result<JsonValue, GitError> {
+    let out = git(
+        cwd,
+        [
+            "status",
+            "--porcelain=v1",
+            "-b",
+            "-z",
+            "--untracked-files=all",
+        ],
+    )?;
+    let mut entries = out.split('\0').filter(|e| !e.is_empty());
+    let mut branch = json!(null);
+    let mut files = Vec::new();
+    while let Some(entry) = entries.next() {
+        if let Some(b) = entry.strip_prefix("## ") {
+            let (name_part, track) = match b.split_once(" [") {
+                Some((n, t)) => (n, t.trim_end_matches(']')),
+                None => (b, ""),
+            };
+            let (name, upstream) = match name_part.split_once("...") {
+                Some((n, u)) => (n, Some(u)),
+                None => (name_part, None),
+            };
+            let name = name.strip_prefix("No commits yet on ").unwrap_or(name);
+            let count = |what: &str| {
+                track
+                    .split(", ")
+                    .find_map(|p| p.strip_prefix(what))
+                    .and_then(|n| n.parse::<u64>().ok())
+                    .unwrap_or(0)
+            };
+            branch = json!({
+                "name": if name == "HEAD (no branch)" { None } else { Some(name) },
+                "upstream": upstream,
+                "ahead": count("ahead "),
+                "behind": count("behind "),
+            });
+            continue;
+        }
+        if entry.len() < 4 {
+            continue;
+        }
+        let (index, worktree, path) = (&entry[..1], &entry[1..2], &entry[3..]);
+        let mut file = json!({
+            "path": path,
+            "index": index,
+            "worktree": worktree,
+            "staged": !matches!(index, " " | "?" | "!"),
+        });
+        if matches!(index, "R" | "C") {
+            file["orig_path"] = json!(entries.next());
+        }
+        files.push(file);
+    }
+    Ok(json!({ "branch": branch, "files": files }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structured_log_show_blame_branches_and_mutations() {
+        let dir = std::env::temp_dir().join(format!("relay-git-ops-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cwd = dir.to_str().unwrap();
+        let run = |args: &[&str]| git(cwd, args).unwrap();
+        run(&["init", "-q", "-b", "main"]);
+        run(&["config", "user.name", "relay"]);
+        run(&["config", "user.email", "relay@example.invalid"]);
+        std::fs::write(dir.join("a.txt"), "one\ntwo\n").unwrap();
+        run(&["add", "-A"]);
+        run(&["commit", "-q", "-m", "init"]);
+
+        std::fs::write(dir.join("a.txt"), "one\n--two\nthree\n").unwrap();
+        std::fs::write(dir.join("b.bin"), [0u8, 1, 2]).unwrap();
+        let st = write(cwd, "rpc.git.stage", &json!({ "paths": ["a.txt"] })).unwrap();
+        let files = st["status"]["files"].as_array().unwrap();
+        assert_eq!(files[0]["path"], "a.txt");
+        assert_eq!(files[0]["staged"], true);
+        assert_eq!(files[1]["index"], "?");
+        assert!(write(cwd, "rpc.git.stage", &json!({})).is_err());
+        write(cwd, "rpc.git.stage", &json!({ "all": true })).unwrap();
+        write(cwd, "rpc.git.unstage", &json!({ "path": "b.bin" })).unwrap();
+        let c = write(
+            cwd,
+            "rpc.git.commit",
+            &json!({ "message": "second\n\nbody" }),
+        )
+        .unwrap();
+        assert_eq!(c["commit"]["subject"], "second");
+        assert_eq!(
+            (
+                c["commit"]["added"].as_u64(),
+                c["commit"]["removed"].as_u64()
+            ),
+            (Some(2), Some(1))
+        );
+
+        let log = read(cwd, "rpc.git.log", &json!({ "limit": 1 })).unwrap();
+        assert_eq!(log["has_more"], true);
+        assert_eq!(log["commits"][0]["files"][0]["path"], "a.txt");
+
+        // A deleted line starting with "--" must not end the hunk.
+        let show = read(cwd, "rpc.git.show", &json!({})).unwrap();
+        assert_eq!(show["commit"]["body"], "body");
+        let hunk = &show["files"][0]["hunks"][0];
+        let kinds: Vec<&str> = hunk["lines"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|l| l["kind"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["context", "del", "add", "add"]);
+        assert_eq!(hunk["lines"][2]["text"], "--two");
+        assert_eq!(hunk["lines"][2]["new_line"], 2);
+        let root = read(cwd, "rpc.git.show", &json!({ "rev": "HEAD~1" })).unwrap();
+        assert_eq!(root["files"][0]["status"], "added");
+
+        let blame = read(
+            cwd,
+            "rpc.git.blame",
+            &json!({ "path": "a.txt", "start_line": 2 }),
+        )
+        .unwrap();
+        let sha = blame["lines"][0]["sha"].as_str().unwrap();
+        assert_eq!(blame["commits"][sha]["summary"], "second");
+        assert_eq!(blame["lines"].as_array().unwrap().len(), 2);
+
+        write(
+            cwd,
+            "rpc.git.checkout",
+            &json!({ "branch": "feature", "create": true }),
+        )
+        .unwrap();
+        let br = read(cwd, "rpc.git.branches", &json!({})).unwrap();
+        assert_eq!(br["current"], "feature");
+        assert_eq!(br["branches"].as_array().unwrap().len(), 2);
+        let co = write(cwd, "rpc.git.checkout", &json!({ "rev": "HEAD~1" })).unwrap();
+        assert_eq!(co["detached"], true);
+        assert!(write(cwd, "rpc.git.checkout", &json!({ "branch": "--orphan" })).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commit_and_checkout_do_not_run_repo_hooks() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("relay-git-ops-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cwd = dir.to_str().unwrap();
+        let run = |args: &[&str]| git(cwd, args).unwrap();
+        run(&["init", "-q", "-b", "main"]);
+        run(&["config", "user.name", "relay"]);
+        run(&["config", "user.email", "relay@example.invalid"]);
+        let marker = dir.join("hook-ran");
+        for hook in ["pre-commit", "commit-msg", "post-commit", "post-checkout"] {
+            let path = dir.join(".git/hooks").join(hook);
+            std::fs::write(
+                &path,
+                format!("#!/bin/sh\necho {hook} >> '{}'\n", marker.display()),
+            )
+            .unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        std::fs::write(dir.join("a.txt"), "a\n").unwrap();
+        write(cwd, "rpc.git.stage", &json!({ "all": true })).unwrap();
+        write(cwd, "rpc.git.commit", &json!({ "message": "init" })).unwrap();
+        write(
+            cwd,
+            "rpc.git.checkout",
+            &json!({ "branch": "feature", "create": true }),
+        )
+        .unwrap();
+        assert!(!marker.exists(), "{:?}", std::fs::read_to_string(&marker));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,8 +1,9 @@
 use axum::{
-    Json, Router,
+    Extension, Json, Router,
     body::{Body, Bytes},
-    extract::{Path, Query, State},
+    extract::{Path, Query, Request, State},
     http::{StatusCode, header},
+    middleware::Next,
     response::Response,
     routing::{get, post},
 };
@@ -34,6 +35,166 @@ fn truncate_chars(s: &str, max_chars: usize) -> String {
 pub struct LocalState {
     pub rm: RunManager,
     pub pending_tool_permissions: Arc<Mutex<HashMap<String, oneshot::Sender<bool>>>>,
+    /// UIDs allowed to call the API; hostd's own UID is always allowed.
+    pub allowed_uids: Vec<u32>,
+    /// Bearer token of the TCP listener (`LOCAL_TCP_TOKEN`).
+    pub tcp_token: Option<String>,
+    /// Token in the 0600 file next to the unix socket (see [`USER_TOKEN_HEADER`]).
+    pub user_token: Option<String>,
+    /// Source of `/events` replay.
+    pub spool: crate::spool::Spool,
+}
+
+/// The connection a request came in on, attached to every request on it.
+#[derive(Clone, Copy, Debug)]
+pub enum Peer {
+    /// Unix socket, with the connected process's credentials (`SO_PEERCRED`).
+    Unix { uid: u32, pid: Option<i32> },
+    /// TCP/TLS listener; there are no credentials, so requests carry the bearer token.
+    Tcp { addr: std::net::SocketAddr },
+}
+
+/// Who a request is from, as verified by [`authorize`].
+#[derive(Clone, Debug)]
+pub enum Caller {
+    /// A local user on the allowlist: full access.
+    User { uid: u32 },
+    /// A TCP client holding the listener's bearer token: full access.
+    Remote { ip: std::net::IpAddr },
+    /// A run's own tools (its `RELAY_RUN_TOKEN`, or any process spawned by the run): limited to
+    /// `/runs/<run_id>/...`.
+    Run { run_id: String },
+}
+
+impl Caller {
+    /// Recorded as `actor` in events: `uid:<uid>` or `run:<run_id>`, followed by `/<label>` when
+    /// the client names itself (e.g. `run:…/codex-mcp`). The label is informational only.
+    pub fn actor(&self, label: Option<&str>) -> String {
+        let id = match self {
+            Caller::User { uid } => format!("uid:{uid}"),
+            Caller::Remote { ip } => format!("tcp:{ip}"),
+            Caller::Run { run_id } => format!("run:{run_id}"),
+        };
+        let label: String = label
+            .unwrap_or("")
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            .take(40)
+            .collect();
+        if label.is_empty() {
+            id
+        } else {
+            format!("{id}/{label}")
+        }
+    }
+}
+
+pub const RUN_TOKEN_HEADER: &str = "x-relay-run-token";
+/// Carries the contents of `<socket>.token`. Needed on the unix socket while runs are active by
+/// callers that are not part of a run's process tree.
+pub const USER_TOKEN_HEADER: &str = "x-relay-user-token";
+
+/// Checks the unix peer UID against the allowlist (or the bearer token on TCP) and resolves the
+/// [`Caller`]; run callers may only use their own `/runs/<run_id>/...` routes.
+///
+/// A unix caller outside every run's process tree is only trusted as the user while no run is
+/// active: a run's process can leave its tree (double fork, reparented to init) and then looks
+/// like any other process of the user, so with runs active it must also present the user token.
+async fn authorize(
+    State(state): State<Arc<LocalState>>,
+    Extension(peer): Extension<Peer>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    if let Peer::Unix { uid, pid } = peer {
+        let own_uid = nix::unistd::geteuid().as_raw();
+        if uid != own_uid && !state.allowed_uids.contains(&uid) {
+            tracing::warn!(uid, ?pid, "local api: uid not allowed");
+            return Err((StatusCode::FORBIDDEN, format!("uid {uid} is not allowed")));
+        }
+    }
+
+    // The TCP listener always needs its bearer token; a run token only narrows the caller.
+    if let Peer::Tcp { addr } = peer {
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim);
+        let ok = matches!(
+            (bearer, state.tcp_token.as_deref()),
+            (Some(got), Some(want)) if crate::run_manager::constant_time_eq(got, want)
+        );
+        if !ok {
+            tracing::warn!(%addr, "local api: missing or bad bearer token");
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "missing or bad bearer token".into(),
+            ));
+        }
+    }
+
+    let token = req
+        .headers()
+        .get(RUN_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    let run_id = match token {
+        Some(token) => Some(
+            state
+                .rm
+                .verify_run_token(&token)
+                .ok_or((StatusCode::UNAUTHORIZED, "invalid run token".to_string()))?,
+        ),
+        None => match peer {
+            Peer::Unix { pid: Some(pid), .. } => state.rm.run_for_pid(pid).await,
+            _ => None,
+        },
+    };
+    let caller = match run_id {
+        Some(run_id) => {
+            let mut segments = req.uri().path().trim_start_matches('/').split('/');
+            let allowed = segments.next() == Some("runs")
+                && segments.next() == Some(run_id.as_str())
+                && segments.next().is_some();
+            if !allowed {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    format!("run {run_id} may only access /runs/{run_id}/..."),
+                ));
+            }
+            Caller::Run { run_id }
+        }
+        None => match peer {
+            Peer::Unix { uid, pid } => {
+                let user_token = req
+                    .headers()
+                    .get(USER_TOKEN_HEADER)
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::trim);
+                let proven = matches!(
+                    (user_token, state.user_token.as_deref()),
+                    (Some(got), Some(want)) if crate::run_manager::constant_time_eq(got, want)
+                );
+                if !proven && state.rm.has_runs().await {
+                    tracing::warn!(uid, ?pid, "local api: unknown caller while runs are active");
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        format!(
+                            "runs are active: send the run's {RUN_TOKEN_HEADER} or the \
+                             {USER_TOKEN_HEADER} from the socket's .token file"
+                        ),
+                    ));
+                }
+                Caller::User { uid }
+            }
+            Peer::Tcp { addr } => Caller::Remote { ip: addr.ip() },
+        },
+    };
+    req.extensions_mut().insert(caller);
+    Ok(next.run(req).await)
 }
 
 #[derive(Deserialize)]
@@ -43,6 +204,30 @@ pub struct StartRunRequest {
     pub cwd: Option<String>,
     #[serde(default)]
     pub model: Option<String>,
+    /// `true`/`false` or `{ "network": bool, "hide_home": bool }`; omitted uses the host default.
+    #[serde(default)]
+    pub sandbox: Option<serde_json::Value>,
+    /// `"worktree"` runs inside a fresh git worktree on branch `relay/<run_id>`.
+    #[serde(default)]
+    pub isolation: Option<String>,
+    /// Extra environment variables for the agent process.
+    #[serde(default)]
+    pub env: Option<serde_json::Value>,
+    /// Sent as the first prompt once the run has started.
+    #[serde(default)]
+    pub initial_prompt: Option<String>,
+    /// Tools pre-approved for the run's session (same as an "always allow" answer).
+    #[serde(default)]
+    pub allow_tools: Option<Vec<String>>,
+    /// Continue this opencode session (structured opencode runs).
+    #[serde(default)]
+    pub resume_session_id: Option<String>,
+    /// Fork this opencode session and continue on the fork.
+    #[serde(default)]
+    pub fork_from_session_id: Option<String>,
+    /// Per-run budget override (`{ soft: { tokens, cost_usd }, hard: { ... } }`).
+    #[serde(default)]
+    pub budget: Option<serde_json::Value>,
 }
 
 #[derive(Serialize)]
@@ -65,37 +250,28 @@ pub struct StopRequest {
 }
 
 #[derive(Deserialize)]
-pub struct ReadFileQuery {
-    pub path: String,
+pub struct WorktreeCleanupRequest {
+    /// `merge | discard | keep_branch` (default `keep_branch`).
+    #[serde(default)]
+    pub action: Option<String>,
+    /// Repository path, only needed when hostd restarted since the run started.
+    #[serde(default)]
+    pub cwd: Option<String>,
     #[serde(default)]
     pub actor: Option<String>,
 }
 
-#[derive(Serialize)]
-pub struct ReadFileResponse {
-    pub path: String,
-    pub content: String,
-    pub truncated: bool,
-}
-
 #[derive(Deserialize)]
-pub struct SearchQuery {
-    pub q: String,
+pub struct ReadFileQuery {
+    pub path: String,
     #[serde(default)]
     pub actor: Option<String>,
 }
 
 #[derive(Serialize)]
-pub struct SearchMatch {
+pub struct ReadFileResponse {
     pub path: String,
-    pub line: i64,
-    pub column: i64,
-    pub text: String,
-}
-
-#[derive(Serialize)]
-pub struct SearchResponse {
-    pub matches: Vec<SearchMatch>,
+    pub content: String,
     pub truncated: bool,
 }
 
@@ -114,9 +290,21 @@ async fn start_run(
     } else {
         req.cmd
     };
+    let opts = crate::run_manager::StartRunOptions::from_request_data(&serde_json::json!({
+        "model": req.model,
+        "sandbox": req.sandbox,
+        "isolation": req.isolation,
+        "env": req.env,
+        "initial_prompt": req.initial_prompt,
+        "allow_tools": req.allow_tools,
+        "resume_session_id": req.resume_session_id,
+        "fork_from_session_id": req.fork_from_session_id,
+        "budget": req.budget,
+    }))
+    .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
     let run_id = state
         .rm
-        .start_run(req.tool, cmd, req.cwd, req.model)
+        .start_run(req.tool, cmd, req.cwd, opts)
         .await
         .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
     Ok(Json(StartRunResponse { run_id }))
@@ -124,10 +312,11 @@ async fn start_run(
 
 async fn send_input(
     State(state): State<Arc<LocalState>>,
+    Extension(caller): Extension<Caller>,
     Path(run_id): Path<String>,
     Json(req): Json<InputRequest>,
 ) -> Result<StatusCode, (StatusCode, String)> {
-    let actor = req.actor.as_deref().unwrap_or("cli");
+    let actor = &caller.actor(req.actor.as_deref());
     state
         .rm
         .send_input(&run_id, actor, &req.input_id, &req.text)
@@ -150,10 +339,244 @@ async fn stop_run(
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Answers a permission request the way `run.permission.approve/deny` from the server does: a
+/// local API tool call parked in `pending_tool_permissions` first, otherwise the agent's own
+/// request through `RunManager::decide_permission`.
+pub async fn resolve_permission(
+    rm: &RunManager,
+    pending_tool_permissions: &Mutex<HashMap<String, oneshot::Sender<bool>>>,
+    run_id: &str,
+    actor: &str,
+    request_id: &str,
+    decision: relay_protocol::PermissionDecision,
+    allow_tools: &[String],
+) -> anyhow::Result<()> {
+    use relay_protocol::PermissionDecision;
+
+    if decision == PermissionDecision::ApproveForSession && !allow_tools.is_empty() {
+        let _ = rm.add_session_allow_tools(run_id, allow_tools).await;
+    }
+    let tx = {
+        let mut map = pending_tool_permissions.lock().await;
+        map.remove(&format!("{run_id}:{request_id}"))
+    };
+    if let Some(tx) = tx {
+        let approved = matches!(
+            decision,
+            PermissionDecision::Approve | PermissionDecision::ApproveForSession
+        );
+        let _ = tx.send(approved);
+        return Ok(());
+    }
+    let decision = match decision {
+        PermissionDecision::Approve => "approve",
+        PermissionDecision::ApproveForSession => "approve_for_session",
+        PermissionDecision::Deny | PermissionDecision::Abort => "deny",
+    };
+    rm.decide_permission(run_id, actor, request_id, decision)
+        .await
+}
+
+async fn list_permissions(
+    State(state): State<Arc<LocalState>>,
+    Path(run_id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let mut pending = Vec::new();
+    for env in state.rm.permission_requests(&run_id) {
+        let request_id = env
+            .data
+            .get("request_id")
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+        let parked = state
+            .pending_tool_permissions
+            .lock()
+            .await
+            .contains_key(&format!("{run_id}:{request_id}"));
+        if parked || state.rm.is_permission_pending(&run_id, request_id).await {
+            let mut item = env.data.clone();
+            if let Some(obj) = item.as_object_mut() {
+                obj.insert("ts".into(), json!(env.ts));
+                obj.insert("seq".into(), json!(env.seq));
+            }
+            pending.push(item);
+        }
+    }
+    Ok(Json(json!({ "run_id": run_id, "pending": pending })))
+}
+
+#[derive(Deserialize)]
+pub struct DecidePermissionRequest {
+    pub decision: relay_protocol::PermissionDecision,
+    /// With `approve_for_session`: tools to auto-approve for the rest of the run.
+    #[serde(default)]
+    pub allow_tools: Vec<String>,
+    #[serde(default)]
+    pub actor: Option<String>,
+}
+
+async fn decide_permission(
+    State(state): State<Arc<LocalState>>,
+    Extension(caller): Extension<Caller>,
+    Path((run_id, request_id)): Path<(String, String)>,
+    Json(req): Json<DecidePermissionRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if matches!(caller, Caller::Run { .. }) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "a run cannot answer permission requests".into(),
+        ));
+    }
+    let parked = state
+        .pending_tool_permissions
+        .lock()
+        .await
+        .contains_key(&format!("{run_id}:{request_id}"));
+    if !parked && !state.rm.is_permission_pending(&run_id, &request_id).await {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "no such pending permission request".into(),
+        ));
+    }
+    let actor = &caller.actor(req.actor.as_deref());
+    resolve_permission(
+        &state.rm,
+        &state.pending_tool_permissions,
+        &run_id,
+        actor,
+        &request_id,
+        req.decision,
+        &req.allow_tools,
+    )
+    .await
+    .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let decision = serde_json::to_value(req.decision).unwrap_or_default();
+    let _ = state
+        .rm
+        .emit_run_event(
+            &run_id,
+            "run.permission_decided",
+            json!({
+                "request_id": request_id,
+                "decision": decision,
+                "actor": actor,
+                "allow_tools": req.allow_tools,
+            }),
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn worktree_cleanup(
+    State(state): State<Arc<LocalState>>,
+    Extension(caller): Extension<Caller>,
+    Path(run_id): Path<String>,
+    Json(req): Json<WorktreeCleanupRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let action = req.action.as_deref().unwrap_or("");
+    let action = crate::fs_git::WorktreeCleanup::parse(action).ok_or((
+        StatusCode::BAD_REQUEST,
+        format!("unsupported action `{action}` (expected merge|discard|keep_branch)"),
+    ))?;
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let call = ToolCall {
+        run_id: &run_id,
+        request_id: &request_id,
+        actor: &caller.actor(req.actor.as_deref()),
+        tool: "rpc.run.worktree.cleanup",
+        started: std::time::Instant::now(),
+    };
+    cleanup_worktree_gated(
+        &state.rm,
+        &state.pending_tool_permissions,
+        &call,
+        action,
+        req.cwd,
+    )
+    .await
+    .map(Json)
+}
+
+/// `rpc.run.worktree.cleanup`: every action removes the checkout (and `merge`/`discard` change
+/// the repo), so it is approved like a write and the worktree is checkpointed first. Emits the
+/// usual `tool.call`/`tool.result`, continuing the exited run's events.
+pub async fn cleanup_worktree_gated(
+    rm: &RunManager,
+    pending_tool_permissions: &Mutex<HashMap<String, oneshot::Sender<bool>>>,
+    call: &ToolCall<'_>,
+    action: crate::fs_git::WorktreeCleanup,
+    repo_cwd: Option<String>,
+) -> Result<serde_json::Value, (StatusCode, String)> {
+    let wt = rm
+        .worktree_for_cleanup(call.run_id, repo_cwd)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    let args = json!({
+        "action": action.as_str(),
+        "branch": wt.branch,
+        "path": wt.path,
+        "repo_root": wt.repo_root,
+    });
+    let _ = rm
+        .emit_run_event(
+            call.run_id,
+            "tool.call",
+            json!({
+                "request_id": call.request_id,
+                "tool": call.tool,
+                "actor": call.actor,
+                "args": args.clone()
+            }),
+        )
+        .await;
+    let summary = truncate_chars(
+        &format!("action={} branch={}", action.as_str(), wt.branch),
+        80,
+    );
+    await_tool_permission(rm, pending_tool_permissions, call, args, summary).await?;
+
+    rm.checkpoint_worktree(call.run_id, call.request_id, &wt)
+        .await;
+    let result = rm
+        .cleanup_worktree(call.run_id, wt, action)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")));
+    let (ok, result_value, error_value) = match &result {
+        Ok(v) => (true, v.clone(), serde_json::Value::Null),
+        Err((_, msg)) => (false, serde_json::Value::Null, json!(msg)),
+    };
+    let _ = rm
+        .emit_run_event(
+            call.run_id,
+            "tool.result",
+            json!({
+                "request_id": call.request_id,
+                "tool": call.tool,
+                "actor": call.actor,
+                "ok": ok,
+                "duration_ms": call.started.elapsed().as_millis() as i64,
+                "result": result_value,
+                "error": error_value
+            }),
+        )
+        .await;
+    result
+}
+
 async fn list_runs(State(state): State<Arc<LocalState>>) -> Json<Vec<RunSummary>> {
     Json(state.rm.list_runs().await)
 }
 
+/// Registered agent runners (built-ins plus `~/.relay/runners.json`) with binary availability.
+async fn list_runners() -> Json<Vec<serde_json::Value>> {
+    Json(
+        crate::runners::registry::registry()
+            .iter()
+            .map(|entry| entry.status())
+            .collect(),
+    )
+}
+
 async fn stream_stdout(
     State(state): State<Arc<LocalState>>,
     Path(run_id): Path<String>,
@@ -188,7 +611,15 @@ async fn stream_stdout(
                             Some(rx),
                         ));
                     }
-                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    // Output was dropped; say so instead of silently splicing the stream.
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        return Some((
+                            Ok(Bytes::from(format!(
+                                "\n[relay: output lagged, {skipped} events skipped]\n"
+                            ))),
+                            Some(rx),
+                        ));
+                    }
                     Err(_) => return None,
                 }
             }
@@ -204,6 +635,207 @@ async fn stream_stdout(
     Ok(resp)
 }
 
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    #[serde(default)]
+    pub run_id: Option<String>,
+    /// Comma-separated event types; `tool.*` matches a prefix.
+    #[serde(default)]
+    pub types: Option<String>,
+    /// Replay the run's spooled events after this `seq` first (needs `run_id`; SSE clients may send
+    /// `Last-Event-ID` instead).
+    #[serde(default)]
+    pub since_seq: Option<i64>,
+    /// `sse` (default) or `ndjson`.
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+/// Spool rows read per replay query; replay pages through until it reaches live events.
+const EVENTS_REPLAY_PAGE: usize = 1_000;
+
+async fn replay_page(
+    spool: &crate::spool::Spool,
+    run_id: &str,
+    after: i64,
+) -> anyhow::Result<Vec<relay_protocol::WsEnvelope>> {
+    let spool = spool.clone();
+    let run_id = run_id.to_string();
+    tokio::task::spawn_blocking(move || spool.events_after(&run_id, after, EVENTS_REPLAY_PAGE))
+        .await?
+}
+
+/// `GET /events`: run events as Server-Sent Events or NDJSON, each a full `WsEnvelope`. Stream
+/// problems are reported in-band as `events.lagged` (the subscriber fell behind; `skipped` events
+/// are lost) and `events.gap` (the spool no longer has some events after `since_seq`).
+async fn stream_events(
+    State(state): State<Arc<LocalState>>,
+    headers: axum::http::HeaderMap,
+    Query(q): Query<EventsQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let run_id = q.run_id.filter(|s| !s.trim().is_empty());
+    let replay_run = run_id.clone();
+    let types: Vec<String> = q
+        .types
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    let ndjson = match q.format.as_deref() {
+        Some("ndjson") => true,
+        Some("sse") | None => false,
+        Some(other) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("unsupported format `{other}` (expected sse|ndjson)"),
+            ));
+        }
+    };
+    let since_seq = q.since_seq.or_else(|| {
+        headers
+            .get("last-event-id")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<i64>().ok())
+    });
+    if since_seq.is_some() && run_id.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "since_seq needs run_id (seq is per run)".into(),
+        ));
+    }
+
+    let wanted = move |env: &relay_protocol::WsEnvelope| {
+        if let Some(run_id) = run_id.as_deref()
+            && env.run_id.as_deref() != Some(run_id)
+        {
+            return false;
+        }
+        types.is_empty()
+            || types.iter().any(|t| match t.strip_suffix('*') {
+                Some(prefix) => env.r#type.starts_with(prefix),
+                None => env.r#type == *t,
+            })
+    };
+    let frame = move |env: &relay_protocol::WsEnvelope| -> Bytes {
+        let json = serde_json::to_string(env).unwrap_or_default();
+        if ndjson {
+            return Bytes::from(format!("{json}\n"));
+        }
+        let mut out = String::new();
+        if let Some(seq) = env.seq {
+            out.push_str(&format!("id: {seq}\n"));
+        }
+        out.push_str(&format!("event: {}\ndata: {json}\n\n", env.r#type));
+        Bytes::from(out)
+    };
+
+    // Subscribe before reading the spool so nothing falls between replay and live events.
+    let mut rx = state.rm.subscribe();
+    let replay = match (since_seq, replay_run) {
+        (Some(after), Some(run_id)) => {
+            let events = replay_page(&state.spool, &run_id, after)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            Some((run_id, after, events))
+        }
+        _ => None,
+    };
+    let spool = state.spool.clone();
+
+    let (tx, body_rx) = tokio::sync::mpsc::channel::<Bytes>(256);
+    tokio::spawn(async move {
+        // Highest seq already sent per run, to drop live duplicates of replayed events.
+        let mut sent: HashMap<String, i64> = HashMap::new();
+        if let Some((run_id, mut after, mut events)) = replay {
+            loop {
+                let full = events.len() == EVENTS_REPLAY_PAGE;
+                for env in events {
+                    let Some(seq) = env.seq else { continue };
+                    if seq > after + 1 {
+                        let mut gap = relay_protocol::WsEnvelope::new(
+                            "events.gap",
+                            json!({ "after_seq": after, "next_seq": seq }),
+                        );
+                        gap.run_id = Some(run_id.clone());
+                        if tx.send(frame(&gap)).await.is_err() {
+                            return;
+                        }
+                    }
+                    sent.insert(run_id.clone(), seq);
+                    after = seq;
+                    if wanted(&env) && tx.send(frame(&env)).await.is_err() {
+                        return;
+                    }
+                }
+                if !full {
+                    break;
+                }
+                events = match replay_page(&spool, &run_id, after).await {
+                    Ok(events) => events,
+                    Err(err) => {
+                        tracing::warn!(error=%err, run_id, "events replay failed");
+                        return;
+                    }
+                };
+            }
+        }
+        let mut keepalive = tokio::time::interval(Duration::from_secs(15));
+        keepalive.tick().await;
+        loop {
+            let out = tokio::select! {
+                _ = keepalive.tick() => {
+                    if ndjson {
+                        continue;
+                    }
+                    Bytes::from_static(b": keepalive\n\n")
+                }
+                ev = rx.recv() => match ev {
+                    Ok(env) => {
+                        if let (Some(run_id), Some(seq)) = (env.run_id.as_ref(), env.seq)
+                            && sent.get(run_id).is_some_and(|last| seq <= *last)
+                        {
+                            continue;
+                        }
+                        if !wanted(&env) {
+                            continue;
+                        }
+                        frame(&env)
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        frame(&relay_protocol::WsEnvelope::new(
+                            "events.lagged",
+                            json!({ "skipped": skipped }),
+                        ))
+                    }
+                    Err(_) => return,
+                },
+            };
+            if tx.send(out).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let stream = futures_util::stream::unfold(body_rx, |mut rx| async move {
+        rx.recv()
+            .await
+            .map(|b| (Ok::<Bytes, std::io::Error>(b), rx))
+    });
+    let mut resp = Response::new(Body::from_stream(stream));
+    let content_type = if ndjson {
+        "application/x-ndjson"
+    } else {
+        "text/event-stream"
+    };
+    resp.headers_mut()
+        .insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+    resp.headers_mut()
+        .insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
+    Ok(resp)
+}
+
 async fn stream_stdin(
     State(state): State<Arc<LocalState>>,
     Path(run_id): Path<String>,
@@ -231,6 +863,7 @@ async fn stream_stdin(
 
 async fn fs_read(
     State(state): State<Arc<LocalState>>,
+    Extension(caller): Extension<Caller>,
     Path(run_id): Path<String>,
     Query(q): Query<ReadFileQuery>,
 ) -> Result<Json<ReadFileResponse>, (StatusCode, String)> {
@@ -239,7 +872,7 @@ async fn fs_read(
         .get_run_cwd(&run_id)
         .await
         .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
-    let actor = q.actor.as_deref().unwrap_or("local");
+    let actor = &caller.actor(q.actor.as_deref());
     const MAX_BYTES: usize = 1024 * 1024;
     let rel = q.path.clone();
     let request_id = uuid::Uuid::new_v4().to_string();
@@ -331,16 +964,17 @@ async fn fs_read(
 
 async fn fs_search(
     State(state): State<Arc<LocalState>>,
+    Extension(caller): Extension<Caller>,
     Path(run_id): Path<String>,
-    Query(q): Query<SearchQuery>,
-) -> Result<Json<SearchResponse>, (StatusCode, String)> {
+    Query(q): Query<ActorQuery>,
+    Query(opts): Query<crate::search::SearchOptions>,
+) -> Result<Json<crate::search::SearchResult>, (StatusCode, String)> {
     let cwd = state
         .rm
         .get_run_cwd(&run_id)
         .await
         .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
-    let qstr = q.q.clone();
-    let actor = q.actor.as_deref().unwrap_or("local");
+    let actor = &caller.actor(q.actor.as_deref());
     let request_id = uuid::Uuid::new_v4().to_string();
     let started = std::time::Instant::now();
     let _ = state
@@ -352,20 +986,15 @@ async fn fs_search(
                 "request_id": request_id,
                 "tool": "fs.search",
                 "actor": actor,
-                "args": { "q": qstr }
+                "args": { "q": opts.q, "path": opts.path, "include": opts.include, "exclude": opts.exclude }
             }),
         )
         .await;
-    let result = tokio::task::spawn_blocking({
-        let cwd = cwd.clone();
-        let qstr = q.q.clone();
-        move || fs_git::rg_search(&cwd, &qstr, 200)
-    })
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    // Dropping this future (client gone) cancels the search.
+    let result = state.rm.search(&run_id, &request_id, cwd, opts).await;
     let duration_ms = started.elapsed().as_millis() as i64;
-    let (rows, truncated) = match result {
-        Ok(Ok(v)) => {
+    match result {
+        Ok(v) => {
             let _ = state
                 .rm
                 .emit_run_event(
@@ -377,13 +1006,13 @@ async fn fs_search(
                         "actor": actor,
                         "ok": true,
                         "duration_ms": duration_ms,
-                        "result": { "truncated": v.1, "count": v.0.len() }
+                        "result": { "truncated": v.truncated, "count": v.matches.len() }
                     }),
                 )
                 .await;
-            v
+            Ok(Json(v))
         }
-        Ok(Err((_, msg))) => {
+        Err((status, msg)) => {
             let _ = state
                 .rm
                 .emit_run_event(
@@ -399,38 +1028,9 @@ async fn fs_search(
                     }),
                 )
                 .await;
-            return Err((StatusCode::BAD_REQUEST, "fs.search failed".into()));
+            Err((status, msg))
         }
-        Err((_, msg)) => {
-            let _ = state
-                .rm
-                .emit_run_event(
-                    &run_id,
-                    "tool.result",
-                    json!({
-                        "request_id": request_id,
-                        "tool": "fs.search",
-                        "actor": actor,
-                        "ok": false,
-                        "duration_ms": duration_ms,
-                        "error": msg
-                    }),
-                )
-                .await;
-            return Err((StatusCode::INTERNAL_SERVER_ERROR, msg));
-        }
-    };
-    let matches = rows
-        .into_iter()
-        .map(|(path, line, column, text)| SearchMatch {
-            path,
-            line,
-            column,
-            text,
-        })
-        .collect::<Vec<_>>();
-
-    Ok(Json(SearchResponse { matches, truncated }))
+    }
 }
 
 #[derive(Deserialize)]
@@ -441,6 +1041,7 @@ pub struct ActorQuery {
 
 async fn git_status(
     State(state): State<Arc<LocalState>>,
+    Extension(caller): Extension<Caller>,
     Path(run_id): Path<String>,
     Query(q): Query<ActorQuery>,
 ) -> Result<Json<GitTextResponse>, (StatusCode, String)> {
@@ -450,7 +1051,7 @@ async fn git_status(
         .await
         .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
     const MAX: usize = 200_000;
-    let actor = q.actor.as_deref().unwrap_or("local");
+    let actor = &caller.actor(q.actor.as_deref());
     let request_id = uuid::Uuid::new_v4().to_string();
     let started = std::time::Instant::now();
     let _ = state
@@ -542,6 +1143,7 @@ pub struct GitDiffQuery {
 
 async fn git_diff(
     State(state): State<Arc<LocalState>>,
+    Extension(caller): Extension<Caller>,
     Path(run_id): Path<String>,
     Query(q): Query<GitDiffQuery>,
 ) -> Result<Json<GitTextResponse>, (StatusCode, String)> {
@@ -553,7 +1155,7 @@ async fn git_diff(
 
     const MAX: usize = 400_000;
     let rel = q.path.clone();
-    let actor = q.actor.as_deref().unwrap_or("local");
+    let actor = &caller.actor(q.actor.as_deref());
     let request_id = uuid::Uuid::new_v4().to_string();
     let started = std::time::Instant::now();
     let _ = state
@@ -654,6 +1256,7 @@ pub struct WriteFileResponse {
 
 async fn fs_write(
     State(state): State<Arc<LocalState>>,
+    Extension(caller): Extension<Caller>,
     Path(run_id): Path<String>,
     Json(req): Json<WriteFileRequest>,
 ) -> Result<Json<WriteFileResponse>, (StatusCode, String)> {
@@ -662,7 +1265,7 @@ async fn fs_write(
         .get_run_cwd(&run_id)
         .await
         .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
-    let actor = req.actor.as_deref().unwrap_or("local");
+    let actor = &caller.actor(req.actor.as_deref());
     let request_id = uuid::Uuid::new_v4().to_string();
     let started = std::time::Instant::now();
 
@@ -693,89 +1296,26 @@ async fn fs_write(
         )
         .await;
 
-    let op_args_summary = truncate_chars(&format!("path={} bytes={}", req.path, bytes), 80);
-    let prompt = if op_args_summary.trim().is_empty() {
-        "需要审批：rpc.fs.write".to_string()
-    } else {
-        format!("需要审批：rpc.fs.write {op_args_summary}")
+    let call = ToolCall {
+        run_id: &run_id,
+        request_id: &request_id,
+        actor,
+        tool: "rpc.fs.write",
+        started,
     };
+    await_tool_permission(
+        &state.rm,
+        &state.pending_tool_permissions,
+        &call,
+        args_for_event,
+        truncate_chars(&format!("path={} bytes={}", req.path, bytes), 80),
+    )
+    .await?;
 
-    let auto_approved = state.rm.is_tool_allowlisted(&run_id, "rpc.fs.write").await;
-
-    let approved = if auto_approved {
-        true
-    } else {
-        let key = format!("{run_id}:{request_id}");
-        let (tx, rx) = oneshot::channel::<bool>();
-        {
-            let mut map = state.pending_tool_permissions.lock().await;
-            map.insert(key.clone(), tx);
-        }
-        let _ = state
-            .rm
-            .emit_run_event(
-                &run_id,
-                "run.permission_requested",
-                json!({
-                    "request_id": request_id,
-                    "reason": "permission",
-                    "prompt": prompt,
-                    "op_tool": "rpc.fs.write",
-                    "op_args": args_for_event,
-                    "op_args_summary": op_args_summary,
-                    "approve_text": "",
-                    "deny_text": ""
-                }),
-            )
-            .await;
-
-        match tokio::time::timeout(Duration::from_secs(600), rx).await {
-            Ok(Ok(v)) => v,
-            Ok(Err(_)) => false,
-            Err(_) => {
-                let mut map = state.pending_tool_permissions.lock().await;
-                map.remove(&key);
-                let duration_ms = started.elapsed().as_millis() as i64;
-                let _ = state
-                    .rm
-                    .emit_run_event(
-                        &run_id,
-                        "tool.result",
-                        json!({
-                            "request_id": request_id,
-                            "tool": "rpc.fs.write",
-                            "actor": actor,
-                            "ok": false,
-                            "duration_ms": duration_ms,
-                            "error": "timeout"
-                        }),
-                    )
-                    .await;
-                return Err((StatusCode::REQUEST_TIMEOUT, "timeout".into()));
-            }
-        }
-    };
-
-    if !approved {
-        let duration_ms = started.elapsed().as_millis() as i64;
-        let _ = state
-            .rm
-            .emit_run_event(
-                &run_id,
-                "tool.result",
-                json!({
-                    "request_id": request_id,
-                    "tool": "rpc.fs.write",
-                    "actor": actor,
-                    "ok": false,
-                    "duration_ms": duration_ms,
-                    "error": "denied"
-                }),
-            )
-            .await;
-        return Err((StatusCode::FORBIDDEN, "denied".into()));
-    }
-
+    let _ = state
+        .rm
+        .checkpoint_run(&run_id, &request_id, "rpc.fs.write")
+        .await;
     let rel_for_exec = rel.clone();
     let content = req.content.clone();
     let result = tokio::task::spawn_blocking(move || {
@@ -848,11 +1388,20 @@ async fn fs_write(
     }))
 }
 
+#[derive(Deserialize)]
+pub struct RestoreCheckpointRequest {
+    #[serde(default)]
+    pub actor: Option<String>,
+}
+
 #[derive(Deserialize)]
 pub struct BashRequest {
     pub cmd: String,
     #[serde(default)]
     pub actor: Option<String>,
+    /// Overrides `RELAY_BASH_TIMEOUT_MS` for this command.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
 }
 
 #[derive(Serialize)]
@@ -865,6 +1414,7 @@ pub struct BashResponse {
 
 async fn bash_run(
     State(state): State<Arc<LocalState>>,
+    Extension(caller): Extension<Caller>,
     Path(run_id): Path<String>,
     Json(req): Json<BashRequest>,
 ) -> Result<Json<BashResponse>, (StatusCode, String)> {
@@ -873,7 +1423,7 @@ async fn bash_run(
         .get_run_cwd(&run_id)
         .await
         .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
-    let actor = req.actor.as_deref().unwrap_or("local");
+    let actor = &caller.actor(req.actor.as_deref());
     let request_id = uuid::Uuid::new_v4().to_string();
     let started = std::time::Instant::now();
 
@@ -893,94 +1443,39 @@ async fn bash_run(
         )
         .await;
 
-    let op_args_summary = truncate_chars(&format!("cmd={cmd_redacted}"), 80);
-    let prompt = if op_args_summary.trim().is_empty() {
-        "需要审批：bash".to_string()
-    } else {
-        format!("需要审批：bash {op_args_summary}")
-    };
-
-    let auto_approved = state.rm.is_tool_allowlisted(&run_id, "bash").await;
-
-    let approved = if auto_approved {
-        true
-    } else {
-        let key = format!("{run_id}:{request_id}");
-        let (tx, rx) = oneshot::channel::<bool>();
-        {
-            let mut map = state.pending_tool_permissions.lock().await;
-            map.insert(key.clone(), tx);
-        }
-        let _ = state
-            .rm
-            .emit_run_event(
-                &run_id,
-                "run.permission_requested",
-                json!({
-                    "request_id": request_id,
-                    "reason": "permission",
-                    "prompt": prompt,
-                    "op_tool": "bash",
-                    "op_args": args_for_event,
-                    "op_args_summary": op_args_summary,
-                    "approve_text": "",
-                    "deny_text": ""
-                }),
-            )
-            .await;
-
-        match tokio::time::timeout(Duration::from_secs(600), rx).await {
-            Ok(Ok(v)) => v,
-            Ok(Err(_)) => false,
-            Err(_) => {
-                let mut map = state.pending_tool_permissions.lock().await;
-                map.remove(&key);
-                let duration_ms = started.elapsed().as_millis() as i64;
-                let _ = state
-                    .rm
-                    .emit_run_event(
-                        &run_id,
-                        "tool.result",
-                        json!({
-                            "request_id": request_id,
-                            "tool": "rpc.bash",
-                            "actor": actor,
-                            "ok": false,
-                            "duration_ms": duration_ms,
-                            "error": "timeout"
-                        }),
-                    )
-                    .await;
-                return Err((StatusCode::REQUEST_TIMEOUT, "timeout".into()));
-            }
-        }
+    let call = ToolCall {
+        run_id: &run_id,
+        request_id: &request_id,
+        actor,
+        tool: "rpc.bash",
+        started,
     };
+    await_tool_permission(
+        &state.rm,
+        &state.pending_tool_permissions,
+        &call,
+        args_for_event,
+        truncate_chars(&format!("cmd={cmd_redacted}"), 80),
+    )
+    .await?;
 
-    if !approved {
-        let duration_ms = started.elapsed().as_millis() as i64;
-        let _ = state
-            .rm
-            .emit_run_event(
-                &run_id,
-                "tool.result",
-                json!({
-                    "request_id": request_id,
-                    "tool": "rpc.bash",
-                    "actor": actor,
-                    "ok": false,
-                    "duration_ms": duration_ms,
-                    "error": "denied"
-                }),
-            )
-            .await;
-        return Err((StatusCode::FORBIDDEN, "denied".into()));
-    }
-
-    let cmd = req.cmd.clone();
-    let result =
-        tokio::task::spawn_blocking(move || crate::fs_git::bash_exec(&cwd, &cmd, 200_000, 200_000))
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    let _ = state
+        .rm
+        .checkpoint_run(&run_id, &request_id, "rpc.bash")
+        .await;
+    let result = state
+        .rm
+        .exec_bash(&run_id, &request_id, cwd, req.cmd.clone(), req.timeout_ms)
+        .await
+        .map(|(outcome, _)| match outcome.error() {
+            None => Ok((
+                outcome.stdout,
+                outcome.stderr,
+                outcome.exit_code.unwrap_or(0),
+                outcome.truncated,
+            )),
+            Some(err) => Err((StatusCode::BAD_REQUEST, err)),
+        });
     let duration_ms = started.elapsed().as_millis() as i64;
 
     let (stdout, stderr, exit_code, truncated) = match result {
@@ -1048,18 +1543,212 @@ async fn bash_run(
     }))
 }
 
+/// A tool call awaiting approval (see [`await_tool_permission`]).
+#[derive(Clone, Copy)]
+pub struct ToolCall<'a> {
+    pub run_id: &'a str,
+    pub request_id: &'a str,
+    pub actor: &'a str,
+    pub tool: &'a str,
+    pub started: std::time::Instant,
+}
+
+/// Emits `run.permission_requested` and waits (600 s) for the decision. On deny/timeout the
+/// failing `tool.result` is emitted here and the HTTP error returned.
+pub(crate) async fn await_tool_permission(
+    rm: &RunManager,
+    pending_tool_permissions: &Mutex<HashMap<String, oneshot::Sender<bool>>>,
+    call: &ToolCall<'_>,
+    op_args: serde_json::Value,
+    op_args_summary: String,
+) -> Result<(), (StatusCode, String)> {
+    let ToolCall {
+        run_id,
+        request_id,
+        actor,
+        tool,
+        started,
+    } = *call;
+    // `rpc.bash` is approved (and allowlisted) under the shorter `bash`, as on the WS path.
+    let op_tool = if tool == "rpc.bash" { "bash" } else { tool };
+    if rm.is_tool_allowlisted(run_id, op_tool).await {
+        return Ok(());
+    }
+    let prompt = if op_args_summary.trim().is_empty() {
+        format!("需要审批：{op_tool}")
+    } else {
+        format!("需要审批：{op_tool} {op_args_summary}")
+    };
+    let key = format!("{run_id}:{request_id}");
+    let (tx, rx) = oneshot::channel::<bool>();
+    {
+        let mut map = pending_tool_permissions.lock().await;
+        map.insert(key.clone(), tx);
+    }
+    let _ = rm
+        .emit_run_event(
+            run_id,
+            "run.permission_requested",
+            json!({
+                "request_id": request_id,
+                "reason": "permission",
+                "prompt": prompt,
+                "op_tool": op_tool,
+                "op_args": op_args,
+                "op_args_summary": op_args_summary,
+                "approve_text": "",
+                "deny_text": ""
+            }),
+        )
+        .await;
+
+    let (status, error) = match tokio::time::timeout(Duration::from_secs(600), rx).await {
+        Ok(Ok(true)) => return Ok(()),
+        Ok(Ok(false)) | Ok(Err(_)) => (StatusCode::FORBIDDEN, "denied"),
+        Err(_) => {
+            let mut map = pending_tool_permissions.lock().await;
+            map.remove(&key);
+            (StatusCode::REQUEST_TIMEOUT, "timeout")
+        }
+    };
+    let _ = rm
+        .emit_run_event(
+            run_id,
+            "tool.result",
+            json!({
+                "request_id": request_id,
+                "tool": tool,
+                "actor": actor,
+                "ok": false,
+                "duration_ms": started.elapsed().as_millis() as i64,
+                "error": error
+            }),
+        )
+        .await;
+    Err((status, error.into()))
+}
+
+async fn list_checkpoints(
+    State(state): State<Arc<LocalState>>,
+    Path(run_id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let cwd = state
+        .rm
+        .get_run_cwd(&run_id)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let checkpoints = tokio::task::spawn_blocking(move || crate::checkpoints::list(&cwd, &run_id))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+    Ok(Json(json!({ "checkpoints": checkpoints })))
+}
+
+async fn restore_checkpoint(
+    State(state): State<Arc<LocalState>>,
+    Extension(caller): Extension<Caller>,
+    Path((run_id, checkpoint_id)): Path<(String, String)>,
+    Json(req): Json<RestoreCheckpointRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let cwd = state
+        .rm
+        .get_run_cwd(&run_id)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let actor = &caller.actor(req.actor.as_deref());
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let started = std::time::Instant::now();
+    let op_tool = "rpc.run.checkpoints.restore";
+    let args = json!({ "checkpoint_id": checkpoint_id });
+
+    let _ = state
+        .rm
+        .emit_run_event(
+            &run_id,
+            "tool.call",
+            json!({
+                "request_id": request_id,
+                "tool": op_tool,
+                "actor": actor,
+                "args": args.clone()
+            }),
+        )
+        .await;
+    let call = ToolCall {
+        run_id: &run_id,
+        request_id: &request_id,
+        actor,
+        tool: op_tool,
+        started,
+    };
+    await_tool_permission(
+        &state.rm,
+        &state.pending_tool_permissions,
+        &call,
+        args,
+        truncate_chars(&format!("checkpoint_id={checkpoint_id}"), 80),
+    )
+    .await?;
+
+    let run_id_exec = run_id.clone();
+    let request_id_exec = request_id.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        crate::checkpoints::restore(&cwd, &run_id_exec, &checkpoint_id, &request_id_exec)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+    .and_then(|r| r);
+    let (ok, result_value, error_value) = match &result {
+        Ok(v) => (true, v.clone(), serde_json::Value::Null),
+        Err((_, msg)) => (false, serde_json::Value::Null, json!(msg)),
+    };
+    let _ = state
+        .rm
+        .emit_run_event(
+            &run_id,
+            "tool.result",
+            json!({
+                "request_id": request_id,
+                "tool": op_tool,
+                "actor": actor,
+                "ok": ok,
+                "duration_ms": started.elapsed().as_millis() as i64,
+                "result": result_value,
+                "error": error_value
+            }),
+        )
+        .await;
+    result.map(Json)
+}
+
 pub fn router(state: Arc<LocalState>) -> Router {
     Router::new()
+        .route("/runners", get(list_runners))
+        .route("/events", get(stream_events))
         .route("/runs", post(start_run).get(list_runs))
         .route("/runs/:run_id/stdout", get(stream_stdout))
         .route("/runs/:run_id/stdin", post(stream_stdin))
         .route("/runs/:run_id/input", post(send_input))
         .route("/runs/:run_id/stop", post(stop_run))
+        .route("/runs/:run_id/permissions", get(list_permissions))
+        .route(
+            "/runs/:run_id/permissions/:request_id",
+            post(decide_permission),
+        )
+        .route("/runs/:run_id/worktree/cleanup", post(worktree_cleanup))
         .route("/runs/:run_id/fs/read", get(fs_read))
         .route("/runs/:run_id/fs/search", get(fs_search))
         .route("/runs/:run_id/fs/write", post(fs_write))
         .route("/runs/:run_id/git/status", get(git_status))
         .route("/runs/:run_id/git/diff", get(git_diff))
         .route("/runs/:run_id/bash", post(bash_run))
+        .route("/runs/:run_id/checkpoints", get(list_checkpoints))
+        .route(
+            "/runs/:run_id/checkpoints/:checkpoint_id/restore",
+            post(restore_checkpoint),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            authorize,
+        ))
         .with_state(state)
 }
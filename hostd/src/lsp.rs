@@ -0,0 +1,1338 @@
+//! Code intelligence for `rpc.code.definition`, `rpc.code.references`, `rpc.code.symbols` and
+//! `rpc.code.diagnostics`: a small Language Server Protocol client.
+//!
+//! One language server process is started lazily per (run cwd, language) and shared by runs with
+//! the same cwd; it is shut down after `RELAY_LSP_IDLE_MS` (default 10 minutes) without requests
+//! or respawned if it died. Files are synced with `didOpen` / full-text `didChange` from disk
+//! before each request. Paths and positions in requests and results use the `rpc.fs.search`
+//! conventions: relative to the run cwd, 1-based lines and 1-based UTF-8 byte columns. Locations
+//! outside the run cwd (toolchain sources, dependencies) are reported without their path.
+//!
+//! Servers come from built-ins (rust-analyzer, typescript-language-server, pyright, gopls) merged
+//! by `language` with `~/.relay/lsp.json` (or `RELAY_LSP_CONFIG`), like the runner registry.
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value as JsonValue, json};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, Weak, mpsc};
+use std::time::{Duration, Instant};
+
+type LspError = (StatusCode, String);
+
+/// Servers index the project on start; the first request may take a while.
+const INIT_TIMEOUT: Duration = Duration::from_secs(60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long `rpc.code.diagnostics` waits for the server to publish after a sync.
+const DIAGNOSTICS_WAIT: Duration = Duration::from_secs(5);
+const MAX_FILE_BYTES: u64 = 4 * 1024 * 1024;
+const MAX_LOCATIONS: usize = 1_000;
+const MAX_SYMBOLS: usize = 2_000;
+
+/// One language server hostd knows how to start.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerSpec {
+    pub language: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// File extensions (without the dot) routed to this server.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// `languageId` per extension when it differs from `language` (e.g. `tsx` → `typescriptreact`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub language_ids: BTreeMap<String, String>,
+    /// Sent as `initializationOptions`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init_options: Option<JsonValue>,
+}
+
+impl ServerSpec {
+    fn new(language: &str, command: &str, args: &[&str], extensions: &[&str]) -> Self {
+        Self {
+            language: language.to_string(),
+            command: command.to_string(),
+            args: args.iter().map(|s| s.to_string()).collect(),
+            extensions: extensions.iter().map(|s| s.to_string()).collect(),
+            language_ids: BTreeMap::new(),
+            init_options: None,
+        }
+    }
+
+    fn language_id(&self, path: &Path) -> String {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        self.language_ids
+            .get(ext)
+            .cloned()
+            .unwrap_or_else(|| self.language.clone())
+    }
+}
+
+fn builtin_specs() -> Vec<ServerSpec> {
+    let mut ts = ServerSpec::new(
+        "typescript",
+        "typescript-language-server",
+        &["--stdio"],
+        &["ts", "tsx", "js", "jsx", "mjs", "cjs", "mts", "cts"],
+    );
+    for (ext, id) in [
+        ("tsx", "typescriptreact"),
+        ("js", "javascript"),
+        ("jsx", "javascriptreact"),
+        ("mjs", "javascript"),
+        ("cjs", "javascript"),
+    ] {
+        ts.language_ids.insert(ext.to_string(), id.to_string());
+    }
+    vec![
+        ServerSpec::new("rust", "rust-analyzer", &[], &["rs"]),
+        ts,
+        ServerSpec::new("python", "pyright-langserver", &["--stdio"], &["py", "pyi"]),
+        ServerSpec::new("go", "gopls", &[], &["go"]),
+    ]
+}
+
+fn config_path() -> Option<PathBuf> {
+    if let Ok(v) = std::env::var("RELAY_LSP_CONFIG") {
+        let v = v.trim().to_string();
+        if !v.is_empty() {
+            return Some(PathBuf::from(v));
+        }
+    }
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".relay").join("lsp.json"))
+}
+
+/// Built-ins plus `{ "servers": [...] }` from the config file; entries are merged onto the
+/// built-in with the same `language` field by field. Invalid entries are skipped with a warning.
+pub fn load_specs() -> Vec<ServerSpec> {
+    let mut specs = builtin_specs();
+    let Some(raw) = config_path().and_then(|p| std::fs::read_to_string(p).ok()) else {
+        return specs;
+    };
+    let items = match serde_json::from_str::<JsonValue>(&raw) {
+        Ok(JsonValue::Object(mut o)) => match o.remove("servers") {
+            Some(JsonValue::Array(items)) => items,
+            _ => Vec::new(),
+        },
+        Ok(JsonValue::Array(items)) => items,
+        _ => {
+            tracing::warn!("invalid lsp config");
+            return specs;
+        }
+    };
+    for item in items {
+        let language = item.get("language").and_then(|v| v.as_str()).unwrap_or("");
+        let idx = specs.iter().position(|s| s.language == language);
+        let mut merged = match idx {
+            Some(i) => serde_json::to_value(&specs[i]).unwrap_or_default(),
+            None => json!({}),
+        };
+        if let (Some(dst), Some(src)) = (merged.as_object_mut(), item.as_object()) {
+            for (k, v) in src {
+                dst.insert(k.clone(), v.clone());
+            }
+        }
+        match serde_json::from_value::<ServerSpec>(merged) {
+            Ok(spec) if !spec.language.is_empty() && !spec.command.is_empty() => match idx {
+                Some(i) => specs[i] = spec,
+                None => specs.push(spec),
+            },
+            _ => tracing::warn!(language, "invalid lsp server entry"),
+        }
+    }
+    specs
+}
+
+fn idle_timeout() -> Duration {
+    let ms = std::env::var("RELAY_LSP_IDLE_MS")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(600_000);
+    Duration::from_millis(ms.max(1_000))
+}
+
+fn path_to_uri(path: &Path) -> String {
+    url::Url::from_file_path(path)
+        .map(|u| u.to_string())
+        .unwrap_or_else(|_| format!("file://{}", path.display()))
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    url::Url::parse(uri).ok()?.to_file_path().ok()
+}
+
+/// Running language servers, keyed by (canonical run cwd, language).
+pub struct LspPool {
+    specs: Vec<ServerSpec>,
+    clients: Mutex<HashMap<(PathBuf, String), Arc<Client>>>,
+}
+
+impl LspPool {
+    /// A pool whose idle servers are shut down by a background thread.
+    pub fn start(specs: Vec<ServerSpec>) -> Arc<Self> {
+        let pool = Arc::new(Self {
+            specs,
+            clients: Mutex::new(HashMap::new()),
+        });
+        let weak: Weak<Self> = Arc::downgrade(&pool);
+        std::thread::spawn(move || {
+            let idle = idle_timeout();
+            loop {
+                std::thread::sleep(Duration::from_secs(5).min(idle));
+                let Some(pool) = weak.upgrade() else { return };
+                pool.reap(idle);
+            }
+        });
+        pool
+    }
+
+    fn reap(&self, idle: Duration) {
+        let stale: Vec<Arc<Client>> = match self.clients.lock() {
+            Ok(mut clients) => {
+                let keys: Vec<_> = clients
+                    .iter()
+                    .filter(|(_, c)| !c.is_alive() || c.idle_for() >= idle)
+                    .map(|(k, _)| k.clone())
+                    .collect();
+                keys.iter().filter_map(|k| clients.remove(k)).collect()
+            }
+            Err(_) => Vec::new(),
+        };
+        for c in stale {
+            c.shutdown();
+        }
+    }
+
+    fn spec_for(
+        &self,
+        path: Option<&Path>,
+        language: Option<&str>,
+    ) -> Result<&ServerSpec, LspError> {
+        if let Some(language) = language {
+            return self.specs.iter().find(|s| s.language == language).ok_or((
+                StatusCode::BAD_REQUEST,
+                format!("unknown language: {language}"),
+            ));
+        }
+        let ext = path
+            .and_then(|p| p.extension())
+            .and_then(|e| e.to_str())
+            .ok_or((StatusCode::BAD_REQUEST, "missing path or language".into()))?;
+        self.specs
+            .iter()
+            .find(|s| s.extensions.iter().any(|e| e == ext))
+            .ok_or((
+                StatusCode::NOT_IMPLEMENTED,
+                format!("no language server configured for .{ext} files"),
+            ))
+    }
+
+    fn client(
+        &self,
+        root: &Path,
+        spec: &ServerSpec,
+        sandbox: Option<&crate::sandbox::SandboxProfile>,
+    ) -> Result<Arc<Client>, LspError> {
+        let key = (root.to_path_buf(), spec.language.clone());
+        let mut clients = self.clients.lock().map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "lsp pool poisoned".to_string(),
+            )
+        })?;
+        if let Some(c) = clients.get(&key) {
+            if c.is_alive() {
+                c.touch();
+                return Ok(c.clone());
+            }
+            clients.remove(&key);
+        }
+        // Held across the start so concurrent first requests do not spawn two servers.
+        let client = Client::start(root, spec, sandbox)?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    /// Handles one `rpc.code.*` request for a run rooted at `run_cwd`. Blocking.
+    pub fn handle(
+        &self,
+        run_cwd: &str,
+        rpc_type: &str,
+        data: &JsonValue,
+        sandbox: Option<&crate::sandbox::SandboxProfile>,
+    ) -> Result<JsonValue, LspError> {
+        let root = std::fs::canonicalize(run_cwd)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("bad run cwd: {e}")))?;
+        let rel = data
+            .get("path")
+            .and_then(|v| v.as_str())
+            .filter(|p| !p.trim().is_empty());
+        let language = data
+            .get("language")
+            .and_then(|v| v.as_str())
+            .filter(|l| !l.trim().is_empty());
+
+        if rpc_type == "rpc.code.diagnostics" && rel.is_none() {
+            return Ok(self.cached_diagnostics(&root, language));
+        }
+        let file = match rel {
+            Some(rel) => {
+                let abs = crate::fs_git::safe_join_run_path(run_cwd, rel)?;
+                if !abs.is_file() {
+                    return Err((StatusCode::BAD_REQUEST, format!("not a file: {rel}")));
+                }
+                Some(abs)
+            }
+            None => None,
+        };
+        let spec = self.spec_for(file.as_deref(), language)?;
+        let client = self.client(&root, spec, sandbox)?;
+
+        match rpc_type {
+            "rpc.code.definition" | "rpc.code.references" => {
+                let file = file.ok_or((StatusCode::BAD_REQUEST, "missing path".into()))?;
+                let line = data.get("line").and_then(|v| v.as_u64()).unwrap_or(0);
+                let column = data.get("column").and_then(|v| v.as_u64()).unwrap_or(1);
+                if line == 0 {
+                    return Err((StatusCode::BAD_REQUEST, "missing line (1-based)".into()));
+                }
+                let text = client.sync(&file)?;
+                let position = client.position(&text, line, column)?;
+                let uri = path_to_uri(&file);
+                let (method, params) = if rpc_type == "rpc.code.definition" {
+                    (
+                        "textDocument/definition",
+                        json!({ "textDocument": { "uri": uri }, "position": position }),
+                    )
+                } else {
+                    let include = data
+                        .get("include_declaration")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(true);
+                    (
+                        "textDocument/references",
+                        json!({
+                            "textDocument": { "uri": uri },
+                            "position": position,
+                            "context": { "includeDeclaration": include }
+                        }),
+                    )
+                };
+                let result = client.request(method, params, REQUEST_TIMEOUT)?;
+                let (locations, truncated) = client.locations(&result);
+                Ok(json!({ "locations": locations, "truncated": truncated }))
+            }
+            "rpc.code.symbols" => match file {
+                Some(file) => {
+                    client.sync(&file)?;
+                    let result = client.request(
+                        "textDocument/documentSymbol",
+                        json!({ "textDocument": { "uri": path_to_uri(&file) } }),
+                        REQUEST_TIMEOUT,
+                    )?;
+                    let (symbols, truncated) = client.symbols(&result, Some(&file));
+                    Ok(json!({ "symbols": symbols, "truncated": truncated }))
+                }
+                None => {
+                    let query = data.get("query").and_then(|v| v.as_str()).unwrap_or("");
+                    let result = client.request(
+                        "workspace/symbol",
+                        json!({ "query": query }),
+                        REQUEST_TIMEOUT,
+                    )?;
+                    let (symbols, truncated) = client.symbols(&result, None);
+                    Ok(json!({ "symbols": symbols, "truncated": truncated }))
+                }
+            },
+            "rpc.code.diagnostics" => {
+                let file = file.ok_or((StatusCode::BAD_REQUEST, "missing path".into()))?;
+                let uri = path_to_uri(&file);
+                let before = client.diagnostics_seq(&uri);
+                let text = client.sync(&file)?;
+                let fresh = client.wait_diagnostics(&uri, before, DIAGNOSTICS_WAIT);
+                let diagnostics = client.diagnostics_for(&uri, Some(&text));
+                Ok(json!({
+                    "path": rel,
+                    "diagnostics": diagnostics,
+                    "fresh": fresh,
+                }))
+            }
+            _ => Err((StatusCode::NOT_IMPLEMENTED, "unknown rpc type".into())),
+        }
+    }
+
+    /// Everything the servers for `root` have published so far.
+    fn cached_diagnostics(&self, root: &Path, language: Option<&str>) -> JsonValue {
+        let clients: Vec<Arc<Client>> = match self.clients.lock() {
+            Ok(c) => c
+                .iter()
+                .filter(|((r, l), _)| r == root && language.is_none_or(|want| want == l))
+                .map(|(_, c)| c.clone())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        let mut files = Vec::new();
+        for c in clients {
+            c.touch();
+            let uris: Vec<String> = c
+                .diagnostics
+                .lock()
+                .map(|d| d.keys().cloned().collect())
+                .unwrap_or_default();
+            for uri in uris {
+                let Some(path) = uri_to_path(&uri).and_then(|p| c.relative(&p)) else {
+                    continue;
+                };
+                let diagnostics = c.diagnostics_for(&uri, None);
+                if diagnostics.as_array().is_some_and(|a| !a.is_empty()) {
+                    files.push(json!({ "path": path, "diagnostics": diagnostics }));
+                }
+            }
+        }
+        json!({ "files": files })
+    }
+}
+
+struct Diagnostics {
+    /// Bumped on every `publishDiagnostics` for the uri.
+    seq: u64,
+    items: JsonValue,
+}
+
+type Waiter = mpsc::Sender<Result<JsonValue, String>>;
+
+/// One running language server.
+struct Client {
+    root: PathBuf,
+    spec: ServerSpec,
+    stdin: Mutex<ChildStdin>,
+    child: Mutex<Child>,
+    next_id: AtomicI64,
+    waiters: Mutex<HashMap<i64, Waiter>>,
+    /// uri -> (version, text) of synced documents.
+    docs: Mutex<HashMap<String, (i64, String)>>,
+    diagnostics: Mutex<HashMap<String, Diagnostics>>,
+    diagnostics_cv: Condvar,
+    /// The server agreed to UTF-8 columns; otherwise LSP's default UTF-16 applies.
+    utf8: OnceLock<bool>,
+    last_used: Mutex<Instant>,
+    alive: AtomicBool,
+}
+
+impl Client {
+    fn start(
+        root: &Path,
+        spec: &ServerSpec,
+        sandbox: Option<&crate::sandbox::SandboxProfile>,
+    ) -> Result<Arc<Self>, LspError> {
+        let cwd = root.to_string_lossy().to_string();
+        let mut cmd = match sandbox {
+            Some(profile) => profile
+                .command(&cwd, &spec.command)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?,
+            None => std::process::Command::new(&spec.command),
+        };
+        crate::config::strip_secret_env(&mut cmd)
+            .args(&spec.args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        let mut child = cmd.spawn().map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => (
+                StatusCode::NOT_IMPLEMENTED,
+                format!(
+                    "language server `{}` not found (install it or configure RELAY_LSP_CONFIG)",
+                    spec.command
+                ),
+            ),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("spawn {}: {e}", spec.command),
+            ),
+        })?;
+        let stdin = child.stdin.take().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "language server stdin".to_string(),
+        ))?;
+        let stdout = child.stdout.take().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "language server stdout".to_string(),
+        ))?;
+
+        let client = Arc::new(Self {
+            root: root.to_path_buf(),
+            spec: spec.clone(),
+            stdin: Mutex::new(stdin),
+            child: Mutex::new(child),
+            next_id: AtomicI64::new(1),
+            waiters: Mutex::new(HashMap::new()),
+            docs: Mutex::new(HashMap::new()),
+            diagnostics: Mutex::new(HashMap::new()),
+            diagnostics_cv: Condvar::new(),
+            utf8: OnceLock::new(),
+            last_used: Mutex::new(Instant::now()),
+            alive: AtomicBool::new(true),
+        });
+        {
+            let c = client.clone();
+            std::thread::spawn(move || c.read_loop(BufReader::new(stdout)));
+        }
+
+        let root_uri = path_to_uri(root);
+        let name = root
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let init = client.request(
+            "initialize",
+            json!({
+                "processId": std::process::id(),
+                "clientInfo": { "name": "relay-hostd" },
+                "rootUri": root_uri,
+                "rootPath": cwd,
+                "workspaceFolders": [{ "uri": root_uri, "name": name }],
+                "initializationOptions": spec.init_options,
+                "capabilities": {
+                    "general": { "positionEncodings": ["utf-8", "utf-16"] },
+                    "textDocument": {
+                        "synchronization": { "didSave": false },
+                        "definition": { "linkSupport": true },
+                        "references": {},
+                        "documentSymbol": { "hierarchicalDocumentSymbolSupport": true },
+                        "publishDiagnostics": { "versionSupport": true }
+                    },
+                    "workspace": { "symbol": {}, "workspaceFolders": true, "configuration": true }
+                }
+            }),
+            INIT_TIMEOUT,
+        );
+        let init = match init {
+            Ok(v) => v,
+            Err(e) => {
+                client.shutdown();
+                return Err(e);
+            }
+        };
+        let encoding = init
+            .pointer("/capabilities/positionEncoding")
+            .and_then(|v| v.as_str());
+        let _ = client.utf8.set(encoding == Some("utf-8"));
+        client.notify("initialized", json!({}))?;
+        Ok(client)
+    }
+
+    fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    fn touch(&self) {
+        if let Ok(mut t) = self.last_used.lock() {
+            *t = Instant::now();
+        }
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_used
+            .lock()
+            .map(|t| t.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Polite `shutdown` / `exit`, then a kill for servers that ignore it.
+    fn shutdown(&self) {
+        if self.is_alive() {
+            let _ = self.request("shutdown", JsonValue::Null, Duration::from_secs(2));
+            let _ = self.notify("exit", JsonValue::Null);
+        }
+        if let Ok(mut child) = self.child.lock() {
+            let deadline = Instant::now() + Duration::from_secs(1);
+            while Instant::now() < deadline {
+                if let Ok(Some(_)) = child.try_wait() {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    fn send(&self, msg: &JsonValue) -> Result<(), LspError> {
+        let body = msg.to_string();
+        let mut w = self.stdin.lock().map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "lsp stdin poisoned".to_string(),
+            )
+        })?;
+        write!(w, "Content-Length: {}\r\n\r\n{body}", body.len())
+            .and_then(|_| w.flush())
+            .map_err(|e| (StatusCode::BAD_GATEWAY, format!("language server: {e}")))
+    }
+
+    fn notify(&self, method: &str, params: JsonValue) -> Result<(), LspError> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        params: JsonValue,
+        timeout: Duration,
+    ) -> Result<JsonValue, LspError> {
+        if !self.is_alive() {
+            return Err((StatusCode::BAD_GATEWAY, "language server exited".into()));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = mpsc::channel();
+        if let Ok(mut w) = self.waiters.lock() {
+            w.insert(id, tx);
+        }
+        let sent =
+            self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        let result = match sent {
+            Ok(()) => match rx.recv_timeout(timeout) {
+                Ok(Ok(v)) => Ok(v),
+                Ok(Err(msg)) => Err((StatusCode::BAD_GATEWAY, format!("{method}: {msg}"))),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    Err((StatusCode::GATEWAY_TIMEOUT, format!("{method} timed out")))
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    Err((StatusCode::BAD_GATEWAY, "language server exited".into()))
+                }
+            },
+            Err(e) => Err(e),
+        };
+        if let Ok(mut w) = self.waiters.lock() {
+            w.remove(&id);
+        }
+        result
+    }
+
+    fn read_loop(&self, mut r: impl BufRead) {
+        while let Some(msg) = read_message(&mut r) {
+            self.dispatch(msg);
+        }
+        self.alive.store(false, Ordering::SeqCst);
+        if let Ok(mut w) = self.waiters.lock() {
+            w.clear();
+        }
+        self.diagnostics_cv.notify_all();
+    }
+
+    fn dispatch(&self, msg: JsonValue) {
+        let method = msg.get("method").and_then(|v| v.as_str());
+        let id = msg.get("id");
+        match (method, id) {
+            // A response to one of our requests.
+            (None, Some(id)) => {
+                let Some(id) = id.as_i64() else { return };
+                let tx = self.waiters.lock().ok().and_then(|mut w| w.remove(&id));
+                if let Some(tx) = tx {
+                    let result = match msg.get("error") {
+                        Some(err) => Err(err
+                            .get("message")
+                            .and_then(|v| v.as_str())
+                            .unwrap_or("error")
+                            .to_string()),
+                        None => Ok(msg.get("result").cloned().unwrap_or(JsonValue::Null)),
+                    };
+                    let _ = tx.send(result);
+                }
+            }
+            // A request from the server; answer the ones servers block on.
+            (Some(method), Some(id)) => {
+                let result = match method {
+                    "workspace/configuration" => {
+                        let n = msg
+                            .pointer("/params/items")
+                            .and_then(|v| v.as_array())
+                            .map(|a| a.len())
+                            .unwrap_or(0);
+                        Some(json!(vec![JsonValue::Null; n]))
+                    }
+                    "workspace/workspaceFolders" => {
+                        Some(json!([{ "uri": path_to_uri(&self.root), "name": "root" }]))
+                    }
+                    "window/workDoneProgress/create"
+                    | "client/registerCapability"
+                    | "client/unregisterCapability"
+                    | "window/showMessageRequest" => Some(JsonValue::Null),
+                    _ => None,
+                };
+                let reply = match result {
+                    Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    None => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32601, "message": format!("unsupported: {method}") }
+                    }),
+                };
+                let _ = self.send(&reply);
+            }
+            (Some("textDocument/publishDiagnostics"), None) => {
+                let Some(uri) = msg.pointer("/params/uri").and_then(|v| v.as_str()) else {
+                    return;
+                };
+                let items = msg
+                    .pointer("/params/diagnostics")
+                    .cloned()
+                    .unwrap_or_else(|| json!([]));
+                if let Ok(mut d) = self.diagnostics.lock() {
+                    let entry = d.entry(uri.to_string()).or_insert(Diagnostics {
+                        seq: 0,
+                        items: json!([]),
+                    });
+                    entry.seq += 1;
+                    entry.items = items;
+                }
+                self.diagnostics_cv.notify_all();
+            }
+            _ => {}
+        }
+    }
+
+    /// Makes the server see the file's current contents; returns them.
+    fn sync(&self, file: &Path) -> Result<String, LspError> {
+        let len = std::fs::metadata(file)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+            .len();
+        if len > MAX_FILE_BYTES {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "file too large".into()));
+        }
+        let bytes = std::fs::read(file).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+        let text = String::from_utf8(bytes)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "file is not UTF-8".to_string()))?;
+        let uri = path_to_uri(file);
+        let mut docs = self.docs.lock().map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "lsp docs poisoned".to_string(),
+            )
+        })?;
+        match docs.get_mut(&uri) {
+            Some((_, old)) if *old == text => {}
+            Some((version, old)) => {
+                *version += 1;
+                *old = text.clone();
+                self.notify(
+                    "textDocument/didChange",
+                    json!({
+                        "textDocument": { "uri": uri, "version": *version },
+                        "contentChanges": [{ "text": text }]
+                    }),
+                )?;
+            }
+            None => {
+                self.notify(
+                    "textDocument/didOpen",
+                    json!({
+                        "textDocument": {
+                            "uri": uri,
+                            "languageId": self.spec.language_id(file),
+                            "version": 1,
+                            "text": text
+                        }
+                    }),
+                )?;
+                docs.insert(uri, (1, text.clone()));
+            }
+        }
+        Ok(text)
+    }
+
+    fn utf8(&self) -> bool {
+        self.utf8.get().copied().unwrap_or(false)
+    }
+
+    /// 1-based line and UTF-8 byte column → LSP `Position`.
+    fn position(&self, text: &str, line: u64, column: u64) -> Result<JsonValue, LspError> {
+        let line_text = text.split('\n').nth((line - 1) as usize).ok_or((
+            StatusCode::BAD_REQUEST,
+            format!("line {line} is past the end of the file"),
+        ))?;
+        let mut byte = (column.max(1) - 1) as usize;
+        byte = byte.min(line_text.len());
+        while !line_text.is_char_boundary(byte) {
+            byte -= 1;
+        }
+        let character = if self.utf8() {
+            byte
+        } else {
+            line_text[..byte].encode_utf16().count()
+        };
+        Ok(json!({ "line": line - 1, "character": character }))
+    }
+
+    /// LSP `Position` on `line_text` → 1-based UTF-8 byte column.
+    fn column(&self, line_text: Option<&str>, character: u64) -> u64 {
+        let Some(line_text) = line_text else {
+            return character + 1;
+        };
+        if self.utf8() {
+            return character.min(line_text.len() as u64) + 1;
+        }
+        let mut units = 0u64;
+        for (i, ch) in line_text.char_indices() {
+            if units >= character {
+                return i as u64 + 1;
+            }
+            units += ch.len_utf16() as u64;
+        }
+        line_text.len() as u64 + 1
+    }
+
+    /// Path relative to the run cwd, or None when `path` is outside it.
+    fn relative(&self, path: &Path) -> Option<String> {
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        path.strip_prefix(&self.root)
+            .ok()
+            .map(|p| p.to_string_lossy().to_string())
+    }
+
+    /// `{ path, line, column, end_line, end_column, text }` for an LSP range in `uri`; `lines`
+    /// caches file contents across one result.
+    fn place(
+        &self,
+        uri: &str,
+        range: &JsonValue,
+        lines: &mut HashMap<PathBuf, Vec<String>>,
+    ) -> JsonValue {
+        let pos = |key: &str, field: &str| {
+            range
+                .pointer(&format!("/{key}/{field}"))
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0)
+        };
+        let (sl, sc, el, ec) = (
+            pos("start", "line"),
+            pos("start", "character"),
+            pos("end", "line"),
+            pos("end", "character"),
+        );
+        let path = uri_to_path(uri);
+        let rel = path.as_deref().and_then(|p| self.relative(p));
+        let (Some(path), Some(rel)) = (path, rel) else {
+            let file_name = uri.rsplit('/').next().unwrap_or("");
+            return json!({
+                "external": true,
+                "file_name": file_name,
+                "line": sl + 1,
+                "end_line": el + 1,
+            });
+        };
+        let file_lines = lines.entry(path.clone()).or_insert_with(|| {
+            std::fs::read(&path)
+                .ok()
+                .filter(|b| b.len() as u64 <= MAX_FILE_BYTES)
+                .map(|b| {
+                    String::from_utf8_lossy(&b)
+                        .split('\n')
+                        .map(|l| l.trim_end_matches('\r').to_string())
+                        .collect()
+                })
+                .unwrap_or_default()
+        });
+        let line_at = |l: u64| file_lines.get(l as usize).map(String::as_str);
+        json!({
+            "path": rel,
+            "line": sl + 1,
+            "column": self.column(line_at(sl), sc),
+            "end_line": el + 1,
+            "end_column": self.column(line_at(el), ec),
+            "text": line_at(sl),
+        })
+    }
+
+    /// `Location | Location[] | LocationLink[] | null` → places.
+    fn locations(&self, result: &JsonValue) -> (Vec<JsonValue>, bool) {
+        let items = match result {
+            JsonValue::Array(a) => a.clone(),
+            JsonValue::Null => Vec::new(),
+            v => vec![v.clone()],
+        };
+        let truncated = items.len() > MAX_LOCATIONS;
+        let mut lines = HashMap::new();
+        let out = items
+            .iter()
+            .take(MAX_LOCATIONS)
+            .filter_map(|loc| {
+                let (uri, range) = match loc.get("targetUri") {
+                    Some(uri) => (
+                        uri,
+                        loc.get("targetSelectionRange").or(loc.get("targetRange"))?,
+                    ),
+                    None => (loc.get("uri")?, loc.get("range")?),
+                };
+                Some(self.place(uri.as_str()?, range, &mut lines))
+            })
+            .collect();
+        (out, truncated)
+    }
+
+    /// `DocumentSymbol[]` (flattened, with `container`) or `SymbolInformation[]` → symbols.
+    fn symbols(&self, result: &JsonValue, file: Option<&Path>) -> (Vec<JsonValue>, bool) {
+        fn walk(
+            client: &Client,
+            items: &[JsonValue],
+            container: Option<&str>,
+            uri: &str,
+            lines: &mut HashMap<PathBuf, Vec<String>>,
+            out: &mut Vec<JsonValue>,
+        ) {
+            for item in items {
+                if out.len() > MAX_SYMBOLS {
+                    return;
+                }
+                let name = item.get("name").and_then(|v| v.as_str()).unwrap_or("");
+                let kind = symbol_kind(item.get("kind").and_then(|v| v.as_u64()).unwrap_or(0));
+                // SymbolInformation carries its own location and container name.
+                let (uri, range, container) = match item.get("location") {
+                    Some(loc) => (
+                        loc.get("uri").and_then(|v| v.as_str()).unwrap_or(uri),
+                        loc.get("range").cloned().unwrap_or_default(),
+                        item.get("containerName")
+                            .and_then(|v| v.as_str())
+                            .or(container),
+                    ),
+                    None => (
+                        uri,
+                        item.get("selectionRange")
+                            .or(item.get("range"))
+                            .cloned()
+                            .unwrap_or_default(),
+                        container,
+                    ),
+                };
+                let mut sym = client.place(uri, &range, lines);
+                sym["name"] = json!(name);
+                sym["kind"] = json!(kind);
+                if let Some(detail) = item.get("detail").and_then(|v| v.as_str()) {
+                    sym["detail"] = json!(detail);
+                }
+                if let Some(container) = container {
+                    sym["container"] = json!(container);
+                }
+                out.push(sym);
+                if let Some(children) = item.get("children").and_then(|v| v.as_array()) {
+                    walk(client, children, Some(name), uri, lines, out);
+                }
+            }
+        }
+        let items = result.as_array().cloned().unwrap_or_default();
+        let uri = file.map(path_to_uri).unwrap_or_default();
+        let mut lines = HashMap::new();
+        let mut out = Vec::new();
+        walk(self, &items, None, &uri, &mut lines, &mut out);
+        let truncated = out.len() > MAX_SYMBOLS;
+        out.truncate(MAX_SYMBOLS);
+        (out, truncated)
+    }
+
+    fn diagnostics_seq(&self, uri: &str) -> u64 {
+        self.diagnostics
+            .lock()
+            .ok()
+            .and_then(|d| d.get(uri).map(|e| e.seq))
+            .unwrap_or(0)
+    }
+
+    /// Waits until the server publishes for `uri` again; false on timeout.
+    fn wait_diagnostics(&self, uri: &str, after: u64, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let Ok(mut d) = self.diagnostics.lock() else {
+            return false;
+        };
+        loop {
+            if d.get(uri).is_some_and(|e| e.seq > after) {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline || !self.is_alive() {
+                return false;
+            }
+            d = match self.diagnostics_cv.wait_timeout(d, deadline - now) {
+                Ok((d, _)) => d,
+                Err(_) => return false,
+            };
+        }
+    }
+
+    fn diagnostics_for(&self, uri: &str, text: Option<&str>) -> JsonValue {
+        let items = self
+            .diagnostics
+            .lock()
+            .ok()
+            .and_then(|d| d.get(uri).map(|e| e.items.clone()))
+            .unwrap_or_else(|| json!([]));
+        let owned;
+        let text = match text {
+            Some(t) => Some(t),
+            None => {
+                owned = uri_to_path(uri).and_then(|p| std::fs::read_to_string(p).ok());
+                owned.as_deref()
+            }
+        };
+        let file_lines: Vec<&str> = text
+            .map(|t| t.split('\n').map(|l| l.trim_end_matches('\r')).collect())
+            .unwrap_or_default();
+        let line_at = |l: u64| file_lines.get(l as usize).copied();
+        let list = items
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .map(|d| {
+                        let pos = |p: &str| d.pointer(p).and_then(|v| v.as_u64()).unwrap_or(0);
+                        let (sl, sc) = (pos("/range/start/line"), pos("/range/start/character"));
+                        let (el, ec) = (pos("/range/end/line"), pos("/range/end/character"));
+                        let severity = match d.get("severity").and_then(|v| v.as_u64()) {
+                            Some(1) => "error",
+                            Some(2) => "warning",
+                            Some(3) => "information",
+                            Some(4) => "hint",
+                            _ => "error",
+                        };
+                        json!({
+                            "severity": severity,
+                            "message": d.get("message").and_then(|v| v.as_str()).unwrap_or(""),
+                            "source": d.get("source"),
+                            "code": d.get("code"),
+                            "line": sl + 1,
+                            "column": self.column(line_at(sl), sc),
+                            "end_line": el + 1,
+                            "end_column": self.column(line_at(el), ec),
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        json!(list)
+    }
+}
+
+/// Reads one `Content-Length` framed message; None at EOF. Lines before a header block that are
+/// not headers are skipped.
+fn read_message(r: &mut impl BufRead) -> Option<JsonValue> {
+    loop {
+        let mut len = None;
+        loop {
+            let mut line = String::new();
+            if r.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                if len.is_some() {
+                    break;
+                }
+                continue;
+            }
+            if let Some((k, v)) = line.split_once(':')
+                && k.eq_ignore_ascii_case("content-length")
+            {
+                len = v.trim().parse::<usize>().ok();
+            }
+        }
+        let mut body = vec![0u8; len?];
+        r.read_exact(&mut body).ok()?;
+        if let Ok(v) = serde_json::from_slice(&body) {
+            return Some(v);
+        }
+    }
+}
+
+fn symbol_kind(kind: u64) -> &'static str {
+    const KINDS: [&str; 26] = [
+        "file",
+        "module",
+        "namespace",
+        "package",
+        "class",
+        "method",
+        "property",
+        "field",
+        "constructor",
+        "enum",
+        "interface",
+        "function",
+        "variable",
+        "constant",
+        "string",
+        "number",
+        "boolean",
+        "array",
+        "object",
+        "key",
+        "null",
+        "enum_member",
+        "struct",
+        "event",
+        "operator",
+        "type_parameter",
+    ];
+    kind.checked_sub(1)
+        .and_then(|i| KINDS.get(i as usize))
+        .copied()
+        .unwrap_or("unknown")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers initialize, definition, references and documentSymbol with canned results and
+    /// publishes one diagnostic per didOpen / didChange. UTF-16 positions (the LSP default).
+    /// Other requests get no answer. Every start appends a line to `<script>.starts`.
+    const FAKE_SERVER: &str = r#"
+echo up >> "$0.starts"
+reply() { printf 'Content-Length: %s\r\n\r\n%s' "${#1}" "$1"; }
+while IFS= read -r line; do
+  case "$line" in
+    Content-Length:*) len=$(printf '%s' "${line#Content-Length: }" | tr -d '\r') ;;
+    $'\r'|'')
+      body=$(dd bs=1 count="$len" 2>/dev/null)
+      id=$(printf '%s' "$body" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+      uri=$(printf '%s' "$body" | sed -n 's/.*"uri":"\([^"]*\)".*/\1/p')
+      case "$body" in
+        *'"method":"initialize"'*) reply "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"capabilities\":{}}}" ;;
+        *'"method":"textDocument/definition"'*) reply "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":[{\"targetUri\":\"$uri\",\"targetRange\":{\"start\":{\"line\":0,\"character\":0},\"end\":{\"line\":0,\"character\":9}},\"targetSelectionRange\":{\"start\":{\"line\":0,\"character\":3},\"end\":{\"line\":0,\"character\":6}}}]}" ;;
+        *'"method":"textDocument/references"'*) reply "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":[{\"uri\":\"$uri\",\"range\":{\"start\":{\"line\":1,\"character\":4},\"end\":{\"line\":1,\"character\":7}}},{\"uri\":\"file:///usr/lib/x.rs\",\"range\":{\"start\":{\"line\":9,\"character\":0},\"end\":{\"line\":9,\"character\":1}}}]}" ;;
+        *'"method":"textDocument/documentSymbol"'*) reply "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":[{\"name\":\"foo\",\"kind\":12,\"range\":{\"start\":{\"line\":0,\"character\":0},\"end\":{\"line\":2,\"character\":1}},\"selectionRange\":{\"start\":{\"line\":0,\"character\":3},\"end\":{\"line\":0,\"character\":6}},\"children\":[{\"name\":\"x\",\"kind\":13,\"range\":{\"start\":{\"line\":1,\"character\":4},\"end\":{\"line\":1,\"character\":5}},\"selectionRange\":{\"start\":{\"line\":1,\"character\":4},\"end\":{\"line\":1,\"character\":5}}}]}]}" ;;
+        *'"method":"textDocument/didOpen"'*|*'"method":"textDocument/didChange"'*) reply "{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{\"uri\":\"$uri\",\"diagnostics\":[{\"severity\":2,\"message\":\"unused\",\"range\":{\"start\":{\"line\":1,\"character\":4},\"end\":{\"line\":1,\"character\":5}}}]}}" ;;
+        *'"method":"shutdown"'*) reply "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":null}" ;;
+        *'"method":"exit"'*) exit 0 ;;
+      esac ;;
+  esac
+done
+"#;
+
+    /// Writes the fake server to a fresh run dir, with `cases` matched before the built-in ones.
+    fn fake_server_dir(cases: &str) -> (PathBuf, ServerSpec) {
+        let dir = std::env::temp_dir().join(format!("relay-lsp-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let script = dir.join("fake-lsp.sh");
+        let text = FAKE_SERVER.replace(
+            "      case \"$body\" in\n",
+            &format!("      case \"$body\" in\n{cases}"),
+        );
+        std::fs::write(&script, text).unwrap();
+        std::fs::write(dir.join("src/a.fake"), "fn foo() {\n}\n").unwrap();
+        let spec = ServerSpec::new("fake", "bash", &[script.to_str().unwrap()], &["fake"]);
+        (dir, spec)
+    }
+
+    fn starts(dir: &Path) -> usize {
+        std::fs::read_to_string(dir.join("fake-lsp.sh.starts"))
+            .map(|s| s.lines().count())
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn crashed_server_fails_the_request_and_is_restarted() {
+        let (dir, spec) =
+            fake_server_dir("        *'\"method\":\"textDocument/definition\"'*) exit 1 ;;\n");
+        let cwd = dir.to_str().unwrap();
+        let pool = LspPool::start(vec![spec]);
+        let definition = || {
+            pool.handle(
+                cwd,
+                "rpc.code.definition",
+                &json!({ "path": "src/a.fake", "line": 1, "column": 4 }),
+                None,
+            )
+        };
+
+        let err = definition().unwrap_err();
+        assert_eq!(
+            err,
+            (StatusCode::BAD_GATEWAY, "language server exited".into())
+        );
+        assert_eq!(starts(&dir), 1);
+        // The dead client is replaced on the next request instead of failing forever.
+        let syms = pool
+            .handle(
+                cwd,
+                "rpc.code.symbols",
+                &json!({ "path": "src/a.fake" }),
+                None,
+            )
+            .unwrap();
+        assert_eq!(syms["symbols"][0]["name"], "foo");
+        assert_eq!(starts(&dir), 2);
+
+        pool.reap(Duration::ZERO);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unanswered_request_times_out_and_the_server_stays_usable() {
+        let (dir, spec) = fake_server_dir("");
+        let root = std::fs::canonicalize(&dir).unwrap();
+        let pool = LspPool::start(vec![spec.clone()]);
+        let client = pool.client(&root, &spec, None).unwrap();
+
+        let started = Instant::now();
+        let err = client
+            .request("textDocument/hover", json!({}), Duration::from_millis(200))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            (
+                StatusCode::GATEWAY_TIMEOUT,
+                "textDocument/hover timed out".into()
+            )
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(client.waiters.lock().unwrap().is_empty());
+
+        // A late or missing answer does not poison the connection.
+        let syms = pool
+            .handle(
+                dir.to_str().unwrap(),
+                "rpc.code.symbols",
+                &json!({ "path": "src/a.fake" }),
+                None,
+            )
+            .unwrap();
+        assert_eq!(syms["symbols"][0]["name"], "foo");
+        assert_eq!(starts(&dir), 1);
+
+        pool.reap(Duration::ZERO);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn missing_server_binary_is_not_implemented() {
+        let (dir, _) = fake_server_dir("");
+        let spec = ServerSpec::new("fake", "relay-no-such-lsp", &[], &["fake"]);
+        let pool = LspPool::start(vec![spec]);
+        let err = pool
+            .handle(
+                dir.to_str().unwrap(),
+                "rpc.code.symbols",
+                &json!({ "path": "src/a.fake" }),
+                None,
+            )
+            .unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_IMPLEMENTED);
+        assert!(err.1.contains("`relay-no-such-lsp` not found"), "{}", err.1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn bridges_requests_to_a_language_server() {
+        let dir = std::env::temp_dir().join(format!("relay-lsp-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let script = dir.join("fake-lsp.sh");
+        std::fs::write(&script, FAKE_SERVER).unwrap();
+        // Non-ASCII before the cursor: UTF-16 and UTF-8 columns differ.
+        std::fs::write(dir.join("src/a.fake"), "fn foo() {\n    x = 1; // é\n}\n").unwrap();
+        let cwd = dir.to_str().unwrap();
+
+        let mut spec = ServerSpec::new("fake", "bash", &[script.to_str().unwrap()], &["fake"]);
+        spec.language_ids.insert("fake".into(), "fakelang".into());
+        let pool = LspPool::start(vec![spec]);
+
+        let def = pool
+            .handle(
+                cwd,
+                "rpc.code.definition",
+                &json!({ "path": "src/a.fake", "line": 2, "column": 5 }),
+                None,
+            )
+            .unwrap();
+        let loc = &def["locations"][0];
+        assert_eq!(
+            (
+                loc["path"].as_str(),
+                loc["line"].as_u64(),
+                loc["column"].as_u64()
+            ),
+            (Some("src/a.fake"), Some(1), Some(4))
+        );
+        assert_eq!(loc["text"], "fn foo() {");
+
+        let refs = pool
+            .handle(
+                cwd,
+                "rpc.code.references",
+                &json!({ "path": "src/a.fake", "line": 1, "column": 4 }),
+                None,
+            )
+            .unwrap();
+        let locs = refs["locations"].as_array().unwrap();
+        assert_eq!(locs[0]["end_column"], 8);
+        assert_eq!(locs[1]["external"], true);
+        assert!(locs[1].get("path").is_none());
+
+        let syms = pool
+            .handle(
+                cwd,
+                "rpc.code.symbols",
+                &json!({ "path": "src/a.fake" }),
+                None,
+            )
+            .unwrap();
+        let names: Vec<_> = syms["symbols"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["name"].as_str().unwrap(), s["kind"].as_str().unwrap()))
+            .collect();
+        assert_eq!(names, [("foo", "function"), ("x", "variable")]);
+        assert_eq!(syms["symbols"][1]["container"], "foo");
+
+        std::fs::write(dir.join("src/a.fake"), "fn foo() {\n    é = 1;\n}\n").unwrap();
+        let diag = pool
+            .handle(
+                cwd,
+                "rpc.code.diagnostics",
+                &json!({ "path": "src/a.fake" }),
+                None,
+            )
+            .unwrap();
+        assert_eq!(diag["fresh"], true);
+        let d = &diag["diagnostics"][0];
+        assert_eq!(
+            (
+                d["severity"].as_str(),
+                d["line"].as_u64(),
+                d["end_column"].as_u64()
+            ),
+            (Some("warning"), Some(2), Some(7))
+        );
+        let all = pool
+            .handle(cwd, "rpc.code.diagnostics", &json!({}), None)
+            .unwrap();
+        assert_eq!(all["files"][0]["path"], "src/a.fake");
+
+        assert!(
+            pool.handle(
+                cwd,
+                "rpc.code.definition",
+                &json!({ "path": "../x.fake", "line": 1 }),
+                None
+            )
+            .is_err()
+        );
+        let err = pool
+            .handle(
+                cwd,
+                "rpc.code.symbols",
+                &json!({ "path": "fake-lsp.sh" }),
+                None,
+            )
+            .unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_IMPLEMENTED);
+
+        pool.reap(Duration::ZERO);
+        assert!(pool.clients.lock().unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
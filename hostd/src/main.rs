@@ -1,18 +1,31 @@
+mod acp;
+mod budget;
+mod checkpoints;
 mod config;
+mod file_changes;
 mod fs_git;
+mod fs_watch;
+mod git_ops;
 mod local_api;
+mod lsp;
+mod opencode_serve;
+mod patch;
 mod run_manager;
 mod runners;
+mod sandbox;
+mod search;
 mod spool;
 mod tool_mode_cache;
+mod tree_watch;
+mod usage;
 
 use futures_util::{SinkExt, StreamExt};
 use relay_protocol::{PermissionApproveData, PermissionDecision, WsEnvelope};
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, path::Path, sync::Arc};
 use tokio::sync::{Mutex, broadcast, mpsc, oneshot};
 
 use crate::config::Config;
-use crate::run_manager::RunManager;
+use crate::run_manager::{RunManager, StartRunOptions};
 use crate::spool::Spool;
 use serde_json::json;
 
@@ -32,7 +45,19 @@ fn truncate_chars(s: &str, max_chars: usize) -> String {
 }
 
 fn rpc_requires_permission(rpc_type: &str) -> bool {
-    matches!(rpc_type, "rpc.fs.write" | "rpc.bash")
+    matches!(
+        rpc_type,
+        "rpc.fs.write"
+            | "rpc.fs.apply_patch"
+            | "rpc.bash"
+            | "rpc.run.checkpoints.restore"
+            | "rpc.fs.download"
+            | "rpc.fs.upload"
+            | "rpc.git.stage"
+            | "rpc.git.unstage"
+            | "rpc.git.commit"
+            | "rpc.git.checkout"
+    )
 }
 
 #[tokio::main]
@@ -47,6 +72,7 @@ async fn main() -> anyhow::Result<()> {
     let _ = rustls::crypto::ring::default_provider().install_default();
 
     let (cfg, cfg_path) = Config::from_env_and_file()?;
+    config::scrub_secret_env();
     if let Some(p) = cfg_path.as_ref() {
         tracing::info!(config_path=%p.display(), "loaded hostd config");
     }
@@ -67,18 +93,23 @@ async fn main() -> anyhow::Result<()> {
     })
     .await??;
 
-    // Periodic spool pruning (3 days).
+    // Periodic spool pruning: 3 days, and `spool_replay_hours` for events the server acked.
     {
         let spool = spool.clone();
+        let replay_hours = i64::from(cfg.spool_replay_hours);
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
             loop {
                 interval.tick().await;
-                let cutoff = (chrono::Utc::now() - chrono::Duration::days(3)).to_rfc3339();
+                let now = chrono::Utc::now();
+                let cutoff = (now - chrono::Duration::days(3)).to_rfc3339();
+                let acked_cutoff = (now - chrono::Duration::hours(replay_hours)).to_rfc3339();
                 let spool = spool.clone();
-                let _ =
-                    tokio::task::spawn_blocking(move || spool.prune_older_than_rfc3339(&cutoff))
-                        .await;
+                let _ = tokio::task::spawn_blocking(move || {
+                    spool.prune_older_than_rfc3339(&cutoff)?;
+                    spool.prune_acked_older_than_rfc3339(&acked_cutoff)
+                })
+                .await;
             }
         });
     }
@@ -115,19 +146,95 @@ async fn main() -> anyhow::Result<()> {
         });
     }
 
+    // Enforce token/cost budgets (usage is counted where it is emitted); forget exited runs.
+    tokio::spawn(rm.clone().enforce_budgets());
+    {
+        let rm = rm.clone();
+        let mut rx = events_tx.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(env) => rm.on_budget_event(&env),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+
+    // Attribute file changes to the tool calls that made them.
+    {
+        let rm = rm.clone();
+        let mut rx = events_tx.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(env) => rm.on_file_tracking_event(&env),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+
     let pending_tool_permissions: Arc<Mutex<HashMap<String, oneshot::Sender<bool>>>> =
         Arc::new(Mutex::new(HashMap::new()));
 
+    // Drop answered permission requests from the local API's list (requests are recorded where
+    // they are emitted, so a lagging subscriber cannot lose one; answers it missed are found by
+    // checking what is still pending).
+    {
+        let rm = rm.clone();
+        let pending_tool_permissions = pending_tool_permissions.clone();
+        let mut rx = events_tx.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(env) => rm.on_permission_event(&env),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "permission log lagged; resyncing");
+                        let parked: std::collections::HashSet<String> = pending_tool_permissions
+                            .lock()
+                            .await
+                            .keys()
+                            .cloned()
+                            .collect();
+                        rm.resync_permission_log(&parked).await;
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+
     // Local unix API server.
+    let user_token = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
     let local = Arc::new(local_api::LocalState {
         rm: rm.clone(),
         pending_tool_permissions: pending_tool_permissions.clone(),
+        allowed_uids: cfg.local_allowed_uids.clone(),
+        tcp_token: cfg.local_tcp_token.clone(),
+        user_token: Some(user_token.clone()),
+        spool: spool.clone(),
     });
+    if cfg.local_tcp_listen.is_some() {
+        let app = local_api::router(local.clone());
+        let cfg = cfg.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve_tcp(&cfg, app).await {
+                tracing::error!(error=%format!("{err:#}"), "local tcp api stopped");
+            }
+        });
+    }
     let local_app = local_api::router(local);
     let sock_path = cfg.local_unix_socket.clone();
+    let sock_mode = cfg.local_socket_mode;
     tokio::spawn(async move {
-        let _ = std::fs::remove_file(&sock_path);
-        if let Err(err) = serve_unix(sock_path, local_app).await {
+        if let Err(err) = serve_unix(sock_path, sock_mode, user_token, local_app).await {
             tracing::error!(error=%err, "local unix api stopped");
         }
     });
@@ -159,7 +266,12 @@ async fn main() -> anyhow::Result<()> {
     }
 }
 
-async fn serve_unix(sock_path: String, app: axum::Router) -> anyhow::Result<()> {
+async fn serve_unix(
+    sock_path: String,
+    mode: u32,
+    user_token: String,
+    app: axum::Router,
+) -> anyhow::Result<()> {
     use hyper::server::conn::http1;
     use hyper_util::{rt::TokioIo, service::TowerToHyperService};
 
@@ -169,11 +281,19 @@ async fn serve_unix(sock_path: String, app: axum::Router) -> anyhow::Result<()>
     if let Some(parent) = sock_path.parent() {
         std::fs::create_dir_all(parent)?;
     }
-    let _ = std::fs::remove_file(&sock_path);
-    let listener = tokio::net::UnixListener::bind(&sock_path)?;
+    let listener = bind_unix_socket(&sock_path, mode)?;
+    write_user_token(&sock_path, &user_token)?;
     loop {
         let (stream, _) = listener.accept().await?;
-        let service = TowerToHyperService::new(app.clone());
+        // Requests are authorized against the connecting process, see `local_api::authorize`.
+        let Ok(cred) = stream.peer_cred() else {
+            continue;
+        };
+        let peer = local_api::Peer::Unix {
+            uid: cred.uid(),
+            pid: cred.pid(),
+        };
+        let service = TowerToHyperService::new(app.clone().layer(axum::Extension(peer)));
         tokio::spawn(async move {
             let io = TokioIo::new(stream);
             let _ = http1::Builder::new().serve_connection(io, service).await;
@@ -181,6 +301,152 @@ async fn serve_unix(sock_path: String, app: axum::Router) -> anyhow::Result<()>
     }
 }
 
+/// Binds `sock_path` without ever exposing it with umask permissions: the socket is created in a
+/// private (0700) directory next to it, chmod'ed to `mode` there and renamed into place, replacing
+/// a stale socket from an earlier run.
+fn bind_unix_socket(sock_path: &Path, mode: u32) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let name = sock_path
+        .file_name()
+        .ok_or_else(|| std::io::Error::other("socket path has no file name"))?;
+    let staging = sock_path.with_file_name(format!(
+        ".{}.{}",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("sock");
+    let bound = tokio::net::UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(&staged, sock_path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_dir_all(&staging);
+    bound
+}
+
+/// Writes `user_token` to `<sock_path>.token` (0600, created that way and renamed into place) for
+/// clients to send as `local_api::USER_TOKEN_HEADER`.
+fn write_user_token(sock_path: &Path, user_token: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut path = sock_path.as_os_str().to_owned();
+    path.push(".token");
+    let path = std::path::PathBuf::from(path);
+    let mut staged = path.as_os_str().to_owned();
+    staged.push(format!(".{}", std::process::id()));
+    let staged = std::path::PathBuf::from(staged);
+    let _ = std::fs::remove_file(&staged);
+    let written = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&staged)
+        .and_then(|mut f| f.write_all(user_token.as_bytes()))
+        .and_then(|()| std::fs::rename(&staged, &path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&staged);
+    }
+    written
+}
+
+/// Optional TCP listener for the local API (containers, WSL, remote dev boxes). Plain TCP only
+/// binds loopback; other addresses need mTLS. Every request carries `LOCAL_TCP_TOKEN` as a bearer
+/// token, see `local_api::authorize`.
+async fn serve_tcp(cfg: &Config, app: axum::Router) -> anyhow::Result<()> {
+    use anyhow::Context;
+    use hyper::server::conn::http1;
+    use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+
+    let listen = cfg.local_tcp_listen.as_deref().unwrap_or_default();
+    let addr: std::net::SocketAddr = listen
+        .parse()
+        .with_context(|| format!("bad LOCAL_TCP_LISTEN `{listen}` (expected ip:port)"))?;
+    anyhow::ensure!(
+        cfg.local_tcp_token
+            .as_deref()
+            .is_some_and(|t| t.len() >= 16),
+        "LOCAL_TCP_LISTEN requires LOCAL_TCP_TOKEN (at least 16 characters)"
+    );
+    let tls = match (cfg.local_tls_cert.as_deref(), cfg.local_tls_key.as_deref()) {
+        (Some(cert), Some(key)) => {
+            Some(tls_acceptor(cert, key, cfg.local_tls_client_ca.as_deref())?)
+        }
+        (None, None) => None,
+        _ => anyhow::bail!("LOCAL_TLS_CERT and LOCAL_TLS_KEY must be set together"),
+    };
+    let mtls = tls.is_some() && cfg.local_tls_client_ca.is_some();
+    anyhow::ensure!(
+        addr.ip().is_loopback() || mtls,
+        "LOCAL_TCP_LISTEN on a non-loopback address requires mTLS (LOCAL_TLS_CERT, LOCAL_TLS_KEY, LOCAL_TLS_CLIENT_CA)"
+    );
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("bind {addr}"))?;
+    tracing::info!(%addr, tls = tls.is_some(), mtls, "local tcp api listening");
+    loop {
+        let (stream, remote) = listener.accept().await?;
+        let peer = local_api::Peer::Tcp { addr: remote };
+        let service = TowerToHyperService::new(app.clone().layer(axum::Extension(peer)));
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            match tls {
+                Some(tls) => {
+                    let Ok(stream) = tls.accept(stream).await else {
+                        return;
+                    };
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                }
+                None => {
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                }
+            }
+        });
+    }
+}
+
+fn tls_acceptor(
+    cert: &str,
+    key: &str,
+    client_ca: Option<&str>,
+) -> anyhow::Result<tokio_rustls::TlsAcceptor> {
+    use anyhow::Context;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|it| it.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("read LOCAL_TLS_CERT {cert}"))?;
+    let key =
+        PrivateKeyDer::from_pem_file(key).with_context(|| format!("read LOCAL_TLS_KEY {key}"))?;
+    let builder = rustls::ServerConfig::builder();
+    let builder = match client_ca {
+        Some(ca) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for c in CertificateDer::pem_file_iter(ca)
+                .with_context(|| format!("read LOCAL_TLS_CLIENT_CA {ca}"))?
+            {
+                roots.add(c.context("parse LOCAL_TLS_CLIENT_CA")?)?;
+            }
+            let verifier =
+                rustls::server::WebPkiClientVerifier::builder(Arc::new(roots)).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(certs, key)
+        .context("LOCAL_TLS_CERT/LOCAL_TLS_KEY")?;
+    Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
+}
+
 async fn connect_and_run(
     ws_url: url::Url,
     cfg: Config,
@@ -274,42 +540,26 @@ async fn connect_and_run(
                                 continue;
                             }
 
-                            let tool_status = |tool: &str| -> serde_json::Value {
-                                let (env_var, default_bin) = match tool {
-                                    "opencode" => ("RELAY_OPENCODE_BIN", "opencode"),
-                                    _ => ("", tool),
-                                };
-                                let resolved = if env_var.is_empty() {
-                                    default_bin.to_string()
-                                } else {
-                                    crate::runners::resolve_tool_bin(tool, env_var, default_bin)
-                                };
-                                let err = crate::runners::validate_bin_exists(&resolved, tool)
-                                    .err()
-                                    .map(|e| e.to_string());
-                                if tool == "opencode" {
+                            let tool_status = |entry: crate::runners::registry::RunnerEntry| -> serde_json::Value {
+                                let mut status = entry.status();
+                                if entry.tool == "opencode" {
                                     let (models, default_model, models_error, models_note) = match crate::run_manager::opencode_structured_model_choices() {
                                         Ok((models, default_model, models_note)) => {
                                             (models, default_model, None::<String>, models_note)
                                         }
                                         Err(e) => (Vec::new(), None, Some(e.to_string()), None::<String>),
                                     };
-                                    json!({
-                                        "tool": tool,
-                                        "bin": resolved,
-                                        "ok": err.is_none(),
-                                        "error": err,
-                                        "models": models,
-                                        "default_model": default_model,
-                                        "models_error": models_error,
-                                        "models_note": models_note,
-                                    })
-                                } else {
-                                    json!({ "tool": tool, "bin": resolved, "ok": err.is_none(), "error": err })
+                                    if let Some(obj) = status.as_object_mut() {
+                                        obj.insert("models".into(), json!(models));
+                                        obj.insert("default_model".into(), json!(default_model));
+                                        obj.insert("models_error".into(), json!(models_error));
+                                        obj.insert("models_note".into(), json!(models_note));
+                                    }
                                 }
+                                status
                             };
 
-                            let tools = ["opencode"]
+                            let tools = crate::runners::registry::registry()
                                 .into_iter()
                                 .map(tool_status)
                                 .collect::<Vec<_>>();
@@ -330,7 +580,6 @@ async fn connect_and_run(
                                 obj.insert(
                                     "deps".to_string(),
                                     json!([
-                                        { "name": "rg", "ok": crate::fs_git::has_cmd("rg") },
                                         { "name": "git", "ok": crate::fs_git::has_cmd("git") }
                                     ]),
                                 );
@@ -380,19 +629,9 @@ async fn connect_and_run(
                                 continue;
                             }
 
-                            let tools = ["opencode"]
-                                .into_iter()
-                                .map(|tool| {
-                                    let (env_var, default_bin) = match tool {
-                                        "opencode" => ("RELAY_OPENCODE_BIN", "opencode"),
-                                        _ => ("", tool),
-                                    };
-                                    let resolved = crate::runners::resolve_tool_bin(tool, env_var, default_bin);
-                                    let err = crate::runners::validate_bin_exists(&resolved, tool)
-                                        .err()
-                                        .map(|e| e.to_string());
-                                    json!({ "tool": tool, "bin": resolved, "ok": err.is_none(), "error": err })
-                                })
+                            let tools = crate::runners::registry::registry()
+                                .iter()
+                                .map(|entry| entry.status())
                                 .collect::<Vec<_>>();
 
                             let result = json!({
@@ -409,13 +648,38 @@ async fn connect_and_run(
                                     "rpc.run.start",
                                     "rpc.fs.read",
                                     "rpc.fs.search",
+                                    "rpc.fs.search.cancel",
                                     "rpc.fs.list",
+                                    "rpc.fs.watch",
+                                    "rpc.fs.unwatch",
                                     "rpc.fs.write",
+                                    "rpc.fs.apply_patch",
+                                    "rpc.fs.download",
+                                    "rpc.fs.upload",
                                     "rpc.git.status",
                                     "rpc.git.diff",
+                                    "rpc.git.log",
+                                    "rpc.git.show",
+                                    "rpc.git.blame",
+                                    "rpc.git.branches",
+                                    "rpc.git.stage",
+                                    "rpc.git.unstage",
+                                    "rpc.git.commit",
+                                    "rpc.git.checkout",
+                                    "rpc.code.definition",
+                                    "rpc.code.references",
+                                    "rpc.code.symbols",
+                                    "rpc.code.diagnostics",
                                     "rpc.bash",
                                     "rpc.run.stop",
+                                    "rpc.run.worktree.cleanup",
+                                    "rpc.run.changes",
+                                    "rpc.run.checkpoints.list",
+                                    "rpc.run.checkpoints.restore",
                                     "rpc.runs.list",
+                                    "rpc.opencode.sessions.list",
+                                    "rpc.opencode.session.fork",
+                                    "rpc.opencode.session.messages",
                                     "rpc.host.info",
                                     "rpc.host.doctor",
                                     "rpc.host.capabilities",
@@ -423,9 +687,12 @@ async fn connect_and_run(
                                 ],
                                 "tools": tools,
                                 "deps": [
-                                    { "name": "rg", "ok": crate::fs_git::has_cmd("rg") },
-                                    { "name": "git", "ok": crate::fs_git::has_cmd("git") }
-                                ]
+                                    { "name": "git", "ok": crate::fs_git::has_cmd("git") },
+                                    { "name": "bwrap", "ok": crate::sandbox::bwrap_available() }
+                                ],
+                                "sandbox_default": crate::sandbox::default_profile()
+                                    .map(|p| p.describe("<run cwd>"))
+                                    .unwrap_or(serde_json::Value::Null)
                             });
 
                             let resp = WsEnvelope::new(
@@ -574,7 +841,13 @@ async fn connect_and_run(
                                 continue;
                             }
 
-                            let decision = parsed.as_ref().and_then(|p| p.decision);
+                            let decision = parsed.as_ref().and_then(|p| p.decision).unwrap_or(
+                                if env.r#type == "run.permission.approve" {
+                                    PermissionDecision::Approve
+                                } else {
+                                    PermissionDecision::Deny
+                                },
+                            );
                             let allow_tools = parsed
                                 .as_ref()
                                 .and_then(|p| p.allow_tools.clone())
@@ -589,33 +862,16 @@ async fn connect_and_run(
                                         })
                                 })
                                 .unwrap_or_default();
-                            let approved = match decision {
-                                Some(PermissionDecision::Approve | PermissionDecision::ApproveForSession) => true,
-                                Some(PermissionDecision::Deny | PermissionDecision::Abort) => false,
-                                None => env.r#type == "run.permission.approve",
-                            };
-
-                            if matches!(decision, Some(PermissionDecision::ApproveForSession)) && !allow_tools.is_empty() {
-                                let _ = rm.add_session_allow_tools(run_id, &allow_tools).await;
-                            }
-                            let key = format!("{run_id}:{request_id}");
-                            let tx = {
-                                let mut map = pending_tool_permissions.lock().await;
-                                map.remove(&key)
-                            };
-                            if let Some(tx) = tx {
-                                let _ = tx.send(approved);
-                            } else {
-                                let decision_str = match decision {
-                                    Some(PermissionDecision::Approve | PermissionDecision::ApproveForSession) => "approve",
-                                    Some(PermissionDecision::Deny) => "deny",
-                                    Some(PermissionDecision::Abort) => "deny",
-                                    None => {
-                                        if approved { "approve" } else { "deny" }
-                                    }
-                                };
-                                let _ = rm.decide_permission(run_id, actor, request_id, decision_str).await;
-                            }
+                            let _ = local_api::resolve_permission(
+                                &rm,
+                                &pending_tool_permissions,
+                                run_id,
+                                actor,
+                                request_id,
+                                decision,
+                                &allow_tools,
+                            )
+                            .await;
                         } else if env.r#type == "run.stop" {
                             let Some(run_id) = env.run_id.as_deref() else { continue; };
                             let signal = env.data.get("signal").and_then(|v| v.as_str()).unwrap_or("term");
@@ -635,13 +891,11 @@ async fn connect_and_run(
                                 .unwrap_or_else(|| tool.clone());
 
                             let cwd = env.data.get("cwd").and_then(|v| v.as_str()).map(|s| s.to_string());
-                            let model = env
-                                .data
-                                .get("model")
-                                .and_then(|v| v.as_str())
-                                .map(|s| s.to_string())
-                                .filter(|s| !s.trim().is_empty());
-                            let run_id = match rm.start_run(tool, cmd, cwd, model).await {
+                            let started = match StartRunOptions::from_request_data(&env.data) {
+                                Ok(opts) => rm.start_run(tool, cmd, cwd, opts).await,
+                                Err(err) => Err(err),
+                            };
+                            let run_id = match started {
                                 Ok(id) => id,
                                 Err(err) => {
                                     let resp = WsEnvelope::new(
@@ -677,6 +931,128 @@ async fn connect_and_run(
                                     serde_json::to_string(&resp)?.into(),
                                 ))
                                 .await;
+                        } else if env.r#type.starts_with("rpc.opencode.") {
+                            // Host-scoped: may have to start an `opencode serve`, so answer off the read loop.
+                            let request_id = env.data.get("request_id").and_then(|v| v.as_str()).unwrap_or("").to_string();
+                            if request_id.is_empty() {
+                                continue;
+                            }
+                            let rm = rm.clone();
+                            let out_tx = out_tx.clone();
+                            tokio::spawn(async move {
+                                let data = match rm.opencode_session_rpc(&env.r#type, &env.data).await {
+                                    Ok(v) => json!({ "request_id": request_id, "ok": true, "rpc_type": env.r#type, "result": v }),
+                                    Err(err) => json!({ "request_id": request_id, "ok": false, "rpc_type": env.r#type, "error": err.to_string() }),
+                                };
+                                let resp = WsEnvelope::new("rpc.response", data);
+                                if let Ok(text) = serde_json::to_string(&resp) {
+                                    let _ = out_tx
+                                        .send(tokio_tungstenite::tungstenite::Message::Text(text.into()))
+                                        .await;
+                                }
+                            });
+                        } else if env.r#type == "rpc.run.changes" {
+                            // Handled before the generic path: it also answers after the run exited.
+                            let Some(run_id) = env.run_id.clone() else { continue; };
+                            let request_id = env.data.get("request_id").and_then(|v| v.as_str()).unwrap_or("").to_string();
+                            if request_id.is_empty() {
+                                continue;
+                            }
+                            let rm = rm.clone();
+                            let out_tx = out_tx.clone();
+                            tokio::spawn(async move {
+                                let data = match rm.run_changes(&run_id, &env.data).await {
+                                    Ok(v) => json!({ "request_id": request_id, "ok": true, "rpc_type": env.r#type, "result": v }),
+                                    Err(err) => json!({ "request_id": request_id, "ok": false, "rpc_type": env.r#type, "error": err.to_string() }),
+                                };
+                                let mut resp = WsEnvelope::new("rpc.response", data);
+                                resp.run_id = Some(run_id);
+                                if let Ok(text) = serde_json::to_string(&resp) {
+                                    let _ = out_tx
+                                        .send(tokio_tungstenite::tungstenite::Message::Text(text.into()))
+                                        .await;
+                                }
+                            });
+                        } else if env.r#type == "rpc.bash.cancel" {
+                            // Not permission-gated: it only stops a command that was already approved.
+                            let Some(run_id) = env.run_id.as_deref() else { continue; };
+                            let request_id = env.data.get("request_id").and_then(|v| v.as_str()).unwrap_or("");
+                            if request_id.is_empty() {
+                                continue;
+                            }
+                            let bash_request_id = env.data.get("bash_request_id").and_then(|v| v.as_str()).unwrap_or("");
+                            let data = if rm.cancel_bash(run_id, bash_request_id) {
+                                json!({ "request_id": request_id, "ok": true, "rpc_type": env.r#type, "result": { "bash_request_id": bash_request_id, "cancelled": true } })
+                            } else {
+                                json!({ "request_id": request_id, "ok": false, "rpc_type": env.r#type, "error": "no running rpc.bash with that bash_request_id" })
+                            };
+                            let mut resp = WsEnvelope::new("rpc.response", data);
+                            resp.run_id = Some(run_id.to_string());
+                            let _ = out_tx
+                                .send(tokio_tungstenite::tungstenite::Message::Text(
+                                    serde_json::to_string(&resp)?.into(),
+                                ))
+                                .await;
+                        } else if env.r#type == "rpc.fs.search.cancel" {
+                            let Some(run_id) = env.run_id.as_deref() else { continue; };
+                            let request_id = env.data.get("request_id").and_then(|v| v.as_str()).unwrap_or("");
+                            if request_id.is_empty() {
+                                continue;
+                            }
+                            let search_request_id = env.data.get("search_request_id").and_then(|v| v.as_str()).unwrap_or("");
+                            let data = if rm.cancel_search(run_id, search_request_id) {
+                                json!({ "request_id": request_id, "ok": true, "rpc_type": env.r#type, "result": { "search_request_id": search_request_id, "cancelled": true } })
+                            } else {
+                                json!({ "request_id": request_id, "ok": false, "rpc_type": env.r#type, "error": "no running rpc.fs.search with that search_request_id" })
+                            };
+                            let mut resp = WsEnvelope::new("rpc.response", data);
+                            resp.run_id = Some(run_id.to_string());
+                            let _ = out_tx
+                                .send(tokio_tungstenite::tungstenite::Message::Text(
+                                    serde_json::to_string(&resp)?.into(),
+                                ))
+                                .await;
+                        } else if env.r#type == "rpc.run.worktree.cleanup" {
+                            // Handled before the generic path: the run has usually exited already.
+                            let Some(run_id) = env.run_id.clone() else { continue; };
+                            let request_id = env.data.get("request_id").and_then(|v| v.as_str()).unwrap_or("").to_string();
+                            if request_id.is_empty() {
+                                continue;
+                            }
+                            let rm = rm.clone();
+                            let out_tx = out_tx.clone();
+                            let pending_tool_permissions = pending_tool_permissions.clone();
+                            tokio::spawn(async move {
+                                let action = env.data.get("action").and_then(|v| v.as_str()).unwrap_or("");
+                                let repo_cwd = env.data.get("cwd").and_then(|v| v.as_str()).map(|s| s.to_string());
+                                let actor = env.data.get("actor").and_then(|v| v.as_str()).unwrap_or("web");
+                                let result = match crate::fs_git::WorktreeCleanup::parse(action) {
+                                    Some(action) => {
+                                        let call = local_api::ToolCall {
+                                            run_id: &run_id,
+                                            request_id: &request_id,
+                                            actor,
+                                            tool: "rpc.run.worktree.cleanup",
+                                            started: std::time::Instant::now(),
+                                        };
+                                        local_api::cleanup_worktree_gated(&rm, &pending_tool_permissions, &call, action, repo_cwd)
+                                            .await
+                                            .map_err(|(_, e)| e)
+                                    }
+                                    None => Err(format!("unsupported action `{action}` (expected merge|discard|keep_branch)")),
+                                };
+                                let data = match result {
+                                    Ok(v) => json!({ "request_id": request_id, "ok": true, "rpc_type": env.r#type, "result": v }),
+                                    Err(err) => json!({ "request_id": request_id, "ok": false, "rpc_type": env.r#type, "error": err }),
+                                };
+                                let mut resp = WsEnvelope::new("rpc.response", data);
+                                resp.run_id = Some(run_id);
+                                if let Ok(text) = serde_json::to_string(&resp) {
+                                    let _ = out_tx
+                                        .send(tokio_tungstenite::tungstenite::Message::Text(text.into()))
+                                        .await;
+                                }
+                            });
                         } else if env.r#type.starts_with("rpc.") {
                             let Some(run_id) = env.run_id.as_deref() else { continue; };
                             let request_id = env.data.get("request_id").and_then(|v| v.as_str()).unwrap_or("");
@@ -716,6 +1092,18 @@ async fn connect_and_run(
                             let data = env.data.clone();
                             let started = std::time::Instant::now();
 
+                            // Validated before approval so the card shows the real diff and bad patches fail fast.
+                            let prepared_patch = if rpc_type_for_exec == "rpc.fs.apply_patch" {
+                                let (cwd, data) = (cwd.clone(), data.clone());
+                                Some(match tokio::task::spawn_blocking(move || crate::patch::prepare(&cwd, &data)).await {
+                                    Ok(Ok(p)) => Ok(p),
+                                    Ok(Err((_, msg))) => Err(msg),
+                                    Err(e) => Err(e.to_string()),
+                                })
+                            } else {
+                                None
+                            };
+
                             let args_for_event = match rpc_type_for_exec.as_str() {
                                 "rpc.fs.write" => {
                                     let path = data.get("path").and_then(|v| v.as_str()).unwrap_or("");
@@ -736,6 +1124,25 @@ async fn connect_and_run(
                                     let cmd = data.get("cmd").and_then(|v| v.as_str()).unwrap_or("");
                                     json!({ "cmd": rm.redact_string(cmd) })
                                 }
+                                "rpc.fs.apply_patch" => match &prepared_patch {
+                                    Some(Ok(p)) => rm.redact_json_value(&p.preview()),
+                                    _ => json!({}),
+                                },
+                                // Never copy file contents into the event log.
+                                "rpc.fs.upload" => {
+                                    let encoded = data.get("data").and_then(|v| v.as_str()).unwrap_or("");
+                                    json!({
+                                        "path": data.get("path"),
+                                        "offset": data.get("offset"),
+                                        "bytes": encoded.len() / 4 * 3,
+                                        "final": data.get("sha256").is_some()
+                                    })
+                                }
+                                "rpc.fs.download" => json!({
+                                    "path": data.get("path"),
+                                    "offset": data.get("offset"),
+                                    "length": data.get("length")
+                                }),
                                 _ => rm.redact_json_value(&data),
                             };
 
@@ -752,6 +1159,34 @@ async fn connect_and_run(
                                 )
                                 .await;
 
+                            if let Some(Err(msg)) = &prepared_patch {
+                                let _ = rm
+                                    .emit_run_event(
+                                        run_id,
+                                        "tool.result",
+                                        json!({
+                                            "request_id": request_id,
+                                            "tool": rpc_type.clone(),
+                                            "actor": actor,
+                                            "ok": false,
+                                            "duration_ms": started.elapsed().as_millis() as i64,
+                                            "error": msg
+                                        }),
+                                    )
+                                    .await;
+                                let mut resp = WsEnvelope::new(
+                                    "rpc.response",
+                                    json!({ "request_id": request_id, "ok": false, "rpc_type": rpc_type, "error": msg }),
+                                );
+                                resp.run_id = Some(run_id.to_string());
+                                let _ = out_tx
+                                    .send(tokio_tungstenite::tungstenite::Message::Text(
+                                        serde_json::to_string(&resp)?.into(),
+                                    ))
+                                    .await;
+                                continue;
+                            }
+
                             if rpc_requires_permission(rpc_type_for_exec.as_str()) {
                                 let (op_tool, op_args, op_args_summary) = match rpc_type_for_exec.as_str() {
                                     "rpc.fs.write" => {
@@ -767,13 +1202,38 @@ async fn connect_and_run(
                                         let summary = truncate_chars(&format!("cmd={cmd}"), 80);
                                         ("bash", json!({ "cmd": cmd }), summary)
                                     }
+                                    "rpc.fs.apply_patch" => {
+                                        let summary = match &prepared_patch {
+                                            Some(Ok(p)) => truncate_chars(&rm.redact_string(&p.summary()), 80),
+                                            _ => String::new(),
+                                        };
+                                        (rpc_type_for_exec.as_str(), args_for_event.clone(), summary)
+                                    }
+                                    "rpc.run.checkpoints.restore" => {
+                                        let id = data.get("checkpoint_id").and_then(|v| v.as_str()).unwrap_or("");
+                                        let summary = truncate_chars(&format!("checkpoint_id={id}"), 80);
+                                        (rpc_type_for_exec.as_str(), json!({ "checkpoint_id": id }), summary)
+                                    }
+                                    "rpc.fs.download" | "rpc.fs.upload" => {
+                                        let path = data.get("path").and_then(|v| v.as_str()).unwrap_or("");
+                                        let summary = truncate_chars(&format!("path={path}"), 80);
+                                        (rpc_type_for_exec.as_str(), args_for_event.clone(), summary)
+                                    }
+                                    "rpc.git.stage" | "rpc.git.unstage" | "rpc.git.commit" | "rpc.git.checkout" => {
+                                        let summary = crate::git_ops::summary(&rpc_type_for_exec, &data);
+                                        let summary = truncate_chars(&rm.redact_string(&summary), 80);
+                                        (rpc_type_for_exec.as_str(), args_for_event.clone(), summary)
+                                    }
                                     _ => {
                                         let summary = truncate_chars(&serde_json::to_string(&args_for_event).unwrap_or_default(), 80);
                                         (rpc_type_for_exec.as_str(), args_for_event.clone(), summary)
                                     }
                                 };
 
-                                let auto_approved = rm.is_tool_allowlisted(run_id, op_tool).await;
+                                // One approval covers every chunk of the same transfer.
+                                let is_transfer = matches!(rpc_type_for_exec.as_str(), "rpc.fs.download" | "rpc.fs.upload");
+                                let auto_approved = (is_transfer && rm.transfer_granted(run_id, &rpc_type_for_exec, &data).await)
+                                    || rm.is_tool_allowlisted(run_id, op_tool).await;
                                 let permission_rx = if auto_approved {
                                     None
                                 } else {
@@ -818,6 +1278,8 @@ async fn connect_and_run(
                                 let rpc_type_for_exec_task = rpc_type_for_exec.clone();
                                 let data_task = data.clone();
                                 let cwd_task = cwd.clone();
+                                let prepared_task = prepared_patch.and_then(Result::ok);
+                                let transfer_task = is_transfer.then(|| (rpc_type_for_exec.clone(), data.clone()));
 
                                 task_set.spawn(async move {
                                     let approved = match permission_rx {
@@ -825,44 +1287,132 @@ async fn connect_and_run(
                                         None => true,
                                     };
 
+                                    if approved && let Some((rpc, data)) = &transfer_task {
+                                        rm_task.grant_transfer(&run_id_task, rpc, data).await;
+                                    }
                                     let (ok, payload) = if !approved {
                                         (false, json!({ "error": "denied" }))
                                     } else {
                                         let exec_started = std::time::Instant::now();
-                                        let result = tokio::task::spawn_blocking(move || {
-                                            match rpc_type_for_exec_task.as_str() {
-                                                "rpc.fs.write" => {
-                                                    let path = data_task.get("path").and_then(|v| v.as_str()).unwrap_or("");
-                                                    let content = data_task.get("content").and_then(|v| v.as_str()).unwrap_or("");
-                                                    let (bytes_written, truncated) = crate::fs_git::write_utf8_file(&cwd_task, path, content, 1024 * 1024)?;
-                                                    Ok(json!({ "path": path, "bytes_written": bytes_written, "truncated": truncated }))
-                                                }
-                                                "rpc.bash" => {
-                                                    let cmd = data_task.get("cmd").and_then(|v| v.as_str()).unwrap_or("");
-                                                    let (stdout, stderr, exit_code, truncated) =
-                                                        crate::fs_git::bash_exec(&cwd_task, cmd, 200_000, 200_000)?;
-                                                    Ok(json!({ "stdout": stdout, "stderr": stderr, "exit_code": exit_code, "truncated": truncated }))
+                                        // Snapshot before anything mutates the tree; restore takes its own. Uploads
+                                        // only touch the target on their final chunk.
+                                        let finishes_upload = rpc_type_for_exec_task == "rpc.fs.upload" && data_task.get("sha256").is_some();
+                                        if matches!(rpc_type_for_exec_task.as_str(), "rpc.fs.write" | "rpc.fs.apply_patch" | "rpc.bash" | "rpc.git.checkout")
+                                            || finishes_upload
+                                        {
+                                            let _ = rm_task
+                                                .checkpoint_run(&run_id_task, &request_id_task, &rpc_type_for_exec_task)
+                                                .await;
+                                        }
+                                        if rpc_type_for_exec_task == "rpc.bash" {
+                                            let cmd = data_task.get("cmd").and_then(|v| v.as_str()).unwrap_or("").to_string();
+                                            let timeout_ms = data_task.get("timeout_ms").and_then(|v| v.as_u64());
+                                            let duration_ms = |t: std::time::Instant| t.elapsed().as_millis() as i64;
+                                            match rm_task.exec_bash(&run_id_task, &request_id_task, cwd_task.clone(), cmd, timeout_ms).await {
+                                                Ok((outcome, timeout)) => {
+                                                    let result = json!({
+                                                        "stdout": outcome.stdout,
+                                                        "stderr": outcome.stderr,
+                                                        "exit_code": outcome.exit_code,
+                                                        "signal": outcome.signal,
+                                                        "timed_out": outcome.timed_out,
+                                                        "cancelled": outcome.cancelled,
+                                                        "timeout_ms": timeout.as_millis() as u64,
+                                                        "truncated": outcome.truncated
+                                                    });
+                                                    match outcome.error() {
+                                                        None => (true, json!({ "result": result, "duration_ms": duration_ms(exec_started) })),
+                                                        Some(err) => (
+                                                            false,
+                                                            json!({ "result": result, "error": err, "duration_ms": duration_ms(exec_started) }),
+                                                        ),
+                                                    }
                                                 }
-                                                _ => Err((axum::http::StatusCode::NOT_IMPLEMENTED, "unknown rpc type".into())),
+                                                Err((_, msg)) => (false, json!({ "error": msg, "duration_ms": duration_ms(exec_started) })),
                                             }
-                                        })
-                                        .await
-                                        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+                                        } else {
+                                            let run_id_exec = run_id_task.clone();
+                                            let request_id_exec = request_id_task.clone();
+                                            let result = tokio::task::spawn_blocking(move || {
+                                                match rpc_type_for_exec_task.as_str() {
+                                                    "rpc.fs.write" => {
+                                                        let path = data_task.get("path").and_then(|v| v.as_str()).unwrap_or("");
+                                                        let content = data_task.get("content").and_then(|v| v.as_str()).unwrap_or("");
+                                                        let (bytes_written, truncated) = crate::fs_git::write_utf8_file(&cwd_task, path, content, 1024 * 1024)?;
+                                                        Ok(json!({ "path": path, "bytes_written": bytes_written, "truncated": truncated }))
+                                                    }
+                                                    "rpc.fs.apply_patch" => match prepared_task {
+                                                        Some(p) => p.apply(),
+                                                        None => Err((axum::http::StatusCode::BAD_REQUEST, "patch was not validated".into())),
+                                                    },
+                                                    "rpc.run.checkpoints.restore" => {
+                                                        let checkpoint_id =
+                                                            data_task.get("checkpoint_id").and_then(|v| v.as_str()).unwrap_or("");
+                                                        crate::checkpoints::restore(&cwd_task, &run_id_exec, checkpoint_id, &request_id_exec)
+                                                    }
+                                                    "rpc.fs.download" => {
+                                                        use base64::Engine;
+                                                        let path = data_task.get("path").and_then(|v| v.as_str()).unwrap_or("");
+                                                        let offset = data_task.get("offset").and_then(|v| v.as_u64()).unwrap_or(0);
+                                                        let length = data_task
+                                                            .get("length")
+                                                            .and_then(|v| v.as_u64())
+                                                            .map(|n| n as usize)
+                                                            .unwrap_or(crate::fs_git::TRANSFER_DEFAULT_CHUNK)
+                                                            .clamp(1, crate::fs_git::TRANSFER_MAX_CHUNK);
+                                                        let chunk = crate::fs_git::read_file_chunk(&cwd_task, path, offset, length)?;
+                                                        Ok(json!({
+                                                            "path": path,
+                                                            "offset": offset,
+                                                            "size": chunk.size,
+                                                            "bytes": chunk.bytes.len(),
+                                                            "data": base64::engine::general_purpose::STANDARD.encode(&chunk.bytes),
+                                                            "eof": chunk.eof,
+                                                            "sha256": chunk.sha256
+                                                        }))
+                                                    }
+                                                    "rpc.fs.upload" => {
+                                                        use base64::Engine;
+                                                        let path = data_task.get("path").and_then(|v| v.as_str()).unwrap_or("");
+                                                        let offset = data_task.get("offset").and_then(|v| v.as_u64()).unwrap_or(0);
+                                                        let encoded = data_task.get("data").and_then(|v| v.as_str()).unwrap_or("");
+                                                        let bytes = base64::engine::general_purpose::STANDARD
+                                                            .decode(encoded)
+                                                            .map_err(|e| (axum::http::StatusCode::BAD_REQUEST, format!("invalid base64 data: {e}")))?;
+                                                        let sha256 = data_task.get("sha256").and_then(|v| v.as_str());
+                                                        let status = crate::fs_git::write_file_chunk(&cwd_task, path, offset, &bytes, sha256)?;
+                                                        Ok(json!({ "path": path, "received": status.received, "done": status.done }))
+                                                    }
+                                                    "rpc.git.stage" | "rpc.git.unstage" | "rpc.git.commit" | "rpc.git.checkout" => {
+                                                        crate::git_ops::write(&cwd_task, &rpc_type_for_exec_task, &data_task)
+                                                    }
+                                                    _ => Err((axum::http::StatusCode::NOT_IMPLEMENTED, "unknown rpc type".into())),
+                                                }
+                                            })
+                                            .await
+                                            .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
 
-                                        match result {
-                                            Ok(Ok(v)) => (true, json!({ "result": v, "duration_ms": exec_started.elapsed().as_millis() as i64 })),
-                                            Ok(Err((_, msg))) => (false, json!({ "error": msg, "duration_ms": exec_started.elapsed().as_millis() as i64 })),
-                                            Err((_, msg)) => (false, json!({ "error": msg, "duration_ms": exec_started.elapsed().as_millis() as i64 })),
+                                            match result {
+                                                Ok(Ok(v)) => (true, json!({ "result": v, "duration_ms": exec_started.elapsed().as_millis() as i64 })),
+                                                Ok(Err((_, msg))) => (false, json!({ "error": msg, "duration_ms": exec_started.elapsed().as_millis() as i64 })),
+                                                Err((_, msg)) => (false, json!({ "error": msg, "duration_ms": exec_started.elapsed().as_millis() as i64 })),
+                                            }
                                         }
                                     };
 
+                                    if approved && let Some((rpc, data)) = &transfer_task {
+                                        rm_task.finish_transfer_chunk(&run_id_task, rpc, data, ok, &payload).await;
+                                    }
                                     let duration_ms = payload.get("duration_ms").and_then(|v| v.as_i64()).unwrap_or(0);
                                     let result_value = payload.get("result").cloned().unwrap_or(serde_json::Value::Null);
                                     let error_value = payload.get("error").cloned().unwrap_or(serde_json::Value::Null);
                                     let request_id_for_event = request_id_task.clone();
                                     let rpc_type_for_event = rpc_type_task.clone();
                                     let actor_for_event = actor_task.clone();
-                                    let result_for_event = result_value.clone();
+                                    let mut result_for_event = result_value.clone();
+                                    if let Some(obj) = result_for_event.as_object_mut() {
+                                        obj.remove("data");
+                                    }
                                     let error_for_event = error_value.clone();
                                     let _ = rm_task
                                         .emit_run_event(
@@ -889,6 +1439,10 @@ async fn connect_and_run(
                                         resp_data["result"] = result_value;
                                     } else {
                                         resp_data["error"] = error_value;
+                                        // Failed commands still report exit code/signal and output.
+                                        if !result_value.is_null() {
+                                            resp_data["result"] = result_value;
+                                        }
                                     }
                                     let mut resp = WsEnvelope::new("rpc.response", resp_data);
                                     resp.run_id = Some(run_id_task);
@@ -902,110 +1456,134 @@ async fn connect_and_run(
                                 continue;
                             }
 
-                            let result = if rpc_type_for_exec == "rpc.run.stop" {
-                                let signal = data.get("signal").and_then(|v| v.as_str()).unwrap_or("term");
-                                match rm.stop_run(run_id, signal).await {
-                                    Ok(()) => Ok(Ok(json!({ "signal": signal }))),
-                                    Err(err) => Ok(Err((axum::http::StatusCode::BAD_REQUEST, err.to_string()))),
-                                }
-                            } else if rpc_type_for_exec == "rpc.runs.list" {
-                                let runs = rm.list_runs().await;
-                                Ok(Ok(json!({ "runs": runs })))
-                            } else {
-                                tokio::task::spawn_blocking(move || match rpc_type_for_exec.as_str() {
-                                    "rpc.fs.read" => {
-                                        let path = data.get("path").and_then(|v| v.as_str()).unwrap_or("");
-                                        let (content, truncated) =
-                                            crate::fs_git::read_utf8_file(&cwd, path, 1024 * 1024)?;
-                                        Ok(json!({ "path": path, "content": content, "truncated": truncated }))
+                            // Answered from a task so slow reads (search, blame) do not hold up this loop, e.g.
+                            // for an `rpc.fs.search.cancel` sent right after.
+                            let rm = rm.clone();
+                            let out_tx = out_tx.clone();
+                            let (run_id, request_id, actor) = (run_id.to_string(), request_id.to_string(), actor.to_string());
+                            tokio::spawn(async move {
+                                let (run_id, request_id, actor) = (run_id.as_str(), request_id.as_str(), actor.as_str());
+                                let result = if rpc_type_for_exec == "rpc.run.stop" {
+                                    let signal = data.get("signal").and_then(|v| v.as_str()).unwrap_or("term");
+                                    match rm.stop_run(run_id, signal).await {
+                                        Ok(()) => Ok(Ok(json!({ "signal": signal }))),
+                                        Err(err) => Ok(Err((axum::http::StatusCode::BAD_REQUEST, err.to_string()))),
                                     }
-                                    "rpc.fs.search" => {
-                                        let q = data.get("q").and_then(|v| v.as_str()).unwrap_or("");
-                                        let (rows, truncated) = crate::fs_git::rg_search(&cwd, q, 200)?;
-                                        let matches = rows
-                                            .into_iter()
-                                            .map(|(path, line, column, text)| {
-                                                json!({ "path": path, "line": line, "column": column, "text": text })
-                                            })
-                                            .collect::<Vec<_>>();
-                                        Ok(json!({ "matches": matches, "truncated": truncated }))
+                                } else if rpc_type_for_exec == "rpc.fs.search" {
+                                    match serde_json::from_value::<crate::search::SearchOptions>(data.clone()) {
+                                        Ok(opts) => Ok(rm
+                                            .search(run_id, request_id, cwd.clone(), opts)
+                                            .await
+                                            .map(|r| serde_json::to_value(r).unwrap_or_default())),
+                                        Err(err) => Ok(Err((axum::http::StatusCode::BAD_REQUEST, err.to_string()))),
                                     }
-                                    "rpc.fs.list" => {
-                                        let path = data.get("path").and_then(|v| v.as_str()).unwrap_or(".");
-                                        let (rows, truncated) = crate::fs_git::list_dir(&cwd, path, 500)?;
-                                        let entries = rows
-                                            .into_iter()
-                                            .map(|(name, is_dir, size_bytes)| {
-                                                json!({ "name": name, "is_dir": is_dir, "size_bytes": size_bytes })
-                                            })
-                                            .collect::<Vec<_>>();
-                                        Ok(json!({ "path": path, "entries": entries, "truncated": truncated }))
-                                    }
-                                    "rpc.git.status" => {
-                                        let (stdout, truncated) = crate::fs_git::git_status(&cwd, 200_000)?;
-                                        Ok(json!({ "stdout": stdout, "truncated": truncated }))
-                                    }
-                                    "rpc.git.diff" => {
-                                        let path = data.get("path").and_then(|v| v.as_str());
-                                        let (stdout, truncated) =
-                                            crate::fs_git::git_diff(&cwd, path, 400_000)?;
-                                        Ok(json!({ "stdout": stdout, "truncated": truncated }))
+                                } else if rpc_type_for_exec.starts_with("rpc.code.") {
+                                    Ok(rm.code_request(run_id, &rpc_type_for_exec, data.clone()).await)
+                                } else if rpc_type_for_exec == "rpc.fs.watch" {
+                                    Ok(rm.watch_fs(run_id, cwd.clone(), &data).await)
+                                } else if rpc_type_for_exec == "rpc.fs.unwatch" {
+                                    let watch_id = data.get("watch_id").and_then(|v| v.as_str()).unwrap_or("");
+                                    if rm.unwatch_fs(run_id, watch_id) {
+                                        Ok(Ok(json!({ "watch_id": watch_id, "stopped": true })))
+                                    } else {
+                                        Ok(Err((axum::http::StatusCode::NOT_FOUND, "no fs watch with that watch_id".to_string())))
                                     }
-                                    _ => Err((
-                                        axum::http::StatusCode::NOT_IMPLEMENTED,
-                                        "unknown rpc type".into(),
-                                    )),
-                                })
-                                .await
-                                .map_err(|e| {
-                                    (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
-                                })
-                            };
+                                } else if rpc_type_for_exec == "rpc.runs.list" {
+                                    let runs = rm.list_runs().await;
+                                    Ok(Ok(json!({ "runs": runs })))
+                                } else {
+                                    let run_id_exec = run_id.to_string();
+                                    tokio::task::spawn_blocking(move || match rpc_type_for_exec.as_str() {
+                                        "rpc.run.checkpoints.list" => {
+                                            let checkpoints = crate::checkpoints::list(&cwd, &run_id_exec)?;
+                                            Ok(json!({ "checkpoints": checkpoints }))
+                                        }
+                                        "rpc.fs.read" => {
+                                            let path = data.get("path").and_then(|v| v.as_str()).unwrap_or("");
+                                            let (content, truncated) =
+                                                crate::fs_git::read_utf8_file(&cwd, path, 1024 * 1024)?;
+                                            Ok(json!({ "path": path, "content": content, "truncated": truncated }))
+                                        }
+                                        "rpc.fs.list" => {
+                                            let path = data.get("path").and_then(|v| v.as_str()).unwrap_or(".");
+                                            let (rows, truncated) = crate::fs_git::list_dir(&cwd, path, 500)?;
+                                            let entries = rows
+                                                .into_iter()
+                                                .map(|(name, is_dir, size_bytes)| {
+                                                    json!({ "name": name, "is_dir": is_dir, "size_bytes": size_bytes })
+                                                })
+                                                .collect::<Vec<_>>();
+                                            Ok(json!({ "path": path, "entries": entries, "truncated": truncated }))
+                                        }
+                                        "rpc.git.status" => {
+                                            let (stdout, truncated) = crate::fs_git::git_status(&cwd, 200_000)?;
+                                            Ok(json!({ "stdout": stdout, "truncated": truncated }))
+                                        }
+                                        "rpc.git.diff" => {
+                                            let path = data.get("path").and_then(|v| v.as_str());
+                                            let (stdout, truncated) =
+                                                crate::fs_git::git_diff(&cwd, path, 400_000)?;
+                                            Ok(json!({ "stdout": stdout, "truncated": truncated }))
+                                        }
+                                        "rpc.git.log" | "rpc.git.show" | "rpc.git.blame" | "rpc.git.branches" => {
+                                            crate::git_ops::read(&cwd, &rpc_type_for_exec, &data)
+                                        }
+                                        _ => Err((
+                                            axum::http::StatusCode::NOT_IMPLEMENTED,
+                                            "unknown rpc type".into(),
+                                        )),
+                                    })
+                                    .await
+                                    .map_err(|e| {
+                                        (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                                    })
+                                };
 
-                            let (ok, payload) = match result {
-                                Ok(Ok(v)) => (true, json!({ "result": v })),
-                                Ok(Err((_, msg))) => (false, json!({ "error": msg })),
-                                Err((_, msg)) => (false, json!({ "error": msg })),
-                            };
-                            let result_value = payload.get("result").cloned().unwrap_or(serde_json::Value::Null);
-                            let error_value = payload.get("error").cloned().unwrap_or(serde_json::Value::Null);
-                            let duration_ms = started.elapsed().as_millis() as i64;
-                            let _ = rm
-                                .emit_run_event(
-                                    run_id,
-                                    "tool.result",
-                                    json!({
-                                        "request_id": request_id,
-                                        "tool": rpc_type,
-                                        "actor": actor,
-                                        "ok": ok,
-                                        "duration_ms": duration_ms,
-                                        "result": result_value,
-                                        "error": error_value
-                                    }),
-                                )
-                                .await;
+                                let (ok, payload) = match result {
+                                    Ok(Ok(v)) => (true, json!({ "result": v })),
+                                    Ok(Err((_, msg))) => (false, json!({ "error": msg })),
+                                    Err((_, msg)) => (false, json!({ "error": msg })),
+                                };
+                                let result_value = payload.get("result").cloned().unwrap_or(serde_json::Value::Null);
+                                let error_value = payload.get("error").cloned().unwrap_or(serde_json::Value::Null);
+                                let duration_ms = started.elapsed().as_millis() as i64;
+                                let _ = rm
+                                    .emit_run_event(
+                                        run_id,
+                                        "tool.result",
+                                        json!({
+                                            "request_id": request_id,
+                                            "tool": rpc_type,
+                                            "actor": actor,
+                                            "ok": ok,
+                                            "duration_ms": duration_ms,
+                                            "result": result_value,
+                                            "error": error_value
+                                        }),
+                                    )
+                                    .await;
 
-                            let mut resp_data = json!({
-                                "request_id": request_id,
-                                "ok": ok,
-                                "rpc_type": rpc_type,
-                            });
-                            if let Some(map) = resp_data.as_object_mut() {
-                                if let Some(obj) = payload.as_object() {
-                                    for (k, v) in obj {
-                                        map.insert(k.clone(), v.clone());
+                                let mut resp_data = json!({
+                                    "request_id": request_id,
+                                    "ok": ok,
+                                    "rpc_type": rpc_type,
+                                });
+                                if let Some(map) = resp_data.as_object_mut() {
+                                    if let Some(obj) = payload.as_object() {
+                                        for (k, v) in obj {
+                                            map.insert(k.clone(), v.clone());
+                                        }
                                     }
                                 }
-                            }
 
-                            let mut resp = WsEnvelope::new("rpc.response", resp_data);
-                            resp.run_id = Some(run_id.to_string());
-                            let _ = out_tx
-                                .send(tokio_tungstenite::tungstenite::Message::Text(
-                                    serde_json::to_string(&resp)?.into(),
-                                ))
-                                .await;
+                                let mut resp = WsEnvelope::new("rpc.response", resp_data);
+                                resp.run_id = Some(run_id.to_string());
+                                let _ = out_tx
+                                    .send(tokio_tungstenite::tungstenite::Message::Text(
+                                        serde_json::to_string(&resp).unwrap_or_default().into(),
+                                    ))
+                                    .await;
+                            });
                         }
                     }
                     tokio_tungstenite::tungstenite::Message::Ping(p) => {
@@ -1020,3 +1598,279 @@ async fn connect_and_run(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn unix_socket_is_bound_with_the_configured_mode() {
+        let dir = std::env::temp_dir().join(format!("relay-hostd-sock-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sock = dir.join("hostd.sock");
+        // A stale socket (or file) from an earlier run is replaced.
+        std::fs::write(&sock, b"stale").unwrap();
+
+        let _listener = bind_unix_socket(&sock, 0o600).unwrap();
+        let meta = std::fs::metadata(&sock).unwrap();
+        assert!(std::os::unix::fs::FileTypeExt::is_socket(&meta.file_type()));
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+        // The staging directory is gone.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        tokio::net::UnixStream::connect(&sock).await.unwrap();
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    const TCP_TOKEN: &str = "0123456789abcdef";
+
+    fn tcp_config(listen: &str) -> Config {
+        Config {
+            server_base_url: "http://127.0.0.1:1".into(),
+            host_id: "host-test".into(),
+            host_token: "t".into(),
+            local_unix_socket: "/tmp/relay-hostd-test.sock".into(),
+            local_socket_mode: 0o600,
+            local_allowed_uids: Vec::new(),
+            local_tcp_listen: Some(listen.into()),
+            local_tcp_token: Some(TCP_TOKEN.into()),
+            local_tls_cert: None,
+            local_tls_key: None,
+            local_tls_client_ca: None,
+            redaction_extra_regex: Vec::new(),
+            spool_db_path: String::new(),
+            spool_replay_hours: 6,
+            log_path: None,
+        }
+    }
+
+    /// Status code of a `GET path` with `headers` against the plain TCP listener at `addr`.
+    async fn tcp_status(addr: &str, path: &str, headers: &[(&str, &str)]) -> u16 {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut req = format!("GET {path} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n");
+        for (name, value) in headers {
+            req.push_str(&format!("{name}: {value}\r\n"));
+        }
+        req.push_str("\r\n");
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).await.unwrap();
+        resp.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    /// The local API on a plain TCP listener, with its spool in `dir`.
+    async fn serve_tcp_api(dir: &Path) -> (String, Arc<local_api::LocalState>) {
+        let (tx, _rx) = broadcast::channel(16);
+        let rm = RunManager::new(
+            "host-test".into(),
+            dir.join("hostd.sock").to_string_lossy().to_string(),
+            Arc::new(relay_protocol::redaction::Redactor::new(&[]).unwrap()),
+            tx,
+        );
+        let spool = Spool::new(dir.join("spool.db").to_string_lossy().into_owned());
+        spool.init().unwrap();
+        let local = Arc::new(local_api::LocalState {
+            rm,
+            pending_tool_permissions: Default::default(),
+            allowed_uids: Vec::new(),
+            tcp_token: Some(TCP_TOKEN.into()),
+            user_token: None,
+            spool,
+        });
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addr = format!("127.0.0.1:{port}");
+        let cfg = tcp_config(&addr);
+        let app = local_api::router(local.clone());
+        tokio::spawn(async move { serve_tcp(&cfg, app).await });
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(&addr).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        (addr, local)
+    }
+
+    #[tokio::test]
+    async fn tcp_listener_requires_the_bearer_token() {
+        let dir = std::env::temp_dir().join(format!("relay-hostd-tcp-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (addr, local) = serve_tcp_api(&dir).await;
+        let run_token = local.rm.run_token("r1");
+
+        let bearer = format!("Bearer {TCP_TOKEN}");
+        let bearer = ("authorization", bearer.as_str());
+        let wrong = ("authorization", "Bearer wrong-token-0000");
+        let run = ("x-relay-run-token", run_token.as_str());
+        assert_eq!(tcp_status(&addr, "/runs", &[]).await, 401);
+        assert_eq!(tcp_status(&addr, "/runs", &[wrong]).await, 401);
+        // A valid run token does not stand in for the listener's token.
+        assert_eq!(tcp_status(&addr, "/runs/r1/permissions", &[run]).await, 401);
+        assert_eq!(tcp_status(&addr, "/runs", &[bearer]).await, 200);
+        // With both, the request is scoped to the run.
+        assert_eq!(tcp_status(&addr, "/runs", &[bearer, run]).await, 403);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Status code of a `GET path` with `headers` over the unix socket at `sock`.
+    async fn unix_status(sock: &Path, path: &str, headers: &[(&str, &str)]) -> u16 {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::UnixStream::connect(sock).await.unwrap();
+        let mut req = format!("GET {path} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n");
+        for (name, value) in headers {
+            req.push_str(&format!("{name}: {value}\r\n"));
+        }
+        req.push_str("\r\n");
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).await.unwrap();
+        resp.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn unix_callers_outside_runs_need_the_user_token_while_runs_are_active() {
+        let dir = std::env::temp_dir().join(format!("relay-hostd-unix-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sock = dir.join("hostd.sock");
+        let (tx, _rx) = broadcast::channel(64);
+        let rm = RunManager::new(
+            "host-test".into(),
+            sock.to_string_lossy().to_string(),
+            Arc::new(relay_protocol::redaction::Redactor::new(&[]).unwrap()),
+            tx,
+        );
+        let spool = Spool::new(dir.join("spool.db").to_string_lossy().into_owned());
+        spool.init().unwrap();
+        let user_token = "user-token-0123456789";
+        let local = Arc::new(local_api::LocalState {
+            rm: rm.clone(),
+            pending_tool_permissions: Default::default(),
+            allowed_uids: Vec::new(),
+            tcp_token: None,
+            user_token: Some(user_token.into()),
+            spool,
+        });
+        let app = local_api::router(local);
+        tokio::spawn(serve_unix(
+            sock.to_string_lossy().into_owned(),
+            0o600,
+            user_token.into(),
+            app,
+        ));
+        let token_file = dir.join("hostd.sock.token");
+        for _ in 0..100 {
+            if token_file.exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(std::fs::read_to_string(&token_file).unwrap(), user_token);
+        let meta = std::fs::metadata(&token_file).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+
+        // No run to escape from: the peer uid is enough.
+        assert_eq!(unix_status(&sock, "/runs", &[]).await, 200);
+
+        let agent = dir.join("fake-aider");
+        std::fs::write(&agent, "#!/bin/sh\nexec sleep 30\n").unwrap();
+        std::fs::set_permissions(&agent, std::fs::Permissions::from_mode(0o755)).unwrap();
+        // Only this test uses the aider runner.
+        unsafe { std::env::set_var("RELAY_AIDER_BIN", &agent) };
+        let run_id = rm
+            .start_run(
+                "aider".into(),
+                String::new(),
+                Some(dir.to_string_lossy().to_string()),
+                Default::default(),
+            )
+            .await
+            .unwrap();
+        unsafe { std::env::remove_var("RELAY_AIDER_BIN") };
+        // This process is outside the run's tree, like one that double-forked out of it.
+        let user = ("x-relay-user-token", user_token);
+        let wrong = ("x-relay-user-token", "user-token-guess");
+        assert_eq!(unix_status(&sock, "/runs", &[]).await, 403);
+        assert_eq!(unix_status(&sock, "/runs", &[wrong]).await, 403);
+        assert_eq!(unix_status(&sock, "/runs", &[user]).await, 200);
+        let run_token = rm.run_token(&run_id);
+        let run = ("x-relay-run-token", run_token.as_str());
+        assert_eq!(unix_status(&sock, "/runs", &[user, run]).await, 403);
+
+        rm.stop_run(&run_id, "kill").await.unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn events_replay_pages_through_the_spool() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+        let dir = std::env::temp_dir().join(format!("relay-hostd-tcp-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (addr, local) = serve_tcp_api(&dir).await;
+        // More than one replay page, with a hole the spool no longer has.
+        let total = 2_500;
+        for seq in (1..=total).filter(|seq| !(1_200..1_300).contains(seq)) {
+            let mut env = WsEnvelope::new("run.output", json!({ "text": "x" }));
+            env.run_id = Some("r1".into());
+            env.seq = Some(seq);
+            local.spool.insert_event(&env).unwrap();
+        }
+
+        let mut stream = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        let req = format!(
+            "GET /events?run_id=r1&since_seq=0&format=ndjson HTTP/1.1\r\nhost: localhost\r\n\
+             authorization: Bearer {TCP_TOKEN}\r\nconnection: close\r\n\r\n"
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut lines = tokio::io::BufReader::new(stream).lines();
+        let mut seqs = Vec::new();
+        let mut gaps = Vec::new();
+        let read = async {
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let Ok(env) = serde_json::from_str::<WsEnvelope>(&line) else {
+                    continue;
+                };
+                match env.r#type.as_str() {
+                    "events.gap" => gaps.push(env.data),
+                    _ => seqs.push(env.seq.unwrap()),
+                }
+                if seqs.last() == Some(&total) {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(10), read)
+            .await
+            .unwrap();
+
+        assert_eq!(seqs.len(), (total - 100) as usize);
+        assert!(seqs.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(gaps, vec![json!({ "after_seq": 1_199, "next_seq": 1_300 })]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn tcp_listener_refuses_to_start_unsafely() {
+        let app = axum::Router::new();
+        let err = serve_tcp(&tcp_config("0.0.0.0:0"), app.clone())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("requires mTLS"), "{err:#}");
+
+        let mut cfg = tcp_config("127.0.0.1:0");
+        cfg.local_tcp_token = Some("short".into());
+        let err = serve_tcp(&cfg, app).await.unwrap_err();
+        assert!(err.to_string().contains("LOCAL_TCP_TOKEN"), "{err:#}");
+    }
+}
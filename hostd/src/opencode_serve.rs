@@ -0,0 +1,840 @@
+//! Client for a long-lived `opencode serve` process (HTTP + SSE on loopback).
+//!
+//! Structured opencode runs keep one server per run: prompts go to
+//! `POST /session/:id/message`, and the `GET /event` stream is mapped onto relay events by
+//! [`EventMapper`]. The process itself is spawned by `run_manager`. The session RPCs
+//! (`rpc.opencode.*`) use the same client to list, fork and read stored sessions.
+//!
+//! Every server gets its own password (`OPENCODE_SERVER_PASSWORD`, HTTP basic auth), so other
+//! local users cannot drive it, e.g. to answer its permission requests behind relay's back.
+
+use anyhow::Context;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::Request;
+use hyper_util::rt::TokioIo;
+use serde_json::{Value as JsonValue, json};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+fn serve_ready_timeout() -> Duration {
+    let ms = std::env::var("RELAY_OPENCODE_SERVE_TIMEOUT_MS")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(30_000);
+    Duration::from_millis(ms.clamp(1_000, 300_000))
+}
+
+/// Picks a free loopback port for `opencode serve --port`. Another process may take it before the
+/// server binds it; the caller retries when the server exits early.
+pub fn free_port() -> anyhow::Result<u16> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind probe port")?;
+    Ok(listener.local_addr().context("probe port addr")?.port())
+}
+
+/// Basic-auth user of `opencode serve` (`OPENCODE_SERVER_USERNAME`).
+pub const SERVER_USERNAME: &str = "opencode";
+
+/// A fresh password for one `opencode serve` (`OPENCODE_SERVER_PASSWORD`).
+pub fn new_password() -> String {
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// Handle to a running `opencode serve`.
+#[derive(Clone)]
+pub struct OpencodeServer {
+    addr: String,
+    /// `Authorization` header value sent with every request.
+    auth: String,
+    pub pid: i32,
+}
+
+impl OpencodeServer {
+    pub fn new(port: u16, pid: i32, password: &str) -> Self {
+        use base64::Engine;
+        let creds = format!("{SERVER_USERNAME}:{password}");
+        Self {
+            addr: format!("127.0.0.1:{port}"),
+            auth: format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(creds)
+            ),
+            pid,
+        }
+    }
+
+    /// Fails unless the server turns away requests without its password, i.e. unless this
+    /// opencode enforces `OPENCODE_SERVER_PASSWORD`.
+    pub async fn check_auth(&self) -> anyhow::Result<()> {
+        let resp = self.send_as(false, "GET", "/session", None).await?;
+        anyhow::ensure!(
+            resp.status() == hyper::StatusCode::UNAUTHORIZED,
+            "opencode serve on {} answers without its password ({}); upgrade opencode to a \
+             version that supports OPENCODE_SERVER_PASSWORD",
+            self.addr,
+            resp.status()
+        );
+        Ok(())
+    }
+
+    /// Polls the server until it answers, or fails when `exited` reports the process is gone.
+    pub async fn wait_ready(&self, mut exited: impl FnMut() -> bool) -> anyhow::Result<()> {
+        let deadline = Instant::now() + serve_ready_timeout();
+        loop {
+            if self.request("GET", "/session", None).await.is_ok() {
+                return Ok(());
+            }
+            if exited() {
+                anyhow::bail!("opencode serve exited before it was ready");
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("opencode serve did not become ready on {}", self.addr);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    async fn send(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&JsonValue>,
+    ) -> anyhow::Result<hyper::Response<hyper::body::Incoming>> {
+        self.send_as(true, method, path, body).await
+    }
+
+    async fn send_as(
+        &self,
+        authorized: bool,
+        method: &str,
+        path: &str,
+        body: Option<&JsonValue>,
+    ) -> anyhow::Result<hyper::Response<hyper::body::Incoming>> {
+        let stream = tokio::net::TcpStream::connect(&self.addr)
+            .await
+            .with_context(|| format!("connect opencode serve: {}", self.addr))?;
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .context("http1 handshake")?;
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+
+        let body = match body {
+            Some(v) => Full::new(Bytes::from(serde_json::to_vec(v).context("encode json")?)),
+            None => Full::new(Bytes::new()),
+        };
+        let mut req = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{path}", self.addr))
+            .header("host", &self.addr)
+            .header("content-type", "application/json");
+        if authorized {
+            req = req.header("authorization", &self.auth);
+        }
+        let req = req.body(body).context("build request")?;
+        sender.send_request(req).await.context("send request")
+    }
+
+    /// JSON request; non-2xx responses become errors carrying the response body.
+    pub async fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&JsonValue>,
+    ) -> anyhow::Result<JsonValue> {
+        let resp = self.send(method, path, body).await?;
+        let status = resp.status();
+        let bytes = resp
+            .into_body()
+            .collect()
+            .await
+            .context("read response body")?
+            .to_bytes();
+        if !status.is_success() {
+            anyhow::bail!(
+                "opencode {method} {path}: {status}: {}",
+                String::from_utf8_lossy(&bytes)
+            );
+        }
+        if bytes.iter().all(|b| b.is_ascii_whitespace()) {
+            return Ok(JsonValue::Null);
+        }
+        serde_json::from_slice(&bytes).context("parse opencode response")
+    }
+
+    pub async fn create_session(&self, body: &JsonValue) -> anyhow::Result<String> {
+        let session = self.request("POST", "/session", Some(body)).await?;
+        session
+            .get("id")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .context("opencode session response has no id")
+    }
+
+    pub async fn list_sessions(&self) -> anyhow::Result<Vec<JsonValue>> {
+        match self.request("GET", "/session", None).await? {
+            JsonValue::Array(items) => Ok(items),
+            _ => anyhow::bail!("opencode session list is not an array"),
+        }
+    }
+
+    pub async fn get_session(&self, session_id: &str) -> anyhow::Result<JsonValue> {
+        check_session_id(session_id)?;
+        self.request("GET", &format!("/session/{session_id}"), None)
+            .await
+    }
+
+    /// Forks a session (optionally only up to `message_id`) and returns the new session.
+    pub async fn fork_session(
+        &self,
+        session_id: &str,
+        message_id: Option<&str>,
+    ) -> anyhow::Result<JsonValue> {
+        check_session_id(session_id)?;
+        let body = match message_id {
+            Some(id) => json!({ "messageID": id }),
+            None => json!({}),
+        };
+        self.request("POST", &format!("/session/{session_id}/fork"), Some(&body))
+            .await
+    }
+
+    /// `GET /session/:id/message`: every message with its parts, oldest first.
+    pub async fn session_messages(&self, session_id: &str) -> anyhow::Result<Vec<JsonValue>> {
+        check_session_id(session_id)?;
+        match self
+            .request("GET", &format!("/session/{session_id}/message"), None)
+            .await?
+        {
+            JsonValue::Array(items) => Ok(items),
+            _ => anyhow::bail!("opencode message list is not an array"),
+        }
+    }
+
+    /// Sends one prompt and waits for the assistant turn to finish.
+    pub async fn prompt(&self, session_id: &str, text: &str) -> anyhow::Result<JsonValue> {
+        self.request(
+            "POST",
+            &format!("/session/{session_id}/message"),
+            Some(&json!({ "parts": [{ "type": "text", "text": text }] })),
+        )
+        .await
+    }
+
+    pub async fn abort(&self, session_id: &str) -> anyhow::Result<()> {
+        self.request("POST", &format!("/session/{session_id}/abort"), None)
+            .await
+            .map(|_| ())
+    }
+
+    /// Answers a permission request with `once`, `always` or `reject`.
+    pub async fn reply_permission(
+        &self,
+        session_id: &str,
+        permission_id: &str,
+        response: &str,
+    ) -> anyhow::Result<()> {
+        self.request(
+            "POST",
+            &format!("/session/{session_id}/permissions/{permission_id}"),
+            Some(&json!({ "response": response })),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Streams `GET /event` and calls `on_event` for every decoded event until the stream ends.
+    pub async fn events(&self, mut on_event: impl FnMut(JsonValue)) -> anyhow::Result<()> {
+        let resp = self.send("GET", "/event", None).await?;
+        anyhow::ensure!(
+            resp.status().is_success(),
+            "opencode event stream: {}",
+            resp.status()
+        );
+        let mut body = resp.into_body();
+        let mut parser = SseParser::default();
+        while let Some(frame) = body.frame().await {
+            let frame = frame.context("read event stream")?;
+            let Some(chunk) = frame.data_ref() else {
+                continue;
+            };
+            for data in parser.push(chunk) {
+                if let Ok(v) = serde_json::from_str::<JsonValue>(&data) {
+                    on_event(v);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Session ids end up in request paths, so only accept opencode's own id alphabet.
+pub fn check_session_id(session_id: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !session_id.is_empty()
+            && session_id.len() <= 128
+            && session_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+        "invalid opencode session id `{session_id}`"
+    );
+    Ok(())
+}
+
+/// Relay's view of an opencode session (`rpc.opencode.sessions.list` / `session.fork`).
+pub fn session_summary(session: &JsonValue) -> JsonValue {
+    let time = session.get("time");
+    json!({
+        "id": session.get("id"),
+        "title": session.get("title"),
+        "parent_id": session.get("parentID"),
+        "directory": session.get("directory"),
+        "created_at_ms": time.and_then(|t| t.get("created")),
+        "updated_at_ms": time.and_then(|t| t.get("updated")),
+    })
+}
+
+/// Flattens `GET /session/:id/message` into history entries: text is joined, tool parts keep
+/// their name, status, input and output. Reasoning and step parts are dropped.
+pub fn history_message(message: &JsonValue) -> JsonValue {
+    let info = message.get("info").unwrap_or(&JsonValue::Null);
+    let mut text = String::new();
+    let mut tools = Vec::new();
+    for part in message
+        .get("parts")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+    {
+        match part.get("type").and_then(|v| v.as_str()).unwrap_or("") {
+            "text"
+                if !part
+                    .get("synthetic")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false) =>
+            {
+                text.push_str(part.get("text").and_then(|v| v.as_str()).unwrap_or(""));
+            }
+            "tool" => {
+                let state = part.get("state").unwrap_or(&JsonValue::Null);
+                tools.push(json!({
+                    "request_id": part.get("callID"),
+                    "tool": part.get("tool"),
+                    "status": state.get("status"),
+                    "args": state.get("input"),
+                    "title": state.get("title"),
+                    "output": state.get("output"),
+                }));
+            }
+            _ => {}
+        }
+    }
+    json!({
+        "id": info.get("id"),
+        "role": info.get("role"),
+        "created_at_ms": info.get("time").and_then(|t| t.get("created")),
+        "model": info.get("modelID"),
+        "text": text,
+        "tools": tools,
+    })
+}
+
+/// Full todo list from a finished `todowrite`/`todoread` tool part, normalized to
+/// `[{ id, content, status, priority }]`. opencode puts the list in `metadata.todos`; older
+/// versions only have it in the `todowrite` input or as JSON in the output.
+pub fn todo_snapshot(tool: &str, state: &JsonValue) -> Option<JsonValue> {
+    if !matches!(tool, "todowrite" | "todoread") {
+        return None;
+    }
+    if state.get("status").and_then(|v| v.as_str()) != Some("completed") {
+        return None;
+    }
+    let from_output = || {
+        state
+            .get("output")
+            .and_then(|v| v.as_str())
+            .and_then(|s| serde_json::from_str::<JsonValue>(s).ok())
+            .filter(|v| v.is_array())
+    };
+    let list = state
+        .get("metadata")
+        .and_then(|m| m.get("todos"))
+        .filter(|v| v.is_array())
+        .cloned()
+        .or_else(|| {
+            state
+                .get("input")
+                .and_then(|i| i.get("todos"))
+                .filter(|v| tool == "todowrite" && v.is_array())
+                .cloned()
+        })
+        .or_else(from_output)?;
+    let todos: Vec<JsonValue> = list
+        .as_array()?
+        .iter()
+        .filter_map(|t| {
+            let content = t.get("content").and_then(|v| v.as_str())?;
+            Some(json!({
+                "id": t.get("id"),
+                "content": content,
+                "status": t.get("status").and_then(|v| v.as_str()).unwrap_or("pending"),
+                "priority": t.get("priority"),
+            }))
+        })
+        .collect();
+    Some(JsonValue::Array(todos))
+}
+
+/// Incremental `text/event-stream` decoder that yields each event's `data`.
+#[derive(Default)]
+struct SseParser {
+    buf: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buf.extend_from_slice(chunk);
+        let mut out = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    out.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(rest) = line.strip_prefix("data:") {
+                self.data
+                    .push(rest.strip_prefix(' ').unwrap_or(rest).to_string());
+            }
+        }
+        out
+    }
+}
+
+/// An opencode permission request (`permission.updated` / `permission.asked`).
+#[derive(Debug, Clone)]
+pub struct PermissionAsk {
+    pub id: String,
+    pub session_id: String,
+    /// Permission type (`edit`, `bash`, `webfetch`, ...), used as relay's `op_tool`.
+    pub op_tool: String,
+    pub title: String,
+    pub metadata: JsonValue,
+}
+
+impl PermissionAsk {
+    /// opencode's reply for a relay decision: `approve_for_session` becomes `always` so opencode
+    /// stops asking for matching calls itself.
+    pub fn response(decision: &str) -> &'static str {
+        match decision {
+            "approve_for_session" => "always",
+            "approve" => "once",
+            _ => "reject",
+        }
+    }
+
+    /// Short `key=value` description for `op_args_summary` (at most 80 chars).
+    pub fn summary(&self) -> String {
+        let get = |k: &str| self.metadata.get(k).and_then(|v| v.as_str());
+        let s = if let Some(cmd) = get("command") {
+            format!("cmd={cmd}")
+        } else if let Some(path) = get("filePath").or_else(|| get("filepath")) {
+            format!("path={path}")
+        } else if let Some(url) = get("url") {
+            format!("url={url}")
+        } else if let Some(pattern) = get("pattern") {
+            format!("pattern={pattern}")
+        } else {
+            String::new()
+        };
+        if s.chars().count() <= 80 {
+            return s;
+        }
+        let mut out: String = s.chars().take(80).collect();
+        out.push('…');
+        out
+    }
+}
+
+#[derive(Debug)]
+pub enum ServeEvent {
+    /// A relay event (`run.output`, `tool.call`, `tool.result`, `run.usage`, `run.todos`) ready
+    /// to be enveloped.
+    Relay(&'static str, JsonValue),
+    Permission(PermissionAsk),
+    Error(String),
+}
+
+/// Turns the `GET /event` stream of one session into relay events.
+#[derive(Default)]
+pub struct EventMapper {
+    user_messages: HashSet<String>,
+    // text part id -> bytes already emitted (parts are re-sent whole on every update).
+    emitted: HashMap<String, usize>,
+    tools_called: HashSet<String>,
+    tools_done: HashSet<String>,
+    steps_done: HashSet<String>,
+    // assistant message id -> `provider/model`, for labelling `run.usage`.
+    models: HashMap<String, String>,
+    // Last `run.todos` snapshot, so a `todoread` of an unchanged list is not re-emitted.
+    todos: Option<JsonValue>,
+    mid_line: bool,
+}
+
+fn event_session_id(props: &JsonValue) -> Option<&str> {
+    props
+        .get("sessionID")
+        .or_else(|| props.get("part").and_then(|p| p.get("sessionID")))
+        .or_else(|| props.get("info").and_then(|i| i.get("sessionID")))
+        .and_then(|v| v.as_str())
+}
+
+impl EventMapper {
+    pub fn map(&mut self, session_id: &str, event: &JsonValue) -> Vec<ServeEvent> {
+        let ty = event.get("type").and_then(|v| v.as_str()).unwrap_or("");
+        let props = event.get("properties").unwrap_or(&JsonValue::Null);
+        if event_session_id(props).is_some_and(|sid| sid != session_id) {
+            return Vec::new();
+        }
+        let mut out = Vec::new();
+        match ty {
+            "message.updated" => {
+                let info = props.get("info").unwrap_or(&JsonValue::Null);
+                let id = info.get("id").and_then(|v| v.as_str()).unwrap_or("");
+                match info.get("role").and_then(|v| v.as_str()) {
+                    Some("user") if !id.is_empty() => {
+                        self.user_messages.insert(id.to_string());
+                    }
+                    Some("assistant") => {
+                        let model = info.get("modelID").and_then(|v| v.as_str());
+                        let provider = info.get("providerID").and_then(|v| v.as_str());
+                        if let Some(model) = model {
+                            let label = match provider {
+                                Some(p) => format!("{p}/{model}"),
+                                None => model.to_string(),
+                            };
+                            self.models.insert(id.to_string(), label);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            "message.part.updated" => {
+                let part = props.get("part").unwrap_or(&JsonValue::Null);
+                self.map_part(part, &mut out);
+            }
+            "permission.updated" | "permission.asked" => {
+                let Some(id) = props.get("id").and_then(|v| v.as_str()) else {
+                    return out;
+                };
+                let op_tool = props
+                    .get("type")
+                    .or_else(|| props.get("permission"))
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("permission")
+                    .to_string();
+                let title = props
+                    .get("title")
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| format!("opencode wants to use {op_tool}"));
+                out.push(ServeEvent::Permission(PermissionAsk {
+                    id: id.to_string(),
+                    session_id: session_id.to_string(),
+                    op_tool,
+                    title,
+                    metadata: props.get("metadata").cloned().unwrap_or(JsonValue::Null),
+                }));
+            }
+            "session.idle" if std::mem::take(&mut self.mid_line) => {
+                out.push(ServeEvent::Relay(
+                    "run.output",
+                    json!({ "stream": "stdout", "text": "\n" }),
+                ));
+            }
+            "session.error" => {
+                let err = props.get("error").cloned().unwrap_or(JsonValue::Null);
+                let msg = err
+                    .get("data")
+                    .and_then(|d| d.get("message"))
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| err.to_string());
+                out.push(ServeEvent::Error(msg));
+            }
+            _ => {}
+        }
+        out
+    }
+
+    fn map_part(&mut self, part: &JsonValue, out: &mut Vec<ServeEvent>) {
+        let message_id = part.get("messageID").and_then(|v| v.as_str()).unwrap_or("");
+        if self.user_messages.contains(message_id) {
+            return;
+        }
+        let id = part.get("id").and_then(|v| v.as_str()).unwrap_or("");
+        match part.get("type").and_then(|v| v.as_str()).unwrap_or("") {
+            "text" => {
+                let text = part.get("text").and_then(|v| v.as_str()).unwrap_or("");
+                let done = self.emitted.entry(id.to_string()).or_insert(0);
+                if text.len() <= *done || !text.is_char_boundary(*done) {
+                    return;
+                }
+                let chunk = &text[*done..];
+                *done = text.len();
+                self.mid_line = !chunk.ends_with('\n');
+                out.push(ServeEvent::Relay(
+                    "run.output",
+                    json!({ "stream": "stdout", "text": chunk }),
+                ));
+            }
+            "tool" => {
+                let call_id = part
+                    .get("callID")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or(id)
+                    .to_string();
+                let tool = part
+                    .get("tool")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("unknown");
+                let state = part.get("state").unwrap_or(&JsonValue::Null);
+                let status = state.get("status").and_then(|v| v.as_str()).unwrap_or("");
+                if status == "pending" && state.get("input").is_none_or(|v| v.is_null()) {
+                    return;
+                }
+                if self.tools_called.insert(call_id.clone()) {
+                    out.push(ServeEvent::Relay(
+                        "tool.call",
+                        json!({
+                            "request_id": call_id,
+                            "tool": tool,
+                            "actor": "opencode",
+                            "args": state.get("input").cloned().unwrap_or(JsonValue::Null),
+                        }),
+                    ));
+                }
+                if matches!(status, "completed" | "error")
+                    && self.tools_done.insert(call_id.clone())
+                {
+                    let ok = status == "completed";
+                    let duration_ms = state
+                        .get("time")
+                        .and_then(|t| Some(t.get("end")?.as_i64()? - t.get("start")?.as_i64()?));
+                    let mut data = json!({
+                        "request_id": call_id,
+                        "tool": tool,
+                        "actor": "opencode",
+                        "ok": ok,
+                        "result": {
+                            "title": state.get("title"),
+                            "output": state.get("output"),
+                        },
+                    });
+                    if let Some(duration_ms) = duration_ms {
+                        data["duration_ms"] = JsonValue::from(duration_ms);
+                    }
+                    if !ok {
+                        data["error"] = state
+                            .get("error")
+                            .cloned()
+                            .unwrap_or_else(|| JsonValue::from("tool failed"));
+                    }
+                    out.push(ServeEvent::Relay("tool.result", data));
+                    if let Some(todos) = todo_snapshot(tool, state)
+                        && self.todos.as_ref() != Some(&todos)
+                    {
+                        self.todos = Some(todos.clone());
+                        out.push(ServeEvent::Relay(
+                            "run.todos",
+                            json!({ "todos": todos, "tool": tool }),
+                        ));
+                    }
+                }
+            }
+            "step-finish" if self.steps_done.insert(id.to_string()) => {
+                let model = self.models.get(message_id).map(|s| s.as_str());
+                if let Some(usage) = crate::usage::Usage::from_opencode_step(part, model) {
+                    out.push(ServeEvent::Relay("run.usage", usage.to_json()));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permission_decisions_map_to_opencode_replies() {
+        assert_eq!(PermissionAsk::response("approve_for_session"), "always");
+        assert_eq!(PermissionAsk::response("approve"), "once");
+        assert_eq!(PermissionAsk::response("deny"), "reject");
+
+        let ask = PermissionAsk {
+            id: "perm1".into(),
+            session_id: "s1".into(),
+            op_tool: "edit".into(),
+            title: "Edit src/main.rs".into(),
+            metadata: json!({ "filePath": "src/main.rs" }),
+        };
+        assert_eq!(ask.summary(), "path=src/main.rs");
+        let long = PermissionAsk {
+            metadata: json!({ "command": "x".repeat(100) }),
+            ..ask
+        };
+        assert_eq!(long.summary().chars().count(), 81);
+    }
+
+    #[tokio::test]
+    async fn streams_events_from_fake_server() {
+        use axum::response::IntoResponse;
+        use axum::routing::get;
+
+        let sse = concat!(
+            "data: {\"type\":\"message.updated\",\"properties\":{\"info\":{\"id\":\"m-user\",\"role\":\"user\",\"sessionID\":\"s1\"}}}\n\n",
+            "data: {\"type\":\"message.part.updated\",\"properties\":{\"part\":{\"id\":\"p0\",\"messageID\":\"m-user\",\"sessionID\":\"s1\",\"type\":\"text\",\"text\":\"the prompt\"}}}\n\n",
+            "data: {\"type\":\"message.part.updated\",\"properties\":{\"part\":{\"id\":\"p1\",\"messageID\":\"m1\",\"sessionID\":\"s1\",\"type\":\"text\",\"text\":\"Hel\"}}}\n\n",
+            "data: {\"type\":\"message.part.updated\",\"properties\":{\"part\":{\"id\":\"p1\",\"messageID\":\"m1\",\"sessionID\":\"s1\",\"type\":\"text\",\"text\":\"Hello\"}}}\n\n",
+            "data: {\"type\":\"message.part.updated\",\"properties\":{\"part\":{\"id\":\"p2\",\"messageID\":\"m1\",\"sessionID\":\"s2\",\"type\":\"text\",\"text\":\"other session\"}}}\n\n",
+            "data: {\"type\":\"message.part.updated\",\"properties\":{\"part\":{\"id\":\"p3\",\"callID\":\"c1\",\"messageID\":\"m1\",\"sessionID\":\"s1\",\"type\":\"tool\",\"tool\":\"bash\",\"state\":{\"status\":\"running\",\"input\":{\"command\":\"ls\"}}}}}\n\n",
+            "data: {\"type\":\"permission.updated\",\"properties\":{\"id\":\"perm1\",\"type\":\"bash\",\"sessionID\":\"s1\",\"title\":\"ls\",\"metadata\":{\"command\":\"ls\"}}}\n\n",
+            "data: {\"type\":\"message.part.updated\",\"properties\":{\"part\":{\"id\":\"p3\",\"callID\":\"c1\",\"messageID\":\"m1\",\"sessionID\":\"s1\",\"type\":\"tool\",\"tool\":\"bash\",\"state\":{\"status\":\"completed\",\"input\":{\"command\":\"ls\"},\"output\":\"a.txt\",\"time\":{\"start\":10,\"end\":25}}}}}\n\n",
+            "data: {\"type\":\"message.updated\",\"properties\":{\"info\":{\"id\":\"m1\",\"role\":\"assistant\",\"sessionID\":\"s1\",\"modelID\":\"m-x\",\"providerID\":\"prov\"}}}\n\n",
+            "data: {\"type\":\"message.part.updated\",\"properties\":{\"part\":{\"id\":\"p4\",\"messageID\":\"m1\",\"sessionID\":\"s1\",\"type\":\"step-finish\",\"tokens\":{\"input\":12,\"output\":3,\"reasoning\":0,\"cache\":{\"read\":40,\"write\":0}},\"cost\":0.01}}}\n\n",
+            "data: {\"type\":\"message.part.updated\",\"properties\":{\"part\":{\"id\":\"p5\",\"callID\":\"c2\",\"messageID\":\"m1\",\"sessionID\":\"s1\",\"type\":\"tool\",\"tool\":\"todowrite\",\"state\":{\"status\":\"completed\",\"input\":{\"todos\":[{\"id\":\"1\",\"content\":\"write tests\",\"status\":\"completed\",\"priority\":\"high\"}]},\"output\":\"[]\"}}}}\n\n",
+            "data: {\"type\":\"session.idle\",\"properties\":{\"sessionID\":\"s1\"}}\n\n",
+        );
+        let app = axum::Router::new()
+            .route(
+                "/session",
+                get(|| async { axum::Json(json!([])) })
+                    .post(|| async { axum::Json(json!({ "id": "s1" })) }),
+            )
+            .route(
+                "/event",
+                get(move || async move {
+                    ([("content-type", "text/event-stream")], sse).into_response()
+                }),
+            );
+        let serve = |app: axum::Router| async move {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                let _ = axum::serve(listener, app).await;
+            });
+            port
+        };
+        let open_port = serve(app.clone()).await;
+        let port = serve(app.layer(axum::middleware::from_fn(
+            |req: axum::extract::Request, next: axum::middleware::Next| async move {
+                // "opencode:pw", as opencode checks OPENCODE_SERVER_PASSWORD.
+                let ok = req
+                    .headers()
+                    .get("authorization")
+                    .is_some_and(|v| v == "Basic b3BlbmNvZGU6cHc=");
+                if ok {
+                    next.run(req).await
+                } else {
+                    axum::http::StatusCode::UNAUTHORIZED.into_response()
+                }
+            },
+        )))
+        .await;
+
+        let server = OpencodeServer::new(port, 0, "pw");
+        server.wait_ready(|| false).await.unwrap();
+        server.check_auth().await.unwrap();
+        assert_eq!(server.create_session(&json!({})).await.unwrap(), "s1");
+        let wrong = OpencodeServer::new(port, 0, "guess");
+        assert!(wrong.create_session(&json!({})).await.is_err());
+        let open = OpencodeServer::new(open_port, 0, "pw");
+        open.wait_ready(|| false).await.unwrap();
+        assert!(open.check_auth().await.is_err());
+
+        let mut mapper = EventMapper::default();
+        let mut mapped = Vec::new();
+        server
+            .events(|ev| mapped.extend(mapper.map("s1", &ev)))
+            .await
+            .unwrap();
+
+        let relay: Vec<(&str, JsonValue)> = mapped
+            .iter()
+            .filter_map(|e| match e {
+                ServeEvent::Relay(t, d) => Some((*t, d.clone())),
+                _ => None,
+            })
+            .collect();
+        let text: String = relay
+            .iter()
+            .filter(|(t, _)| *t == "run.output")
+            .filter_map(|(_, d)| d["text"].as_str())
+            .collect();
+        assert_eq!(text, "Hello\n");
+        let types: Vec<&str> = relay.iter().map(|(t, _)| *t).collect();
+        assert_eq!(
+            types,
+            vec![
+                "run.output",
+                "run.output",
+                "tool.call",
+                "tool.result",
+                "run.usage",
+                "tool.call",
+                "tool.result",
+                "run.todos",
+                "run.output"
+            ]
+        );
+        assert_eq!(relay[3].1["duration_ms"], 15);
+        assert_eq!(relay[4].1["model"], "prov/m-x");
+        assert_eq!(relay[4].1["cache_read_tokens"], 40);
+        assert_eq!(relay[7].1["todos"][0]["content"], "write tests");
+        assert_eq!(relay[7].1["todos"][0]["status"], "completed");
+        let perm = mapped
+            .iter()
+            .find_map(|e| match e {
+                ServeEvent::Permission(p) => Some(p.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(perm.id, "perm1");
+        assert_eq!(perm.op_tool, "bash");
+        assert_eq!(perm.metadata["command"], "ls");
+        assert_eq!(perm.summary(), "cmd=ls");
+    }
+
+    #[test]
+    fn history_message_flattens_parts() {
+        let msg = json!({
+            "info": { "id": "m1", "role": "assistant", "time": { "created": 5 }, "modelID": "x" },
+            "parts": [
+                { "type": "step-start" },
+                { "type": "text", "text": "Hel" },
+                { "type": "text", "text": "lo" },
+                { "type": "text", "text": "ctx", "synthetic": true },
+                { "type": "tool", "callID": "c1", "tool": "bash",
+                  "state": { "status": "completed", "input": { "command": "ls" }, "output": "a\n" } }
+            ]
+        });
+        let h = history_message(&msg);
+        assert_eq!(h["id"], "m1");
+        assert_eq!(h["created_at_ms"], 5);
+        assert_eq!(h["text"], "Hello");
+        assert_eq!(h["tools"][0]["tool"], "bash");
+        assert_eq!(h["tools"][0]["args"]["command"], "ls");
+        assert!(check_session_id("ses_abc-1").is_ok());
+        assert!(check_session_id("../x").is_err());
+    }
+}
@@ -0,0 +1,814 @@
+//! `rpc.fs.apply_patch`: unified diffs (any number of files) or search/replace edits against the
+//! run cwd.
+//!
+//! The patch is validated before approval and turned into per-file before/after contents, so the
+//! approval card shows the real diff instead of a content preview. On approval every file is
+//! staged next to its target and then swapped in; if anything fails the files already swapped are
+//! restored, so the patch lands completely or not at all.
+
+use axum::http::StatusCode;
+use regex::Regex;
+use serde_json::{Value as JsonValue, json};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+const MAX_FILE_BYTES: usize = 1024 * 1024;
+const MAX_FILES: usize = 200;
+/// Diff text copied into `tool.call` / `run.permission_requested`.
+const MAX_PREVIEW_CHARS: usize = 20_000;
+
+type PatchError = (StatusCode, String);
+
+fn bad_request(msg: String) -> PatchError {
+    (StatusCode::BAD_REQUEST, msg)
+}
+
+fn conflict(msg: String) -> PatchError {
+    (StatusCode::CONFLICT, msg)
+}
+
+/// One file touched by a validated patch.
+struct FileEdit {
+    path: String,
+    target: PathBuf,
+    /// Content on disk at validation time; `None` when the patch creates the file.
+    before: Option<String>,
+    /// Content once patched; `None` when the patch deletes the file.
+    after: Option<String>,
+}
+
+impl FileEdit {
+    fn status(&self) -> &'static str {
+        match (&self.before, &self.after) {
+            (None, _) => "added",
+            (_, None) => "deleted",
+            _ => "modified",
+        }
+    }
+
+    fn diff(&self) -> crate::file_changes::Diff {
+        crate::file_changes::unified_diff(
+            &self.path,
+            self.status(),
+            self.before.as_deref().unwrap_or(""),
+            self.after.as_deref().unwrap_or(""),
+        )
+    }
+}
+
+/// A patch that applies cleanly to the current tree.
+pub struct Prepared {
+    files: Vec<FileEdit>,
+}
+
+/// Parses and validates `data.patch` (unified diff) and/or `data.edits` (search/replace blocks).
+pub fn prepare(run_cwd: &str, data: &JsonValue) -> Result<Prepared, PatchError> {
+    let mut files: Vec<FileEdit> = Vec::new();
+
+    if let Some(patch) = data.get("patch").and_then(|v| v.as_str()) {
+        for fp in parse_unified(patch)? {
+            let idx = load(run_cwd, &mut files, &fp.path)?;
+            let edit = &mut files[idx];
+            let current = match (&edit.after, fp.create) {
+                (Some(_), true) => return Err(conflict(format!("{}: already exists", fp.path))),
+                (None, false) => return Err(conflict(format!("{}: does not exist", fp.path))),
+                (Some(content), false) => content.clone(),
+                (None, true) => String::new(),
+            };
+            let patched = apply_hunks(&fp.path, &current, &fp.hunks)?;
+            edit.after = if fp.delete {
+                if !patched.is_empty() {
+                    return Err(conflict(format!(
+                        "{}: delete patch does not remove the whole file",
+                        fp.path
+                    )));
+                }
+                None
+            } else {
+                Some(patched)
+            };
+        }
+    }
+
+    if let Some(edits) = data.get("edits") {
+        let edits = edits
+            .as_array()
+            .ok_or_else(|| bad_request("`edits` must be an array".into()))?;
+        for (n, e) in edits.iter().enumerate() {
+            let n = n + 1;
+            let path = e.get("path").and_then(|v| v.as_str()).unwrap_or("");
+            let search = e.get("search").and_then(|v| v.as_str()).unwrap_or("");
+            let replace = e.get("replace").and_then(|v| v.as_str()).unwrap_or("");
+            let replace_all = e
+                .get("replace_all")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false);
+            let idx = load(run_cwd, &mut files, path)?;
+            let edit = &mut files[idx];
+            let path = edit.path.clone();
+            edit.after = Some(match edit.after.as_deref() {
+                None if search.is_empty() => replace.to_string(),
+                None => return Err(conflict(format!("{path}: edit #{n}: file does not exist"))),
+                Some(_) if search.is_empty() => {
+                    return Err(bad_request(format!(
+                        "{path}: edit #{n}: empty `search` only creates new files"
+                    )));
+                }
+                Some(content) => match content.matches(search).count() {
+                    0 => {
+                        return Err(conflict(format!(
+                            "{path}: edit #{n}: search text not found{}",
+                            closest_match(content, search)
+                        )));
+                    }
+                    1 => content.replacen(search, replace, 1),
+                    _ if replace_all => content.replace(search, replace),
+                    count => {
+                        return Err(conflict(format!(
+                            "{path}: edit #{n}: search text matches {count} times; include more context or set replace_all"
+                        )));
+                    }
+                },
+            });
+        }
+    }
+
+    if files.is_empty() {
+        return Err(bad_request(
+            "expected `patch` (unified diff) or `edits` (search/replace blocks)".into(),
+        ));
+    }
+    files.retain(|f| f.before != f.after);
+    if files.is_empty() {
+        return Err(bad_request("patch does not change any file".into()));
+    }
+    if files.len() > MAX_FILES {
+        return Err(bad_request(format!(
+            "patch touches more than {MAX_FILES} files"
+        )));
+    }
+    for f in &files {
+        if f.after.as_ref().is_some_and(|c| c.len() > MAX_FILE_BYTES) {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("{}: patched file exceeds {MAX_FILE_BYTES} bytes", f.path),
+            ));
+        }
+    }
+    Ok(Prepared { files })
+}
+
+/// Index of `rel` in `files`, reading it from disk on first use.
+fn load(run_cwd: &str, files: &mut Vec<FileEdit>, rel: &str) -> Result<usize, PatchError> {
+    let rel = rel.trim_start_matches("./");
+    if rel.is_empty() {
+        return Err(bad_request("missing path".into()));
+    }
+    if let Some(idx) = files.iter().position(|f| f.path == rel) {
+        return Ok(idx);
+    }
+    let target = crate::fs_git::safe_join_run_path_allow_create(run_cwd, rel)
+        .map_err(|(code, msg)| (code, format!("{rel}: {msg}")))?;
+    let before = read_current(rel, &target)?;
+    files.push(FileEdit {
+        path: rel.to_string(),
+        target,
+        after: before.clone(),
+        before,
+    });
+    Ok(files.len() - 1)
+}
+
+fn read_current(rel: &str, target: &std::path::Path) -> Result<Option<String>, PatchError> {
+    let md = match std::fs::metadata(target) {
+        Ok(md) => md,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(bad_request(format!("{rel}: {e}"))),
+    };
+    if !md.is_file() {
+        return Err(bad_request(format!("{rel}: not a regular file")));
+    }
+    if md.len() > MAX_FILE_BYTES as u64 {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("{rel}: file exceeds {MAX_FILE_BYTES} bytes"),
+        ));
+    }
+    let bytes = std::fs::read(target).map_err(|e| bad_request(format!("{rel}: {e}")))?;
+    String::from_utf8(bytes)
+        .map(Some)
+        .map_err(|_| bad_request(format!("{rel}: not a UTF-8 text file")))
+}
+
+/// Where the search block's first line best lines up with the file, for the "not found" error.
+fn closest_match(content: &str, search: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let wanted: Vec<&str> = search.lines().collect();
+    let Some(first) = wanted.first() else {
+        return String::new();
+    };
+    let best = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| l.trim() == first.trim() && !first.trim().is_empty())
+        .map(|(i, _)| {
+            let same = wanted
+                .iter()
+                .zip(&lines[i..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            (i, same)
+        })
+        .max_by_key(|&(_, same)| same);
+    match best {
+        Some((i, same)) if same < wanted.len() => format!(
+            " (closest match at line {}: search line {} is {:?}, file has {:?})",
+            i + 1,
+            same + 1,
+            wanted[same],
+            lines.get(i + same).copied().unwrap_or("<end of file>")
+        ),
+        _ => String::new(),
+    }
+}
+
+struct Hunk {
+    header: String,
+    old_start: usize,
+    old_len: usize,
+    /// `(' ' | '-' | '+', line including its newline)`.
+    lines: Vec<(char, String)>,
+}
+
+struct FilePatch {
+    path: String,
+    create: bool,
+    delete: bool,
+    hunks: Vec<Hunk>,
+}
+
+/// `a/foo`, `b/foo`, `foo\t2024-01-01 ...` → `foo`; `/dev/null` → `None`.
+fn header_path(raw: &str) -> Option<String> {
+    let raw = raw
+        .split('\t')
+        .next()
+        .unwrap_or("")
+        .trim_end_matches('\r')
+        .trim();
+    if raw == "/dev/null" {
+        return None;
+    }
+    let raw = raw
+        .strip_prefix("a/")
+        .or_else(|| raw.strip_prefix("b/"))
+        .unwrap_or(raw);
+    Some(raw.to_string())
+}
+
+fn hunk_header_re() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"^@@ -(\d+)(?:,(\d+))? \+(\d+)(?:,(\d+))? @@").expect("hunk header regex")
+    })
+}
+
+fn parse_unified(text: &str) -> Result<Vec<FilePatch>, PatchError> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut out: Vec<FilePatch> = Vec::new();
+    // `diff --git` extended headers that matter before the `---`/`+++` pair.
+    let mut git_mode: Option<&'static str> = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if line.starts_with("diff --git ") {
+            git_mode = None;
+        } else if line.starts_with("new file mode") {
+            git_mode = Some("create");
+        } else if line.starts_with("deleted file mode") {
+            git_mode = Some("delete");
+        } else if line.starts_with("rename from") || line.starts_with("copy from") {
+            return Err(bad_request("renames and copies are not supported".into()));
+        } else if line.starts_with("GIT binary patch") || line.starts_with("Binary files ") {
+            return Err(bad_request("binary patches are not supported".into()));
+        } else if let Some(old) = line.strip_prefix("--- ")
+            && let Some(new) = lines.get(i + 1).and_then(|l| l.strip_prefix("+++ "))
+        {
+            let (old, new) = (header_path(old), header_path(new));
+            let path = match (&old, &new) {
+                (Some(a), Some(b)) if a != b => {
+                    return Err(bad_request(format!(
+                        "renames are not supported ({a} -> {b})"
+                    )));
+                }
+                (_, Some(p)) | (Some(p), None) => p.clone(),
+                (None, None) => return Err(bad_request("patch header without a path".into())),
+            };
+            out.push(FilePatch {
+                path,
+                create: old.is_none() || git_mode == Some("create"),
+                delete: new.is_none() || git_mode == Some("delete"),
+                hunks: Vec::new(),
+            });
+            git_mode = None;
+            i += 2;
+            continue;
+        } else if line.starts_with("@@") {
+            let fp = out
+                .last_mut()
+                .ok_or_else(|| bad_request("hunk before any `---`/`+++` file header".into()))?;
+            let (hunk, next) = parse_hunk(&fp.path, fp.hunks.len() + 1, &lines, i)?;
+            fp.hunks.push(hunk);
+            i = next;
+            continue;
+        }
+        // Anything else (commit message, `index` lines, ...) is ignored, like `git apply`.
+        i += 1;
+    }
+    if out.is_empty() {
+        return Err(bad_request(
+            "no file headers (`--- a/...` / `+++ b/...`) in patch".into(),
+        ));
+    }
+    Ok(out)
+}
+
+fn parse_hunk(
+    path: &str,
+    n: usize,
+    lines: &[&str],
+    start: usize,
+) -> Result<(Hunk, usize), PatchError> {
+    let header = lines[start].trim_end().to_string();
+    let caps = hunk_header_re()
+        .captures(&header)
+        .ok_or_else(|| bad_request(format!("{path}: malformed hunk header {header:?}")))?;
+    let num = |i: usize| {
+        caps.get(i)
+            .map(|m| m.as_str().parse::<usize>().unwrap_or(0))
+            .unwrap_or(1)
+    };
+    let (old_start, old_len, new_len) = (num(1), num(2), num(4));
+    let (mut old_left, mut new_left) = (old_len, new_len);
+    let mut body: Vec<(char, String)> = Vec::new();
+    let mut i = start + 1;
+    while old_left > 0 || new_left > 0 {
+        let Some(line) = lines.get(i) else {
+            return Err(bad_request(format!(
+                "{path}: hunk #{n} ({header}) is truncated: {old_left} old / {new_left} new lines missing"
+            )));
+        };
+        // Some tools strip the single space of empty context lines.
+        let (tag, rest) = match line.chars().next() {
+            Some('\n') | Some('\r') => (' ', *line),
+            Some(c @ (' ' | '-' | '+')) => (c, &line[1..]),
+            Some('\\') => {
+                if let Some(last) = body.last_mut() {
+                    strip_newline(&mut last.1);
+                }
+                i += 1;
+                continue;
+            }
+            _ => {
+                return Err(bad_request(format!(
+                    "{path}: hunk #{n} ({header}) ends early: {old_left} old / {new_left} new lines missing before {:?}",
+                    line.trim_end()
+                )));
+            }
+        };
+        match tag {
+            ' ' if old_left > 0 && new_left > 0 => {
+                old_left -= 1;
+                new_left -= 1;
+            }
+            '-' if old_left > 0 => old_left -= 1,
+            '+' if new_left > 0 => new_left -= 1,
+            _ => {
+                return Err(bad_request(format!(
+                    "{path}: hunk #{n} ({header}) has more lines than its header counts"
+                )));
+            }
+        }
+        body.push((tag, rest.to_string()));
+        i += 1;
+    }
+    if let Some(line) = lines.get(i)
+        && line.starts_with('\\')
+    {
+        if let Some(last) = body.last_mut() {
+            strip_newline(&mut last.1);
+        }
+        i += 1;
+    }
+    Ok((
+        Hunk {
+            header,
+            old_start,
+            old_len,
+            lines: body,
+        },
+        i,
+    ))
+}
+
+fn strip_newline(line: &mut String) {
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+}
+
+/// Applies hunks in order. Like `git apply`, a hunk whose context moved is searched for nearby
+/// (nearest position first) but its lines must match exactly.
+fn apply_hunks(path: &str, content: &str, hunks: &[Hunk]) -> Result<String, PatchError> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let mut out = String::with_capacity(content.len());
+    let mut pos = 0usize;
+    let mut delta = 0isize;
+    for (n, h) in hunks.iter().enumerate() {
+        let old: Vec<&str> = h
+            .lines
+            .iter()
+            .filter(|(t, _)| *t != '+')
+            .map(|(_, l)| l.as_str())
+            .collect();
+        // `-N,0` inserts after line N; otherwise N is the first old line (1-based).
+        let base = if h.old_len == 0 {
+            h.old_start
+        } else {
+            h.old_start.saturating_sub(1)
+        };
+        let expected = (base as isize + delta).clamp(pos as isize, lines.len() as isize) as usize;
+        let fits =
+            |at: usize| at + old.len() <= lines.len() && lines[at..at + old.len()] == old[..];
+        let found = (0..=lines.len()).find_map(|off| {
+            [expected.checked_add(off), expected.checked_sub(off)]
+                .into_iter()
+                .flatten()
+                .find(|&at| at >= pos && fits(at))
+        });
+        let Some(at) = found else {
+            let k = old
+                .iter()
+                .enumerate()
+                .find(|&(k, l)| lines.get(expected + k) != Some(l))
+                .map(|(k, _)| k)
+                .unwrap_or(0);
+            return Err(conflict(format!(
+                "{path}: hunk #{} ({}) does not apply: line {} should be {:?} but is {:?}",
+                n + 1,
+                h.header,
+                expected + k + 1,
+                old.get(k).copied().unwrap_or(""),
+                lines.get(expected + k).copied().unwrap_or("<end of file>")
+            )));
+        };
+        out.push_str(&lines[pos..at].concat());
+        for (tag, l) in &h.lines {
+            if *tag != '-' {
+                out.push_str(l);
+            }
+        }
+        pos = at + old.len();
+        delta = at as isize - base as isize;
+    }
+    out.push_str(&lines[pos..].concat());
+    Ok(out)
+}
+
+fn staging_path(target: &std::path::Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{name}.relay-patch"))
+}
+
+impl Prepared {
+    /// Redaction is left to the caller.
+    pub fn preview(&self) -> JsonValue {
+        let mut diff = String::new();
+        let (mut added, mut removed) = (0, 0);
+        let mut files = Vec::new();
+        for f in &self.files {
+            let d = f.diff();
+            added += d.added;
+            removed += d.removed;
+            diff.push_str(&d.text);
+            files.push(json!({
+                "path": f.path,
+                "status": f.status(),
+                "added": d.added,
+                "removed": d.removed,
+            }));
+        }
+        let diff_truncated = diff.chars().count() > MAX_PREVIEW_CHARS;
+        if diff_truncated {
+            diff = diff.chars().take(MAX_PREVIEW_CHARS).collect();
+        }
+        json!({
+            "files": files,
+            "added": added,
+            "removed": removed,
+            "diff": diff,
+            "diff_truncated": diff_truncated,
+        })
+    }
+
+    /// Short form for `op_args_summary`, e.g. `src/a.rs, src/b.rs (+12 -3)`.
+    pub fn summary(&self) -> String {
+        let (added, removed) = self.files.iter().fold((0, 0), |(a, r), f| {
+            let d = f.diff();
+            (a + d.added, r + d.removed)
+        });
+        let paths: Vec<&str> = self.files.iter().map(|f| f.path.as_str()).collect();
+        format!("{} (+{added} -{removed})", paths.join(", "))
+    }
+
+    /// Writes every file or none. Fails with `409` if a file changed since validation.
+    pub fn apply(&self) -> Result<JsonValue, PatchError> {
+        for f in &self.files {
+            if read_current(&f.path, &f.target)? != f.before {
+                return Err(conflict(format!(
+                    "{}: changed since the patch was validated; no files were changed",
+                    f.path
+                )));
+            }
+        }
+
+        let mut staged: Vec<PathBuf> = Vec::new();
+        let cleanup = |staged: &[PathBuf]| {
+            for p in staged {
+                let _ = std::fs::remove_file(p);
+            }
+        };
+        for f in &self.files {
+            let Some(after) = &f.after else { continue };
+            let tmp = staging_path(&f.target);
+            let written = std::fs::write(&tmp, after).and_then(|_| match &f.before {
+                Some(_) => std::fs::metadata(&f.target)
+                    .and_then(|md| std::fs::set_permissions(&tmp, md.permissions())),
+                None => Ok(()),
+            });
+            staged.push(tmp);
+            if let Err(e) = written {
+                cleanup(&staged);
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("{}: {e}; no files were changed", f.path),
+                ));
+            }
+        }
+
+        for (i, f) in self.files.iter().enumerate() {
+            let swapped = match &f.after {
+                Some(_) => std::fs::rename(staging_path(&f.target), &f.target),
+                None => std::fs::remove_file(&f.target),
+            };
+            if let Err(e) = swapped {
+                for done in &self.files[..i] {
+                    let _ = match &done.before {
+                        Some(before) => std::fs::write(&done.target, before),
+                        None => std::fs::remove_file(&done.target),
+                    };
+                }
+                cleanup(&staged);
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("{}: {e}; no files were changed", f.path),
+                ));
+            }
+        }
+
+        let preview = self.preview();
+        Ok(json!({
+            "files": preview["files"],
+            "added": preview["added"],
+            "removed": preview["removed"],
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_cwd() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("relay-patch-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn prepare_err(cwd: &std::path::Path, data: JsonValue) -> PatchError {
+        prepare(cwd.to_str().unwrap(), &data)
+            .err()
+            .expect("patch should fail")
+    }
+
+    #[test]
+    fn unified_and_search_replace_patches_apply_atomically() {
+        let dir = temp_cwd();
+        let cwd = dir.to_str().unwrap();
+        std::fs::write(dir.join("a.txt"), "one\ntwo\nthree\nfour\nfive\n").unwrap();
+        std::fs::write(dir.join("gone.txt"), "bye\n").unwrap();
+
+        // Hunk line numbers are off by one; the context still locates it.
+        let patch = "\
+diff --git a/a.txt b/a.txt
+--- a/a.txt
++++ b/a.txt
+@@ -3,3 +3,3 @@
+ two
+-three
++THREE
+ four
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++fresh
+\\ No newline at end of file
+--- a/gone.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-bye
+";
+        let prepared = prepare(cwd, &json!({ "patch": patch })).unwrap();
+        let preview = prepared.preview();
+        assert_eq!(preview["files"].as_array().unwrap().len(), 3);
+        assert!(
+            preview["diff"]
+                .as_str()
+                .unwrap()
+                .contains("-three\n+THREE\n")
+        );
+        assert_eq!(prepared.summary(), "a.txt, new.txt, gone.txt (+2 -2)");
+        prepared.apply().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a.txt")).unwrap(),
+            "one\ntwo\nTHREE\nfour\nfive\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("new.txt")).unwrap(),
+            "fresh"
+        );
+        assert!(!dir.join("gone.txt").exists());
+
+        let prepared = prepare(
+            cwd,
+            &json!({ "edits": [{ "path": "a.txt", "search": "four\n", "replace": "4\n" }] }),
+        )
+        .unwrap();
+        prepared.apply().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a.txt")).unwrap(),
+            "one\ntwo\nTHREE\n4\nfive\n"
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stale_hunk_conflict_names_file_hunk_and_line() {
+        let dir = temp_cwd();
+        std::fs::write(dir.join("a.txt"), "one\ntwo\nTHREE\nfour\n").unwrap();
+        let patch = "--- a/a.txt\n+++ b/a.txt\n@@ -2,3 +2,3 @@\n two\n-three\n+3\n four\n";
+        let err = prepare_err(&dir, json!({ "patch": patch }));
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        assert_eq!(
+            err.1,
+            "a.txt: hunk #1 (@@ -2,3 +2,3 @@) does not apply: line 3 should be \"three\\n\" but is \"THREE\\n\""
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn hunks_must_apply_in_order_without_overlap() {
+        let dir = temp_cwd();
+        std::fs::write(dir.join("a.txt"), "a\nb\nc\nd\n").unwrap();
+        // The second hunk edits a line the first one already consumed.
+        let patch = "\
+--- a/a.txt
++++ b/a.txt
+@@ -2,2 +2,2 @@
+-b
+-c
++B
++C
+@@ -3,1 +3,1 @@
+-c
++X
+";
+        let err = prepare_err(&dir, json!({ "patch": patch }));
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        assert!(
+            err.1.starts_with("a.txt: hunk #2 (@@ -3,1 +3,1 @@)"),
+            "{}",
+            err.1
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn create_delete_and_missing_file_conflicts() {
+        let dir = temp_cwd();
+        std::fs::write(dir.join("a.txt"), "a\nb\n").unwrap();
+
+        let create = "--- /dev/null\n+++ b/a.txt\n@@ -0,0 +1 @@\n+x\n";
+        let err = prepare_err(&dir, json!({ "patch": create }));
+        assert_eq!(err, (StatusCode::CONFLICT, "a.txt: already exists".into()));
+
+        let modify = "--- a/missing.txt\n+++ b/missing.txt\n@@ -1 +1 @@\n-a\n+b\n";
+        let err = prepare_err(&dir, json!({ "patch": modify }));
+        assert_eq!(
+            err,
+            (StatusCode::CONFLICT, "missing.txt: does not exist".into())
+        );
+
+        let partial_delete = "--- a/a.txt\n+++ /dev/null\n@@ -1 +0,0 @@\n-a\n";
+        let err = prepare_err(&dir, json!({ "patch": partial_delete }));
+        assert_eq!(
+            err,
+            (
+                StatusCode::CONFLICT,
+                "a.txt: delete patch does not remove the whole file".into()
+            )
+        );
+
+        // A conflict in a later file rejects the whole patch before anything is written.
+        let both = format!("--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-a\n+A\n{modify}");
+        prepare_err(&dir, json!({ "patch": both }));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a.txt")).unwrap(),
+            "a\nb\n"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn search_replace_conflicts() {
+        let dir = temp_cwd();
+        std::fs::write(dir.join("a.txt"), "one\ntwo\nfour\nfour\n").unwrap();
+
+        let err = prepare_err(
+            &dir,
+            json!({ "edits": [{ "path": "a.txt", "search": "one\nthree\n", "replace": "" }] }),
+        );
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        assert_eq!(
+            err.1,
+            "a.txt: edit #1: search text not found (closest match at line 1: search line 2 is \"three\", file has \"two\")"
+        );
+
+        let err = prepare_err(
+            &dir,
+            json!({ "edits": [{ "path": "a.txt", "search": "four\n", "replace": "4\n" }] }),
+        );
+        assert!(err.1.contains("matches 2 times"), "{}", err.1);
+
+        let prepared = prepare(
+            dir.to_str().unwrap(),
+            &json!({ "edits": [{ "path": "a.txt", "search": "four\n", "replace": "4\n", "replace_all": true }] }),
+        )
+        .unwrap();
+        // A file changed after validation aborts the whole patch.
+        std::fs::write(dir.join("a.txt"), "edited elsewhere\n").unwrap();
+        let err = prepared.apply().err().unwrap();
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a.txt")).unwrap(),
+            "edited elsewhere\n"
+        );
+        assert!(!dir.join(".a.txt.relay-patch").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn crlf_patches_keep_line_endings() {
+        let dir = temp_cwd();
+        let cwd = dir.to_str().unwrap();
+        std::fs::write(dir.join("win.txt"), "one\r\ntwo\r\n\r\nthree").unwrap();
+
+        // CRLF patch, an empty context line with its space stripped, and no final newline.
+        let patch = "--- a/win.txt\r\n+++ b/win.txt\r\n@@ -1,4 +1,4 @@\r\n one\r\n-two\r\n+TWO\r\n\r\n-three\r\n\\ No newline at end of file\r\n+THREE\r\n\\ No newline at end of file\r\n";
+        prepare(cwd, &json!({ "patch": patch }))
+            .unwrap()
+            .apply()
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("win.txt")).unwrap(),
+            "one\r\nTWO\r\n\r\nTHREE"
+        );
+
+        // LF context does not silently match CRLF lines (as with `git apply`).
+        let lf = "--- a/win.txt\n+++ b/win.txt\n@@ -1,2 +1,2 @@\n one\n-TWO\n+two\n";
+        let err = prepare_err(&dir, json!({ "patch": lf }));
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        assert!(
+            err.1.contains(r#"should be "one\n" but is "one\r\n""#),
+            "{}",
+            err.1
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
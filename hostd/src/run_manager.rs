@@ -265,6 +265,76 @@ impl Drop for TempOpencodeConfigHome {
     }
 }
 
+/// The `opencode serve` process behind a structured run, plus the config home it reads.
+struct OpencodeServe {
+    server: crate::opencode_serve::OpencodeServer,
+    _config_home: TempOpencodeConfigHome,
+}
+
+/// A short-lived `opencode serve` started to answer session RPCs; stopped on drop. Kept for
+/// [`OPENCODE_API_IDLE`] after its last use so browsing sessions does not restart it per call.
+struct EphemeralOpencodeServe {
+    serve: OpencodeServe,
+    sandbox: Option<crate::sandbox::SandboxProfile>,
+    child: StdMutex<Option<std::process::Child>>,
+    last_used: StdMutex<std::time::Instant>,
+}
+
+const OPENCODE_API_IDLE: Duration = Duration::from_secs(60);
+
+/// Marks an [`EphemeralOpencodeServe`] as in use; its idle time starts when the lease drops.
+struct OpencodeApiLease(Arc<EphemeralOpencodeServe>);
+
+impl Drop for OpencodeApiLease {
+    fn drop(&mut self) {
+        self.0.touch();
+    }
+}
+
+impl EphemeralOpencodeServe {
+    fn is_alive(&self) -> bool {
+        self.child
+            .lock()
+            .ok()
+            .and_then(|mut c| c.as_mut().map(|c| matches!(c.try_wait(), Ok(None))))
+            .unwrap_or(false)
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_used
+            .lock()
+            .map(|t| t.elapsed())
+            .unwrap_or_default()
+    }
+
+    fn touch(&self) {
+        if let Ok(mut t) = self.last_used.lock() {
+            *t = std::time::Instant::now();
+        }
+    }
+}
+
+impl Drop for EphemeralOpencodeServe {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        let _ =
+            signal_opencode_process_group(self.serve.server.pid, nix::sys::signal::Signal::SIGKILL);
+        let Some(mut child) = self.child.get_mut().ok().and_then(|c| c.take()) else {
+            return;
+        };
+        let _ = child.kill();
+        // Reap off the async workers; the process is already killed, so this is quick.
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(move || child.wait());
+            }
+            Err(_) => {
+                let _ = child.wait();
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct RunManager {
     host_id: String,
@@ -272,11 +342,37 @@ pub struct RunManager {
     redactor: Arc<Redactor>,
     events: broadcast::Sender<WsEnvelope>,
     runs: Arc<RwLock<HashMap<String, Arc<Run>>>>,
+    /// Worktrees of `isolation: "worktree"` runs, kept after exit until cleaned up.
+    worktrees: Arc<RwLock<HashMap<String, crate::fs_git::RunWorktree>>>,
+    /// Event counters of those runs, so cleanup events continue the run's `seq` after exit.
+    worktree_seqs: Arc<StdMutex<HashMap<String, Arc<AtomicI64>>>>,
+    budgets: Arc<StdMutex<crate::budget::Budgets>>,
+    budget_meter: BudgetMeter,
+    /// Taken by [`RunManager::enforce_budgets`].
+    budget_verdicts: Arc<StdMutex<Option<BudgetVerdicts>>>,
+    file_trackers: Arc<StdMutex<crate::file_changes::Trackers>>,
+    /// Cancel flags of running `rpc.bash` commands, keyed `<run_id>:<request_id>`.
+    bash_cancels: Arc<StdMutex<HashMap<String, Arc<AtomicBool>>>>,
+    search_cancels: Arc<StdMutex<HashMap<String, Arc<AtomicBool>>>>,
+    lsp: Arc<crate::lsp::LspPool>,
+    /// `rpc.fs.watch` subscriptions, keyed `<run_id>:<watch_id>`.
+    fs_watches: Arc<StdMutex<HashMap<String, Arc<crate::fs_watch::Watch>>>>,
+    fs_watch_limits: crate::fs_watch::Limits,
+    /// Per-process secret behind the `RELAY_RUN_TOKEN` handed to each run.
+    run_token_key: Arc<[u8; 32]>,
+    /// Pane process of tmux-backed runs (the agent is a child of the tmux server, not of `pid`).
+    tmux_pane_pids: Arc<StdMutex<HashMap<String, i32>>>,
+    permission_log: PermissionLog,
+    /// Idle `opencode serve` processes for `rpc.opencode.*`, keyed by cwd.
+    opencode_api_serves: Arc<StdMutex<HashMap<String, Arc<EphemeralOpencodeServe>>>>,
 }
 
 struct Run {
     run_id: String,
-    seq: AtomicI64,
+    /// Shared so worktree runs can keep numbering events after exit (see `worktree_seqs`).
+    seq: Arc<AtomicI64>,
+    budget: BudgetMeter,
+    permissions: PermissionLog,
     pty: Option<StdMutex<Box<dyn MasterPty + Send>>>,
     writer: Mutex<Box<dyn Write + Send>>,
     pid: i32,
@@ -287,6 +383,7 @@ struct Run {
     stdin_line_buf: Mutex<Vec<u8>>,
     processed_input_ids: Mutex<HashSet<String>>,
     session_allow_tools: Mutex<HashSet<String>>,
+    transfer_grants: Mutex<TransferGrants>,
     pending_permission: Mutex<Option<PendingPermission>>,
     codex_mcp: Mutex<Option<CodexMcpState>>,
     codex_rpc_waiters: StdMutex<HashMap<i64, oneshot::Sender<JsonValue>>>,
@@ -297,9 +394,210 @@ struct Run {
     opencode_stop_requested: Mutex<bool>,
     opencode_call_lock: Mutex<()>,
     opencode_model: Option<String>,
+    acp: Option<Arc<crate::acp::AcpSession>>,
+    opencode_serve: Option<OpencodeServe>,
     tmux_session: Option<String>,
     default_approve_text: String,
     default_deny_text: String,
+    launch: RunLaunch,
+}
+
+/// Optional knobs for [`RunManager::start_run`].
+#[derive(Debug, Clone, Default)]
+pub struct StartRunOptions {
+    pub model: Option<String>,
+    pub sandbox: Option<crate::sandbox::SandboxProfile>,
+    pub isolation: IsolationMode,
+    /// Extra environment for the agent process (e.g. from a server-side launch profile).
+    pub env: Vec<(String, String)>,
+    /// Sent as the first input once the run is up.
+    pub initial_prompt: Option<String>,
+    /// Op tools pre-approved for the whole run, as if approved with `approve_for_session`.
+    pub allow_tools: Vec<String>,
+    /// Continue or fork an existing opencode session instead of creating a new one.
+    pub opencode_session: Option<OpencodeSessionStart>,
+    /// Replaces the `run` limits from budgets.json for this run.
+    pub budget: Option<crate::budget::Limits>,
+}
+
+/// Env vars a request may not set besides `RELAY_*`/`LD_*`/`DYLD_*`: anything that swaps the
+/// agent's config (and with it the injected permission policy), its shell init or its binaries.
+const RESERVED_ENV_VARS: &[&str] = &[
+    "OPENCODE_PERMISSION",
+    "OPENCODE_CONFIG",
+    "OPENCODE_CONFIG_DIR",
+    "OPENCODE_CONFIG_CONTENT",
+    "CODEX_HOME",
+    "HOME",
+    "XDG_CONFIG_HOME",
+    "PATH",
+    "BASH_ENV",
+    "ENV",
+    "SHELLOPTS",
+    "BASHOPTS",
+    "PROMPT_COMMAND",
+];
+
+/// Env vars a request may not set: relay's own (`RELAY_*`, e.g. the run token and socket),
+/// dynamic-loader hooks and [`RESERVED_ENV_VARS`].
+fn reserved_env_var(key: &str) -> bool {
+    key.starts_with("RELAY_")
+        || key.starts_with("LD_")
+        || key.starts_with("DYLD_")
+        || RESERVED_ENV_VARS.contains(&key)
+}
+
+/// Where a structured opencode run gets its session (`resume_session_id` /
+/// `fork_from_session_id` on `rpc.run.start`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpencodeSessionStart {
+    Resume(String),
+    Fork(String),
+}
+
+impl StartRunOptions {
+    /// Parses the optional fields of `rpc.run.start` / local `POST /runs` (`model`, `sandbox`,
+    /// `isolation`, `env`, `initial_prompt`, `allow_tools`, `resume_session_id`,
+    /// `fork_from_session_id`, `budget`).
+    pub fn from_request_data(data: &JsonValue) -> anyhow::Result<Self> {
+        let model = data
+            .get("model")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .filter(|s| !s.trim().is_empty());
+        let sandbox = crate::sandbox::profile_from_request(
+            data.get("sandbox"),
+            crate::sandbox::default_profile(),
+        )?;
+        let isolation = IsolationMode::parse(data.get("isolation").and_then(|v| v.as_str()))?;
+
+        let mut env = Vec::new();
+        match data.get("env") {
+            None | Some(JsonValue::Null) => {}
+            Some(JsonValue::Object(map)) => {
+                for (k, v) in map {
+                    anyhow::ensure!(
+                        !k.is_empty()
+                            && !k.contains('=')
+                            && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                        "invalid env var name `{k}`"
+                    );
+                    anyhow::ensure!(
+                        !reserved_env_var(k),
+                        "env var `{k}` is reserved and cannot be set per run"
+                    );
+                    let v = v
+                        .as_str()
+                        .with_context(|| format!("env.{k} must be a string"))?;
+                    env.push((k.clone(), v.to_string()));
+                }
+            }
+            Some(_) => anyhow::bail!("env must be an object of strings"),
+        }
+
+        let initial_prompt = data
+            .get("initial_prompt")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .filter(|s| !s.trim().is_empty());
+
+        let allow_tools = match data.get("allow_tools") {
+            None | Some(JsonValue::Null) => Vec::new(),
+            Some(JsonValue::Array(items)) => items
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(|s| s.to_string())
+                        .context("allow_tools must be an array of strings")
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            Some(_) => anyhow::bail!("allow_tools must be an array of strings"),
+        };
+
+        let session_field = |k: &str| {
+            data.get(k)
+                .and_then(|v| v.as_str())
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let opencode_session = match (
+            session_field("resume_session_id"),
+            session_field("fork_from_session_id"),
+        ) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("resume_session_id and fork_from_session_id are mutually exclusive")
+            }
+            (Some(id), None) => Some(OpencodeSessionStart::Resume(id)),
+            (None, Some(id)) => Some(OpencodeSessionStart::Fork(id)),
+            (None, None) => None,
+        };
+        if let Some(OpencodeSessionStart::Resume(id) | OpencodeSessionStart::Fork(id)) =
+            opencode_session.as_ref()
+        {
+            crate::opencode_serve::check_session_id(id)?;
+        }
+
+        let budget = crate::budget::limits_from_request(data.get("budget"))?;
+
+        Ok(Self {
+            model,
+            sandbox,
+            isolation,
+            env,
+            initial_prompt,
+            allow_tools,
+            opencode_session,
+            budget,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IsolationMode {
+    #[default]
+    None,
+    /// Run inside a fresh `git worktree` on a `relay/<run_id>` branch.
+    Worktree,
+}
+
+impl IsolationMode {
+    pub fn parse(v: Option<&str>) -> anyhow::Result<Self> {
+        match v.map(|s| s.trim()).unwrap_or("") {
+            "" | "none" => Ok(Self::None),
+            "worktree" => Ok(Self::Worktree),
+            other => anyhow::bail!("unsupported isolation `{other}` (expected none|worktree)"),
+        }
+    }
+}
+
+/// Launch context (sandbox, worktree, env overrides) resolved by `start_run` and kept on the
+/// `Run`.
+#[derive(Debug, Clone, Default)]
+struct RunLaunch {
+    sandbox: Option<crate::sandbox::SandboxProfile>,
+    worktree: Option<crate::fs_git::RunWorktree>,
+    env: Vec<(String, String)>,
+    opencode_session: Option<OpencodeSessionStart>,
+}
+
+impl RunLaunch {
+    /// Adds the sandbox/worktree fields to a `run.started` payload.
+    fn describe_into(&self, cwd: &str, data: &mut JsonValue) {
+        let Some(obj) = data.as_object_mut() else {
+            return;
+        };
+        obj.insert(
+            "sandbox".to_string(),
+            crate::sandbox::describe_opt(self.sandbox.as_ref(), cwd),
+        );
+        if let Some(wt) = self.worktree.as_ref() {
+            obj.insert(
+                "isolation".to_string(),
+                JsonValue::String("worktree".to_string()),
+            );
+            obj.insert("worktree".to_string(), json!(wt));
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -310,6 +608,15 @@ struct PendingPermission {
     approve_text: String,
     deny_text: String,
     rpc_request_id: Option<i64>,
+    acp_request: Option<AcpPermissionRequest>,
+    opencode_permission: Option<crate::opencode_serve::PermissionAsk>,
+}
+
+/// An ACP `session/request_permission` awaiting a relay decision.
+#[derive(Clone)]
+struct AcpPermissionRequest {
+    rpc_id: JsonValue,
+    options: JsonValue,
 }
 
 #[derive(Clone)]
@@ -330,6 +637,78 @@ impl Run {
     fn next_seq(&self) -> i64 {
         self.seq.fetch_add(1, Ordering::SeqCst) + 1
     }
+
+    /// `run.exited` payload; worktree runs also advertise the branch awaiting
+    /// `rpc.run.worktree.cleanup`.
+    fn exited_data(&self, exit_code: i64) -> JsonValue {
+        let mut data = json!({ "exit_code": exit_code });
+        if let Some(wt) = self.launch.worktree.as_ref() {
+            data["worktree"] = json!({
+                "path": wt.path,
+                "branch": wt.branch,
+                "base_branch": wt.base_branch,
+                "dirty": crate::fs_git::worktree_is_dirty(wt).ok(),
+            });
+        }
+        data
+    }
+}
+
+/// Snapshots the run cwd (see `crate::checkpoints`) and announces it as `run.checkpoint`.
+async fn snapshot_run(
+    run: &Arc<Run>,
+    events: &broadcast::Sender<WsEnvelope>,
+    host_id: &str,
+    request_id: &str,
+    tool: &str,
+) -> Option<crate::checkpoints::Checkpoint> {
+    let run = run.clone();
+    let events = events.clone();
+    let host_id = host_id.to_string();
+    let request_id = request_id.to_string();
+    let tool = tool.to_string();
+    tokio::task::spawn_blocking(move || {
+        snapshot_run_blocking(&run, &events, &host_id, &request_id, &tool)
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::warn!(error=%e, "checkpoint task failed");
+        None
+    })
+}
+
+/// [`snapshot_run`] for the agent reader threads.
+fn snapshot_run_blocking(
+    run: &Run,
+    events: &broadcast::Sender<WsEnvelope>,
+    host_id: &str,
+    request_id: &str,
+    tool: &str,
+) -> Option<crate::checkpoints::Checkpoint> {
+    let checkpoint = match crate::checkpoints::create(&run.cwd, &run.run_id, request_id, tool) {
+        Ok(Some(c)) => c,
+        Ok(None) => return None,
+        Err((_, e)) => {
+            tracing::warn!(run_id=%run.run_id, error=%e, "checkpoint failed");
+            return None;
+        }
+    };
+    emit_run_event_blocking(events, host_id, run, "run.checkpoint", json!(checkpoint));
+    Some(checkpoint)
+}
+
+fn emit_run_event_blocking(
+    events: &broadcast::Sender<WsEnvelope>,
+    host_id: &str,
+    run: &Run,
+    r#type: &str,
+    data: JsonValue,
+) {
+    let mut env = WsEnvelope::new(r#type, data);
+    env.host_id = Some(host_id.to_string());
+    env.run_id = Some(run.run_id.clone());
+    env.seq = Some(run.next_seq());
+    let _ = events.send(env);
 }
 
 fn emit_run_metadata(
@@ -345,6 +724,170 @@ fn emit_run_metadata(
     let _ = events.send(env);
 }
 
+/// How long an approved transfer may sit idle before its grant lapses.
+const TRANSFER_GRANT_IDLE: Duration = Duration::from_secs(10 * 60);
+
+/// Approved `rpc.fs.download`/`rpc.fs.upload` transfers in progress, keyed by rpc type, path and
+/// the client's `transfer_id`, with the time the grant was last used.
+/// A grant lasts from the approved first chunk (offset 0) until the transfer completes, fails,
+/// starts over or idles for [`TRANSFER_GRANT_IDLE`], so every new transfer asks again. Chunks
+/// without a `transfer_id` are never granted: each one asks.
+#[derive(Default)]
+struct TransferGrants(HashMap<(String, String, String), std::time::Instant>);
+
+impl TransferGrants {
+    fn key(rpc_type: &str, data: &JsonValue) -> Option<(String, String, String)> {
+        let transfer_id = data
+            .get("transfer_id")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())?;
+        let path = data.get("path").and_then(|v| v.as_str()).unwrap_or("");
+        Some((
+            rpc_type.to_string(),
+            path.to_string(),
+            transfer_id.to_string(),
+        ))
+    }
+
+    /// Whether `data` starts a new transfer: a download from offset 0, or an upload chunk
+    /// written at offset 0 (a status query with no data continues the current one).
+    fn starts(rpc_type: &str, data: &JsonValue) -> bool {
+        let offset = data.get("offset").and_then(|v| v.as_u64()).unwrap_or(0);
+        let has_data = data
+            .get("data")
+            .and_then(|v| v.as_str())
+            .is_some_and(|s| !s.is_empty());
+        offset == 0 && (rpc_type == "rpc.fs.download" || has_data || data.get("sha256").is_some())
+    }
+
+    /// Whether the chunk may skip approval. A chunk that starts a transfer drops any earlier
+    /// grant for the same path first.
+    /// Drops grants idle for [`TRANSFER_GRANT_IDLE`] as of `now`.
+    fn expire(&mut self, now: std::time::Instant) {
+        self.0
+            .retain(|_, used| now.duration_since(*used) < TRANSFER_GRANT_IDLE);
+    }
+
+    fn admits(&mut self, rpc_type: &str, data: &JsonValue) -> bool {
+        self.expire(std::time::Instant::now());
+        let Some(key) = Self::key(rpc_type, data) else {
+            return false;
+        };
+        if Self::starts(rpc_type, data) {
+            self.0.remove(&key);
+            return false;
+        }
+        match self.0.get_mut(&key) {
+            Some(used) => {
+                *used = std::time::Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn grant(&mut self, rpc_type: &str, data: &JsonValue) {
+        if let Some(key) = Self::key(rpc_type, data) {
+            self.0.insert(key, std::time::Instant::now());
+        }
+    }
+
+    /// Ends the grant once a chunk finished the transfer (`eof`/`done`) or failed it. An upload
+    /// `offset mismatch` only tells the client where to resume, so it keeps the grant.
+    fn finish_chunk(&mut self, rpc_type: &str, data: &JsonValue, ok: bool, payload: &JsonValue) {
+        let ended = if ok {
+            let result = payload.get("result");
+            let flag = |k: &str| {
+                result
+                    .and_then(|r| r.get(k))
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false)
+            };
+            flag("eof") || flag("done")
+        } else {
+            let error = payload.get("error").and_then(|v| v.as_str()).unwrap_or("");
+            !error.starts_with("offset mismatch")
+        };
+        if ended && let Some(key) = Self::key(rpc_type, data) {
+            self.0.remove(&key);
+        }
+    }
+}
+
+/// `run.permission_requested` events not yet answered, keyed `<run_id>:<request_id>`. Requests
+/// are recorded where they are emitted, so none is missed; answers are applied as they are seen.
+#[derive(Clone, Default)]
+struct PermissionLog(Arc<StdMutex<HashMap<String, WsEnvelope>>>);
+
+impl PermissionLog {
+    /// Records a request, or forgets the request answered by `env` (`run.permission_decided`,
+    /// `run.input` or `tool.result` with the same id, or `run.exited`).
+    fn observe(&self, env: &WsEnvelope) {
+        let Some(run_id) = env.run_id.as_deref() else {
+            return;
+        };
+        let Ok(mut requests) = self.0.lock() else {
+            return;
+        };
+        let id_field = match env.r#type.as_str() {
+            "run.exited" => {
+                let prefix = format!("{run_id}:");
+                requests.retain(|k, _| !k.starts_with(&prefix));
+                return;
+            }
+            "run.input" => "input_id",
+            "run.permission_requested" | "run.permission_decided" | "tool.result" => "request_id",
+            _ => return,
+        };
+        let Some(request_id) = env.data.get(id_field).and_then(|v| v.as_str()) else {
+            return;
+        };
+        let key = format!("{run_id}:{request_id}");
+        if env.r#type == "run.permission_requested" {
+            requests.insert(key, env.clone());
+        } else {
+            requests.remove(&key);
+        }
+    }
+}
+
+/// Counts usage against the budgets where `run.usage` is emitted, so no event is missed; crossed
+/// limits are acted on by [`RunManager::enforce_budgets`].
+#[derive(Clone)]
+struct BudgetMeter {
+    budgets: Arc<StdMutex<crate::budget::Budgets>>,
+    verdicts: tokio::sync::mpsc::UnboundedSender<(String, crate::budget::Verdict)>,
+}
+
+type BudgetVerdicts = tokio::sync::mpsc::UnboundedReceiver<(String, crate::budget::Verdict)>;
+
+impl BudgetMeter {
+    fn record(&self, run_id: &str, usage: &JsonValue) {
+        let verdict = match self.budgets.lock() {
+            Ok(mut b) => b.record(run_id, usage),
+            Err(_) => return,
+        };
+        if let Some(v) = verdict {
+            let _ = self.verdicts.send((run_id.to_string(), v));
+        }
+    }
+}
+
+fn emit_run_usage(
+    events: &broadcast::Sender<WsEnvelope>,
+    host_id: &str,
+    run: &Run,
+    usage: &crate::usage::Usage,
+) {
+    let data = usage.to_json();
+    run.budget.record(&run.run_id, &data);
+    let mut env = WsEnvelope::new("run.usage", data);
+    env.host_id = Some(host_id.to_string());
+    env.run_id = Some(run.run_id.clone());
+    env.seq = Some(run.next_seq());
+    let _ = events.send(env);
+}
+
 #[cfg(unix)]
 fn signal_opencode_process_group(pid: i32, signal: nix::sys::signal::Signal) -> anyhow::Result<()> {
     use nix::sys::signal::kill;
@@ -379,7 +922,7 @@ async fn finalize_structured_run_exit(
         return;
     }
 
-    let mut env = WsEnvelope::new("run.exited", json!({ "exit_code": exit_code }));
+    let mut env = WsEnvelope::new("run.exited", run.exited_data(exit_code));
     env.host_id = Some(host_id);
     env.run_id = Some(run.run_id.clone());
     env.seq = Some(run.next_seq());
@@ -428,8 +971,11 @@ fn opencode_mode_setting() -> OpencodeModeSetting {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum OpencodePermissionSetting {
-    /// Preserve existing behavior: if OPENCODE_PERMISSION is unset, inject an allow-all policy so
-    /// non-TTY structured runs don't block on interactive permission prompts.
+    /// Default: `opencode serve` asks for edit/bash/webfetch and hostd forwards each request as
+    /// `run.permission_requested`. The process-per-prompt `run` backend cannot answer prompts, so
+    /// it falls back to allow-all.
+    Ask,
+    /// If OPENCODE_PERMISSION is unset, inject an allow-all policy so runs never block.
     AutoAllowAll,
     /// Never inject OPENCODE_PERMISSION; rely on whatever the user/exported environment provides.
     Inherit,
@@ -443,11 +989,69 @@ fn opencode_permission_setting() -> OpencodePermissionSetting {
         .to_ascii_lowercase();
     match v.as_str() {
         "inherit" | "env" => OpencodePermissionSetting::Inherit,
-        "auto" | "allow" | "allow_all" | "allow-all" | "" => {
-            OpencodePermissionSetting::AutoAllowAll
+        "auto" | "allow" | "allow_all" | "allow-all" => OpencodePermissionSetting::AutoAllowAll,
+        "ask" | "relay" | "" => OpencodePermissionSetting::Ask,
+        _ => OpencodePermissionSetting::Ask,
+    }
+}
+
+/// The OPENCODE_PERMISSION value hostd injects (unless the user exported one), given the backend.
+fn opencode_permission_env(serve: bool) -> Option<&'static str> {
+    if std::env::var_os("OPENCODE_PERMISSION").is_some() {
+        return None;
+    }
+    match opencode_permission_setting() {
+        OpencodePermissionSetting::Ask if serve => {
+            Some(r#"{"edit":"ask","bash":"ask","webfetch":"ask"}"#)
+        }
+        OpencodePermissionSetting::Ask | OpencodePermissionSetting::AutoAllowAll => {
+            Some(r#"{"*":"allow"}"#)
+        }
+        OpencodePermissionSetting::Inherit => None,
+    }
+}
+
+fn opencode_permission_mode(serve: bool) -> &'static str {
+    if std::env::var_os("OPENCODE_PERMISSION").is_some() {
+        return "env";
+    }
+    match opencode_permission_setting() {
+        OpencodePermissionSetting::Ask if serve => "relay_ask",
+        OpencodePermissionSetting::Ask | OpencodePermissionSetting::AutoAllowAll => {
+            "relay_auto_allow_all"
         }
-        _ => OpencodePermissionSetting::AutoAllowAll,
+        OpencodePermissionSetting::Inherit => "inherit",
+    }
+}
+
+/// Structured opencode runs use a long-lived `opencode serve` unless
+/// `RELAY_OPENCODE_BACKEND=run` asks for the older process-per-prompt `opencode run`.
+/// Sandboxes without network need `run` (hostd could not reach the server's port), which cannot
+/// ask for permissions; rather than silently allowing everything, ask-mode runs are refused.
+fn opencode_serve_enabled(
+    sandbox: Option<&crate::sandbox::SandboxProfile>,
+) -> anyhow::Result<bool> {
+    let v = std::env::var("RELAY_OPENCODE_BACKEND")
+        .ok()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if matches!(v.as_str(), "run" | "cli") {
+        return Ok(false);
+    }
+    if sandbox.is_none_or(|p| p.network) {
+        return Ok(true);
     }
+    anyhow::ensure!(
+        std::env::var_os("OPENCODE_PERMISSION").is_some()
+            || !matches!(
+                opencode_permission_setting(),
+                OpencodePermissionSetting::Ask
+            ),
+        "opencode cannot ask for permissions in a sandbox without network; allow sandbox.network \
+         or set RELAY_OPENCODE_PERMISSION_MODE=auto"
+    );
+    Ok(false)
 }
 
 fn codex_probe_timeout() -> Duration {
@@ -474,6 +1078,18 @@ fn pty_output_max_bytes() -> usize {
     v.clamp(1024, 1024 * 1024)
 }
 
+/// `rpc.bash` timeout: the request's `timeout_ms`, else `RELAY_BASH_TIMEOUT_MS` (default 10 min).
+fn bash_timeout(requested_ms: Option<u64>) -> Duration {
+    let ms = requested_ms
+        .or_else(|| {
+            std::env::var("RELAY_BASH_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+        })
+        .unwrap_or(600_000);
+    Duration::from_millis(ms.clamp(1_000, 24 * 60 * 60 * 1000))
+}
+
 fn opencode_silent_timeout() -> Duration {
     let ms = std::env::var("RELAY_OPENCODE_SILENT_TIMEOUT_MS")
         .ok()
@@ -667,70 +1283,671 @@ async fn codex_mcp_submit_prompt(
     Ok(())
 }
 
-async fn opencode_submit_prompt(
-    run: Arc<Run>,
-    runs: Arc<RwLock<HashMap<String, Arc<Run>>>>,
-    redactor: Arc<Redactor>,
-    events: broadcast::Sender<WsEnvelope>,
-    host_id: String,
-    prompt: String,
-) -> anyhow::Result<()> {
-    let _guard = run.opencode_call_lock.lock().await;
+fn emit_run_output(
+    events: &broadcast::Sender<WsEnvelope>,
+    host_id: &str,
+    run: &Run,
+    stream: &str,
+    text: String,
+) {
+    let mut env = WsEnvelope::new("run.output", json!({ "stream": stream, "text": text }));
+    env.host_id = Some(host_id.to_string());
+    env.run_id = Some(run.run_id.clone());
+    env.seq = Some(run.next_seq());
+    let _ = events.send(env);
+}
 
-    fn redact_json_value(redactor: &Redactor, v: &JsonValue) -> JsonValue {
-        fn walk(redactor: &Redactor, v: &JsonValue) -> JsonValue {
-            match v {
-                JsonValue::String(s) => JsonValue::String(redactor.redact(s).text_redacted),
-                JsonValue::Array(arr) => {
-                    JsonValue::Array(arr.iter().map(|x| walk(redactor, x)).collect())
-                }
-                JsonValue::Object(map) => JsonValue::Object(
-                    map.iter()
-                        .map(|(k, val)| (k.clone(), walk(redactor, val)))
-                        .collect(),
-                ),
-                _ => v.clone(),
-            }
+fn redact_json_with(redactor: &Redactor, v: &JsonValue) -> JsonValue {
+    match v {
+        JsonValue::String(s) => JsonValue::String(redactor.redact(s).text_redacted),
+        JsonValue::Array(arr) => {
+            JsonValue::Array(arr.iter().map(|x| redact_json_with(redactor, x)).collect())
         }
-        walk(redactor, v)
+        JsonValue::Object(map) => JsonValue::Object(
+            map.iter()
+                .map(|(k, val)| (k.clone(), redact_json_with(redactor, val)))
+                .collect(),
+        ),
+        _ => v.clone(),
     }
+}
 
-    let bin = resolve_opencode_structured_bin(&crate::runners::resolve_tool_bin(
-        "opencode",
-        "RELAY_OPENCODE_BIN",
-        "opencode",
-    ));
-    crate::runners::validate_bin_exists(
-        &bin,
-        "opencode (set RELAY_OPENCODE_BIN=/path/to/opencode or install shims to record real path)",
-    )?;
+async fn acp_write(run: &Run, msg: &JsonValue) -> anyhow::Result<()> {
+    let mut w = run.writer.lock().await;
+    w.write_all(msg.to_string().as_bytes())
+        .context("write acp message")?;
+    w.write_all(b"\n").context("write newline")?;
+    w.flush().ok();
+    Ok(())
+}
 
-    let session_id = run.opencode_session_id.lock().ok().and_then(|v| v.clone());
-    let temp_config_home = TempOpencodeConfigHome::create(run.opencode_model.as_deref())?;
+/// Same as [`acp_write`], for the stdout reader thread.
+fn acp_write_blocking(run: &Run, msg: &JsonValue) {
+    let mut w = run.writer.blocking_lock();
+    let _ = w.write_all(msg.to_string().as_bytes());
+    let _ = w.write_all(b"\n");
+    let _ = w.flush();
+}
 
-    let mut child_cmd = Command::new(&bin);
-    child_cmd.arg("run").arg("--format").arg("json");
-    if let Some(session_id) = session_id.as_deref().filter(|s| !s.trim().is_empty()) {
-        child_cmd.arg("--session").arg(session_id);
+async fn acp_rpc_request(
+    run: &Arc<Run>,
+    method: &str,
+    params: JsonValue,
+    timeout: Option<Duration>,
+) -> anyhow::Result<JsonValue> {
+    let acp = run.acp.as_ref().context("acp session missing")?;
+    let (id, rx) = acp.register();
+    if let Err(e) = acp_write(run, &crate::acp::request(id, method, params)).await {
+        acp.forget(id);
+        return Err(e);
     }
-    child_cmd.arg(&prompt);
-
-    // Avoid interactive permission prompts in non-TTY mode.
-    // Default remains auto-allow (for backward compatibility), but can be disabled.
-    if std::env::var_os("OPENCODE_PERMISSION").is_none()
-        && opencode_permission_setting() == OpencodePermissionSetting::AutoAllowAll
-    {
-        child_cmd.env("OPENCODE_PERMISSION", r#"{"*":"allow"}"#);
+    match timeout {
+        Some(t) => match tokio::time::timeout(t, rx).await {
+            Ok(v) => v.context("acp agent exited"),
+            Err(_) => {
+                acp.forget(id);
+                Err(anyhow::anyhow!("acp {method} timed out"))
+            }
+        },
+        None => rx.await.context("acp agent exited"),
     }
-    child_cmd.env("XDG_CONFIG_HOME", temp_config_home.path());
+}
 
-    child_cmd.current_dir(&run.cwd);
-    child_cmd.stdin(Stdio::null());
-    child_cmd.stdout(Stdio::piped());
-    child_cmd.stderr(Stdio::piped());
+async fn acp_submit_prompt(
+    run: Arc<Run>,
+    events: broadcast::Sender<WsEnvelope>,
+    host_id: String,
+    prompt: String,
+) -> anyhow::Result<()> {
+    let acp = run.acp.clone().context("acp session missing")?;
+    let _guard = acp.prompt_lock.lock().await;
+    let session_id = acp.session_id().context("acp session not started")?;
+
+    let resp = acp_rpc_request(
+        &run,
+        "session/prompt",
+        json!({
+            "sessionId": session_id,
+            "prompt": [{ "type": "text", "text": prompt }],
+        }),
+        None,
+    )
+    .await?;
+    if acp.take_mid_line() {
+        emit_run_output(&events, &host_id, &run, "stdout", "\n".to_string());
+    }
+    if let Some(err) = resp.get("error") {
+        emit_run_output(
+            &events,
+            &host_id,
+            &run,
+            "stderr",
+            format!("acp session/prompt error: {err}\n"),
+        );
+        return Ok(());
+    }
+    let stop_reason = resp
+        .get("result")
+        .and_then(|r| r.get("stopReason"))
+        .and_then(|v| v.as_str())
+        .unwrap_or("end_turn");
+    if stop_reason != "end_turn" {
+        emit_run_output(
+            &events,
+            &host_id,
+            &run,
+            "stderr",
+            format!("acp prompt ended: {stop_reason}\n"),
+        );
+    }
+    Ok(())
+}
+
+/// Reads the agent's stdout: responses go to their waiters, `session/update` notifications
+/// become relay events and agent-initiated requests are answered.
+fn acp_read_loop(
+    run: Arc<Run>,
+    events: broadcast::Sender<WsEnvelope>,
+    host_id: String,
+    redactor: Arc<Redactor>,
+    stdout: impl Read,
+) {
+    let Some(acp) = run.acp.clone() else {
+        return;
+    };
+    let mut r = BufReader::new(stdout);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match r.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let raw = line.trim_end_matches(&['\r', '\n'][..]);
+        if raw.is_empty() {
+            continue;
+        }
+        let Ok(v) = serde_json::from_str::<JsonValue>(raw) else {
+            emit_run_output(&events, &host_id, &run, "stdout", line.clone());
+            continue;
+        };
+        let method = v.get("method").and_then(|m| m.as_str());
+        let rpc_id = v.get("id").filter(|id| !id.is_null()).cloned();
+        let params = v.get("params").cloned().unwrap_or(JsonValue::Null);
+
+        match (method, rpc_id) {
+            (None, Some(id)) => {
+                if let Some(id) = id.as_i64() {
+                    acp.resolve(id, v);
+                }
+            }
+            (Some("session/update"), None) => {
+                let update = params.get("update").unwrap_or(&JsonValue::Null);
+                for (t, data) in acp.map_update(&run.tool, update) {
+                    let data = if t == "run.output" {
+                        data
+                    } else {
+                        redact_json_with(&redactor, &data)
+                    };
+                    if t == "run.usage" {
+                        run.budget.record(&run.run_id, &data);
+                    }
+                    let mut env = WsEnvelope::new(t, data);
+                    env.host_id = Some(host_id.clone());
+                    env.run_id = Some(run.run_id.clone());
+                    env.seq = Some(run.next_seq());
+                    let _ = events.send(env);
+                }
+            }
+            (Some("session/request_permission"), Some(id)) => {
+                let options = params.get("options").cloned().unwrap_or(JsonValue::Null);
+                let op_tool = crate::acp::permission_op_tool(&params);
+                if run.session_allow_tools.blocking_lock().contains(&op_tool) {
+                    let outcome = crate::acp::permission_outcome(&options, true);
+                    acp_write_blocking(&run, &crate::acp::response(&id, outcome));
+                    continue;
+                }
+
+                let prompt = crate::acp::permission_prompt(&params);
+                let op_args = params
+                    .get("toolCall")
+                    .and_then(|t| t.get("rawInput"))
+                    .map(|v| redact_json_with(&redactor, v))
+                    .unwrap_or(JsonValue::Null);
+                let request_id = uuid::Uuid::new_v4().to_string();
+                *run.pending_permission.blocking_lock() = Some(PendingPermission {
+                    request_id: request_id.clone(),
+                    reason: "permission".to_string(),
+                    prompt: prompt.clone(),
+                    approve_text: "".to_string(),
+                    deny_text: "".to_string(),
+                    rpc_request_id: None,
+                    acp_request: Some(AcpPermissionRequest {
+                        rpc_id: id,
+                        options,
+                    }),
+                    opencode_permission: None,
+                });
+                *run.awaiting_input.blocking_lock() = true;
+
+                let mut pr = WsEnvelope::new(
+                    "run.permission_requested",
+                    json!({
+                        "request_id": request_id,
+                        "reason": "permission",
+                        "prompt": prompt,
+                        "op_tool": op_tool,
+                        "op_args": op_args,
+                        "approve_text": "",
+                        "deny_text": ""
+                    }),
+                );
+                pr.host_id = Some(host_id.clone());
+                pr.run_id = Some(run.run_id.clone());
+                pr.seq = Some(run.next_seq());
+                run.permissions.observe(&pr);
+                let _ = events.send(pr);
+
+                let mut p = WsEnvelope::new(
+                    "run.awaiting_input",
+                    json!({
+                        "reason": "permission",
+                        "prompt": prompt,
+                        "request_id": request_id
+                    }),
+                );
+                p.host_id = Some(host_id.clone());
+                p.run_id = Some(run.run_id.clone());
+                p.seq = Some(run.next_seq());
+                let _ = events.send(p);
+            }
+            (Some("fs/write_text_file"), Some(id)) => {
+                // Same bookkeeping as `rpc.fs.write`: a tool call, a checkpoint of what the
+                // write replaces, then the result.
+                let request_id = uuid::Uuid::new_v4().to_string();
+                let started = std::time::Instant::now();
+                let tool = "fs/write_text_file";
+                let content = params.get("content").and_then(|v| v.as_str()).unwrap_or("");
+                let preview_limit = 2000;
+                emit_run_event_blocking(
+                    &events,
+                    &host_id,
+                    &run,
+                    "tool.call",
+                    json!({
+                        "request_id": request_id,
+                        "tool": tool,
+                        "actor": run.tool,
+                        "args": {
+                            "path": params.get("path").cloned().unwrap_or(JsonValue::Null),
+                            "bytes": content.len(),
+                            "content_preview": redactor
+                                .redact(&content.chars().take(preview_limit).collect::<String>())
+                                .text_redacted,
+                            "content_truncated": content.chars().count() > preview_limit,
+                        }
+                    }),
+                );
+                if crate::checkpoints::enabled() {
+                    snapshot_run_blocking(&run, &events, &host_id, &request_id, tool);
+                }
+                let result = crate::acp::handle_fs_request(&run.cwd, tool, &params);
+                let (ok, error) = match &result {
+                    Ok(_) => (true, JsonValue::Null),
+                    Err(e) => (false, json!(e)),
+                };
+                emit_run_event_blocking(
+                    &events,
+                    &host_id,
+                    &run,
+                    "tool.result",
+                    json!({
+                        "request_id": request_id,
+                        "tool": tool,
+                        "actor": run.tool,
+                        "ok": ok,
+                        "duration_ms": started.elapsed().as_millis() as i64,
+                        "error": error
+                    }),
+                );
+                let resp = match result {
+                    Ok(result) => crate::acp::response(&id, result),
+                    Err(e) => crate::acp::error_response(&id, -32603, &e),
+                };
+                acp_write_blocking(&run, &resp);
+            }
+            (Some(m @ "fs/read_text_file"), Some(id)) => {
+                let resp = match crate::acp::handle_fs_request(&run.cwd, m, &params) {
+                    Ok(result) => crate::acp::response(&id, result),
+                    Err(e) => crate::acp::error_response(&id, -32603, &e),
+                };
+                acp_write_blocking(&run, &resp);
+            }
+            (Some(m), Some(id)) => {
+                let msg = format!("method not supported by relay: {m}");
+                acp_write_blocking(&run, &crate::acp::error_response(&id, -32601, &msg));
+            }
+            _ => {}
+        }
+    }
+    acp.fail_all();
+}
+
+fn spawn_opencode_serve(
+    cwd: &str,
+    model: Option<&str>,
+    launch: &RunLaunch,
+) -> anyhow::Result<(OpencodeServe, std::process::Child)> {
+    let bin = resolve_opencode_structured_bin(&crate::runners::resolve_tool_bin(
+        "opencode",
+        "RELAY_OPENCODE_BIN",
+        "opencode",
+    ));
+    crate::runners::validate_bin_exists(
+        &bin,
+        "opencode (set RELAY_OPENCODE_BIN=/path/to/opencode or install shims to record real path)",
+    )?;
+    let config_home = TempOpencodeConfigHome::create(model)?;
+    let port = crate::opencode_serve::free_port()?;
+    let password = crate::opencode_serve::new_password();
+
+    let mut child_cmd = match launch.sandbox.as_ref() {
+        Some(profile) => profile
+            .clone()
+            .with_ro_path(config_home.path())
+            .command(cwd, &bin)?,
+        None => Command::new(&bin),
+    };
+    child_cmd
+        .arg("serve")
+        .arg("--hostname")
+        .arg("127.0.0.1")
+        .arg("--port")
+        .arg(port.to_string());
+    child_cmd.envs(launch.env.iter().map(|(k, v)| (k, v)));
+    if let Some(policy) = opencode_permission_env(true) {
+        child_cmd.env("OPENCODE_PERMISSION", policy);
+    }
+    child_cmd.env("XDG_CONFIG_HOME", config_home.path());
+    child_cmd.env(
+        "OPENCODE_SERVER_USERNAME",
+        crate::opencode_serve::SERVER_USERNAME,
+    );
+    child_cmd.env("OPENCODE_SERVER_PASSWORD", &password);
+    child_cmd.current_dir(cwd);
+    child_cmd.stdin(Stdio::null());
+    child_cmd.stdout(Stdio::null());
+    child_cmd.stderr(Stdio::piped());
+    #[cfg(unix)]
+    child_cmd.process_group(0);
+
+    crate::config::strip_secret_env(&mut child_cmd);
+    let child = child_cmd.spawn().context("spawn opencode serve")?;
+    let server = crate::opencode_serve::OpencodeServer::new(port, child.id() as i32, &password);
+    Ok((
+        OpencodeServe {
+            server,
+            _config_home: config_home,
+        },
+        child,
+    ))
+}
+
+/// Attempts at starting `opencode serve` before giving up; see [`start_opencode_serve`].
+const OPENCODE_SERVE_START_ATTEMPTS: usize = 3;
+
+/// Spawns `opencode serve` and waits until it answers and is known to require its password.
+///
+/// The port is probed before the server binds it, so another process can take it in between; a
+/// server that exits before it is ready is retried on a fresh port.
+async fn start_opencode_serve(
+    cwd: &str,
+    model: Option<&str>,
+    launch: &RunLaunch,
+) -> anyhow::Result<(OpencodeServe, std::process::Child)> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let (serve, mut child) = spawn_opencode_serve(cwd, model, launch)?;
+        let ready = match serve
+            .server
+            .wait_ready(|| child.try_wait().ok().flatten().is_some())
+            .await
+        {
+            Ok(()) => serve.server.check_auth().await,
+            Err(e) => Err(e),
+        };
+        // Whoever answered may not be our child if it lost the port.
+        let exited = child.try_wait().ok().flatten();
+        match (ready, exited) {
+            (Ok(()), None) => return Ok((serve, child)),
+            (Err(e), None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+            (_, Some(status)) if attempt >= OPENCODE_SERVE_START_ATTEMPTS => {
+                anyhow::bail!("opencode serve exited before it was ready ({status})");
+            }
+            (_, Some(status)) => {
+                tracing::debug!(%status, attempt, "opencode serve exited early; retrying");
+            }
+        }
+    }
+}
+
+/// Follows the server's event stream for the lifetime of the run, reconnecting if it drops.
+async fn opencode_serve_event_loop(
+    run: Arc<Run>,
+    runs: Arc<RwLock<HashMap<String, Arc<Run>>>>,
+    redactor: Arc<Redactor>,
+    events: broadcast::Sender<WsEnvelope>,
+    host_id: String,
+) {
+    let Some(serve) = run.opencode_serve.as_ref() else {
+        return;
+    };
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<JsonValue>();
+    {
+        let run = run.clone();
+        let runs = runs.clone();
+        tokio::spawn(async move {
+            let Some(serve) = run.opencode_serve.as_ref() else {
+                return;
+            };
+            loop {
+                let _ = serve
+                    .server
+                    .events(|ev| {
+                        let _ = tx.send(ev);
+                    })
+                    .await;
+                if !runs.read().await.contains_key(&run.run_id) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+        });
+    }
+
+    let mut mapper = crate::opencode_serve::EventMapper::default();
+    while let Some(ev) = rx.recv().await {
+        let session_id = run
+            .opencode_session_id
+            .lock()
+            .ok()
+            .and_then(|s| s.clone())
+            .unwrap_or_default();
+        for mapped in mapper.map(&session_id, &ev) {
+            match mapped {
+                crate::opencode_serve::ServeEvent::Relay(t, data) => {
+                    let data = if t == "run.output" {
+                        data
+                    } else {
+                        redact_json_with(&redactor, &data)
+                    };
+                    if t == "run.usage" {
+                        run.budget.record(&run.run_id, &data);
+                    }
+                    let mut env = WsEnvelope::new(t, data);
+                    env.host_id = Some(host_id.clone());
+                    env.run_id = Some(run.run_id.clone());
+                    env.seq = Some(run.next_seq());
+                    let _ = events.send(env);
+                }
+                crate::opencode_serve::ServeEvent::Error(msg) => {
+                    emit_run_output(
+                        &events,
+                        &host_id,
+                        &run,
+                        "stderr",
+                        format!("opencode error: {msg}\n"),
+                    );
+                }
+                crate::opencode_serve::ServeEvent::Permission(ask) => {
+                    if run.session_allow_tools.lock().await.contains(&ask.op_tool) {
+                        if let Err(e) = serve
+                            .server
+                            .reply_permission(&ask.session_id, &ask.id, "once")
+                            .await
+                        {
+                            tracing::warn!(run_id=%run.run_id, error=%e, "opencode permission reply failed");
+                        }
+                        continue;
+                    }
+                    let request_id = uuid::Uuid::new_v4().to_string();
+                    let prompt = redactor.redact(&ask.title).text_redacted;
+                    let op_tool = ask.op_tool.clone();
+                    let op_args = redact_json_with(&redactor, &ask.metadata);
+                    let op_args_summary = redactor.redact(&ask.summary()).text_redacted;
+                    *run.pending_permission.lock().await = Some(PendingPermission {
+                        request_id: request_id.clone(),
+                        reason: "permission".to_string(),
+                        prompt: prompt.clone(),
+                        approve_text: "".to_string(),
+                        deny_text: "".to_string(),
+                        rpc_request_id: None,
+                        acp_request: None,
+                        opencode_permission: Some(ask),
+                    });
+                    *run.awaiting_input.lock().await = true;
+
+                    let mut pr = WsEnvelope::new(
+                        "run.permission_requested",
+                        json!({
+                            "request_id": request_id,
+                            "reason": "permission",
+                            "prompt": prompt,
+                            "op_tool": op_tool,
+                            "op_args": op_args,
+                            "op_args_summary": op_args_summary,
+                            "approve_text": "",
+                            "deny_text": ""
+                        }),
+                    );
+                    pr.host_id = Some(host_id.clone());
+                    pr.run_id = Some(run.run_id.clone());
+                    pr.seq = Some(run.next_seq());
+                    run.permissions.observe(&pr);
+                    let _ = events.send(pr);
+
+                    let mut p = WsEnvelope::new(
+                        "run.awaiting_input",
+                        json!({
+                            "reason": "permission",
+                            "prompt": prompt,
+                            "request_id": request_id
+                        }),
+                    );
+                    p.host_id = Some(host_id.clone());
+                    p.run_id = Some(run.run_id.clone());
+                    p.seq = Some(run.next_seq());
+                    let _ = events.send(p);
+                }
+            }
+        }
+    }
+}
+
+/// Aborting a session rejects its outstanding permission asks, so drop the matching relay card.
+async fn clear_opencode_permission(run: &Run) {
+    let mut pending = run.pending_permission.lock().await;
+    if pending
+        .as_ref()
+        .is_some_and(|p| p.opencode_permission.is_some())
+    {
+        *pending = None;
+        *run.awaiting_input.lock().await = false;
+    }
+}
+
+async fn opencode_serve_submit_prompt(
+    run: Arc<Run>,
+    events: broadcast::Sender<WsEnvelope>,
+    host_id: String,
+    prompt: String,
+) -> anyhow::Result<()> {
+    let serve = run
+        .opencode_serve
+        .as_ref()
+        .context("opencode serve missing")?;
+    let _guard = run.opencode_call_lock.lock().await;
+    let session_id = run
+        .opencode_session_id
+        .lock()
+        .ok()
+        .and_then(|s| s.clone())
+        .context("opencode session not started")?;
+    let resp = serve.server.prompt(&session_id, &prompt).await?;
+    if let Some(err) = resp.get("info").and_then(|i| i.get("error")) {
+        let msg = err
+            .get("data")
+            .and_then(|d| d.get("message"))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| err.to_string());
+        emit_run_output(
+            &events,
+            &host_id,
+            &run,
+            "stderr",
+            format!("opencode error: {msg}\n"),
+        );
+    }
+    Ok(())
+}
+
+async fn opencode_submit_prompt(
+    run: Arc<Run>,
+    runs: Arc<RwLock<HashMap<String, Arc<Run>>>>,
+    redactor: Arc<Redactor>,
+    events: broadcast::Sender<WsEnvelope>,
+    host_id: String,
+    prompt: String,
+) -> anyhow::Result<()> {
+    let _guard = run.opencode_call_lock.lock().await;
+
+    fn redact_json_value(redactor: &Redactor, v: &JsonValue) -> JsonValue {
+        fn walk(redactor: &Redactor, v: &JsonValue) -> JsonValue {
+            match v {
+                JsonValue::String(s) => JsonValue::String(redactor.redact(s).text_redacted),
+                JsonValue::Array(arr) => {
+                    JsonValue::Array(arr.iter().map(|x| walk(redactor, x)).collect())
+                }
+                JsonValue::Object(map) => JsonValue::Object(
+                    map.iter()
+                        .map(|(k, val)| (k.clone(), walk(redactor, val)))
+                        .collect(),
+                ),
+                _ => v.clone(),
+            }
+        }
+        walk(redactor, v)
+    }
+
+    let bin = resolve_opencode_structured_bin(&crate::runners::resolve_tool_bin(
+        "opencode",
+        "RELAY_OPENCODE_BIN",
+        "opencode",
+    ));
+    crate::runners::validate_bin_exists(
+        &bin,
+        "opencode (set RELAY_OPENCODE_BIN=/path/to/opencode or install shims to record real path)",
+    )?;
+
+    let session_id = run.opencode_session_id.lock().ok().and_then(|v| v.clone());
+    let temp_config_home = TempOpencodeConfigHome::create(run.opencode_model.as_deref())?;
+
+    let mut child_cmd = match run.launch.sandbox.as_ref() {
+        // The temp config home lives under the sandbox's private /tmp, so expose it explicitly.
+        Some(profile) => profile
+            .clone()
+            .with_ro_path(temp_config_home.path())
+            .command(&run.cwd, &bin)?,
+        None => Command::new(&bin),
+    };
+    child_cmd.arg("run").arg("--format").arg("json");
+    child_cmd.envs(run.launch.env.iter().map(|(k, v)| (k, v)));
+    if let Some(session_id) = session_id.as_deref().filter(|s| !s.trim().is_empty()) {
+        child_cmd.arg("--session").arg(session_id);
+    }
+    child_cmd.arg(&prompt);
+
+    // Avoid interactive permission prompts in non-TTY mode: `opencode run` has no way to answer
+    // them, so this backend auto-allows unless RELAY_OPENCODE_PERMISSION_MODE=inherit.
+    if let Some(policy) = opencode_permission_env(false) {
+        child_cmd.env("OPENCODE_PERMISSION", policy);
+    }
+    child_cmd.env("XDG_CONFIG_HOME", temp_config_home.path());
+
+    child_cmd.current_dir(&run.cwd);
+    child_cmd.stdin(Stdio::null());
+    child_cmd.stdout(Stdio::piped());
+    child_cmd.stderr(Stdio::piped());
     #[cfg(unix)]
     child_cmd.process_group(0);
 
+    crate::config::strip_secret_env(&mut child_cmd);
     let mut child = child_cmd.spawn().context("spawn opencode run")?;
     let pid = child.id() as i32;
     if let Ok(mut p) = run.opencode_active_pid.lock() {
@@ -824,6 +2041,7 @@ async fn opencode_submit_prompt(
     // Parse JSONL events from stdout and map them to relay events.
     let mut r = BufReader::new(stdout);
     let mut line = String::new();
+    let mut last_todos: Option<JsonValue> = None;
     loop {
         line.clear();
         match r.read_line(&mut line) {
@@ -951,6 +2169,33 @@ async fn opencode_submit_prompt(
                         result.run_id = Some(run.run_id.clone());
                         result.seq = Some(run.next_seq());
                         let _ = events.send(result);
+
+                        let state = part.get("state").unwrap_or(&JsonValue::Null);
+                        if let Some(todos) = crate::opencode_serve::todo_snapshot(tool, state)
+                            && last_todos.as_ref() != Some(&todos)
+                        {
+                            last_todos = Some(todos.clone());
+                            let mut env = WsEnvelope::new(
+                                "run.todos",
+                                json!({
+                                    "todos": redact_json_value(&redactor, &todos),
+                                    "tool": tool,
+                                }),
+                            );
+                            env.host_id = Some(host_id.clone());
+                            env.run_id = Some(run.run_id.clone());
+                            env.seq = Some(run.next_seq());
+                            let _ = events.send(env);
+                        }
+                    }
+                    "step_finish" => {
+                        let part = v.get("part").unwrap_or(&JsonValue::Null);
+                        if let Some(usage) = crate::usage::Usage::from_opencode_step(
+                            part,
+                            run.opencode_model.as_deref(),
+                        ) {
+                            emit_run_usage(&events, &host_id, &run, &usage);
+                        }
                     }
                     "error" => {
                         let err = v.get("error").cloned().unwrap_or(JsonValue::Null);
@@ -1035,12 +2280,37 @@ impl RunManager {
         redactor: Arc<Redactor>,
         events: broadcast::Sender<WsEnvelope>,
     ) -> Self {
+        let budgets = Arc::new(StdMutex::new(crate::budget::Budgets::load()));
+        let (verdicts, verdicts_rx) = tokio::sync::mpsc::unbounded_channel();
         Self {
             host_id,
             local_unix_socket,
             redactor,
             events,
             runs: Arc::new(RwLock::new(HashMap::new())),
+            worktrees: Arc::new(RwLock::new(HashMap::new())),
+            worktree_seqs: Arc::new(StdMutex::new(HashMap::new())),
+            budget_meter: BudgetMeter {
+                budgets: budgets.clone(),
+                verdicts,
+            },
+            budget_verdicts: Arc::new(StdMutex::new(Some(verdicts_rx))),
+            budgets,
+            file_trackers: Arc::new(StdMutex::new(Default::default())),
+            bash_cancels: Arc::new(StdMutex::new(HashMap::new())),
+            search_cancels: Arc::new(StdMutex::new(HashMap::new())),
+            lsp: crate::lsp::LspPool::start(crate::lsp::load_specs()),
+            fs_watches: Arc::new(StdMutex::new(HashMap::new())),
+            fs_watch_limits: crate::fs_watch::Limits::from_env(),
+            run_token_key: Arc::new({
+                let mut key = [0u8; 32];
+                key[..16].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+                key[16..].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+                key
+            }),
+            tmux_pane_pids: Arc::new(StdMutex::new(HashMap::new())),
+            permission_log: PermissionLog::default(),
+            opencode_api_serves: Arc::new(StdMutex::new(HashMap::new())),
         }
     }
 
@@ -1048,30 +2318,256 @@ impl RunManager {
         self.events.subscribe()
     }
 
-    pub fn host_id_value(&self) -> String {
-        self.host_id.clone()
+    /// Capability token for the local API, scoped to `run_id`: `<run_id>.<mac>`.
+    pub fn run_token(&self, run_id: &str) -> String {
+        use sha2::Digest;
+        let mut h = sha2::Sha256::new();
+        h.update(self.run_token_key.as_slice());
+        h.update(run_id.as_bytes());
+        format!("{run_id}.{}", hex::encode(&h.finalize()[..16]))
     }
 
-    pub fn redact_string(&self, raw: &str) -> String {
-        self.redactor.redact(raw).text_redacted
+    /// The run a `RELAY_RUN_TOKEN` was issued for.
+    pub fn verify_run_token(&self, token: &str) -> Option<String> {
+        let (run_id, _) = token.rsplit_once('.')?;
+        constant_time_eq(&self.run_token(run_id), token).then(|| run_id.to_string())
     }
 
-    pub fn redact_json_value(&self, v: &JsonValue) -> JsonValue {
-        fn walk(redactor: &Redactor, v: &JsonValue) -> JsonValue {
-            match v {
-                JsonValue::String(s) => JsonValue::String(redactor.redact(s).text_redacted),
-                JsonValue::Array(arr) => {
-                    JsonValue::Array(arr.iter().map(|x| walk(redactor, x)).collect())
-                }
-                JsonValue::Object(map) => JsonValue::Object(
-                    map.iter()
-                        .map(|(k, val)| (k.clone(), walk(redactor, val)))
-                        .collect(),
-                ),
-                _ => v.clone(),
+    /// Whether any run is active.
+    pub async fn has_runs(&self) -> bool {
+        !self.runs.read().await.is_empty()
+    }
+
+    /// The run whose process tree contains `pid` (via `/proc`), so that callers spawned by a run
+    /// are confined to it even without presenting its token. `None` also when the walk fails, so
+    /// callers must not treat it as proof that `pid` is outside every run.
+    pub async fn run_for_pid(&self, pid: i32) -> Option<String> {
+        let roots = {
+            let runs = self.runs.read().await;
+            let mut roots: HashMap<i32, String> = HashMap::new();
+            if let Ok(mut pane_pids) = self.tmux_pane_pids.lock() {
+                pane_pids.retain(|run_id, _| runs.contains_key(run_id));
+                for (run_id, pid) in pane_pids.iter() {
+                    roots.insert(*pid, run_id.clone());
+                }
+            }
+            for (run_id, run) in runs.iter() {
+                if run.pid > 1 {
+                    roots.insert(run.pid, run_id.clone());
+                }
+                if let Some(pid) = run.opencode_active_pid.lock().ok().and_then(|p| *p)
+                    && pid > 1
+                {
+                    roots.insert(pid, run_id.clone());
+                }
+            }
+            roots
+        };
+        if roots.is_empty() {
+            return None;
+        }
+        // `/proc` reads block.
+        tokio::task::spawn_blocking(move || run_for_pid_blocking(pid, &roots))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub fn host_id_value(&self) -> String {
+        self.host_id.clone()
+    }
+
+    pub fn redact_string(&self, raw: &str) -> String {
+        self.redactor.redact(raw).text_redacted
+    }
+
+    pub fn redact_json_value(&self, v: &JsonValue) -> JsonValue {
+        redact_json_with(&self.redactor, v)
+    }
+
+    /// A server for `rpc.opencode.*`: a live serve-backed run in the same cwd and sandbox when
+    /// there is one, otherwise a `opencode serve` of our own that stays up while in use and for
+    /// [`OPENCODE_API_IDLE`] after (the returned guard marks it in use).
+    async fn opencode_api_server(
+        &self,
+        cwd: &str,
+        sandbox: Option<crate::sandbox::SandboxProfile>,
+    ) -> anyhow::Result<(
+        crate::opencode_serve::OpencodeServer,
+        Option<OpencodeApiLease>,
+    )> {
+        // Same adjustments `start_run` applies, so a run started with this profile matches.
+        let sandbox = sandbox.map(|p| {
+            p.with_tool_dirs("opencode")
+                .with_ro_path(self.local_unix_socket.clone())
+        });
+        {
+            let runs = self.runs.read().await;
+            if let Some(serve) = runs
+                .values()
+                .filter(|r| r.cwd == cwd && r.launch.sandbox == sandbox)
+                .find_map(|r| r.opencode_serve.as_ref())
+            {
+                return Ok((serve.server.clone(), None));
+            }
+        }
+        let cached = self
+            .opencode_api_serves
+            .lock()
+            .ok()
+            .and_then(|m| m.get(cwd).cloned())
+            .filter(|e| e.sandbox == sandbox && e.is_alive());
+        if let Some(ephemeral) = cached {
+            return Ok((
+                ephemeral.serve.server.clone(),
+                Some(OpencodeApiLease(ephemeral)),
+            ));
+        }
+
+        let launch = RunLaunch {
+            sandbox: sandbox.clone(),
+            worktree: None,
+            env: Vec::new(),
+            opencode_session: None,
+        };
+        let (serve, mut child) = start_opencode_serve(cwd, None, &launch)
+            .await
+            .context("start opencode serve")?;
+        if let Some(stderr) = child.stderr.take() {
+            // Nobody reads it; drain so a chatty server cannot block on a full pipe.
+            std::thread::spawn(move || {
+                let _ = std::io::copy(&mut BufReader::new(stderr), &mut std::io::sink());
+            });
+        }
+        let ephemeral = Arc::new(EphemeralOpencodeServe {
+            serve,
+            sandbox,
+            child: StdMutex::new(Some(child)),
+            last_used: StdMutex::new(std::time::Instant::now()),
+        });
+        let replaced = self
+            .opencode_api_serves
+            .lock()
+            .ok()
+            .and_then(|mut m| m.insert(cwd.to_string(), ephemeral.clone()));
+        drop(replaced);
+        tokio::spawn(
+            self.clone()
+                .expire_opencode_api_server(cwd.to_string(), ephemeral.clone()),
+        );
+        Ok((
+            ephemeral.serve.server.clone(),
+            Some(OpencodeApiLease(ephemeral)),
+        ))
+    }
+
+    /// Stops `ephemeral` once it has been idle for [`OPENCODE_API_IDLE`] with no request using it.
+    async fn expire_opencode_api_server(self, cwd: String, ephemeral: Arc<EphemeralOpencodeServe>) {
+        let ephemeral = Arc::downgrade(&ephemeral);
+        loop {
+            tokio::time::sleep(OPENCODE_API_IDLE).await;
+            let Some(current) = ephemeral.upgrade() else {
+                return;
+            };
+            let removed = {
+                let Ok(mut map) = self.opencode_api_serves.lock() else {
+                    return;
+                };
+                match map.get(&cwd) {
+                    Some(e) if Arc::ptr_eq(e, &current) => {
+                        // Held by the map, `current` and nobody else: not in use.
+                        if Arc::strong_count(&current) > 2 || current.idle_for() < OPENCODE_API_IDLE
+                        {
+                            continue;
+                        }
+                        map.remove(&cwd)
+                    }
+                    // Replaced (e.g. after a crash): the last request using it stops it.
+                    _ => return,
+                }
+            };
+            drop(removed);
+            return;
+        }
+    }
+
+    /// `rpc.opencode.sessions.list` / `rpc.opencode.session.fork` / `rpc.opencode.session.messages`.
+    /// opencode scopes sessions by project, so every call names the `cwd` to look in.
+    pub async fn opencode_session_rpc(
+        &self,
+        rpc_type: &str,
+        data: &JsonValue,
+    ) -> anyhow::Result<JsonValue> {
+        let cwd = data
+            .get("cwd")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.trim().is_empty())
+            .context("cwd is required")?;
+        validate_run_cwd(cwd)?;
+        let session_id = data.get("session_id").and_then(|v| v.as_str());
+        let limit = |default: i64, max: i64| {
+            data.get("limit")
+                .and_then(|v| v.as_i64())
+                .unwrap_or(default)
+                .clamp(1, max) as usize
+        };
+
+        let sandbox = crate::sandbox::profile_from_request(
+            data.get("sandbox"),
+            crate::sandbox::default_profile(),
+        )?;
+        let (server, _lease) = self.opencode_api_server(cwd, sandbox).await?;
+        match rpc_type {
+            "rpc.opencode.sessions.list" => {
+                let mut sessions = server.list_sessions().await?;
+                let updated = |s: &JsonValue| {
+                    s.get("time")
+                        .and_then(|t| t.get("updated"))
+                        .and_then(|v| v.as_i64())
+                        .unwrap_or(0)
+                };
+                sessions.sort_by_key(|s| std::cmp::Reverse(updated(s)));
+                let total = sessions.len();
+                sessions.truncate(limit(50, 500));
+                let sessions: Vec<JsonValue> = sessions
+                    .iter()
+                    .map(crate::opencode_serve::session_summary)
+                    .collect();
+                Ok(json!({
+                    "cwd": cwd,
+                    "sessions": redact_json_with(&self.redactor, &JsonValue::Array(sessions)),
+                    "total": total,
+                }))
+            }
+            "rpc.opencode.session.fork" => {
+                let session_id = session_id.context("session_id is required")?;
+                let message_id = data.get("message_id").and_then(|v| v.as_str());
+                let forked = server.fork_session(session_id, message_id).await?;
+                Ok(json!({
+                    "forked_from_session_id": session_id,
+                    "session": redact_json_with(
+                        &self.redactor,
+                        &crate::opencode_serve::session_summary(&forked),
+                    ),
+                }))
+            }
+            "rpc.opencode.session.messages" => {
+                let session_id = session_id.context("session_id is required")?;
+                let messages = server.session_messages(session_id).await?;
+                let keep = limit(200, 1000);
+                let truncated = messages.len() > keep;
+                let messages: Vec<JsonValue> = messages[messages.len().saturating_sub(keep)..]
+                    .iter()
+                    .map(crate::opencode_serve::history_message)
+                    .collect();
+                Ok(json!({
+                    "session_id": session_id,
+                    "messages": redact_json_with(&self.redactor, &JsonValue::Array(messages)),
+                    "truncated": truncated,
+                }))
             }
+            _ => anyhow::bail!("unsupported rpc `{rpc_type}`"),
         }
-        walk(&self.redactor, v)
     }
 
     pub async fn add_session_allow_tools(
@@ -1115,13 +2611,66 @@ impl RunManager {
         set.contains(tool)
     }
 
+    /// Whether this `rpc.fs.download`/`rpc.fs.upload` chunk continues an approved transfer.
+    pub async fn transfer_granted(&self, run_id: &str, rpc_type: &str, data: &JsonValue) -> bool {
+        let run = {
+            let runs = self.runs.read().await;
+            runs.get(run_id).cloned()
+        };
+        let Some(run) = run else {
+            return false;
+        };
+        run.transfer_grants.lock().await.admits(rpc_type, data)
+    }
+
+    pub async fn grant_transfer(&self, run_id: &str, rpc_type: &str, data: &JsonValue) {
+        let run = {
+            let runs = self.runs.read().await;
+            runs.get(run_id).cloned()
+        };
+        if let Some(run) = run {
+            run.transfer_grants.lock().await.grant(rpc_type, data);
+        }
+    }
+
+    /// Records a transfer chunk's outcome; `payload` is `{result}` or `{error}`.
+    pub async fn finish_transfer_chunk(
+        &self,
+        run_id: &str,
+        rpc_type: &str,
+        data: &JsonValue,
+        ok: bool,
+        payload: &JsonValue,
+    ) {
+        let run = {
+            let runs = self.runs.read().await;
+            runs.get(run_id).cloned()
+        };
+        if let Some(run) = run {
+            run.transfer_grants
+                .lock()
+                .await
+                .finish_chunk(rpc_type, data, ok, payload);
+        }
+    }
+
     pub async fn start_run(
         &self,
         tool: String,
         cmd: String,
         cwd: Option<String>,
-        model: Option<String>,
+        opts: StartRunOptions,
     ) -> anyhow::Result<String> {
+        let StartRunOptions {
+            model,
+            sandbox,
+            isolation,
+            env,
+            initial_prompt,
+            allow_tools,
+            opencode_session,
+            budget,
+        } = opts;
         let run_id = format!("run-{}", uuid::Uuid::new_v4());
         let resolved_cwd = match cwd.as_deref() {
             Some(c) => c.to_string(),
@@ -1134,26 +2683,180 @@ impl RunManager {
         validate_run_cwd(&resolved_cwd)?;
 
         anyhow::ensure!(
-            tool == "opencode",
-            "unsupported tool `{}`; current relay build only enables opencode",
+            tool == "opencode" || crate::runners::registry::lookup(&tool).is_some(),
+            "unsupported tool `{}`; register it in ~/.relay/runners.json",
             tool
         );
+        anyhow::ensure!(
+            opencode_session.is_none()
+                || (tool == "opencode"
+                    && opencode_mode_setting() == OpencodeModeSetting::Structured),
+            "resume_session_id/fork_from_session_id require a structured opencode run"
+        );
+        self.budgets
+            .lock()
+            .map_err(|_| anyhow::anyhow!("budgets lock poisoned"))?
+            .check_start()?;
+
+        let mut sandbox = sandbox.map(|p| {
+            p.with_tool_dirs(&tool)
+                .with_ro_path(self.local_unix_socket.clone())
+        });
+
+        let worktree = if isolation == IsolationMode::Worktree {
+            let cwd_for_git = resolved_cwd.clone();
+            let run_id_for_git = run_id.clone();
+            let wt = tokio::task::spawn_blocking(move || {
+                crate::fs_git::worktree_create(&cwd_for_git, &run_id_for_git)
+            })
+            .await
+            .context("join worktree create")?
+            .map_err(|(_, e)| anyhow::anyhow!("create worktree: {e}"))?;
+            // Commits from inside the sandbox write to the shared object store/refs.
+            sandbox = sandbox.map(|p| p.with_rw_path(wt.git_common_dir.clone()));
+            Some(wt)
+        } else {
+            None
+        };
+        let resolved_cwd = worktree
+            .as_ref()
+            .map(|wt| wt.run_cwd())
+            .unwrap_or(resolved_cwd);
+        let launch = RunLaunch {
+            sandbox,
+            worktree: worktree.clone(),
+            env,
+            opencode_session,
+        };
+        // Snapshot before the tool starts so its first writes are already diffed.
+        let tracker = if crate::file_changes::enabled() {
+            let cwd = resolved_cwd.clone();
+            match tokio::task::spawn_blocking(move || crate::file_changes::Tracker::start(&cwd))
+                .await
+            {
+                Ok(Ok(t)) => Some(t),
+                Ok(Err(err)) => {
+                    tracing::warn!(run_id=%run_id, error=%err, "file tracking unavailable");
+                    None
+                }
+                Err(_) => None,
+            }
+        } else {
+            None
+        };
+
+        // Before launch, so usage from the run's first moments counts against its limits.
+        if let Ok(mut budgets) = self.budgets.lock() {
+            budgets.start_run(&run_id, budget);
+        }
+        let run_id_for_budget = run_id.clone();
+        let started = self
+            .start_run_with_launch(run_id, tool, cmd, resolved_cwd, model, launch)
+            .await;
+        if let Some(wt) = worktree {
+            match started.as_ref() {
+                Ok(run_id) => {
+                    if let Some(run) = self.runs.read().await.get(run_id)
+                        && let Ok(mut seqs) = self.worktree_seqs.lock()
+                    {
+                        seqs.insert(run_id.clone(), run.seq.clone());
+                    }
+                    let mut map = self.worktrees.write().await;
+                    map.insert(run_id.clone(), wt);
+                }
+                Err(_) => {
+                    let _ = tokio::task::spawn_blocking(move || {
+                        crate::fs_git::worktree_cleanup(
+                            &wt,
+                            crate::fs_git::WorktreeCleanup::Discard,
+                        )
+                    })
+                    .await;
+                }
+            }
+        }
+        if let Some(tracker) = tracker {
+            match started.as_ref() {
+                Ok(run_id) => {
+                    if let Ok(mut trackers) = self.file_trackers.lock() {
+                        trackers.insert(run_id, tracker.clone());
+                    }
+                    tokio::spawn(self.clone().report_file_changes(run_id.clone(), tracker));
+                }
+                Err(_) => tracker.stop(),
+            }
+        }
+        if started.is_err()
+            && let Ok(mut budgets) = self.budgets.lock()
+        {
+            budgets.end_run(&run_id_for_budget);
+        }
+        let run_id = started?;
+
+        // The run is live from here on: a failure below is reported on the run instead of
+        // failing the start, which would leave the caller without the id of a running agent.
+        if !allow_tools.is_empty()
+            && let Err(err) = self.add_session_allow_tools(&run_id, &allow_tools).await
+        {
+            self.warn_run(
+                &run_id,
+                &format!("relay: could not apply allow_tools: {err}"),
+            )
+            .await;
+        }
+        if let Some(prompt) = initial_prompt.filter(|p| !p.trim().is_empty()) {
+            let input_id = uuid::Uuid::new_v4().to_string();
+            if let Err(err) = self
+                .send_input(&run_id, "system", &input_id, &format!("{prompt}\n"))
+                .await
+            {
+                self.warn_run(
+                    &run_id,
+                    &format!("relay: could not send initial prompt: {err}"),
+                )
+                .await;
+            }
+        }
+        Ok(run_id)
+    }
+
+    async fn warn_run(&self, run_id: &str, msg: &str) {
+        tracing::warn!(run_id=%run_id, "{msg}");
+        let _ = self
+            .emit_run_event(
+                run_id,
+                "run.output",
+                json!({ "stream": "stderr", "text": format!("{msg}\n") }),
+            )
+            .await;
+    }
 
+    async fn start_run_with_launch(
+        &self,
+        run_id: String,
+        tool: String,
+        cmd: String,
+        resolved_cwd: String,
+        model: Option<String>,
+        launch: RunLaunch,
+    ) -> anyhow::Result<String> {
         if tool == "codex" {
             match codex_mode_setting() {
                 CodexModeSetting::Tui => {
                     return self
-                        .start_run_pty_with_id(run_id, tool, cmd, resolved_cwd)
+                        .start_run_pty_with_id(run_id, tool, cmd, resolved_cwd, launch)
                         .await;
                 }
                 CodexModeSetting::Structured => {
                     let args = self.probe_codex_mcp_args(&resolved_cwd).await?;
                     return self
-                        .start_run_codex_mcp_with_id(run_id, cmd, resolved_cwd, args)
+                        .start_run_codex_mcp_with_id(run_id, cmd, resolved_cwd, args, launch)
                         .await;
                 }
                 CodexModeSetting::Auto => {
-                    return self.start_run_codex_auto(run_id, cmd, resolved_cwd).await;
+                    return self
+                        .start_run_codex_auto(run_id, cmd, resolved_cwd, launch)
+                        .await;
                 }
             }
         }
@@ -1162,18 +2865,32 @@ impl RunManager {
             match opencode_mode_setting() {
                 OpencodeModeSetting::Structured => {
                     return self
-                        .start_run_opencode_structured_with_id(run_id, cmd, resolved_cwd, model)
+                        .start_run_opencode_structured_with_id(
+                            run_id,
+                            cmd,
+                            resolved_cwd,
+                            model,
+                            launch,
+                        )
                         .await;
                 }
                 OpencodeModeSetting::Tui => {
                     return self
-                        .start_run_pty_with_id(run_id, tool, cmd, resolved_cwd)
+                        .start_run_pty_with_id(run_id, tool, cmd, resolved_cwd, launch)
                         .await;
                 }
             }
         }
 
-        self.start_run_pty_with_id(run_id, tool, cmd, resolved_cwd)
+        if let Some(entry) = crate::runners::registry::lookup(&tool)
+            .filter(|e| e.protocol == crate::runners::registry::RunnerProtocol::Acp)
+        {
+            return self
+                .start_run_acp_with_id(run_id, entry, cmd, resolved_cwd, launch)
+                .await;
+        }
+
+        self.start_run_pty_with_id(run_id, tool, cmd, resolved_cwd, launch)
             .await
     }
 
@@ -1183,6 +2900,7 @@ impl RunManager {
         tool: String,
         cmd: String,
         cwd: String,
+        launch: RunLaunch,
     ) -> anyhow::Result<String> {
         let pty_system = portable_pty::native_pty_system();
         let pair = pty_system
@@ -1198,6 +2916,7 @@ impl RunManager {
         let spec = crate::runners::for_tool(&tool).build(&cmd, &cwd)?;
         let mut command: CommandBuilder = spec.command;
         command.env("RELAY_RUN_ID", &run_id);
+        command.env("RELAY_RUN_TOKEN", self.run_token(&run_id));
         command.env("RELAY_TOOL", &tool);
         command.env("RELAY_HOSTD_SOCK", &self.local_unix_socket);
         command.env("RELAY_CWD", &cwd);
@@ -1207,6 +2926,13 @@ impl RunManager {
         if std::env::var_os("COLORTERM").is_none() {
             command.env("COLORTERM", "truecolor");
         }
+        for (k, v) in &launch.env {
+            command.env(k, v);
+        }
+        if let Some(profile) = launch.sandbox.as_ref() {
+            let argv = command.get_argv().clone();
+            *command.get_argv_mut() = profile.wrap_argv(&cwd, argv, true)?;
+        }
 
         // Always prefer tmux for PTY runs when available.
         // This allows inspecting/attaching to the same session locally (SSH) while relay remains primary.
@@ -1268,6 +2994,9 @@ impl RunManager {
             None
         };
 
+        for name in crate::config::SECRET_ENV_VARS {
+            command.env_remove(name);
+        }
         let mut child = slave.spawn_command(command).context("spawn_command")?;
         let pid = child.process_id().context("process_id")? as i32;
 
@@ -1276,7 +3005,9 @@ impl RunManager {
 
         let run = Arc::new(Run {
             run_id: run_id.clone(),
-            seq: AtomicI64::new(0),
+            seq: Arc::new(AtomicI64::new(0)),
+            budget: self.budget_meter.clone(),
+            permissions: self.permission_log.clone(),
             pty: Some(StdMutex::new(master)),
             writer: Mutex::new(writer),
             pid,
@@ -1287,6 +3018,7 @@ impl RunManager {
             stdin_line_buf: Mutex::new(Vec::new()),
             processed_input_ids: Mutex::new(HashSet::new()),
             session_allow_tools: Mutex::new(HashSet::new()),
+            transfer_grants: Mutex::new(TransferGrants::default()),
             pending_permission: Mutex::new(None),
             codex_mcp: Mutex::new(None),
             codex_rpc_waiters: StdMutex::new(HashMap::new()),
@@ -1297,9 +3029,12 @@ impl RunManager {
             opencode_stop_requested: Mutex::new(false),
             opencode_call_lock: Mutex::new(()),
             opencode_model: None,
+            acp: None,
+            opencode_serve: None,
             tmux_session: tmux_session.clone(),
             default_approve_text: spec.approve_text.clone(),
             default_deny_text: spec.deny_text.clone(),
+            launch,
         });
 
         {
@@ -1312,6 +3047,7 @@ impl RunManager {
             "cwd": run.cwd,
             "command": cmd,
         });
+        run.launch.describe_into(&run.cwd, &mut started_data);
         if let (Some(session), Some(obj)) = (tmux_session.as_deref(), started_data.as_object_mut())
         {
             obj.insert(
@@ -1329,6 +3065,18 @@ impl RunManager {
         started.seq = Some(run.next_seq());
         let _ = self.events.send(started);
 
+        // The agent runs under the tmux server, not under `pid`; record its pane for
+        // `run_for_pid` once, off the request path.
+        if let Some(session) = tmux_session.clone() {
+            let pane_pids = self.tmux_pane_pids.clone();
+            let run_id = run_id.clone();
+            tokio::task::spawn_blocking(move || {
+                if let (Some(pid), Ok(mut m)) = (tmux_pane_pid(&session), pane_pids.lock()) {
+                    m.insert(run_id, pid);
+                }
+            });
+        }
+
         let runner_mode = if tmux_session.is_some() {
             "tmux"
         } else {
@@ -1431,6 +3179,8 @@ impl RunManager {
                                             approve_text: approve_text.clone(),
                                             deny_text: deny_text.clone(),
                                             rpc_request_id: None,
+                                            acp_request: None,
+                                            opencode_permission: None,
                                         });
                                     }
 
@@ -1447,6 +3197,7 @@ impl RunManager {
                                     pr.host_id = Some(host_id.clone());
                                     pr.run_id = Some(run_for_thread.run_id.clone());
                                     pr.seq = Some(run_for_thread.next_seq());
+                                    run_for_thread.permissions.observe(&pr);
                                     let _ = events.send(pr);
 
                                     let mut p = WsEnvelope::new(
@@ -1488,7 +3239,7 @@ impl RunManager {
         std::thread::spawn(move || {
             let exit = child.wait();
             let exit_code = exit.map(|s| s.exit_code() as i64).unwrap_or(-1);
-            let mut env = WsEnvelope::new("run.exited", json!({ "exit_code": exit_code }));
+            let mut env = WsEnvelope::new("run.exited", run_for_thread.exited_data(exit_code));
             env.host_id = Some(host_id);
             env.run_id = Some(run_for_thread.run_id.clone());
             env.seq = Some(run_for_thread.next_seq());
@@ -1507,6 +3258,7 @@ impl RunManager {
         run_id: String,
         cmd: String,
         cwd: String,
+        launch: RunLaunch,
     ) -> anyhow::Result<String> {
         let now = Utc::now();
 
@@ -1546,7 +3298,13 @@ impl RunManager {
         let run_id = if selected_mode == ToolRunMode::Structured {
             if let Some(args) = entry.mcp_args.clone() {
                 match self
-                    .start_run_codex_mcp_with_id(run_id.clone(), cmd.clone(), cwd.clone(), args)
+                    .start_run_codex_mcp_with_id(
+                        run_id.clone(),
+                        cmd.clone(),
+                        cwd.clone(),
+                        args,
+                        launch.clone(),
+                    )
                     .await
                 {
                     Ok(id) => id,
@@ -1562,16 +3320,16 @@ impl RunManager {
                             );
                             let _ = c.save();
                         }
-                        self.start_run_pty_with_id(run_id, "codex".to_string(), cmd, cwd)
+                        self.start_run_pty_with_id(run_id, "codex".to_string(), cmd, cwd, launch)
                             .await?
                     }
                 }
             } else {
-                self.start_run_pty_with_id(run_id, "codex".to_string(), cmd, cwd)
+                self.start_run_pty_with_id(run_id, "codex".to_string(), cmd, cwd, launch)
                     .await?
             }
         } else {
-            self.start_run_pty_with_id(run_id, "codex".to_string(), cmd, cwd)
+            self.start_run_pty_with_id(run_id, "codex".to_string(), cmd, cwd, launch)
                 .await?
         };
 
@@ -1589,6 +3347,7 @@ impl RunManager {
         cmd: String,
         cwd: String,
         model: Option<String>,
+        launch: RunLaunch,
     ) -> anyhow::Result<String> {
         validate_opencode_structured_model(model.as_deref())?;
         let bin = crate::runners::resolve_tool_bin("opencode", "RELAY_OPENCODE_BIN", "opencode");
@@ -1597,9 +3356,60 @@ impl RunManager {
             "opencode (set RELAY_OPENCODE_BIN=/path/to/opencode or install shims to record real path)",
         )?;
 
+        let mut serve_child = None;
+        let mut session_id = None;
+        let serve = if opencode_serve_enabled(launch.sandbox.as_ref())? {
+            let model = model.as_deref().map(str::trim).filter(|s| !s.is_empty());
+            let (serve, mut child) = start_opencode_serve(&cwd, model, &launch)
+                .await
+                .context("start opencode serve")?;
+            let ready: anyhow::Result<String> = async {
+                match launch.opencode_session.as_ref() {
+                    None => serve.server.create_session(&json!({})).await,
+                    Some(OpencodeSessionStart::Resume(id)) => {
+                        serve.server.get_session(id).await?;
+                        Ok(id.clone())
+                    }
+                    Some(OpencodeSessionStart::Fork(id)) => serve
+                        .server
+                        .fork_session(id, None)
+                        .await?
+                        .get("id")
+                        .and_then(|v| v.as_str())
+                        .map(|s| s.to_string())
+                        .context("opencode fork response has no id"),
+                }
+            }
+            .await;
+            match ready {
+                Ok(id) => session_id = Some(id),
+                Err(e) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(e.context("start opencode serve"));
+                }
+            }
+            serve_child = Some(child);
+            Some(serve)
+        } else {
+            // `opencode run --session` can continue a session but has no way to fork one.
+            match launch.opencode_session.as_ref() {
+                Some(OpencodeSessionStart::Resume(id)) => session_id = Some(id.clone()),
+                Some(OpencodeSessionStart::Fork(_)) => {
+                    anyhow::bail!("fork_from_session_id requires the opencode serve backend")
+                }
+                None => {}
+            }
+            None
+        };
+        let serve_backend = serve.is_some();
+        let backend = if serve_backend { "serve" } else { "run" };
+
         let run = Arc::new(Run {
             run_id: run_id.clone(),
-            seq: AtomicI64::new(0),
+            seq: Arc::new(AtomicI64::new(0)),
+            budget: self.budget_meter.clone(),
+            permissions: self.permission_log.clone(),
             pty: None,
             writer: Mutex::new(Box::new(std::io::sink())),
             pid: 0,
@@ -1610,12 +3420,13 @@ impl RunManager {
             stdin_line_buf: Mutex::new(Vec::new()),
             processed_input_ids: Mutex::new(HashSet::new()),
             session_allow_tools: Mutex::new(HashSet::new()),
+            transfer_grants: Mutex::new(TransferGrants::default()),
             pending_permission: Mutex::new(None),
             codex_mcp: Mutex::new(None),
             codex_rpc_waiters: StdMutex::new(HashMap::new()),
             codex_call_lock: Mutex::new(()),
             opencode_structured: true,
-            opencode_session_id: StdMutex::new(None),
+            opencode_session_id: StdMutex::new(session_id.clone()),
             opencode_active_pid: StdMutex::new(None),
             opencode_stop_requested: Mutex::new(false),
             opencode_call_lock: Mutex::new(()),
@@ -1623,9 +3434,12 @@ impl RunManager {
                 .clone()
                 .map(|s| s.trim().to_string())
                 .filter(|s| !s.is_empty()),
+            acp: None,
+            opencode_serve: serve,
             tmux_session: None,
             default_approve_text: "y\n".to_string(),
             default_deny_text: "n\n".to_string(),
+            launch,
         });
 
         {
@@ -1641,17 +3455,21 @@ impl RunManager {
                 "command": cmd,
                 "mode": "structured",
                 "model": model.clone().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()),
-                "opencode_session_id": JsonValue::Null,
+                "opencode_session_id": session_id,
+                "resume_session_id": match run.launch.opencode_session.as_ref() {
+                    Some(OpencodeSessionStart::Resume(id)) => Some(id),
+                    _ => None,
+                },
+                "fork_from_session_id": match run.launch.opencode_session.as_ref() {
+                    Some(OpencodeSessionStart::Fork(id)) => Some(id),
+                    _ => None,
+                },
+                "backend": backend,
                 "permission_env_set": std::env::var_os("OPENCODE_PERMISSION").is_some(),
-                "permission_mode": if std::env::var_os("OPENCODE_PERMISSION").is_some() {
-                    "env"
-                } else if opencode_permission_setting() == OpencodePermissionSetting::AutoAllowAll {
-                    "relay_auto_allow_all"
-                } else {
-                    "inherit"
-                }
+                "permission_mode": opencode_permission_mode(serve_backend),
             }),
         );
+        run.launch.describe_into(&run.cwd, &mut started.data);
         started.host_id = Some(self.host_id.clone());
         started.run_id = Some(run_id.clone());
         started.seq = Some(run.next_seq());
@@ -1663,6 +3481,47 @@ impl RunManager {
         ready.seq = Some(run.next_seq());
         let _ = self.events.send(ready);
 
+        if let Some(mut child) = serve_child {
+            if let Some(stderr) = child.stderr.take() {
+                let events = self.events.clone();
+                let host_id = self.host_id.clone();
+                let run = run.clone();
+                std::thread::spawn(move || {
+                    let mut r = BufReader::new(stderr);
+                    let mut line = String::new();
+                    loop {
+                        line.clear();
+                        match r.read_line(&mut line) {
+                            Ok(0) | Err(_) => break,
+                            Ok(_) => {
+                                emit_run_output(&events, &host_id, &run, "stderr", line.clone())
+                            }
+                        }
+                    }
+                });
+            }
+
+            tokio::spawn(opencode_serve_event_loop(
+                run.clone(),
+                self.runs.clone(),
+                self.redactor.clone(),
+                self.events.clone(),
+                self.host_id.clone(),
+            ));
+
+            let runs = self.runs.clone();
+            let events = self.events.clone();
+            let host_id = self.host_id.clone();
+            tokio::spawn(async move {
+                let status = tokio::task::spawn_blocking(move || child.wait()).await;
+                let exit_code = match status {
+                    Ok(Ok(status)) => status.code().map(i64::from).unwrap_or(-1),
+                    _ => -1,
+                };
+                finalize_structured_run_exit(runs, events, host_id, run, exit_code).await;
+            });
+        }
+
         Ok(run_id)
     }
 
@@ -1672,6 +3531,7 @@ impl RunManager {
         cmd: String,
         cwd: String,
         mcp_args: Vec<String>,
+        launch: RunLaunch,
     ) -> anyhow::Result<String> {
         fn escape_toml_basic_string(s: &str) -> String {
             s.replace('\\', "\\\\").replace('\"', "\\\"")
@@ -1752,10 +3612,14 @@ impl RunManager {
             "codex (set RELAY_CODEX_BIN=/path/to/codex or install shims to record real path)",
         )?;
 
-        let mut child_cmd = Command::new(&bin);
+        let mut child_cmd = match launch.sandbox.as_ref() {
+            Some(profile) => profile.command(&cwd, &bin)?,
+            None => Command::new(&bin),
+        };
         for a in &mcp_args {
             child_cmd.arg(a);
         }
+        child_cmd.envs(launch.env.iter().map(|(k, v)| (k, v)));
 
         if !env_truthy("RELAY_CODEX_KEEP_USER_MCP") {
             child_cmd.arg("--config");
@@ -1775,7 +3639,7 @@ impl RunManager {
                 ));
                 child_cmd.arg("--config");
                 child_cmd.arg(
-                    r#"mcp_servers.relay.env={RELAY_RUN_ID="${RELAY_RUN_ID}", RELAY_HOSTD_SOCK="${RELAY_HOSTD_SOCK}", RELAY_TOOL="${RELAY_TOOL}"}"#,
+                    r#"mcp_servers.relay.env={RELAY_RUN_ID="${RELAY_RUN_ID}", RELAY_RUN_TOKEN="${RELAY_RUN_TOKEN}", RELAY_HOSTD_SOCK="${RELAY_HOSTD_SOCK}", RELAY_TOOL="${RELAY_TOOL}"}"#,
                 );
             }
         }
@@ -1787,6 +3651,7 @@ impl RunManager {
         child_cmd.env("no_proxy", &no_proxy);
 
         child_cmd.env("RELAY_RUN_ID", &run_id);
+        child_cmd.env("RELAY_RUN_TOKEN", self.run_token(&run_id));
         child_cmd.env("RELAY_TOOL", "codex");
         child_cmd.env("RELAY_HOSTD_SOCK", &self.local_unix_socket);
         child_cmd.env("RELAY_CWD", &cwd);
@@ -1794,6 +3659,7 @@ impl RunManager {
         child_cmd.stdout(Stdio::piped());
         child_cmd.stderr(Stdio::piped());
 
+        crate::config::strip_secret_env(&mut child_cmd);
         let mut child = child_cmd.spawn().context("spawn codex mcp server")?;
         let pid = child.id() as i32;
 
@@ -1803,7 +3669,9 @@ impl RunManager {
 
         let run = Arc::new(Run {
             run_id: run_id.clone(),
-            seq: AtomicI64::new(0),
+            seq: Arc::new(AtomicI64::new(0)),
+            budget: self.budget_meter.clone(),
+            permissions: self.permission_log.clone(),
             pty: None,
             writer: Mutex::new(Box::new(stdin)),
             pid,
@@ -1814,6 +3682,7 @@ impl RunManager {
             stdin_line_buf: Mutex::new(Vec::new()),
             processed_input_ids: Mutex::new(HashSet::new()),
             session_allow_tools: Mutex::new(HashSet::new()),
+            transfer_grants: Mutex::new(TransferGrants::default()),
             pending_permission: Mutex::new(None),
             codex_mcp: Mutex::new(Some(CodexMcpState {
                 next_id: 1,
@@ -1830,9 +3699,12 @@ impl RunManager {
             opencode_stop_requested: Mutex::new(false),
             opencode_call_lock: Mutex::new(()),
             opencode_model: None,
+            acp: None,
+            opencode_serve: None,
             tmux_session: None,
             default_approve_text: "y\n".to_string(),
             default_deny_text: "n\n".to_string(),
+            launch,
         });
 
         {
@@ -1850,6 +3722,7 @@ impl RunManager {
                 "mcp_args": mcp_args,
             }),
         );
+        run.launch.describe_into(&run.cwd, &mut started.data);
         started.host_id = Some(self.host_id.clone());
         started.run_id = Some(run_id.clone());
         started.seq = Some(run.next_seq());
@@ -1869,6 +3742,8 @@ impl RunManager {
             std::thread::spawn(move || {
                 let mut r = BufReader::new(stdout);
                 let mut line = String::new();
+                // From codex's `session_configured` event; labels `run.usage`.
+                let mut codex_model: Option<String> = None;
                 loop {
                     line.clear();
                     match r.read_line(&mut line) {
@@ -1893,6 +3768,36 @@ impl RunManager {
                                 }
 
                                 let method = v.get("method").and_then(|m| m.as_str()).unwrap_or("");
+                                if method == "codex/event" {
+                                    let msg = v
+                                        .get("params")
+                                        .and_then(|p| p.get("msg"))
+                                        .unwrap_or(&JsonValue::Null);
+                                    match msg.get("type").and_then(|t| t.as_str()) {
+                                        Some("session_configured") => {
+                                            codex_model = msg
+                                                .get("model")
+                                                .and_then(|m| m.as_str())
+                                                .map(|s| s.to_string());
+                                        }
+                                        Some("token_count") => {
+                                            if let Some(usage) =
+                                                crate::usage::Usage::from_codex_token_count(
+                                                    msg,
+                                                    codex_model.as_deref(),
+                                                )
+                                            {
+                                                emit_run_usage(
+                                                    &events,
+                                                    &host_id,
+                                                    &run_for_thread,
+                                                    &usage,
+                                                );
+                                            }
+                                        }
+                                        _ => {}
+                                    }
+                                }
                                 if method == "elicitation/create" {
                                     if let Some(rpc_request_id) =
                                         v.get("id").and_then(|v| v.as_i64())
@@ -1915,6 +3820,8 @@ impl RunManager {
                                                 approve_text: "".to_string(),
                                                 deny_text: "".to_string(),
                                                 rpc_request_id: Some(rpc_request_id),
+                                                acp_request: None,
+                                                opencode_permission: None,
                                             });
                                         }
 
@@ -1938,6 +3845,7 @@ impl RunManager {
                                         pr.host_id = Some(host_id.clone());
                                         pr.run_id = Some(run_for_thread.run_id.clone());
                                         pr.seq = Some(run_for_thread.next_seq());
+                                        run_for_thread.permissions.observe(&pr);
                                         let _ = events.send(pr);
 
                                         let mut p = WsEnvelope::new(
@@ -2081,7 +3989,7 @@ impl RunManager {
                 map.remove(&run_id);
             }
 
-            let mut exited = WsEnvelope::new("run.exited", json!({ "exit_code": -1 }));
+            let mut exited = WsEnvelope::new("run.exited", run.exited_data(-1));
             exited.host_id = Some(self.host_id.clone());
             exited.run_id = Some(run_id.clone());
             exited.seq = Some(run.next_seq());
@@ -2099,7 +4007,7 @@ impl RunManager {
             std::thread::spawn(move || {
                 let exit = child.wait();
                 let exit_code = exit.map(|s| s.code().unwrap_or(-1) as i64).unwrap_or(-1);
-                let mut env = WsEnvelope::new("run.exited", json!({ "exit_code": exit_code }));
+                let mut env = WsEnvelope::new("run.exited", run_for_thread.exited_data(exit_code));
                 env.host_id = Some(host_id);
                 env.run_id = Some(run_for_thread.run_id.clone());
                 env.seq = Some(run_for_thread.next_seq());
@@ -2114,31 +4022,314 @@ impl RunManager {
         Ok(run_id)
     }
 
-    async fn probe_codex_mcp_args(&self, cwd: &str) -> anyhow::Result<Vec<String>> {
-        let cwd = cwd.to_string();
-        let timeout = codex_probe_timeout();
-        tokio::task::spawn_blocking(move || {
-            fn try_args(
-                bin: &str,
-                cwd: &str,
-                args: &[String],
-                timeout: Duration,
-            ) -> anyhow::Result<()> {
-                use std::sync::mpsc;
-                use std::time::Instant;
+    async fn start_run_acp_with_id(
+        &self,
+        run_id: String,
+        entry: crate::runners::registry::RunnerEntry,
+        cmd: String,
+        cwd: String,
+        launch: RunLaunch,
+    ) -> anyhow::Result<String> {
+        let bin = entry.resolve_bin();
+        crate::runners::validate_bin_exists(&bin, &entry.bin_hint())?;
 
-                let mut child_cmd = Command::new(bin);
-                for a in args {
-                    child_cmd.arg(a);
-                }
-                child_cmd.current_dir(cwd);
-                child_cmd.stdin(Stdio::piped());
-                child_cmd.stdout(Stdio::piped());
-                child_cmd.stderr(Stdio::piped());
+        let mut child_cmd = match launch.sandbox.as_ref() {
+            Some(profile) => profile.command(&cwd, &bin)?,
+            None => Command::new(&bin),
+        };
+        child_cmd.args(&entry.args);
+        child_cmd.envs(entry.env.iter());
+        child_cmd.envs(launch.env.iter().map(|(k, v)| (k, v)));
+        child_cmd.current_dir(&cwd);
+        child_cmd.env("RELAY_RUN_ID", &run_id);
+        child_cmd.env("RELAY_RUN_TOKEN", self.run_token(&run_id));
+        child_cmd.env("RELAY_TOOL", &entry.tool);
+        child_cmd.env("RELAY_HOSTD_SOCK", &self.local_unix_socket);
+        child_cmd.env("RELAY_CWD", &cwd);
+        child_cmd.stdin(Stdio::piped());
+        child_cmd.stdout(Stdio::piped());
+        child_cmd.stderr(Stdio::piped());
+        crate::config::strip_secret_env(&mut child_cmd);
 
-                let mut child = child_cmd.spawn().context("spawn codex mcp probe")?;
-                let mut stdin = child.stdin.take().context("take stdin")?;
-                let stdout = child.stdout.take().context("take stdout")?;
+        let mut child = child_cmd
+            .spawn()
+            .with_context(|| format!("spawn {} (acp)", entry.tool))?;
+        let pid = child.id() as i32;
+
+        let stdin = child.stdin.take().context("take stdin")?;
+        let stdout = child.stdout.take().context("take stdout")?;
+        let stderr = child.stderr.take().context("take stderr")?;
+
+        let run = Arc::new(Run {
+            run_id: run_id.clone(),
+            seq: Arc::new(AtomicI64::new(0)),
+            budget: self.budget_meter.clone(),
+            permissions: self.permission_log.clone(),
+            pty: None,
+            writer: Mutex::new(Box::new(stdin)),
+            pid,
+            cwd,
+            tool: entry.tool.clone(),
+            prompt_regex: entry.prompt_regex(),
+            awaiting_input: Mutex::new(false),
+            stdin_line_buf: Mutex::new(Vec::new()),
+            processed_input_ids: Mutex::new(HashSet::new()),
+            session_allow_tools: Mutex::new(HashSet::new()),
+            transfer_grants: Mutex::new(TransferGrants::default()),
+            pending_permission: Mutex::new(None),
+            codex_mcp: Mutex::new(None),
+            codex_rpc_waiters: StdMutex::new(HashMap::new()),
+            codex_call_lock: Mutex::new(()),
+            opencode_structured: false,
+            opencode_session_id: StdMutex::new(None),
+            opencode_active_pid: StdMutex::new(None),
+            opencode_stop_requested: Mutex::new(false),
+            opencode_call_lock: Mutex::new(()),
+            opencode_model: None,
+            acp: Some(Arc::new(crate::acp::AcpSession::new())),
+            opencode_serve: None,
+            tmux_session: None,
+            default_approve_text: entry.approve_text.clone(),
+            default_deny_text: entry.deny_text.clone(),
+            launch,
+        });
+
+        {
+            let mut runs = self.runs.write().await;
+            runs.insert(run_id.clone(), run.clone());
+        }
+
+        let mut started = WsEnvelope::new(
+            "run.started",
+            json!({
+                "tool": entry.tool,
+                "cwd": run.cwd,
+                "command": cmd,
+                "runner_mode": "structured",
+                "protocol": "acp",
+            }),
+        );
+        run.launch.describe_into(&run.cwd, &mut started.data);
+        started.host_id = Some(self.host_id.clone());
+        started.run_id = Some(run_id.clone());
+        started.seq = Some(run.next_seq());
+        let _ = self.events.send(started);
+
+        let mut ready = WsEnvelope::new("run.ready", json!({ "runner_mode": "structured" }));
+        ready.host_id = Some(self.host_id.clone());
+        ready.run_id = Some(run_id.clone());
+        ready.seq = Some(run.next_seq());
+        let _ = self.events.send(ready);
+
+        {
+            let run = run.clone();
+            let events = self.events.clone();
+            let host_id = self.host_id.clone();
+            let redactor = self.redactor.clone();
+            std::thread::spawn(move || acp_read_loop(run, events, host_id, redactor, stdout));
+        }
+
+        // Stderr reader (logs).
+        {
+            let events = self.events.clone();
+            let host_id = self.host_id.clone();
+            let run_for_thread = run.clone();
+            std::thread::spawn(move || {
+                let mut r = BufReader::new(stderr);
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    match r.read_line(&mut line) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => {
+                            emit_run_output(
+                                &events,
+                                &host_id,
+                                &run_for_thread,
+                                "stderr",
+                                line.clone(),
+                            );
+                        }
+                    }
+                }
+            });
+        }
+
+        let init_result: anyhow::Result<String> = async {
+            let timeout = Some(crate::acp::INIT_TIMEOUT);
+            let init =
+                acp_rpc_request(&run, "initialize", crate::acp::initialize_params(), timeout)
+                    .await?;
+            if let Some(err) = init.get("error") {
+                anyhow::bail!("acp initialize failed: {err}");
+            }
+            let session = acp_rpc_request(
+                &run,
+                "session/new",
+                json!({ "cwd": run.cwd, "mcpServers": [] }),
+                timeout,
+            )
+            .await?;
+            if let Some(err) = session.get("error") {
+                anyhow::bail!("acp session/new failed: {err}");
+            }
+            session
+                .get("result")
+                .and_then(|r| r.get("sessionId"))
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+                .context("acp session/new returned no sessionId")
+        }
+        .await;
+
+        let session_id = match init_result {
+            Ok(id) => id,
+            Err(e) => {
+                emit_run_output(
+                    &self.events,
+                    &self.host_id,
+                    &run,
+                    "stderr",
+                    format!("acp init failed: {e:#}"),
+                );
+                let _ = child.kill();
+                let _ = child.wait();
+                {
+                    let mut map = self.runs.write().await;
+                    map.remove(&run_id);
+                }
+                let mut exited = WsEnvelope::new("run.exited", run.exited_data(-1));
+                exited.host_id = Some(self.host_id.clone());
+                exited.run_id = Some(run_id.clone());
+                exited.seq = Some(run.next_seq());
+                let _ = self.events.send(exited);
+                return Err(e);
+            }
+        };
+        if let Some(acp) = run.acp.as_ref() {
+            acp.set_session_id(&session_id);
+        }
+        emit_run_metadata(
+            &self.events,
+            &self.host_id,
+            &run,
+            json!({
+                "tool": entry.tool,
+                "mode": "structured",
+                "acp_session_id": session_id,
+            }),
+        );
+
+        // Exit waiter thread.
+        {
+            let events = self.events.clone();
+            let host_id = self.host_id.clone();
+            let run_for_thread = run.clone();
+            let runs_map = self.runs.clone();
+            std::thread::spawn(move || {
+                let exit = child.wait();
+                let exit_code = exit.map(|s| s.code().unwrap_or(-1) as i64).unwrap_or(-1);
+                let mut env = WsEnvelope::new("run.exited", run_for_thread.exited_data(exit_code));
+                env.host_id = Some(host_id);
+                env.run_id = Some(run_for_thread.run_id.clone());
+                env.seq = Some(run_for_thread.next_seq());
+                let _ = events.send(env);
+
+                if let Ok(mut map) = runs_map.try_write() {
+                    map.remove(&run_for_thread.run_id);
+                }
+            });
+        }
+
+        Ok(run_id)
+    }
+
+    fn spawn_opencode_serve_prompt(&self, run: Arc<Run>, input_id: String, prompt: String) {
+        let events = self.events.clone();
+        let host_id = self.host_id.clone();
+        tokio::spawn(async move {
+            // One turn at a time: a new input interrupts the turn still running.
+            if run.opencode_call_lock.try_lock().is_err()
+                && let Some(serve) = run.opencode_serve.as_ref()
+                && let Some(session_id) =
+                    run.opencode_session_id.lock().ok().and_then(|s| s.clone())
+            {
+                emit_run_output(
+                    &events,
+                    &host_id,
+                    &run,
+                    "stderr",
+                    "opencode previous prompt still active; interrupting it before sending the new input\n"
+                        .to_string(),
+                );
+                let _ = serve.server.abort(&session_id).await;
+                clear_opencode_permission(&run).await;
+            }
+            if crate::checkpoints::enabled() && crate::checkpoints::prompts_enabled() {
+                let _ = snapshot_run(&run, &events, &host_id, &input_id, "prompt").await;
+            }
+            if let Err(e) =
+                opencode_serve_submit_prompt(run.clone(), events.clone(), host_id.clone(), prompt)
+                    .await
+            {
+                emit_run_output(
+                    &events,
+                    &host_id,
+                    &run,
+                    "stderr",
+                    format!("opencode prompt failed: {e:#}\n"),
+                );
+            }
+        });
+    }
+
+    fn spawn_acp_prompt(&self, run: Arc<Run>, input_id: String, prompt: String) {
+        let events = self.events.clone();
+        let host_id = self.host_id.clone();
+        tokio::spawn(async move {
+            if crate::checkpoints::enabled() && crate::checkpoints::prompts_enabled() {
+                let _ = snapshot_run(&run, &events, &host_id, &input_id, "prompt").await;
+            }
+            if let Err(e) =
+                acp_submit_prompt(run.clone(), events.clone(), host_id.clone(), prompt).await
+            {
+                emit_run_output(
+                    &events,
+                    &host_id,
+                    &run,
+                    "stderr",
+                    format!("acp prompt failed: {e:#}\n"),
+                );
+            }
+        });
+    }
+
+    async fn probe_codex_mcp_args(&self, cwd: &str) -> anyhow::Result<Vec<String>> {
+        let cwd = cwd.to_string();
+        let timeout = codex_probe_timeout();
+        tokio::task::spawn_blocking(move || {
+            fn try_args(
+                bin: &str,
+                cwd: &str,
+                args: &[String],
+                timeout: Duration,
+            ) -> anyhow::Result<()> {
+                use std::sync::mpsc;
+                use std::time::Instant;
+
+                let mut child_cmd = Command::new(bin);
+                for a in args {
+                    child_cmd.arg(a);
+                }
+                child_cmd.current_dir(cwd);
+                child_cmd.stdin(Stdio::piped());
+                child_cmd.stdout(Stdio::piped());
+                child_cmd.stderr(Stdio::piped());
+                crate::config::strip_secret_env(&mut child_cmd);
+
+                let mut child = child_cmd.spawn().context("spawn codex mcp probe")?;
+                let mut stdin = child.stdin.take().context("take stdin")?;
+                let stdout = child.stdout.take().context("take stdout")?;
 
                 let (tx, rx) = mpsc::channel::<String>();
                 std::thread::spawn(move || {
@@ -2271,6 +4462,7 @@ impl RunManager {
             runs.get(run_id).cloned()
         }
         .context("unknown run_id")?;
+        self.check_budget_hold(run_id)?;
 
         // Idempotency: ignore duplicate input_id for the same run.
         {
@@ -2283,9 +4475,10 @@ impl RunManager {
 
         let is_codex_mcp = { run.codex_mcp.lock().await.is_some() };
         let is_opencode_structured = run.opencode_structured;
+        let is_acp = run.acp.is_some();
 
         let mut wrote_to_process = false;
-        if !is_codex_mcp && !is_opencode_structured {
+        if !is_codex_mcp && !is_opencode_structured && !is_acp {
             if let Some(session) = run.tmux_session.as_deref() {
                 #[cfg(unix)]
                 {
@@ -2376,7 +4569,12 @@ impl RunManager {
         env.seq = Some(run.next_seq());
         let _ = self.events.send(env);
 
-        if is_codex_mcp {
+        if is_acp {
+            let prompt = text.trim_end_matches(&['\r', '\n'][..]).to_string();
+            if !prompt.trim().is_empty() {
+                self.spawn_acp_prompt(run.clone(), input_id.to_string(), prompt);
+            }
+        } else if is_codex_mcp {
             let prompt = text.trim_end_matches(&['\r', '\n'][..]).to_string();
             if !prompt.trim().is_empty() {
                 let run2 = run.clone();
@@ -2404,7 +4602,9 @@ impl RunManager {
                 anyhow::bail!("opencode run is stopping");
             }
             let prompt = text.trim_end_matches(&['\r', '\n'][..]).to_string();
-            if !prompt.trim().is_empty() {
+            if !prompt.trim().is_empty() && run.opencode_serve.is_some() {
+                self.spawn_opencode_serve_prompt(run.clone(), input_id.to_string(), prompt);
+            } else if !prompt.trim().is_empty() {
                 #[cfg(unix)]
                 {
                     use nix::sys::signal::Signal;
@@ -2431,7 +4631,11 @@ impl RunManager {
                 let redactor = self.redactor.clone();
                 let events = self.events.clone();
                 let host_id = self.host_id.clone();
+                let input_id = input_id.to_string();
                 tokio::spawn(async move {
+                    if crate::checkpoints::enabled() && crate::checkpoints::prompts_enabled() {
+                        let _ = snapshot_run(&run2, &events, &host_id, &input_id, "prompt").await;
+                    }
                     if let Err(e) = opencode_submit_prompt(
                         run2.clone(),
                         runs,
@@ -2469,10 +4673,12 @@ impl RunManager {
             runs.get(run_id).cloned()
         }
         .context("unknown run_id")?;
+        self.check_budget_hold(run_id)?;
 
         let is_codex_mcp = { run.codex_mcp.lock().await.is_some() };
         let is_opencode_structured = run.opencode_structured;
-        if !is_codex_mcp && !is_opencode_structured {
+        let is_acp = run.acp.is_some();
+        if !is_codex_mcp && !is_opencode_structured && !is_acp {
             let mut w = run.writer.lock().await;
             w.write_all(bytes).context("write stdin")?;
             w.flush().ok();
@@ -2547,7 +4753,12 @@ impl RunManager {
             env.seq = Some(run.next_seq());
             let _ = self.events.send(env);
 
-            if is_codex_mcp {
+            if is_acp {
+                let prompt = text.trim_end_matches(&['\r', '\n'][..]).to_string();
+                if !prompt.trim().is_empty() {
+                    self.spawn_acp_prompt(run.clone(), input_id, prompt);
+                }
+            } else if is_codex_mcp {
                 let prompt = text.trim_end_matches(&['\r', '\n'][..]).to_string();
                 if !prompt.trim().is_empty() {
                     let run2 = run.clone();
@@ -2575,13 +4786,20 @@ impl RunManager {
                     anyhow::bail!("opencode run is stopping");
                 }
                 let prompt = text.trim_end_matches(&['\r', '\n'][..]).to_string();
-                if !prompt.trim().is_empty() {
+                if !prompt.trim().is_empty() && run.opencode_serve.is_some() {
+                    self.spawn_opencode_serve_prompt(run.clone(), input_id, prompt);
+                } else if !prompt.trim().is_empty() {
                     let run2 = run.clone();
                     let runs = self.runs.clone();
                     let redactor = self.redactor.clone();
                     let events = self.events.clone();
                     let host_id = self.host_id.clone();
                     tokio::spawn(async move {
+                        if crate::checkpoints::enabled() && crate::checkpoints::prompts_enabled() {
+                            let input_id = uuid::Uuid::new_v4().to_string();
+                            let _ =
+                                snapshot_run(&run2, &events, &host_id, &input_id, "prompt").await;
+                        }
                         if let Err(e) = opencode_submit_prompt(
                             run2.clone(),
                             runs,
@@ -2622,6 +4840,35 @@ impl RunManager {
         }
         .context("unknown run_id")?;
 
+        let approved = matches!(decision, "approve" | "approve_for_session");
+        let budget_hold = self
+            .budgets
+            .lock()
+            .is_ok_and(|mut b| b.resolve_hold(run_id, request_id, approved));
+        if budget_hold {
+            *run.awaiting_input.lock().await = false;
+            let redacted = self
+                .redactor
+                .redact(if approved { "approve" } else { "deny" });
+            let mut env = WsEnvelope::new(
+                "run.input",
+                json!({
+                    "actor": actor,
+                    "input_id": request_id,
+                    "text_redacted": redacted.text_redacted,
+                    "text_sha256": redacted.text_sha256
+                }),
+            );
+            env.host_id = Some(self.host_id.clone());
+            env.run_id = Some(run.run_id.clone());
+            env.seq = Some(run.next_seq());
+            let _ = self.events.send(env);
+            if !approved {
+                self.stop_run(run_id, "term").await?;
+            }
+            return Ok(());
+        }
+
         let pending = { run.pending_permission.lock().await.clone() };
         let Some(pending) = pending else {
             return Ok(());
@@ -2630,7 +4877,10 @@ impl RunManager {
             return Ok(());
         }
 
-        if let Some(rpc_id) = pending.rpc_request_id {
+        if pending.rpc_request_id.is_some()
+            || pending.acp_request.is_some()
+            || pending.opencode_permission.is_some()
+        {
             // Idempotency: ignore duplicate request_id decisions.
             {
                 let mut processed = run.processed_input_ids.lock().await;
@@ -2641,27 +4891,55 @@ impl RunManager {
             }
 
             let approved = match decision {
-                "approve" => true,
+                "approve" | "approve_for_session" => true,
                 "deny" => false,
                 _ => return Err(anyhow::anyhow!("invalid decision")),
             };
 
-            // MCP elicitation: respond to the JSON-RPC request id.
-            let result = json!({
-                "action": if approved { "accept" } else { "decline" },
-                "content": { "approved": approved }
-            });
-            let resp = json!({
-                "jsonrpc": "2.0",
-                "id": rpc_id,
-                "result": result
-            });
-            {
-                let mut w = run.writer.lock().await;
-                w.write_all(resp.to_string().as_bytes())
-                    .context("write elicitation response")?;
-                w.write_all(b"\n").context("write newline")?;
-                w.flush().ok();
+            if let Some(ask) = pending.opencode_permission.as_ref() {
+                let serve = run
+                    .opencode_serve
+                    .as_ref()
+                    .context("opencode serve missing")?;
+                // "always" lets opencode skip matching asks itself; the allowlist covers the rest.
+                if decision == "approve_for_session" {
+                    run.session_allow_tools
+                        .lock()
+                        .await
+                        .insert(ask.op_tool.clone());
+                }
+                serve
+                    .server
+                    .reply_permission(
+                        &ask.session_id,
+                        &ask.id,
+                        crate::opencode_serve::PermissionAsk::response(decision),
+                    )
+                    .await?;
+            } else {
+                let resp = match (pending.acp_request.as_ref(), pending.rpc_request_id) {
+                    // ACP permission request: answer with the matching option.
+                    (Some(req), _) => crate::acp::response(
+                        &req.rpc_id,
+                        crate::acp::permission_outcome(&req.options, approved),
+                    ),
+                    // MCP elicitation: respond to the JSON-RPC request id.
+                    (None, rpc_id) => json!({
+                        "jsonrpc": "2.0",
+                        "id": rpc_id,
+                        "result": {
+                            "action": if approved { "accept" } else { "decline" },
+                            "content": { "approved": approved }
+                        }
+                    }),
+                };
+                {
+                    let mut w = run.writer.lock().await;
+                    w.write_all(resp.to_string().as_bytes())
+                        .context("write permission response")?;
+                    w.write_all(b"\n").context("write newline")?;
+                    w.flush().ok();
+                }
             }
 
             {
@@ -2693,7 +4971,7 @@ impl RunManager {
         }
 
         let text = match decision {
-            "approve" => pending.approve_text,
+            "approve" | "approve_for_session" => pending.approve_text,
             "deny" => pending.deny_text,
             _ => return Err(anyhow::anyhow!("invalid decision")),
         };
@@ -2726,6 +5004,49 @@ impl RunManager {
             return Ok(());
         }
 
+        // ACP: "int" cancels the current prompt turn and keeps the agent running.
+        if signal == "int"
+            && let Some(acp) = run.acp.as_ref()
+        {
+            let pending = { run.pending_permission.lock().await.take() };
+            if let Some(req) = pending.and_then(|p| p.acp_request) {
+                let resp = crate::acp::response(&req.rpc_id, crate::acp::cancelled_outcome());
+                acp_write(&run, &resp).await?;
+                *run.awaiting_input.lock().await = false;
+            }
+            if let Some(session_id) = acp.session_id() {
+                let msg =
+                    crate::acp::notification("session/cancel", json!({ "sessionId": session_id }));
+                acp_write(&run, &msg).await?;
+            }
+            return Ok(());
+        }
+
+        // opencode serve: "int" aborts the current turn; term/kill stop the server, and its exit
+        // waiter finalizes the run.
+        if let Some(serve) = run.opencode_serve.as_ref() {
+            if signal == "int" {
+                let session_id = run.opencode_session_id.lock().ok().and_then(|s| s.clone());
+                if let Some(session_id) = session_id {
+                    serve.server.abort(&session_id).await?;
+                }
+                clear_opencode_permission(&run).await;
+                return Ok(());
+            }
+            #[cfg(unix)]
+            {
+                use nix::sys::signal::Signal;
+
+                let sig = if signal == "kill" {
+                    Signal::SIGKILL
+                } else {
+                    Signal::SIGTERM
+                };
+                signal_opencode_process_group(serve.server.pid, sig)?;
+            }
+            return Ok(());
+        }
+
         if run.opencode_structured {
             #[cfg(unix)]
             {
@@ -2816,8 +5137,8 @@ impl RunManager {
         }
         .context("unknown run_id")?;
 
-        // Structured (opencode) runs don't have a PTY to resize.
-        if run.opencode_structured {
+        // Structured (opencode/ACP) runs don't have a PTY to resize.
+        if run.opencode_structured || run.acp.is_some() {
             return Ok(());
         }
 
@@ -2860,24 +5181,679 @@ impl RunManager {
         Ok(run.cwd.clone())
     }
 
-    pub async fn emit_run_event(
+    /// Resolves the worktree of an exited run for `rpc.run.worktree.cleanup`: the one recorded at
+    /// start, or (after a hostd restart) the one found in `repo_cwd`, which is then adopted so
+    /// the cleanup's events still reach the run.
+    pub async fn worktree_for_cleanup(
         &self,
         run_id: &str,
-        event_type: &str,
-        data: serde_json::Value,
-    ) -> anyhow::Result<()> {
-        let run = {
+        repo_cwd: Option<String>,
+    ) -> anyhow::Result<crate::fs_git::RunWorktree> {
+        {
             let runs = self.runs.read().await;
-            runs.get(run_id).cloned()
+            anyhow::ensure!(
+                !runs.contains_key(run_id),
+                "run is still active; stop it before cleaning up its worktree"
+            );
         }
-        .context("unknown run_id")?;
-
-        let mut env = WsEnvelope::new(event_type, data);
-        env.host_id = Some(self.host_id.clone());
-        env.run_id = Some(run.run_id.clone());
-        env.seq = Some(run.next_seq());
-        let _ = self.events.send(env);
-        Ok(())
+        if let Some(wt) = self.worktrees.read().await.get(run_id).cloned() {
+            return Ok(wt);
+        }
+        let Some(repo) = repo_cwd else {
+            anyhow::bail!("unknown worktree run; pass `cwd` of the repository");
+        };
+        validate_run_cwd(&repo)?;
+        let run_id_owned = run_id.to_string();
+        let wt =
+            tokio::task::spawn_blocking(move || crate::fs_git::worktree_find(&repo, &run_id_owned))
+                .await
+                .context("join worktree lookup")?
+                .map_err(|(_, e)| anyhow::anyhow!(e))?;
+        let mut map = self.worktrees.write().await;
+        map.insert(run_id.to_string(), wt.clone());
+        Ok(wt)
+    }
+
+    /// Snapshots the worktree (including uncommitted changes) before cleanup removes it; the
+    /// checkpoint refs live in the shared repo, so the work stays reachable afterwards.
+    pub async fn checkpoint_worktree(
+        &self,
+        run_id: &str,
+        request_id: &str,
+        wt: &crate::fs_git::RunWorktree,
+    ) -> Option<crate::checkpoints::Checkpoint> {
+        if !crate::checkpoints::enabled() {
+            return None;
+        }
+        let path = wt.path.clone();
+        let run_id_owned = run_id.to_string();
+        let request_id_owned = request_id.to_string();
+        let created = tokio::task::spawn_blocking(move || {
+            crate::checkpoints::create(
+                &path,
+                &run_id_owned,
+                &request_id_owned,
+                "rpc.run.worktree.cleanup",
+            )
+        })
+        .await;
+        let checkpoint = match created {
+            Ok(Ok(Some(c))) => c,
+            Ok(Ok(None)) => return None,
+            Ok(Err((_, e))) => {
+                tracing::warn!(run_id=%run_id, error=%e, "worktree checkpoint failed");
+                return None;
+            }
+            Err(e) => {
+                tracing::warn!(run_id=%run_id, error=%e, "worktree checkpoint task failed");
+                return None;
+            }
+        };
+        let _ = self
+            .emit_run_event(run_id, "run.checkpoint", json!(checkpoint))
+            .await;
+        Some(checkpoint)
+    }
+
+    /// Finishes a worktree run (merge/discard/keep_branch) resolved by `worktree_for_cleanup`.
+    pub async fn cleanup_worktree(
+        &self,
+        run_id: &str,
+        wt: crate::fs_git::RunWorktree,
+        action: crate::fs_git::WorktreeCleanup,
+    ) -> anyhow::Result<JsonValue> {
+        let result =
+            tokio::task::spawn_blocking(move || crate::fs_git::worktree_cleanup(&wt, action))
+                .await
+                .context("join worktree cleanup")?
+                .map_err(|(_, e)| anyhow::anyhow!(e))?;
+
+        let mut map = self.worktrees.write().await;
+        map.remove(run_id);
+        if let Ok(mut seqs) = self.worktree_seqs.lock() {
+            seqs.remove(run_id);
+        }
+        Ok(result)
+    }
+
+    /// Automatic pre-operation snapshot (no-op when `RELAY_CHECKPOINTS=0` or the cwd is not a
+    /// git work tree).
+    pub async fn checkpoint_run(
+        &self,
+        run_id: &str,
+        request_id: &str,
+        tool: &str,
+    ) -> Option<crate::checkpoints::Checkpoint> {
+        if !crate::checkpoints::enabled() {
+            return None;
+        }
+        let run = {
+            let runs = self.runs.read().await;
+            runs.get(run_id).cloned()
+        }?;
+        snapshot_run(&run, &self.events, &self.host_id, request_id, tool).await
+    }
+
+    pub async fn get_run_sandbox(
+        &self,
+        run_id: &str,
+    ) -> anyhow::Result<Option<crate::sandbox::SandboxProfile>> {
+        let run = {
+            let runs = self.runs.read().await;
+            runs.get(run_id).cloned()
+        }
+        .context("unknown run_id")?;
+        Ok(run.launch.sandbox.clone())
+    }
+
+    /// Emits `run.files_changed` for the run's settled changes until the run exits.
+    async fn report_file_changes(self, run_id: String, tracker: Arc<crate::file_changes::Tracker>) {
+        while !tracker.is_stopped() {
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            let files = tracker.take_pending(false);
+            if !files.is_empty() {
+                let _ = self
+                    .emit_run_event(&run_id, "run.files_changed", json!({ "files": files }))
+                    .await;
+            }
+        }
+    }
+
+    /// Feeds `tool.call`/`tool.result` into the run's file tracker so changes can be attributed,
+    /// and stops watching when the run exits.
+    pub fn on_file_tracking_event(&self, env: &WsEnvelope) {
+        let Some(run_id) = env.run_id.as_deref() else {
+            return;
+        };
+        if env.r#type == "run.exited" {
+            self.stop_fs_watches(run_id);
+        }
+        let Ok(mut trackers) = self.file_trackers.lock() else {
+            return;
+        };
+        match env.r#type.as_str() {
+            "run.exited" => trackers.finish(run_id),
+            t @ ("tool.call" | "tool.result") => {
+                if let Some(tracker) = trackers.get(run_id)
+                    && let Some(request_id) = env.data.get("request_id").and_then(|v| v.as_str())
+                {
+                    tracker.note_tool(request_id, t == "tool.result");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Forgets permission requests once they are answered or the run exits. Requests themselves
+    /// are recorded when emitted; a late copy from the event stream must not bring back one that
+    /// was already answered.
+    pub fn on_permission_event(&self, env: &WsEnvelope) {
+        if env.r#type != "run.permission_requested" {
+            self.permission_log.observe(env);
+        }
+    }
+
+    /// Rebuilds the permission log from run state after its subscriber lagged and may have missed
+    /// answers: keeps only requests a run, a budget hold or `parked` (tool calls waiting for a
+    /// decision, keyed `<run_id>:<request_id>`) still waits on.
+    pub async fn resync_permission_log(&self, parked: &HashSet<String>) {
+        let keys: Vec<String> = self
+            .permission_log
+            .0
+            .lock()
+            .map(|m| m.keys().cloned().collect())
+            .unwrap_or_default();
+        let mut stale = Vec::new();
+        for key in keys {
+            let Some((run_id, request_id)) = key.split_once(':') else {
+                continue;
+            };
+            if !parked.contains(&key) && !self.is_permission_pending(run_id, request_id).await {
+                stale.push(key);
+            }
+        }
+        if let Ok(mut m) = self.permission_log.0.lock() {
+            for key in stale {
+                m.remove(&key);
+            }
+        }
+    }
+
+    /// Permission requests of `run_id` that have not been answered yet, oldest first.
+    pub fn permission_requests(&self, run_id: &str) -> Vec<WsEnvelope> {
+        let prefix = format!("{run_id}:");
+        let mut out: Vec<WsEnvelope> = self
+            .permission_log
+            .0
+            .lock()
+            .map(|m| {
+                m.iter()
+                    .filter(|(k, _)| k.starts_with(&prefix))
+                    .map(|(_, env)| env.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        out.sort_by_key(|env| env.seq);
+        out
+    }
+
+    /// Whether the agent itself (ACP/MCP elicitation, opencode ask) or a budget hold is still
+    /// waiting on `request_id`; see `decide_permission`.
+    pub async fn is_permission_pending(&self, run_id: &str, request_id: &str) -> bool {
+        if self
+            .budgets
+            .lock()
+            .is_ok_and(|b| b.is_holding(run_id, request_id))
+        {
+            return true;
+        }
+        let run = {
+            let runs = self.runs.read().await;
+            runs.get(run_id).cloned()
+        };
+        match run {
+            Some(run) => run
+                .pending_permission
+                .lock()
+                .await
+                .as_ref()
+                .is_some_and(|p| p.request_id == request_id),
+            None => false,
+        }
+    }
+
+    /// `rpc.run.changes`: net file changes since the run started. Also answers for recently
+    /// exited runs.
+    pub async fn run_changes(&self, run_id: &str, data: &JsonValue) -> anyhow::Result<JsonValue> {
+        let tracker = self
+            .file_trackers
+            .lock()
+            .ok()
+            .and_then(|t| t.get(run_id))
+            .context("no file tracking for this run (unknown run_id or RELAY_FILE_TRACKING=0)")?;
+        let path = data
+            .get("path")
+            .and_then(|v| v.as_str())
+            .map(|p| p.trim_start_matches("./").to_string());
+        let with_diff = data.get("diff").and_then(|v| v.as_bool()).unwrap_or(true);
+        let v = tokio::task::spawn_blocking(move || tracker.changes(path.as_deref(), with_diff))
+            .await
+            .context("join file changes")?;
+        Ok(redact_json_with(&self.redactor, &v))
+    }
+
+    /// Runs an approved `rpc.bash` command, streaming its output as `tool.output` events until
+    /// it exits, times out or is cancelled through `rpc.bash.cancel`. Returns the outcome with
+    /// the timeout that applied.
+    pub async fn exec_bash(
+        &self,
+        run_id: &str,
+        request_id: &str,
+        cwd: String,
+        cmd: String,
+        timeout_ms: Option<u64>,
+    ) -> Result<(crate::fs_git::BashOutcome, Duration), (axum::http::StatusCode, String)> {
+        const MAX_STREAMED_BYTES: usize = 1024 * 1024;
+
+        let sandbox = self
+            .get_run_sandbox(run_id)
+            .await
+            .map_err(|e| (axum::http::StatusCode::BAD_REQUEST, e.to_string()))?;
+        let timeout = bash_timeout(timeout_ms);
+        let key = format!("{run_id}:{request_id}");
+        let cancel = Arc::new(AtomicBool::new(false));
+        if let Ok(mut m) = self.bash_cancels.lock() {
+            m.insert(key.clone(), cancel.clone());
+        }
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<(&'static str, String)>();
+        let exec = tokio::task::spawn_blocking(move || {
+            crate::fs_git::bash_stream(
+                &cwd,
+                &cmd,
+                200_000,
+                sandbox.as_ref(),
+                timeout,
+                &cancel,
+                |stream, text| {
+                    let _ = tx.send((stream, text));
+                },
+            )
+        });
+
+        // Batch chunks per stream so chatty commands do not turn into one event per read.
+        let mut ticker = tokio::time::interval(pty_output_flush_interval());
+        let mut buf = String::new();
+        let mut buf_stream = "stdout";
+        let mut streamed = 0usize;
+        loop {
+            let next = tokio::select! {
+                msg = rx.recv() => msg.map(Some),
+                _ = ticker.tick() => Some(None),
+            };
+            let flush = match &next {
+                Some(Some((stream, _))) => {
+                    *stream != buf_stream || buf.len() >= pty_output_max_bytes()
+                }
+                _ => true,
+            };
+            if flush && !buf.is_empty() && streamed < MAX_STREAMED_BYTES {
+                let mut text = std::mem::take(&mut buf);
+                let capped = streamed + text.len() > MAX_STREAMED_BYTES;
+                if capped {
+                    let mut end = MAX_STREAMED_BYTES - streamed;
+                    while !text.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    text.truncate(end);
+                }
+                streamed += text.len();
+                if capped {
+                    streamed = MAX_STREAMED_BYTES;
+                }
+                let _ = self
+                    .emit_run_event(
+                        run_id,
+                        "tool.output",
+                        json!({
+                            "request_id": request_id,
+                            "tool": "rpc.bash",
+                            "stream": buf_stream,
+                            "text": self.redact_string(&text),
+                            "truncated": capped,
+                        }),
+                    )
+                    .await;
+            }
+            if flush {
+                buf.clear();
+            }
+            match next {
+                Some(Some((stream, text))) => {
+                    buf_stream = stream;
+                    buf.push_str(&text);
+                }
+                Some(None) => {}
+                None => break,
+            }
+        }
+
+        let result = exec
+            .await
+            .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+        if let Ok(mut m) = self.bash_cancels.lock() {
+            m.remove(&key);
+        }
+        result?.map(|outcome| (outcome, timeout))
+    }
+
+    /// `rpc.bash.cancel`: stops a running `rpc.bash`; false when nothing is running under that id.
+    pub fn cancel_bash(&self, run_id: &str, request_id: &str) -> bool {
+        let flag = self
+            .bash_cancels
+            .lock()
+            .ok()
+            .and_then(|m| m.get(&format!("{run_id}:{request_id}")).cloned());
+        match flag {
+            Some(flag) => {
+                flag.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs a search in the run cwd, cancellable by `cancel_search` or by dropping the future (a
+    /// local client that disconnects).
+    pub async fn search(
+        &self,
+        run_id: &str,
+        request_id: &str,
+        cwd: String,
+        opts: crate::search::SearchOptions,
+    ) -> Result<crate::search::SearchResult, (axum::http::StatusCode, String)> {
+        struct Registration {
+            cancels: Arc<StdMutex<HashMap<String, Arc<AtomicBool>>>>,
+            key: String,
+            cancel: Arc<AtomicBool>,
+        }
+        impl Drop for Registration {
+            fn drop(&mut self) {
+                self.cancel.store(true, Ordering::Relaxed);
+                if let Ok(mut m) = self.cancels.lock() {
+                    m.remove(&self.key);
+                }
+            }
+        }
+
+        let reg = Registration {
+            cancels: self.search_cancels.clone(),
+            key: format!("{run_id}:{request_id}"),
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
+        if let Ok(mut m) = self.search_cancels.lock() {
+            m.insert(reg.key.clone(), reg.cancel.clone());
+        }
+        let cancel = reg.cancel.clone();
+        let result =
+            tokio::task::spawn_blocking(move || crate::search::search(&cwd, &opts, &cancel)).await;
+        drop(reg);
+        result.map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    }
+
+    /// `rpc.fs.search.cancel`: stops a running search; false when nothing is running under that id.
+    pub fn cancel_search(&self, run_id: &str, request_id: &str) -> bool {
+        let flag = self
+            .search_cancels
+            .lock()
+            .ok()
+            .and_then(|m| m.get(&format!("{run_id}:{request_id}")).cloned());
+        match flag {
+            Some(flag) => {
+                flag.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// `rpc.code.*`: asks the language server for the run's cwd (started in the run's sandbox on
+    /// first use).
+    pub async fn code_request(
+        &self,
+        run_id: &str,
+        rpc_type: &str,
+        data: JsonValue,
+    ) -> Result<JsonValue, (axum::http::StatusCode, String)> {
+        let bad_request = |e: anyhow::Error| (axum::http::StatusCode::BAD_REQUEST, e.to_string());
+        let cwd = self.get_run_cwd(run_id).await.map_err(bad_request)?;
+        let sandbox = self.get_run_sandbox(run_id).await.map_err(bad_request)?;
+        let lsp = self.lsp.clone();
+        let rpc_type = rpc_type.to_string();
+        tokio::task::spawn_blocking(move || lsp.handle(&cwd, &rpc_type, &data, sandbox.as_ref()))
+            .await
+            .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    }
+
+    /// `rpc.fs.watch`: watches a directory under the run cwd and emits debounced `fs.changed`
+    /// events until `rpc.fs.unwatch` or the run exits.
+    pub async fn watch_fs(
+        &self,
+        run_id: &str,
+        cwd: String,
+        data: &JsonValue,
+    ) -> Result<JsonValue, (axum::http::StatusCode, String)> {
+        let limits = self.fs_watch_limits.clone();
+        let active = self
+            .fs_watches
+            .lock()
+            .map(|m| {
+                m.keys()
+                    .filter(|k| k.starts_with(&format!("{run_id}:")))
+                    .count()
+            })
+            .unwrap_or(0);
+        if active >= limits.max_per_run {
+            return Err((
+                axum::http::StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "too many fs watches for this run (limit {})",
+                    limits.max_per_run
+                ),
+            ));
+        }
+        let path = data
+            .get("path")
+            .and_then(|v| v.as_str())
+            .unwrap_or(".")
+            .to_string();
+        let depth = data
+            .get("depth")
+            .and_then(|v| v.as_u64())
+            .map(|d| d as usize);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<JsonValue>();
+        let watch = tokio::task::spawn_blocking(move || {
+            crate::fs_watch::start(&cwd, &path, depth, &limits, move |batch| {
+                let _ = tx.send(batch);
+            })
+        })
+        .await
+        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+
+        let watch_id = format!("w_{}", uuid::Uuid::new_v4().simple());
+        let key = format!("{run_id}:{watch_id}");
+        if let Ok(mut m) = self.fs_watches.lock() {
+            m.insert(key.clone(), watch.clone());
+        }
+        let mut info = watch.info();
+        info["watch_id"] = json!(watch_id);
+
+        let rm = self.clone();
+        let run_id = run_id.to_string();
+        tokio::spawn(async move {
+            // Ends when the watch thread exits (unwatched, run exited, or directory removed).
+            while let Some(mut batch) = rx.recv().await {
+                batch["watch_id"] = json!(watch_id);
+                if rm
+                    .emit_run_event(&run_id, "fs.changed", batch)
+                    .await
+                    .is_err()
+                {
+                    watch.stop();
+                    break;
+                }
+            }
+            if let Ok(mut m) = rm.fs_watches.lock() {
+                m.remove(&key);
+            }
+        });
+        Ok(info)
+    }
+
+    /// `rpc.fs.unwatch`: false when the run has no watch with that id.
+    pub fn unwatch_fs(&self, run_id: &str, watch_id: &str) -> bool {
+        let watch = self
+            .fs_watches
+            .lock()
+            .ok()
+            .and_then(|mut m| m.remove(&format!("{run_id}:{watch_id}")));
+        match watch {
+            Some(watch) => {
+                watch.stop();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn stop_fs_watches(&self, run_id: &str) {
+        let prefix = format!("{run_id}:");
+        if let Ok(mut m) = self.fs_watches.lock() {
+            m.retain(|key, watch| {
+                if key.starts_with(&prefix) {
+                    watch.stop();
+                    return false;
+                }
+                true
+            });
+        }
+    }
+
+    fn check_budget_hold(&self, run_id: &str) -> anyhow::Result<()> {
+        let reason = self.budgets.lock().ok().and_then(|b| b.hold_reason(run_id));
+        match reason {
+            Some(reason) => Err(anyhow::anyhow!(reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Drops a run's budget state once it exits.
+    pub fn on_budget_event(&self, env: &WsEnvelope) {
+        if env.r#type == "run.exited"
+            && let Some(run_id) = env.run_id.as_deref()
+            && let Ok(mut b) = self.budgets.lock()
+        {
+            b.end_run(run_id);
+        }
+    }
+
+    /// Acts on the limits [`BudgetMeter`] reports: a crossed soft limit holds the run behind a
+    /// `reason: "budget"` permission request, a hard limit stops it. Runs until hostd exits.
+    pub async fn enforce_budgets(self) {
+        let rx = self
+            .budget_verdicts
+            .lock()
+            .ok()
+            .and_then(|mut rx| rx.take());
+        let Some(mut rx) = rx else {
+            return;
+        };
+        while let Some((run_id, v)) = rx.recv().await {
+            self.apply_budget_verdict(&run_id, &v).await;
+        }
+    }
+
+    async fn apply_budget_verdict(&self, run_id: &str, v: &crate::budget::Verdict) {
+        match v {
+            crate::budget::Verdict::Hard { .. } => {
+                tracing::warn!(run_id=%run_id, summary=%v.summary(), "budget hard limit reached");
+                let _ = self
+                    .emit_run_event(
+                        run_id,
+                        "run.output",
+                        json!({
+                            "stream": "stderr",
+                            "text": format!("budget hard limit reached ({}); stopping run\n", v.summary()),
+                        }),
+                    )
+                    .await;
+                let _ = self.stop_run(run_id, "term").await;
+            }
+            crate::budget::Verdict::Soft { request_id, .. } => {
+                let prompt = format!(
+                    "Budget soft limit reached ({}). Approve to keep sending prompts, deny to stop the run.",
+                    v.summary()
+                );
+                if let Some(run) = self.runs.read().await.get(run_id) {
+                    *run.awaiting_input.lock().await = true;
+                }
+                let _ = self
+                    .emit_run_event(
+                        run_id,
+                        "run.permission_requested",
+                        json!({
+                            "request_id": request_id,
+                            "reason": "budget",
+                            "prompt": prompt,
+                            "op_tool": "budget",
+                            "op_args": v.details(),
+                            "op_args_summary": v.summary(),
+                            "approve_text": "",
+                            "deny_text": ""
+                        }),
+                    )
+                    .await;
+                let _ = self
+                    .emit_run_event(
+                        run_id,
+                        "run.awaiting_input",
+                        json!({ "reason": "budget", "prompt": prompt, "request_id": request_id }),
+                    )
+                    .await;
+            }
+        }
+    }
+
+    pub async fn emit_run_event(
+        &self,
+        run_id: &str,
+        event_type: &str,
+        data: serde_json::Value,
+    ) -> anyhow::Result<()> {
+        let run = {
+            let runs = self.runs.read().await;
+            runs.get(run_id).cloned()
+        };
+        let seq = match run {
+            Some(run) => Some(run.next_seq()),
+            // Exited runs whose worktree awaits cleanup; unnumbered after a hostd restart.
+            None if self.worktrees.read().await.contains_key(run_id) => self
+                .worktree_seqs
+                .lock()
+                .ok()
+                .and_then(|seqs| seqs.get(run_id).cloned())
+                .map(|seq| seq.fetch_add(1, Ordering::SeqCst) + 1),
+            None => anyhow::bail!("unknown run_id"),
+        };
+
+        let mut env = WsEnvelope::new(event_type, data);
+        env.host_id = Some(self.host_id.clone());
+        env.run_id = Some(run_id.to_string());
+        env.seq = seq;
+        self.permission_log.observe(&env);
+        let _ = self.events.send(env);
+        Ok(())
     }
 
     pub async fn list_runs(&self) -> Vec<RunSummary> {
@@ -2913,10 +5889,65 @@ pub struct RunSummary {
     pub pending_request_id: Option<String>,
 }
 
+/// Pid of the first pane of tmux `session`, polled for a few seconds while the session starts.
+fn tmux_pane_pid(session: &str) -> Option<i32> {
+    for _ in 0..50 {
+        let pid = Command::new("tmux")
+            .args(["list-panes", "-t", session, "-F", "#{pane_pid}"])
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .and_then(|o| {
+                String::from_utf8_lossy(&o.stdout)
+                    .lines()
+                    .next()
+                    .and_then(|l| l.trim().parse::<i32>().ok())
+            });
+        if pid.is_some_and(|p| p > 1) {
+            return pid;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    None
+}
+
+fn run_for_pid_blocking(pid: i32, roots: &HashMap<i32, String>) -> Option<String> {
+    let mut cur = pid;
+    for _ in 0..128 {
+        if let Some(run_id) = roots.get(&cur) {
+            return Some(run_id.clone());
+        }
+        let stat = std::fs::read_to_string(format!("/proc/{cur}/stat")).ok()?;
+        // `pid (comm) state ppid ...`; comm may contain spaces or parentheses.
+        let ppid = stat
+            .rsplit_once(')')?
+            .1
+            .split_whitespace()
+            .nth(1)?
+            .parse::<i32>()
+            .ok()?;
+        if ppid <= 1 {
+            return None;
+        }
+        cur = ppid;
+    }
+    None
+}
+
+/// Token comparison that does not stop at the first differing byte.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
-        opencode_model_choices, opencode_structured_config_value,
+        OpencodeSessionStart, StartRunOptions, opencode_model_choices, opencode_permission_env,
+        opencode_permission_mode, opencode_structured_config_value,
         opencode_structured_model_choices, validate_opencode_structured_model, validate_run_cwd,
     };
     use serde_json::json;
@@ -2958,6 +5989,236 @@ mod tests {
         }
     }
 
+    #[test]
+    fn start_options_parse_opencode_session() {
+        let opts = StartRunOptions::from_request_data(&json!({ "resume_session_id": "ses_1" }))
+            .expect("resume");
+        assert_eq!(
+            opts.opencode_session,
+            Some(OpencodeSessionStart::Resume("ses_1".to_string()))
+        );
+        let opts = StartRunOptions::from_request_data(&json!({ "fork_from_session_id": "ses_2" }))
+            .expect("fork");
+        assert_eq!(
+            opts.opencode_session,
+            Some(OpencodeSessionStart::Fork("ses_2".to_string()))
+        );
+        assert!(
+            StartRunOptions::from_request_data(
+                &json!({ "resume_session_id": "a", "fork_from_session_id": "b" })
+            )
+            .is_err()
+        );
+        assert!(
+            StartRunOptions::from_request_data(&json!({ "resume_session_id": "a/b" })).is_err()
+        );
+    }
+
+    #[test]
+    fn opencode_permission_policy_defaults_to_ask_on_serve() {
+        let _env = EnvVarGuard::unset("OPENCODE_PERMISSION");
+        let _mode = EnvVarGuard::unset("RELAY_OPENCODE_PERMISSION_MODE");
+        let policy: serde_json::Value =
+            serde_json::from_str(opencode_permission_env(true).unwrap()).unwrap();
+        for tool in ["edit", "bash", "webfetch"] {
+            assert_eq!(policy[tool], "ask", "{tool}");
+        }
+        assert_eq!(opencode_permission_mode(true), "relay_ask");
+        // The per-prompt `run` backend cannot answer asks.
+        assert_eq!(opencode_permission_env(false), Some(r#"{"*":"allow"}"#));
+        assert_eq!(opencode_permission_mode(false), "relay_auto_allow_all");
+
+        // A sandbox without network would need that backend: ask-mode runs are refused instead.
+        let _backend = EnvVarGuard::unset("RELAY_OPENCODE_BACKEND");
+        let offline = crate::sandbox::SandboxProfile {
+            network: false,
+            ..Default::default()
+        };
+        assert!(super::opencode_serve_enabled(Some(&offline)).is_err());
+        let sandboxed = crate::sandbox::SandboxProfile::default();
+        assert!(super::opencode_serve_enabled(Some(&sandboxed)).unwrap());
+        assert!(super::opencode_serve_enabled(None).unwrap());
+
+        let _mode = EnvVarGuard::set("RELAY_OPENCODE_PERMISSION_MODE", "inherit");
+        assert!(!super::opencode_serve_enabled(Some(&offline)).unwrap());
+        assert_eq!(opencode_permission_env(true), None);
+        let _env = EnvVarGuard::set("OPENCODE_PERMISSION", r#"{"*":"deny"}"#);
+        assert_eq!(opencode_permission_mode(true), "env");
+    }
+
+    #[test]
+    fn budget_meter_reports_limits_as_usage_is_emitted() {
+        use std::sync::{Arc, Mutex};
+        let budgets = Arc::new(Mutex::new(crate::budget::Budgets::default()));
+        let (verdicts, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let meter = super::BudgetMeter {
+            budgets: budgets.clone(),
+            verdicts,
+        };
+        let limits: crate::budget::Limits =
+            serde_json::from_value(json!({ "hard": { "tokens": 100 } })).unwrap();
+        budgets.lock().unwrap().start_run("run-1", Some(limits));
+
+        // No event subscriber is involved, so nothing can be dropped on lag.
+        meter.record("run-1", &json!({ "input_tokens": 60 }));
+        assert!(rx.try_recv().is_err());
+        meter.record("run-1", &json!({ "input_tokens": 60 }));
+        let (run_id, verdict) = rx.try_recv().unwrap();
+        assert_eq!(run_id, "run-1");
+        assert!(matches!(verdict, crate::budget::Verdict::Hard { .. }));
+    }
+
+    #[test]
+    fn transfer_grants_cover_one_transfer() {
+        let mut grants = super::TransferGrants::default();
+        let up = |offset: u64, data: &str| json!({ "path": "a.bin", "offset": offset, "data": data, "transfer_id": "t1" });
+        let done = json!({ "result": { "received": 8, "done": true } });
+
+        // First upload: the opening chunk asks, the rest of the transfer does not.
+        assert!(!grants.admits("rpc.fs.upload", &up(0, "AAAA")));
+        grants.grant("rpc.fs.upload", &up(0, "AAAA"));
+        assert!(grants.admits("rpc.fs.upload", &up(3, "")));
+        assert!(grants.admits("rpc.fs.upload", &up(3, "AAAA")));
+        assert!(!grants.admits(
+            "rpc.fs.upload",
+            &json!({ "path": "b.bin", "offset": 3, "transfer_id": "t1" })
+        ));
+        // Another transfer of the same path, or a chunk naming no transfer, asks.
+        assert!(!grants.admits(
+            "rpc.fs.upload",
+            &json!({ "path": "a.bin", "offset": 3, "data": "AAAA", "transfer_id": "t2" })
+        ));
+        assert!(!grants.admits(
+            "rpc.fs.upload",
+            &json!({ "path": "a.bin", "offset": 3, "data": "AAAA" })
+        ));
+        // Resume negotiation keeps the grant; finishing ends it.
+        let mismatch = json!({ "error": "offset mismatch: 3 bytes received so far" });
+        grants.finish_chunk("rpc.fs.upload", &up(6, "AAAA"), false, &mismatch);
+        assert!(grants.admits("rpc.fs.upload", &up(3, "AAAA")));
+        grants.finish_chunk("rpc.fs.upload", &up(3, "AAAA"), true, &done);
+        // A second upload of the same path asks again.
+        assert!(!grants.admits("rpc.fs.upload", &up(3, "AAAA")));
+
+        // Restarting at offset 0 drops the grant even if the restart is then denied.
+        grants.grant("rpc.fs.upload", &up(0, "AAAA"));
+        assert!(!grants.admits("rpc.fs.upload", &up(0, "AAAA")));
+        assert!(!grants.admits("rpc.fs.upload", &up(3, "AAAA")));
+
+        // A failed final chunk ends the transfer too.
+        grants.grant("rpc.fs.upload", &up(0, "AAAA"));
+        let bad_sha =
+            json!({ "error": "sha256 mismatch: uploaded data hashes to 00; upload discarded" });
+        grants.finish_chunk("rpc.fs.upload", &up(3, "AAAA"), false, &bad_sha);
+        assert!(!grants.admits("rpc.fs.upload", &up(3, "")));
+
+        // Downloads: granted until the eof chunk, and offset 0 always asks.
+        let dl = |offset: u64| json!({ "path": "a.bin", "offset": offset, "transfer_id": "t1" });
+        grants.grant("rpc.fs.download", &dl(0));
+        assert!(grants.admits("rpc.fs.download", &dl(4)));
+        assert!(!grants.admits("rpc.fs.upload", &up(3, "AAAA")));
+        grants.finish_chunk(
+            "rpc.fs.download",
+            &dl(4),
+            true,
+            &json!({ "result": { "eof": true } }),
+        );
+        assert!(!grants.admits("rpc.fs.download", &dl(8)));
+        grants.grant("rpc.fs.download", &dl(0));
+        assert!(!grants.admits("rpc.fs.download", &dl(0)));
+
+        // Grants lapse once the transfer idles.
+        grants.grant("rpc.fs.download", &dl(0));
+        grants.expire(std::time::Instant::now() + super::TRANSFER_GRANT_IDLE);
+        assert!(!grants.admits("rpc.fs.download", &dl(4)));
+    }
+
+    #[tokio::test]
+    async fn runs_do_not_inherit_hostd_secrets() {
+        let dir = std::env::temp_dir().join(format!("relay-hostd-env-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let agent = dir.join("fake-gemini");
+        fs::write(
+            &agent,
+            "#!/bin/sh\nenv > \"$PWD/env.tmp\"\nmv \"$PWD/env.tmp\" \"$PWD/env.txt\"\nexec sleep 30\n",
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&agent, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let _bin = EnvVarGuard::set("RELAY_GEMINI_BIN", agent.to_str().unwrap());
+        let _tcp = EnvVarGuard::set("LOCAL_TCP_TOKEN", "hostd-tcp-secret");
+        let _host = EnvVarGuard::set("HOST_TOKEN", "hostd-host-secret");
+
+        let (tx, _rx) = tokio::sync::broadcast::channel(64);
+        let rm = super::RunManager::new(
+            "host-test".into(),
+            dir.join("hostd.sock").to_string_lossy().to_string(),
+            std::sync::Arc::new(relay_protocol::redaction::Redactor::new(&[]).unwrap()),
+            tx,
+        );
+        let run_id = rm
+            .start_run(
+                "gemini".into(),
+                String::new(),
+                Some(dir.to_string_lossy().to_string()),
+                Default::default(),
+            )
+            .await
+            .unwrap();
+        let env_file = dir.join("env.txt");
+        let mut env = String::new();
+        for _ in 0..100 {
+            if let Ok(text) = fs::read_to_string(&env_file) {
+                env = text;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        rm.stop_run(&run_id, "kill").await.unwrap();
+        assert!(env.contains("PATH="), "{env}");
+        assert!(!env.contains("hostd-tcp-secret") && !env.contains("hostd-host-secret"));
+
+        // `rpc.bash` commands neither.
+        let cancel = std::sync::atomic::AtomicBool::new(false);
+        let mut out = String::new();
+        crate::fs_git::bash_stream(
+            dir.to_str().unwrap(),
+            "env",
+            1 << 16,
+            None,
+            std::time::Duration::from_secs(10),
+            &cancel,
+            |_, text| out.push_str(&text),
+        )
+        .unwrap();
+        assert!(out.contains("PATH="), "{out}");
+        assert!(!out.contains("hostd-tcp-secret") && !out.contains("hostd-host-secret"));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn start_options_reject_reserved_env() {
+        for key in [
+            "OPENCODE_PERMISSION",
+            "OPENCODE_CONFIG_CONTENT",
+            "XDG_CONFIG_HOME",
+            "BASH_ENV",
+            "PATH",
+            "LD_PRELOAD",
+            "RELAY_RUN_TOKEN",
+        ] {
+            let err =
+                StartRunOptions::from_request_data(&json!({ "env": { key: "x" } })).expect_err(key);
+            assert!(err.to_string().contains("reserved"), "{err}");
+        }
+        let opts = StartRunOptions::from_request_data(&json!({ "env": { "CI": "1" } })).unwrap();
+        assert_eq!(opts.env, vec![("CI".to_string(), "1".to_string())]);
+    }
+
     #[test]
     fn validate_run_cwd_rejects_missing_directory() {
         let missing = format!("/tmp/relay-missing-cwd-{}", uuid::Uuid::new_v4().simple());
@@ -3101,6 +6362,145 @@ mod tests {
         fs::remove_dir_all(&temp).ok();
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn acp_runner_drives_fake_agent() {
+        let dir = std::env::temp_dir().join(format!("relay-acp-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // Fake ACP agent: answers initialize/session/new, then streams a reply, asks for
+        // permission to edit, writes a file through the client and ends the turn.
+        let agent = dir.join("fake-acp-agent");
+        fs::write(
+            &agent,
+            r#"#!/bin/sh
+read line
+echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":1,"agentCapabilities":{}}}'
+read line
+echo '{"jsonrpc":"2.0","id":2,"result":{"sessionId":"sess-1"}}'
+read line
+echo '{"jsonrpc":"2.0","method":"session/update","params":{"sessionId":"sess-1","update":{"sessionUpdate":"agent_message_chunk","content":{"type":"text","text":"hello from acp"}}}}'
+echo '{"jsonrpc":"2.0","method":"session/update","params":{"sessionId":"sess-1","update":{"sessionUpdate":"tool_call","toolCallId":"call-1","title":"Write notes.txt","kind":"edit","status":"pending","rawInput":{"path":"notes.txt"}}}}'
+echo '{"jsonrpc":"2.0","id":"perm-1","method":"session/request_permission","params":{"sessionId":"sess-1","toolCall":{"toolCallId":"call-1","title":"Write notes.txt","kind":"edit"},"options":[{"optionId":"yes","name":"Allow","kind":"allow_once"},{"optionId":"no","name":"Reject","kind":"reject_once"}]}}'
+read answer
+case "$answer" in
+  *'"optionId":"yes"'*) ;;
+  *) echo '{"jsonrpc":"2.0","id":3,"result":{"stopReason":"refusal"}}'; exit 3 ;;
+esac
+echo '{"jsonrpc":"2.0","id":"w-1","method":"fs/write_text_file","params":{"sessionId":"sess-1","path":"'"$(pwd)"'/notes.txt","content":"written via acp"}}'
+read ack
+echo '{"jsonrpc":"2.0","method":"session/update","params":{"sessionId":"sess-1","update":{"sessionUpdate":"tool_call_update","toolCallId":"call-1","status":"completed","rawOutput":{"bytes":15}}}}'
+echo '{"jsonrpc":"2.0","id":3,"result":{"stopReason":"end_turn"}}'
+read line
+"#,
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&agent, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let config = dir.join("runners.json");
+        fs::write(
+            &config,
+            json!({
+                "runners": [
+                    { "tool": "fakeacp", "default_bin": agent.to_str().unwrap(), "protocol": "acp" }
+                ]
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let _config = EnvVarGuard::set("RELAY_RUNNERS_CONFIG", config.to_str().unwrap());
+        let _checkpoints = EnvVarGuard::set("RELAY_CHECKPOINTS", "1");
+        std::process::Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(&dir)
+            .status()
+            .unwrap();
+
+        let (tx, mut rx) = tokio::sync::broadcast::channel(256);
+        let rm = super::RunManager::new(
+            "host-test".into(),
+            dir.join("hostd.sock").to_string_lossy().to_string(),
+            std::sync::Arc::new(relay_protocol::redaction::Redactor::new(&[]).unwrap()),
+            tx,
+        );
+        let run_id = rm
+            .start_run(
+                "fakeacp".into(),
+                String::new(),
+                Some(dir.to_string_lossy().to_string()),
+                Default::default(),
+            )
+            .await
+            .unwrap();
+
+        async fn next_until(
+            rx: &mut tokio::sync::broadcast::Receiver<relay_protocol::WsEnvelope>,
+            seen: &mut Vec<relay_protocol::WsEnvelope>,
+            ty: &str,
+        ) -> relay_protocol::WsEnvelope {
+            loop {
+                let env = tokio::time::timeout(std::time::Duration::from_secs(10), rx.recv())
+                    .await
+                    .expect("timed out waiting for event")
+                    .unwrap();
+                seen.push(env.clone());
+                if env.r#type == ty {
+                    return env;
+                }
+            }
+        }
+
+        let mut seen = Vec::new();
+        let meta = next_until(&mut rx, &mut seen, "run.metadata").await;
+        assert_eq!(meta.data["acp_session_id"], "sess-1");
+        rm.send_input(&run_id, "user", "input-1", "write the notes\n")
+            .await
+            .unwrap();
+        let perm = next_until(&mut rx, &mut seen, "run.permission_requested").await;
+        assert_eq!(perm.data["op_tool"], "edit");
+        assert_eq!(perm.data["prompt"], "Write notes.txt");
+        let request_id = perm.data["request_id"].as_str().unwrap().to_string();
+        rm.decide_permission(&run_id, "user", &request_id, "approve")
+            .await
+            .unwrap();
+        let write = next_until(&mut rx, &mut seen, "tool.result").await;
+        assert_eq!(write.data["tool"], "fs/write_text_file");
+        assert_eq!(write.data["ok"], true);
+        let write_call = seen
+            .iter()
+            .find(|e| e.r#type == "tool.call" && e.data["request_id"] == write.data["request_id"])
+            .expect("tool.call for the write");
+        assert_eq!(write_call.data["args"]["bytes"], 15);
+        let checkpoint = seen
+            .iter()
+            .find(|e| e.r#type == "run.checkpoint")
+            .expect("checkpoint before the write");
+        assert_eq!(checkpoint.data["request_id"], write.data["request_id"]);
+        assert!(checkpoint.seq < write.seq);
+        let result = next_until(&mut rx, &mut seen, "tool.result").await;
+        assert_eq!(result.data["request_id"], "call-1");
+        assert_eq!(result.data["ok"], true);
+
+        let output: String = seen
+            .iter()
+            .filter(|e| e.r#type == "run.output")
+            .filter_map(|e| e.data["text"].as_str())
+            .collect();
+        assert!(output.contains("hello from acp"), "{output}");
+        assert!(seen.iter().any(|e| e.r#type == "tool.call"
+            && e.data["tool"] == "edit"
+            && e.data["args"]["path"] == "notes.txt"));
+        assert_eq!(
+            fs::read_to_string(dir.join("notes.txt")).unwrap(),
+            "written via acp"
+        );
+
+        rm.stop_run(&run_id, "kill").await.unwrap();
+        fs::remove_dir_all(&dir).ok();
+    }
+
     #[test]
     fn validate_opencode_structured_model_rejects_known_incompatible_model() {
         let err = validate_opencode_structured_model(Some("cch-claude/claude-opus-4-6"))
@@ -3112,4 +6512,89 @@ mod tests {
             .expect_err("cch-g model should be rejected");
         assert!(err.to_string().contains("cch-g/g3-flash-preview"));
     }
+
+    #[tokio::test]
+    async fn run_tokens_are_scoped_to_their_run() {
+        let (tx, _rx) = tokio::sync::broadcast::channel(16);
+        let rm = super::RunManager::new(
+            "host-test".into(),
+            "/tmp/unused.sock".into(),
+            std::sync::Arc::new(relay_protocol::redaction::Redactor::new(&[]).unwrap()),
+            tx,
+        );
+        let token = rm.run_token("run-a");
+        assert_eq!(rm.verify_run_token(&token).as_deref(), Some("run-a"));
+        let forged = token.replacen("run-a", "run-b", 1);
+        assert_eq!(rm.verify_run_token(&forged), None);
+        assert_eq!(rm.verify_run_token("run-a"), None);
+        // No runs: nobody is confined by process ancestry.
+        assert_eq!(rm.run_for_pid(std::process::id() as i32).await, None);
+    }
+
+    #[tokio::test]
+    async fn permission_requests_are_listed_until_answered() {
+        let (tx, _rx) = tokio::sync::broadcast::channel(16);
+        let rm = super::RunManager::new(
+            "host-test".into(),
+            "/tmp/unused.sock".into(),
+            std::sync::Arc::new(relay_protocol::redaction::Redactor::new(&[]).unwrap()),
+            tx,
+        );
+        let event = |t: &str, seq: i64, data: serde_json::Value| {
+            let mut env = relay_protocol::WsEnvelope::new(t, data);
+            env.run_id = Some("run-a".into());
+            env.seq = Some(seq);
+            env
+        };
+        let ids = |rm: &super::RunManager| -> Vec<String> {
+            rm.permission_requests("run-a")
+                .iter()
+                .map(|e| e.data["request_id"].as_str().unwrap_or("").to_string())
+                .collect()
+        };
+        rm.permission_log.observe(&event(
+            "run.permission_requested",
+            2,
+            json!({ "request_id": "p2" }),
+        ));
+        rm.permission_log.observe(&event(
+            "run.permission_requested",
+            1,
+            json!({ "request_id": "p1" }),
+        ));
+        rm.permission_log.observe(&event(
+            "run.permission_requested",
+            3,
+            json!({ "request_id": "p3" }),
+        ));
+        assert_eq!(ids(&rm), ["p1", "p2", "p3"]);
+        assert!(rm.permission_requests("run-b").is_empty());
+
+        rm.on_permission_event(&event("tool.result", 4, json!({ "request_id": "p1" })));
+        rm.on_permission_event(&event("run.input", 5, json!({ "input_id": "p3" })));
+        assert_eq!(ids(&rm), ["p2"]);
+        // Requests are recorded when emitted; a late copy from the stream does not revive one.
+        rm.on_permission_event(&event(
+            "run.permission_requested",
+            1,
+            json!({ "request_id": "p1" }),
+        ));
+        assert_eq!(ids(&rm), ["p2"]);
+        rm.on_permission_event(&event("run.exited", 6, json!({})));
+        assert!(ids(&rm).is_empty());
+        // Unknown runs have nothing waiting inside the agent either.
+        assert!(!rm.is_permission_pending("run-a", "p2").await);
+
+        // After a lag, only requests something still waits on are kept.
+        for (seq, id) in [(7, "p4"), (8, "p5")] {
+            rm.permission_log.observe(&event(
+                "run.permission_requested",
+                seq,
+                json!({ "request_id": id }),
+            ));
+        }
+        let parked = std::collections::HashSet::from(["run-a:p5".to_string()]);
+        rm.resync_permission_log(&parked).await;
+        assert_eq!(ids(&rm), ["p5"]);
+    }
 }
@@ -1,9 +1,13 @@
+use super::registry::RunnerEntry;
 use super::{
-    Runner, RunnerSpec, base_prompt_regex, command_from_cmdline, command_from_shell, find_in_path,
-    looks_like_shell, resolve_tool_bin, swap_leading_token, validate_bin_exists,
+    Runner, RunnerSpec, command_from_cmdline, command_from_shell, find_in_path, looks_like_shell,
+    swap_leading_token, validate_bin_exists,
 };
 
-pub struct CodexRunner;
+/// Codex needs extra CLI flags on top of the registry entry (MCP server toggles, proxy env).
+pub struct CodexRunner {
+    pub entry: RunnerEntry,
+}
 
 fn escape_toml_basic_string(s: &str) -> String {
     s.replace('\\', "\\\\").replace('\"', "\\\"")
@@ -90,10 +94,13 @@ impl Runner for CodexRunner {
         // - if the user installs a `codex` wrapper in PATH, hostd must NOT call it (would recurse).
         //   We read `~/.relay/bin-map.json` (written by scripts/install-shims.sh) to find the real
         //   binary path to execute.
-        let bin = resolve_tool_bin("codex", "RELAY_CODEX_BIN", "codex");
+        let bin = self.entry.resolve_bin();
         validate_bin_exists(
             &bin,
-            "codex (set RELAY_CODEX_BIN=/path/to/codex or install shims to record real path)",
+            &format!(
+                "codex (set {}=/path/to/codex or install shims to record real path)",
+                self.entry.bin_env
+            ),
         )?;
 
         let mut final_cmd = cmd.trim().to_string();
@@ -103,7 +110,7 @@ impl Runner for CodexRunner {
             final_cmd = swap_leading_token(&final_cmd, "codex", &bin);
         }
 
-        let command = if looks_like_shell(&final_cmd) {
+        let mut command = if looks_like_shell(&final_cmd) {
             let mut command = command_from_shell(&final_cmd, cwd);
             let no_proxy = merged_no_proxy_for_localhost();
             command.env("NO_PROXY", &no_proxy);
@@ -139,19 +146,22 @@ impl Runner for CodexRunner {
                     ));
                     command.arg("--config");
                     command.arg(
-                        r#"mcp_servers.relay.env={RELAY_RUN_ID="${RELAY_RUN_ID}", RELAY_HOSTD_SOCK="${RELAY_HOSTD_SOCK}", RELAY_TOOL="${RELAY_TOOL}"}"#,
+                        r#"mcp_servers.relay.env={RELAY_RUN_ID="${RELAY_RUN_ID}", RELAY_RUN_TOKEN="${RELAY_RUN_TOKEN}", RELAY_HOSTD_SOCK="${RELAY_HOSTD_SOCK}", RELAY_TOOL="${RELAY_TOOL}"}"#,
                     );
                 }
             }
 
             command
         };
+        for (k, v) in &self.entry.env {
+            command.env(k, v);
+        }
 
         Ok(RunnerSpec {
             command,
-            prompt_regex: base_prompt_regex("codex"),
-            approve_text: "y\n".to_string(),
-            deny_text: "n\n".to_string(),
+            prompt_regex: self.entry.prompt_regex(),
+            approve_text: self.entry.approve_text.clone(),
+            deny_text: self.entry.deny_text.clone(),
         })
     }
 }
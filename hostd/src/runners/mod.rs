@@ -132,11 +132,13 @@ pub fn validate_bin_exists(bin: &str, hint: &str) -> anyhow::Result<()> {
     Err(anyhow::anyhow!("{hint}: binary not found in PATH: {bin}"))
 }
 
+/// Picks the PTY runner for `tool` from the registry; unregistered tools run as plain shell
+/// commands.
 pub fn for_tool(tool: &str) -> Box<dyn Runner> {
-    match tool {
-        "codex" => Box::new(crate::runners::codex::CodexRunner {}),
-        "opencode" => Box::new(crate::runners::opencode::OpencodeRunner {}),
-        _ => Box::new(crate::runners::shell::ShellRunner {}),
+    match registry::lookup(tool) {
+        Some(entry) if tool == "codex" => Box::new(crate::runners::codex::CodexRunner { entry }),
+        Some(entry) => Box::new(registry::ConfiguredRunner { entry }),
+        None => Box::new(crate::runners::shell::ShellRunner {}),
     }
 }
 
@@ -229,7 +231,7 @@ pub fn base_prompt_regex(tool: &str) -> Arc<Regex> {
 }
 
 pub mod codex;
-pub mod opencode;
+pub mod registry;
 pub mod shell;
 
 #[cfg(test)]
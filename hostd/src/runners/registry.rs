@@ -0,0 +1,455 @@
+use super::{
+    Runner, RunnerSpec, base_prompt_regex, command_from_cmdline, command_from_shell,
+    looks_like_shell, resolve_tool_bin, swap_leading_token, validate_bin_exists,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// One agent CLI hostd knows how to launch.
+///
+/// Built-in entries cover codex/opencode/claude/aider/gemini; `~/.relay/runners.json` (or
+/// `RELAY_RUNNERS_CONFIG`) can override them field by field or register new tools, so adding an
+/// agent does not need a rebuild.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunnerEntry {
+    pub tool: String,
+    /// Env var that overrides the binary path (default `RELAY_<TOOL>_BIN`).
+    #[serde(default)]
+    pub bin_env: String,
+    /// Binary looked up in PATH when `bin_env` is unset (default: the tool name).
+    #[serde(default)]
+    pub default_bin: String,
+    /// Regex that marks an interactive approval prompt in PTY output (default: the generic
+    /// `(y/n)` / `proceed?` patterns).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_regex: Option<String>,
+    #[serde(default = "default_approve_text")]
+    pub approve_text: String,
+    #[serde(default = "default_deny_text")]
+    pub deny_text: String,
+    /// Whether hostd has a structured (non-PTY) mode for this tool.
+    #[serde(default)]
+    pub structured: bool,
+    /// How hostd talks to the agent: a PTY (default) or the Agent Client Protocol over stdio.
+    #[serde(default)]
+    pub protocol: RunnerProtocol,
+    /// Arguments that start the agent in ACP mode (e.g. `["--experimental-acp"]`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Extra environment variables for the agent process.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunnerProtocol {
+    #[default]
+    Pty,
+    /// JSON-RPC over stdio, see `crate::acp`.
+    Acp,
+}
+
+fn default_approve_text() -> String {
+    "y\n".to_string()
+}
+
+fn default_deny_text() -> String {
+    "n\n".to_string()
+}
+
+impl RunnerEntry {
+    fn new(tool: &str, description: &str) -> Self {
+        Self {
+            tool: tool.to_string(),
+            bin_env: String::new(),
+            default_bin: String::new(),
+            prompt_regex: None,
+            approve_text: default_approve_text(),
+            deny_text: default_deny_text(),
+            structured: false,
+            protocol: RunnerProtocol::Pty,
+            args: Vec::new(),
+            env: BTreeMap::new(),
+            description: Some(description.to_string()),
+        }
+        .normalized()
+    }
+
+    fn normalized(mut self) -> Self {
+        self.tool = self.tool.trim().to_string();
+        if self.bin_env.trim().is_empty() {
+            let upper: String = self
+                .tool
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            self.bin_env = format!("RELAY_{upper}_BIN");
+        }
+        if self.default_bin.trim().is_empty() {
+            self.default_bin = self.tool.clone();
+        }
+        if self.protocol == RunnerProtocol::Acp {
+            self.structured = true;
+        }
+        self
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.tool.is_empty()
+                && self
+                    .tool
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "invalid tool name `{}`",
+            self.tool
+        );
+        if let Some(pat) = self.prompt_regex.as_deref() {
+            Regex::new(pat)
+                .map_err(|e| anyhow::anyhow!("{}: invalid prompt_regex: {e}", self.tool))?;
+        }
+        Ok(())
+    }
+
+    pub fn prompt_regex(&self) -> Arc<Regex> {
+        self.prompt_regex
+            .as_deref()
+            .and_then(|pat| Regex::new(pat).ok())
+            .map(Arc::new)
+            .unwrap_or_else(|| base_prompt_regex(&self.tool))
+    }
+
+    pub fn resolve_bin(&self) -> String {
+        resolve_tool_bin(&self.tool, &self.bin_env, &self.default_bin)
+    }
+
+    pub fn bin_hint(&self) -> String {
+        format!(
+            "{} (set {}=/path/to/{} or install shims to record real path)",
+            self.tool, self.bin_env, self.default_bin
+        )
+    }
+
+    /// Resolved binary plus availability, as reported by `rpc.host.capabilities` / `GET /runners`.
+    pub fn status(&self) -> serde_json::Value {
+        let bin = self.resolve_bin();
+        let err = validate_bin_exists(&bin, &self.tool)
+            .err()
+            .map(|e| e.to_string());
+        serde_json::json!({
+            "tool": self.tool,
+            "bin": bin,
+            "bin_env": self.bin_env,
+            "ok": err.is_none(),
+            "error": err,
+            "structured": self.structured,
+            "protocol": self.protocol,
+            "description": self.description,
+        })
+    }
+}
+
+fn builtin_entries() -> Vec<RunnerEntry> {
+    vec![
+        RunnerEntry {
+            structured: true,
+            ..RunnerEntry::new("codex", "OpenAI Codex CLI")
+        },
+        RunnerEntry {
+            structured: true,
+            ..RunnerEntry::new("opencode", "opencode")
+        },
+        RunnerEntry {
+            // Permission prompts are a numbered menu ("1. Yes" / "... No"); Esc declines.
+            prompt_regex: Some(
+                r"(?i)(do\s+you\s+want\s+to\s+(proceed|make\s+this\s+edit|create|run)\b.*\?)|(\(\s*y\s*/\s*n\s*\))"
+                    .to_string(),
+            ),
+            approve_text: "1".to_string(),
+            deny_text: "\x1b".to_string(),
+            ..RunnerEntry::new("claude", "Claude Code")
+        },
+        RunnerEntry {
+            prompt_regex: Some(
+                r"(?i)(\(y\)es/\(n\)o)|(\[\s*y\s*/\s*n\s*\])|(\(\s*y\s*/\s*n\s*\))".to_string(),
+            ),
+            ..RunnerEntry::new("aider", "Aider")
+        },
+        RunnerEntry {
+            prompt_regex: Some(
+                r"(?i)(allow\s+execution\b.*\?)|(apply\s+this\s+change\?)|(\(\s*y\s*/\s*n\s*\))"
+                    .to_string(),
+            ),
+            approve_text: "1".to_string(),
+            deny_text: "\x1b".to_string(),
+            ..RunnerEntry::new("gemini", "Gemini CLI")
+        },
+    ]
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RegistryFile {
+    Wrapped { runners: Vec<serde_json::Value> },
+    List(Vec<serde_json::Value>),
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    if let Ok(v) = std::env::var("RELAY_RUNNERS_CONFIG") {
+        let v = v.trim().to_string();
+        if !v.is_empty() {
+            return Some(PathBuf::from(v));
+        }
+    }
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".relay").join("runners.json"))
+}
+
+/// Merges a config entry onto an existing one: only fields present in the JSON override.
+fn merge_entry(base: Option<&RunnerEntry>, raw: &serde_json::Value) -> anyhow::Result<RunnerEntry> {
+    let mut merged = match base {
+        Some(base) => serde_json::to_value(base)?,
+        None => serde_json::json!({}),
+    };
+    if let (Some(dst), Some(src)) = (merged.as_object_mut(), raw.as_object()) {
+        for (k, v) in src {
+            dst.insert(k.clone(), v.clone());
+        }
+    } else {
+        anyhow::bail!("runner entry must be an object");
+    }
+    let entry = serde_json::from_value::<RunnerEntry>(merged)?.normalized();
+    entry.validate()?;
+    Ok(entry)
+}
+
+/// Built-ins plus the entries from `path` (when it exists). Invalid entries are skipped with a
+/// warning so one typo does not take down every runner.
+pub fn load_from(path: Option<&Path>) -> Vec<RunnerEntry> {
+    let mut entries = builtin_entries();
+    let Some(path) = path else {
+        return entries;
+    };
+    let Ok(raw) = std::fs::read_to_string(path) else {
+        return entries;
+    };
+    let file = match serde_json::from_str::<RegistryFile>(&raw) {
+        Ok(f) => f,
+        Err(e) => {
+            tracing::warn!(path=%path.display(), error=%e, "invalid runners config");
+            return entries;
+        }
+    };
+    let items = match file {
+        RegistryFile::Wrapped { runners } => runners,
+        RegistryFile::List(items) => items,
+    };
+    for raw in items {
+        let tool = raw
+            .get("tool")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let pos = entries.iter().position(|e| e.tool == tool);
+        match merge_entry(pos.map(|i| &entries[i]), &raw) {
+            Ok(entry) => match pos {
+                Some(i) => entries[i] = entry,
+                None => entries.push(entry),
+            },
+            Err(e) => {
+                tracing::warn!(path=%path.display(), %tool, error=%e, "skipping runner entry");
+            }
+        }
+    }
+    entries
+}
+
+/// Identifies one version of the config file: its path plus mtime/size (`None` when missing).
+type ConfigStamp = (Option<PathBuf>, Option<(std::time::SystemTime, u64)>);
+
+fn config_stamp(path: Option<&Path>) -> ConfigStamp {
+    let meta = path
+        .and_then(|p| std::fs::metadata(p).ok())
+        .and_then(|m| Some((m.modified().ok()?, m.len())));
+    (path.map(Path::to_path_buf), meta)
+}
+
+/// The current registry. The config file is parsed again only when its path, mtime or size
+/// changes, so edits still apply to the next run without a read per lookup.
+pub fn registry() -> Vec<RunnerEntry> {
+    static CACHE: Mutex<Option<(ConfigStamp, Vec<RunnerEntry>)>> = Mutex::new(None);
+    let path = default_config_path();
+    let stamp = config_stamp(path.as_deref());
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((cached, entries)) = cache.as_ref()
+        && *cached == stamp
+    {
+        return entries.clone();
+    }
+    let entries = load_from(path.as_deref());
+    *cache = Some((stamp, entries.clone()));
+    entries
+}
+
+pub fn lookup(tool: &str) -> Option<RunnerEntry> {
+    registry().into_iter().find(|e| e.tool == tool)
+}
+
+/// PTY runner driven entirely by a [`RunnerEntry`].
+pub struct ConfiguredRunner {
+    pub entry: RunnerEntry,
+}
+
+impl Runner for ConfiguredRunner {
+    fn build(&self, cmd: &str, cwd: &str) -> anyhow::Result<RunnerSpec> {
+        let entry = &self.entry;
+        let bin = entry.resolve_bin();
+        validate_bin_exists(&bin, &entry.bin_hint())?;
+
+        let mut final_cmd = cmd.trim().to_string();
+        if final_cmd.is_empty() {
+            final_cmd = bin.clone();
+        } else {
+            final_cmd = swap_leading_token(&final_cmd, &entry.tool, &bin);
+            if entry.default_bin != entry.tool {
+                final_cmd = swap_leading_token(&final_cmd, &entry.default_bin, &bin);
+            }
+        }
+
+        let mut command = if looks_like_shell(&final_cmd) {
+            command_from_shell(&final_cmd, cwd)
+        } else {
+            command_from_cmdline(&final_cmd, cwd)
+        };
+        for (k, v) in &entry.env {
+            command.env(k, v);
+        }
+
+        Ok(RunnerSpec {
+            command,
+            prompt_regex: entry.prompt_regex(),
+            approve_text: entry.approve_text.clone(),
+            deny_text: entry.deny_text.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn config_stamp_tracks_edits() {
+        let path =
+            std::env::temp_dir().join(format!("relay-runners-{}.json", uuid::Uuid::new_v4()));
+        let missing = config_stamp(Some(&path));
+        assert!(missing.1.is_none());
+
+        std::fs::write(&path, "[]").unwrap();
+        let first = config_stamp(Some(&path));
+        assert_ne!(first, missing);
+        assert_eq!(config_stamp(Some(&path)), first);
+
+        std::fs::write(&path, r#"[{ "tool": "aider" }]"#).unwrap();
+        assert_ne!(config_stamp(Some(&path)), first);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn config_entry_registers_mock_agent() {
+        let dir = std::env::temp_dir().join(format!("relay-runners-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A fake agent: asks for approval, then reports what it got.
+        let agent = dir.join("mock-agent");
+        std::fs::write(
+            &agent,
+            "#!/bin/sh\nprintf 'Run migrations? <approve|deny>\\n'\nread answer\necho \"mock-agent got=$answer greeting=$MOCK_GREETING\"\n",
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&agent, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let config = dir.join("runners.json");
+        std::fs::write(
+            &config,
+            serde_json::json!({
+                "runners": [
+                    {
+                        "tool": "mockagent",
+                        "default_bin": agent.to_str().unwrap(),
+                        "prompt_regex": r"<approve\|deny>",
+                        "approve_text": "approve\n",
+                        "deny_text": "deny\n",
+                        "env": { "MOCK_GREETING": "hi" },
+                    },
+                    { "tool": "aider", "approve_text": "yes\n" },
+                    { "tool": "broken", "prompt_regex": "(" },
+                ]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let entries = load_from(Some(&config));
+        let mock = entries.iter().find(|e| e.tool == "mockagent").unwrap();
+        assert_eq!(mock.bin_env, "RELAY_MOCKAGENT_BIN");
+        assert!(!mock.structured);
+        let aider = entries.iter().find(|e| e.tool == "aider").unwrap();
+        assert_eq!(aider.approve_text, "yes\n");
+        assert_eq!(aider.default_bin, "aider");
+        assert!(
+            aider.prompt_regex.is_some(),
+            "override keeps built-in fields"
+        );
+        assert!(entries.iter().all(|e| e.tool != "broken"));
+        assert!(entries.iter().any(|e| e.tool == "codex" && e.structured));
+
+        let spec = ConfiguredRunner {
+            entry: mock.clone(),
+        }
+        .build("mockagent", dir.to_str().unwrap())
+        .unwrap();
+        assert!(spec.prompt_regex.is_match("Run migrations? <approve|deny>"));
+        assert_eq!(spec.approve_text, "approve\n");
+
+        let argv = spec.command.get_argv();
+        let mut child = std::process::Command::new(&argv[0])
+            .args(&argv[1..])
+            .envs(spec.command.iter_extra_env_as_str())
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(spec.approve_text.as_bytes())
+            .unwrap();
+        let out = child.wait_with_output().unwrap();
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(spec.prompt_regex.is_match(&stdout));
+        assert!(stdout.contains("mock-agent got=approve greeting=hi"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,336 @@
+use serde_json::Value as JsonValue;
+use serde_json::json;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Opt-in namespace sandbox (via bubblewrap) applied to runs and `rpc.bash`.
+///
+/// The run cwd is bound read-write, the rest of `/` is bound read-only, `$HOME` is replaced by an
+/// empty tmpfs (unless `hide_home` is false) and the network namespace is unshared if `network` is
+/// false. Network stays on by default: agents need it to reach their model API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxProfile {
+    pub network: bool,
+    pub hide_home: bool,
+    /// Extra paths bound read-write (e.g. agent session/state dirs under a hidden `$HOME`).
+    pub extra_rw: Vec<PathBuf>,
+    /// Extra paths bound read-only (e.g. agent config dirs under a hidden `$HOME`).
+    pub extra_ro: Vec<PathBuf>,
+}
+
+impl Default for SandboxProfile {
+    fn default() -> Self {
+        Self {
+            network: true,
+            hide_home: true,
+            extra_rw: Vec::new(),
+            extra_ro: Vec::new(),
+        }
+    }
+}
+
+fn env_flag(name: &str) -> Option<bool> {
+    let v = std::env::var(name).ok()?.trim().to_ascii_lowercase();
+    match v.as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Host-wide default, used when `rpc.run.start` does not carry a `sandbox` field.
+///
+/// `RELAY_SANDBOX=1` enables it, `RELAY_SANDBOX_NETWORK=0` cuts network access and
+/// `RELAY_SANDBOX_HIDE_HOME=0` leaves `$HOME` visible (read-only).
+pub fn default_profile() -> Option<SandboxProfile> {
+    if !env_flag("RELAY_SANDBOX").unwrap_or(false) {
+        return None;
+    }
+    Some(SandboxProfile {
+        network: env_flag("RELAY_SANDBOX_NETWORK").unwrap_or(true),
+        hide_home: env_flag("RELAY_SANDBOX_HIDE_HOME").unwrap_or(true),
+        ..Default::default()
+    })
+}
+
+/// Parses the `sandbox` field of a run start request.
+///
+/// Accepts `true`/`false` or an object `{ "network": bool, "hide_home": bool }`; a missing/null
+/// field falls back to `host_default` (see [`default_profile`]). A request may tighten the host's
+/// sandbox but not turn it off or loosen it.
+pub fn profile_from_request(
+    v: Option<&JsonValue>,
+    host_default: Option<SandboxProfile>,
+) -> anyhow::Result<Option<SandboxProfile>> {
+    let enforced = host_default.is_some();
+    let base = host_default.clone().unwrap_or_default();
+    match v {
+        None | Some(JsonValue::Null) => Ok(host_default),
+        Some(JsonValue::Bool(false)) if enforced => {
+            anyhow::bail!(
+                "this host enforces a sandbox (RELAY_SANDBOX=1); sandbox=false is not allowed"
+            )
+        }
+        Some(JsonValue::Bool(false)) => Ok(None),
+        Some(JsonValue::Bool(true)) => Ok(Some(base)),
+        Some(JsonValue::Object(obj)) => {
+            let flag = |key: &str, default: bool| -> anyhow::Result<bool> {
+                match obj.get(key) {
+                    None | Some(JsonValue::Null) => Ok(default),
+                    Some(JsonValue::Bool(b)) => Ok(*b),
+                    Some(_) => anyhow::bail!("sandbox.{key} must be a boolean"),
+                }
+            };
+            let network = flag("network", base.network)?;
+            let hide_home = flag("hide_home", base.hide_home)?;
+            anyhow::ensure!(
+                !enforced || base.network || !network,
+                "this host's sandbox has no network (RELAY_SANDBOX_NETWORK=0); sandbox.network=true is not allowed"
+            );
+            anyhow::ensure!(
+                !enforced || !base.hide_home || hide_home,
+                "this host's sandbox hides $HOME; sandbox.hide_home=false is not allowed"
+            );
+            Ok(Some(SandboxProfile {
+                network,
+                hide_home,
+                ..base
+            }))
+        }
+        Some(_) => anyhow::bail!("sandbox must be a boolean or an object"),
+    }
+}
+
+pub fn bwrap_bin() -> String {
+    std::env::var("RELAY_BWRAP_BIN")
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "bwrap".to_string())
+}
+
+pub fn bwrap_available() -> bool {
+    let bin = bwrap_bin();
+    if bin.contains('/') {
+        Path::new(&bin).is_file()
+    } else {
+        crate::runners::find_in_path(&bin)
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var("HOME")
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .map(PathBuf::from)
+}
+
+impl SandboxProfile {
+    /// Adds the per-tool state dirs an agent needs even when `$HOME` is hidden: config is exposed
+    /// read-only, session data/cache/state read-write.
+    pub fn with_tool_dirs(mut self, tool: &str) -> Self {
+        let Some(home) = home_dir() else {
+            return self;
+        };
+        self.extra_ro.push(home.join(".config").join(tool));
+        for sub in [".local/share", ".local/state", ".cache"] {
+            self.extra_rw.push(home.join(sub).join(tool));
+        }
+        self
+    }
+
+    /// Binds `path` read-only inside the sandbox (e.g. a temp config dir that would otherwise be
+    /// shadowed by the private `/tmp`).
+    pub fn with_ro_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.extra_ro.push(path.into());
+        self
+    }
+
+    pub fn with_rw_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.extra_rw.push(path.into());
+        self
+    }
+
+    /// bubblewrap arguments (without the binary and the trailing `--`).
+    pub fn bwrap_args(&self, cwd: &str, home: Option<&Path>, interactive: bool) -> Vec<OsString> {
+        let mut args: Vec<OsString> = ["--unshare-user", "--unshare-ipc", "--unshare-uts"]
+            .into_iter()
+            .map(OsString::from)
+            .collect();
+        if !self.network {
+            args.push("--unshare-net".into());
+        }
+        args.push("--die-with-parent".into());
+        if !interactive {
+            // Prevents TIOCSTI injection into the controlling terminal.
+            args.push("--new-session".into());
+        }
+        for a in [
+            "--ro-bind",
+            "/",
+            "/",
+            "--dev",
+            "/dev",
+            "--proc",
+            "/proc",
+            "--tmpfs",
+            "/tmp",
+        ] {
+            args.push(a.into());
+        }
+        if self.hide_home
+            && let Some(home) = home
+        {
+            args.push("--tmpfs".into());
+            args.push(home.into());
+        }
+        for (flag, paths) in [
+            ("--ro-bind-try", &self.extra_ro),
+            ("--bind-try", &self.extra_rw),
+        ] {
+            for p in paths {
+                args.push(flag.into());
+                args.push(p.into());
+                args.push(p.into());
+            }
+        }
+        // The project dir goes last so it wins over the tmpfs mounts above.
+        for a in ["--bind", cwd, cwd, "--chdir", cwd] {
+            args.push(a.into());
+        }
+        args
+    }
+
+    /// Wraps `argv` so it runs inside the sandbox.
+    pub fn wrap_argv(
+        &self,
+        cwd: &str,
+        argv: Vec<OsString>,
+        interactive: bool,
+    ) -> anyhow::Result<Vec<OsString>> {
+        anyhow::ensure!(
+            bwrap_available(),
+            "sandbox requested but bubblewrap was not found (install bwrap or set RELAY_BWRAP_BIN)"
+        );
+        let mut out = vec![OsString::from(bwrap_bin())];
+        out.extend(self.bwrap_args(cwd, home_dir().as_deref(), interactive));
+        out.push(OsString::from("--"));
+        out.extend(argv);
+        Ok(out)
+    }
+
+    /// A non-interactive `std::process::Command` for `program` inside the sandbox; callers append
+    /// the program's own args.
+    pub fn command(&self, cwd: &str, program: &str) -> anyhow::Result<std::process::Command> {
+        let argv = self.wrap_argv(cwd, vec![OsString::from(program)], false)?;
+        let mut cmd = std::process::Command::new(&argv[0]);
+        cmd.args(&argv[1..]);
+        cmd.current_dir(cwd);
+        Ok(cmd)
+    }
+
+    /// Profile description reported in `run.started`.
+    pub fn describe(&self, cwd: &str) -> JsonValue {
+        let paths = |v: &[PathBuf]| {
+            v.iter()
+                .map(|p| p.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+        let mut rw = vec![cwd.to_string()];
+        rw.extend(paths(&self.extra_rw));
+        json!({
+            "kind": "bwrap",
+            "network": self.network,
+            "home": if self.hide_home { "hidden" } else { "read_only" },
+            "filesystem": "read_only",
+            "rw_paths": rw,
+            "ro_paths": paths(&self.extra_ro),
+        })
+    }
+}
+
+pub fn describe_opt(profile: Option<&SandboxProfile>, cwd: &str) -> JsonValue {
+    profile.map(|p| p.describe(cwd)).unwrap_or(JsonValue::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args_str(p: &SandboxProfile, home: Option<&Path>) -> Vec<String> {
+        p.bwrap_args("/work/proj", home, false)
+            .into_iter()
+            .map(|s| s.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn bwrap_args_bind_project_rw_and_hide_home() {
+        assert!(!args_str(&SandboxProfile::default(), None).contains(&"--unshare-net".to_string()));
+        let offline = SandboxProfile {
+            network: false,
+            ..Default::default()
+        };
+        let args = args_str(&offline, Some(Path::new("/home/u")));
+        assert!(args.contains(&"--unshare-net".to_string()));
+        let joined = args.join(" ");
+        assert!(joined.contains("--ro-bind / /"));
+        assert!(joined.contains("--tmpfs /home/u"));
+        assert!(joined.ends_with("--bind /work/proj /work/proj --chdir /work/proj"));
+
+        let open = SandboxProfile {
+            network: true,
+            hide_home: false,
+            ..Default::default()
+        };
+        let args = args_str(&open, Some(Path::new("/home/u")));
+        assert!(!args.contains(&"--unshare-net".to_string()));
+        assert!(!args.join(" ").contains("--tmpfs /home/u"));
+    }
+
+    #[test]
+    fn profile_from_request_accepts_bool_and_object() {
+        assert_eq!(
+            profile_from_request(Some(&json!(false)), None).unwrap(),
+            None
+        );
+        assert_eq!(profile_from_request(None, None).unwrap(), None);
+        let p = profile_from_request(Some(&json!(true)), None)
+            .unwrap()
+            .unwrap();
+        assert!(p.network);
+        let p = profile_from_request(Some(&json!({ "network": false })), None)
+            .unwrap()
+            .unwrap();
+        assert!(!p.network);
+        assert!(p.hide_home);
+        assert!(profile_from_request(Some(&json!("yes")), None).is_err());
+        assert!(profile_from_request(Some(&json!({ "network": "no" })), None).is_err());
+    }
+
+    #[test]
+    fn profile_from_request_cannot_loosen_the_host_sandbox() {
+        let host = SandboxProfile {
+            network: false,
+            ..Default::default()
+        };
+        let p = profile_from_request(None, Some(host.clone())).unwrap();
+        assert_eq!(p.as_ref(), Some(&host));
+        let err = profile_from_request(Some(&json!(false)), Some(host.clone())).unwrap_err();
+        assert!(err.to_string().contains("enforces a sandbox"), "{err}");
+        assert!(
+            profile_from_request(Some(&json!({ "network": true })), Some(host.clone())).is_err()
+        );
+        assert!(
+            profile_from_request(Some(&json!({ "hide_home": false })), Some(host.clone())).is_err()
+        );
+
+        // Tightening is fine.
+        let open = SandboxProfile::default();
+        let p = profile_from_request(Some(&json!({ "network": false })), Some(open))
+            .unwrap()
+            .unwrap();
+        assert!(!p.network);
+    }
+}
@@ -0,0 +1,416 @@
+//! In-process code search for `rpc.fs.search` and local `GET /runs/:run_id/fs/search`.
+//!
+//! Walks the run cwd with the `ignore` crate (honouring `.gitignore`, `.ignore` and global git
+//! excludes, also outside git repositories) and searches each file with `grep-searcher`, so hosts
+//! do not need ripgrep. Files are visited in path order; a page ends after `limit` matching lines
+//! and `next_cursor` (the position of the last returned match) resumes the same query after it.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::http::StatusCode;
+use base64::Engine;
+use grep_matcher::Matcher;
+use grep_regex::{RegexMatcher, RegexMatcherBuilder};
+use grep_searcher::{BinaryDetection, Searcher, SearcherBuilder, Sink, SinkContext, SinkMatch};
+use serde::{Deserialize, Deserializer, Serialize};
+
+const DEFAULT_LIMIT: usize = 200;
+const MAX_LIMIT: usize = 2_000;
+const MAX_CONTEXT: usize = 10;
+const MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+/// Longer lines are cut; a minified bundle should not blow up the response.
+const MAX_LINE_CHARS: usize = 1_000;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    pub q: String,
+    /// Match `q` as a fixed string instead of a regex.
+    pub literal: bool,
+    pub case_insensitive: bool,
+    /// Only search files matching one of these globs (gitignore syntax, relative to the run cwd).
+    #[serde(deserialize_with = "globs")]
+    pub include: Vec<String>,
+    #[serde(deserialize_with = "globs")]
+    pub exclude: Vec<String>,
+    /// Sub-directory or file to search, relative to the run cwd.
+    pub path: Option<String>,
+    /// Also search hidden files and directories (`.git` is always skipped).
+    pub hidden: bool,
+    /// Lines of context on both sides; `before` / `after` override it per side.
+    pub context: Option<usize>,
+    pub before: Option<usize>,
+    pub after: Option<usize>,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+/// Globs as an array or a comma-separated string (query strings cannot carry arrays).
+fn globs<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Globs {
+        One(String),
+        Many(Vec<String>),
+    }
+    let list = match Option::<Globs>::deserialize(d)? {
+        None => Vec::new(),
+        Some(Globs::One(s)) => s.split(',').map(str::to_string).collect(),
+        Some(Globs::Many(v)) => v,
+    };
+    Ok(list
+        .into_iter()
+        .map(|g| g.trim().to_string())
+        .filter(|g| !g.is_empty())
+        .collect())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContextLine {
+    pub line: u64,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchMatch {
+    pub path: String,
+    pub line: u64,
+    /// 1-based byte column of the first match on the line.
+    pub column: u64,
+    pub text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub before: Vec<ContextLine>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<ContextLine>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub matches: Vec<SearchMatch>,
+    /// More matches may follow; pass `next_cursor` to get them.
+    pub truncated: bool,
+    pub next_cursor: Option<String>,
+    pub files_searched: u64,
+    pub cancelled: bool,
+}
+
+fn encode_cursor(path: &str, line: u64) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(format!("{line}\n{path}"))
+}
+
+fn decode_cursor(cursor: &str) -> Option<(PathBuf, u64)> {
+    let raw = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor.trim())
+        .ok()?;
+    let (line, path) = std::str::from_utf8(&raw).ok()?.split_once('\n')?;
+    Some((PathBuf::from(path), line.parse().ok()?))
+}
+
+fn line_text(bytes: &[u8]) -> String {
+    let s = String::from_utf8_lossy(bytes);
+    let s = s.trim_end_matches(['\n', '\r']);
+    match s.char_indices().nth(MAX_LINE_CHARS) {
+        Some((i, _)) => s[..i].to_string(),
+        None => s.to_string(),
+    }
+}
+
+pub fn search(
+    run_cwd: &str,
+    opts: &SearchOptions,
+    cancel: &AtomicBool,
+) -> Result<SearchResult, (StatusCode, String)> {
+    if opts.q.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "missing q".into()));
+    }
+    let base = std::fs::canonicalize(run_cwd)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("bad run cwd: {e}")))?;
+    let root = match opts
+        .path
+        .as_deref()
+        .filter(|p| !p.trim().is_empty() && *p != ".")
+    {
+        Some(p) => crate::fs_git::safe_join_run_path(run_cwd, p)?,
+        None => base.clone(),
+    };
+    let cursor = match opts.cursor.as_deref().filter(|c| !c.is_empty()) {
+        Some(c) => {
+            Some(decode_cursor(c).ok_or((StatusCode::BAD_REQUEST, "invalid cursor".into()))?)
+        }
+        None => None,
+    };
+
+    let matcher = RegexMatcherBuilder::new()
+        .case_insensitive(opts.case_insensitive)
+        .fixed_strings(opts.literal)
+        .line_terminator(Some(b'\n'))
+        .build(&opts.q)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid pattern: {e}")))?;
+
+    let mut overrides = ignore::overrides::OverrideBuilder::new(&base);
+    for glob in &opts.include {
+        overrides.add(glob).map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("invalid include glob: {e}"),
+            )
+        })?;
+    }
+    for glob in &opts.exclude {
+        overrides.add(&format!("!{glob}")).map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("invalid exclude glob: {e}"),
+            )
+        })?;
+    }
+    let overrides = overrides
+        .build()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let context = opts.context.unwrap_or(0);
+    let before = opts.before.unwrap_or(context).min(MAX_CONTEXT);
+    let after = opts.after.unwrap_or(context).min(MAX_CONTEXT);
+    let limit = opts.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let mut searcher = SearcherBuilder::new()
+        .line_number(true)
+        .before_context(before)
+        .after_context(after)
+        .binary_detection(BinaryDetection::quit(0))
+        .build();
+
+    let walker = ignore::WalkBuilder::new(&root)
+        .hidden(!opts.hidden)
+        .require_git(false)
+        .max_filesize(Some(MAX_FILE_BYTES))
+        .overrides(overrides)
+        .sort_by_file_path(|a, b| a.cmp(b))
+        .filter_entry(|e| e.file_name() != ".git")
+        .build();
+
+    let mut out = SearchResult {
+        matches: Vec::new(),
+        truncated: false,
+        next_cursor: None,
+        files_searched: 0,
+        cancelled: false,
+    };
+    for entry in walker {
+        if cancel.load(Ordering::Relaxed) {
+            out.cancelled = true;
+            break;
+        }
+        let Ok(entry) = entry else { continue };
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let Ok(rel) = entry.path().strip_prefix(&base) else {
+            continue;
+        };
+        // Files are walked in `Path` order, so everything up to the cursor file was served already.
+        let skip_through = match &cursor {
+            Some((path, _)) if rel < path.as_path() => continue,
+            Some((path, line)) if rel == path.as_path() => *line,
+            _ => 0,
+        };
+        out.files_searched += 1;
+        let rel = rel.to_string_lossy().to_string();
+        let mut sink = FileSink {
+            matcher: &matcher,
+            rel: &rel,
+            after,
+            before,
+            skip_through,
+            limit,
+            out: &mut out,
+            recent: VecDeque::new(),
+            open: Vec::new(),
+            cancel,
+        };
+        // Unreadable files are skipped, like rg does after printing a warning.
+        let _ = searcher.search_path(&matcher, entry.path(), &mut sink);
+        if out.truncated || out.cancelled {
+            break;
+        }
+    }
+    if out.cancelled {
+        out.truncated = true;
+    }
+    if out.truncated {
+        out.next_cursor = out.matches.last().map(|m| encode_cursor(&m.path, m.line));
+    }
+    Ok(out)
+}
+
+struct FileSink<'a> {
+    matcher: &'a RegexMatcher,
+    rel: &'a str,
+    before: usize,
+    after: usize,
+    /// Matches on lines up to this one were returned by an earlier page.
+    skip_through: u64,
+    limit: usize,
+    out: &'a mut SearchResult,
+    /// The last `before` lines seen, for the before-context of the next match.
+    recent: VecDeque<ContextLine>,
+    /// Indices of matches still collecting after-context.
+    open: Vec<usize>,
+    cancel: &'a AtomicBool,
+}
+
+impl FileSink<'_> {
+    /// Handles one reported line; returns whether to keep searching this file.
+    fn line(&mut self, line: u64, bytes: &[u8], is_match: bool) -> bool {
+        if self.cancel.load(Ordering::Relaxed) {
+            self.out.cancelled = true;
+            return false;
+        }
+        let text = line_text(bytes);
+        let (after, matches) = (self.after, &mut self.out.matches);
+        self.open.retain(|&i| {
+            let m = &mut matches[i];
+            m.after.push(ContextLine {
+                line,
+                text: text.clone(),
+            });
+            m.after.len() < after
+        });
+
+        if is_match && line > self.skip_through {
+            if self.out.matches.len() >= self.limit {
+                // One match past the page proves there is more; finish pending context first.
+                self.out.truncated = true;
+            } else {
+                let mut expect = line;
+                let before = self
+                    .recent
+                    .iter()
+                    .rev()
+                    .take_while(|c| {
+                        expect -= 1;
+                        c.line == expect
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let column = self
+                    .matcher
+                    .find(bytes)
+                    .ok()
+                    .flatten()
+                    .map(|m| m.start() as u64 + 1)
+                    .unwrap_or(1);
+                self.out.matches.push(SearchMatch {
+                    path: self.rel.to_string(),
+                    line,
+                    column,
+                    text: text.clone(),
+                    before: before.into_iter().rev().collect(),
+                    after: Vec::new(),
+                });
+                if self.after > 0 {
+                    self.open.push(self.out.matches.len() - 1);
+                }
+            }
+        }
+
+        if self.before > 0 {
+            if self.recent.len() == self.before {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(ContextLine { line, text });
+        }
+        !(self.out.truncated && self.open.is_empty())
+    }
+}
+
+impl Sink for FileSink<'_> {
+    type Error = std::io::Error;
+
+    fn matched(&mut self, _: &Searcher, mat: &SinkMatch<'_>) -> Result<bool, Self::Error> {
+        let line = mat.line_number().unwrap_or(0);
+        Ok(self.line(line, mat.bytes(), true))
+    }
+
+    fn context(&mut self, _: &Searcher, ctx: &SinkContext<'_>) -> Result<bool, Self::Error> {
+        let line = ctx.line_number().unwrap_or(0);
+        Ok(self.line(line, ctx.bytes(), false))
+    }
+
+    fn context_break(&mut self, _: &Searcher) -> Result<bool, Self::Error> {
+        self.recent.clear();
+        self.open.clear();
+        Ok(!self.out.truncated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_respects_gitignore_globs_context_and_cursor() {
+        let dir = std::env::temp_dir().join(format!("relay-search-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        std::fs::write(dir.join("target/out.rs"), "needle\n").unwrap();
+        std::fs::write(
+            dir.join("src/a.rs"),
+            "one\nneedle 1\ntwo\nthree\nneedle 2\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("src/b.txt"), "Needle.b\n").unwrap();
+        std::fs::write(dir.join("bin.dat"), b"needle\0\x01").unwrap();
+        let cwd = dir.to_str().unwrap();
+        let never = AtomicBool::new(false);
+        let run = |v: serde_json::Value| {
+            let opts: SearchOptions = serde_json::from_value(v).unwrap();
+            search(cwd, &opts, &never).unwrap()
+        };
+
+        let r = run(serde_json::json!({ "q": "needle" }));
+        let found: Vec<_> = r
+            .matches
+            .iter()
+            .map(|m| (m.path.as_str(), m.line))
+            .collect();
+        assert_eq!(found, [("src/a.rs", 2), ("src/a.rs", 5)]);
+        assert!(!r.truncated);
+
+        let r =
+            run(serde_json::json!({ "q": "needle.", "literal": true, "case_insensitive": true }));
+        assert_eq!(r.matches.len(), 1);
+        assert_eq!(r.matches[0].path, "src/b.txt");
+        let r = run(serde_json::json!({ "q": "e", "include": "*.txt" }));
+        assert!(r.matches.iter().all(|m| m.path == "src/b.txt"));
+        let r = run(serde_json::json!({ "q": "needle", "exclude": ["a.rs"] }));
+        assert!(r.matches.is_empty());
+
+        let r = run(serde_json::json!({ "q": "needle", "context": 1 }));
+        let m = &r.matches[1];
+        assert_eq!((m.before[0].line, m.before[0].text.as_str()), (4, "three"));
+        assert_eq!(r.matches[0].after[0].text, "two");
+
+        let page = run(serde_json::json!({ "q": "needle", "limit": 1, "after": 3 }));
+        assert!(page.truncated);
+        assert_eq!(page.matches[0].after.len(), 3);
+        let cursor = page.next_cursor.unwrap();
+        let rest = run(serde_json::json!({ "q": "needle", "limit": 1, "cursor": cursor }));
+        assert_eq!((rest.matches[0].line, rest.truncated), (5, false));
+
+        let cancelled = AtomicBool::new(true);
+        let opts: SearchOptions =
+            serde_json::from_value(serde_json::json!({ "q": "needle" })).unwrap();
+        assert!(search(cwd, &opts, &cancelled).unwrap().cancelled);
+        assert!(run_err(cwd, serde_json::json!({ "q": "x", "path": "../" })));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn run_err(cwd: &str, v: serde_json::Value) -> bool {
+        let opts: SearchOptions = serde_json::from_value(v).unwrap();
+        search(cwd, &opts, &AtomicBool::new(false)).is_err()
+    }
+}
@@ -29,6 +29,18 @@ CREATE TABLE IF NOT EXISTS spool_acks (
 );
 "#,
         )?;
+        // Acked events are kept for local replay; `acked` keeps them out of `pending_events`.
+        let has_acked = conn
+            .prepare("SELECT 1 FROM pragma_table_info('spool_events') WHERE name = 'acked'")?
+            .exists([])?;
+        if !has_acked {
+            conn.execute_batch(
+                "ALTER TABLE spool_events ADD COLUMN acked INTEGER NOT NULL DEFAULT 0;",
+            )?;
+        }
+        conn.execute_batch(
+            "CREATE INDEX IF NOT EXISTS spool_events_pending ON spool_events(run_id, seq) WHERE acked = 0;",
+        )?;
         Ok(())
     }
 
@@ -58,8 +70,10 @@ END
 "#,
             params![run_id, last_seq],
         )?;
+        // Acked events stay (until `prune_acked_older_than_rfc3339`) so local clients can replay
+        // them, see `events_after`.
         conn.execute(
-            "DELETE FROM spool_events WHERE run_id=?1 AND seq <= ?2",
+            "UPDATE spool_events SET acked = 1 WHERE run_id = ?1 AND seq <= ?2 AND acked = 0",
             params![run_id, last_seq],
         )?;
         Ok(())
@@ -72,7 +86,7 @@ END
 SELECT e.json
 FROM spool_events e
 LEFT JOIN spool_acks a ON a.run_id = e.run_id
-WHERE e.seq > COALESCE(a.last_seq, 0)
+WHERE e.acked = 0 AND e.seq > COALESCE(a.last_seq, 0)
 ORDER BY e.run_id ASC, e.seq ASC
 LIMIT ?1
 "#,
@@ -88,9 +102,87 @@ LIMIT ?1
         Ok(out)
     }
 
+    /// Spooled events of `run_id` with `seq > after_seq`, oldest first (acked ones included).
+    pub fn events_after(
+        &self,
+        run_id: &str,
+        after_seq: i64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<WsEnvelope>> {
+        let conn = Connection::open(&self.path)?;
+        let mut stmt = conn.prepare(
+            "SELECT json FROM spool_events WHERE run_id=?1 AND seq > ?2 ORDER BY seq ASC LIMIT ?3",
+        )?;
+        let mut rows = stmt.query(params![run_id, after_seq, limit as i64])?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            let json: String = row.get(0)?;
+            out.push(serde_json::from_str(&json)?);
+        }
+        Ok(out)
+    }
+
     pub fn prune_older_than_rfc3339(&self, cutoff_ts: &str) -> anyhow::Result<()> {
         let conn = Connection::open(&self.path)?;
         conn.execute("DELETE FROM spool_events WHERE ts < ?1", params![cutoff_ts])?;
         Ok(())
     }
+
+    /// Drops acked events older than `cutoff_ts`; they only remain for local replay.
+    pub fn prune_acked_older_than_rfc3339(&self, cutoff_ts: &str) -> anyhow::Result<()> {
+        let conn = Connection::open(&self.path)?;
+        conn.execute(
+            "DELETE FROM spool_events WHERE acked = 1 AND ts < ?1",
+            params![cutoff_ts],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acked_events_stay_replayable() {
+        let path =
+            std::env::temp_dir().join(format!("relay-spool-test-{}.db", uuid::Uuid::new_v4()));
+        let spool = Spool::new(path.to_string_lossy().into_owned());
+        spool.init().unwrap();
+        for seq in 1..=3 {
+            let mut env = WsEnvelope::new("run.output", serde_json::json!({ "text": "x" }));
+            env.run_id = Some("r1".into());
+            env.seq = Some(seq);
+            spool.insert_event(&env).unwrap();
+        }
+        spool.apply_ack("r1", 2).unwrap();
+
+        let pending: Vec<_> = spool
+            .pending_events(10)
+            .unwrap()
+            .iter()
+            .map(|e| e.seq)
+            .collect();
+        assert_eq!(pending, vec![Some(3)]);
+        let replay: Vec<_> = spool
+            .events_after("r1", 1, 10)
+            .unwrap()
+            .iter()
+            .map(|e| e.seq)
+            .collect();
+        assert_eq!(replay, vec![Some(2), Some(3)]);
+
+        // Replay retention only drops acked events.
+        let later = (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+        spool.prune_acked_older_than_rfc3339(&later).unwrap();
+        let left: Vec<_> = spool
+            .events_after("r1", 0, 10)
+            .unwrap()
+            .iter()
+            .map(|e| e.seq)
+            .collect();
+        assert_eq!(left, vec![Some(3)]);
+
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -0,0 +1,517 @@
+//! Directory trees under a run cwd: `.gitignore`-aware walking and, on Linux, one recursive
+//! inotify watch shared by `file_changes` (per-run tracking) and `fs_watch` (`rpc.fs.watch`).
+//!
+//! `.git` and ignored directories are never descended into. A directory created inside a watched
+//! one is watched and rescanned right away, so files written into it before its watch existed
+//! are still reported. A kernel queue overflow surfaces as [`TreeEvent::Overflow`]: events were
+//! lost and the caller has to fall back to a full listing.
+
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Walks stop collecting files past this many (see [`Tree::truncated`]).
+pub const MAX_FILES: usize = 200_000;
+
+pub fn join_rel(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// `.gitignore` matching via `ignore::gitignore`, one matcher per file. The deepest file that
+/// has an opinion on a path wins, as in git; `.git/info/exclude` ranks below the root
+/// `.gitignore`. Rules are read from the run cwd down.
+#[derive(Clone, Default)]
+pub struct Ignore {
+    /// `(directory relative to the run cwd, source file, matcher)`, parents before children.
+    files: Vec<(String, PathBuf, Arc<Gitignore>)>,
+}
+
+impl Ignore {
+    fn add_file(&mut self, base: &str, path: &Path) {
+        let Ok(text) = std::fs::read_to_string(path) else {
+            return;
+        };
+        self.add_lines(base, path, text.lines());
+    }
+
+    fn add_lines<'a>(&mut self, base: &str, source: &Path, lines: impl Iterator<Item = &'a str>) {
+        // Root "." disables prefix stripping: paths are passed already relative to `base`.
+        let mut builder = GitignoreBuilder::new(".");
+        for line in lines {
+            let _ = builder.add_line(Some(source.to_path_buf()), line);
+        }
+        let Ok(gi) = builder.build() else {
+            return;
+        };
+        let gi = Arc::new(gi);
+        // A re-created directory re-reads its file: replace rather than stack.
+        match self.files.iter_mut().find(|(_, src, _)| src == source) {
+            Some(entry) => entry.2 = gi,
+            None => self
+                .files
+                .push((base.to_string(), source.to_path_buf(), gi)),
+        }
+    }
+
+    /// Loads `.git/info/exclude` and the `.gitignore` of every directory above `rel`, for walks
+    /// that start below the root.
+    fn add_ancestors(&mut self, root: &Path, rel: &str) {
+        self.add_file("", &root.join(".git/info/exclude"));
+        let mut dir = String::new();
+        for part in rel.split('/').filter(|p| !p.is_empty()) {
+            self.add_file(&dir, &root.join(&dir).join(".gitignore"));
+            dir = join_rel(&dir, part);
+        }
+    }
+
+    /// Whether `rel` (relative to the run cwd) is ignored. Callers never descend into ignored
+    /// directories, so parents are not re-checked here.
+    pub fn is_ignored(&self, rel: &str, is_dir: bool) -> bool {
+        if rel.split('/').any(|c| c == ".git") {
+            return true;
+        }
+        for (base, _, gi) in self.files.iter().rev() {
+            let sub = if base.is_empty() {
+                rel
+            } else {
+                match rel
+                    .strip_prefix(base.as_str())
+                    .and_then(|s| s.strip_prefix('/'))
+                {
+                    Some(s) => s,
+                    None => continue,
+                }
+            };
+            match gi.matched(sub, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+#[derive(Default)]
+pub struct Tree {
+    pub dirs: Vec<String>,
+    pub files: Vec<(String, std::fs::Metadata)>,
+    /// More than [`MAX_FILES`] files; `files` is incomplete.
+    pub truncated: bool,
+}
+
+/// One walk over a tree; see [`Walk::run`].
+struct Walk<'a> {
+    root: &'a Path,
+    ignore: &'a mut Ignore,
+    max_depth: usize,
+    /// Read each directory's `.gitignore` before its entries are filtered.
+    load_rules: bool,
+    with_files: bool,
+}
+
+impl Walk<'_> {
+    /// Walks `start` (relative to the root, `level` below the start of the watch) without
+    /// following symlinks, down to `max_depth`. `on_dir(rel, level)` runs before a directory is
+    /// listed.
+    fn run(&mut self, start: &str, level: usize, on_dir: &mut dyn FnMut(&str, usize)) -> Tree {
+        let mut tree = Tree::default();
+        if self.load_rules && start.is_empty() {
+            self.ignore
+                .add_file("", &self.root.join(".git/info/exclude"));
+        }
+        let mut stack = vec![(start.to_string(), level)];
+        while let Some((dir, level)) = stack.pop() {
+            let abs = if dir.is_empty() {
+                self.root.to_path_buf()
+            } else {
+                self.root.join(&dir)
+            };
+            if self.load_rules {
+                self.ignore.add_file(&dir, &abs.join(".gitignore"));
+            }
+            on_dir(&dir, level);
+            let Ok(entries) = std::fs::read_dir(&abs) else {
+                continue;
+            };
+            tree.dirs.push(dir.clone());
+            for entry in entries.flatten() {
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                let rel = join_rel(&dir, &name);
+                let Ok(ft) = entry.file_type() else {
+                    continue;
+                };
+                if ft.is_dir() {
+                    if level < self.max_depth && !self.ignore.is_ignored(&rel, true) {
+                        stack.push((rel, level + 1));
+                    }
+                } else if self.with_files && ft.is_file() && !self.ignore.is_ignored(&rel, false) {
+                    if tree.files.len() >= MAX_FILES {
+                        tree.truncated = true;
+                        return tree;
+                    }
+                    if let Ok(md) = entry.metadata() {
+                        tree.files.push((rel, md));
+                    }
+                }
+            }
+        }
+        tree
+    }
+}
+
+/// Lists every directory and file under `start`. With `load_rules`, `.gitignore` files found on
+/// the way are added to `ignore`.
+pub fn scan(root: &Path, start: &str, ignore: &mut Ignore, load_rules: bool) -> Tree {
+    Walk {
+        root,
+        ignore,
+        max_depth: usize::MAX,
+        load_rules,
+        with_files: true,
+    }
+    .run(start, 0, &mut |_, _| {})
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Created,
+    Modified,
+    Deleted,
+    /// One half of a rename; both halves carry the same cookie.
+    MovedFrom(u32),
+    MovedTo(u32),
+}
+
+pub enum TreeEvent {
+    /// `path` (relative to the root) changed. Ignored entries are reported with `ignored` set
+    /// but never descended into. `found` lists the files already inside a directory that just
+    /// appeared.
+    Entry {
+        path: String,
+        change: Change,
+        is_dir: bool,
+        ignored: bool,
+        found: Vec<String>,
+    },
+    /// The kernel dropped events.
+    Overflow,
+    /// The start directory itself was deleted.
+    RootGone,
+}
+
+/// Recursive inotify watch on `start` (relative to `root`) and the directories below it.
+#[cfg(target_os = "linux")]
+pub struct TreeWatch {
+    inotify: nix::sys::inotify::Inotify,
+    root: PathBuf,
+    start: String,
+    max_depth: usize,
+    max_dirs: usize,
+    with_files: bool,
+    ignore: Ignore,
+    /// Watched directory (relative to the root) and its depth below `start`.
+    dirs: std::collections::HashMap<nix::sys::inotify::WatchDescriptor, (String, usize)>,
+    /// `max_dirs` was hit or a watch could not be added: some directories are not watched.
+    truncated: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl TreeWatch {
+    /// Watches `start` and its sub-directories up to `max_depth` levels, at most `max_dirs`
+    /// directories. Each watch is added before its directory is listed, so nothing written after
+    /// this returns is missed. Returns the initial listing (files only with `with_files`).
+    pub fn new(
+        root: &Path,
+        start: &str,
+        max_depth: usize,
+        max_dirs: usize,
+        with_files: bool,
+    ) -> std::io::Result<(Self, Tree)> {
+        use nix::sys::inotify::{InitFlags, Inotify};
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let mut ignore = Ignore::default();
+        if !start.is_empty() {
+            ignore.add_ancestors(root, start);
+        }
+        let mut watch = Self {
+            inotify,
+            root: root.to_path_buf(),
+            start: start.to_string(),
+            max_depth,
+            max_dirs,
+            with_files,
+            ignore,
+            dirs: std::collections::HashMap::new(),
+            truncated: false,
+        };
+        let tree = watch.add_tree(start, 0);
+        Ok((watch, tree))
+    }
+
+    pub fn ignore(&self) -> &Ignore {
+        &self.ignore
+    }
+
+    pub fn watched_dirs(&self) -> usize {
+        self.dirs.len()
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    fn add_tree(&mut self, dir: &str, level: usize) -> Tree {
+        use nix::sys::inotify::AddWatchFlags as F;
+        let mask = F::IN_CREATE
+            | F::IN_DELETE
+            | F::IN_MODIFY
+            | F::IN_MOVED_FROM
+            | F::IN_MOVED_TO
+            | F::IN_DELETE_SELF
+            | F::IN_ONLYDIR;
+        let (max_dirs, max_depth, with_files) = (self.max_dirs, self.max_depth, self.with_files);
+        let Self {
+            ref inotify,
+            ref root,
+            ref mut ignore,
+            ref mut dirs,
+            ref mut truncated,
+            ..
+        } = *self;
+        let mut walk = Walk {
+            root,
+            ignore,
+            max_depth,
+            load_rules: true,
+            with_files,
+        };
+        walk.run(dir, level, &mut |rel: &str, level: usize| {
+            if dirs.len() >= max_dirs {
+                *truncated = true;
+                return;
+            }
+            match inotify.add_watch(&root.join(rel), mask) {
+                Ok(wd) => {
+                    dirs.insert(wd, (rel.to_string(), level));
+                }
+                Err(_) => *truncated = true,
+            }
+        })
+    }
+
+    /// Drops the watches on `dir` and below (it moved away or was deleted).
+    fn remove_tree(&mut self, dir: &str) {
+        let prefix = format!("{dir}/");
+        let gone: Vec<_> = self
+            .dirs
+            .iter()
+            .filter(|(_, (d, _))| d == dir || d.starts_with(&prefix))
+            .map(|(wd, _)| *wd)
+            .collect();
+        for wd in gone {
+            let _ = self.inotify.rm_watch(wd);
+            self.dirs.remove(&wd);
+        }
+    }
+
+    /// Events queued since the last call; empty when there are none. Never blocks.
+    pub fn read(&mut self) -> nix::Result<Vec<TreeEvent>> {
+        use nix::sys::inotify::AddWatchFlags as F;
+        let events = match self.inotify.read_events() {
+            Ok(events) => events,
+            Err(nix::errno::Errno::EAGAIN) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut out = Vec::new();
+        for ev in events {
+            if ev.mask.contains(F::IN_Q_OVERFLOW) {
+                out.push(TreeEvent::Overflow);
+                continue;
+            }
+            let Some((dir, level)) = self.dirs.get(&ev.wd).cloned() else {
+                continue;
+            };
+            if ev.mask.contains(F::IN_DELETE_SELF) {
+                if dir == self.start {
+                    out.push(TreeEvent::RootGone);
+                }
+                continue;
+            }
+            if ev.mask.contains(F::IN_IGNORED) {
+                self.dirs.remove(&ev.wd);
+                continue;
+            }
+            let Some(name) = ev.name.and_then(|n| n.into_string().ok()) else {
+                continue;
+            };
+            if name == ".git" {
+                continue;
+            }
+            let path = join_rel(&dir, &name);
+            let is_dir = ev.mask.contains(F::IN_ISDIR);
+            let ignored = self.ignore.is_ignored(&path, is_dir);
+            let change = if ev.mask.contains(F::IN_MOVED_FROM) {
+                Change::MovedFrom(ev.cookie)
+            } else if ev.mask.contains(F::IN_MOVED_TO) {
+                Change::MovedTo(ev.cookie)
+            } else if ev.mask.contains(F::IN_CREATE) {
+                Change::Created
+            } else if ev.mask.contains(F::IN_DELETE) {
+                Change::Deleted
+            } else {
+                Change::Modified
+            };
+            let mut found = Vec::new();
+            if is_dir {
+                match change {
+                    Change::Deleted | Change::MovedFrom(_) => self.remove_tree(&path),
+                    Change::Created | Change::MovedTo(_) if !ignored && level < self.max_depth => {
+                        let tree = self.add_tree(&path, level + 1);
+                        found = tree.files.into_iter().map(|(f, _)| f).collect();
+                    }
+                    _ => {}
+                }
+            }
+            out.push(TreeEvent::Entry {
+                path,
+                change,
+                is_dir,
+                ignored,
+                found,
+            });
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gitignore_rules() {
+        let mut ig = Ignore::default();
+        ig.add_lines(
+            "",
+            Path::new(".gitignore"),
+            ["# c", "*.log", "/build/", "!keep.log", "docs/**/*.tmp"].into_iter(),
+        );
+        ig.add_lines(
+            "sub",
+            Path::new("sub/.gitignore"),
+            ["local.txt"].into_iter(),
+        );
+        assert!(ig.is_ignored("a/b/x.log", false));
+        assert!(!ig.is_ignored("a/keep.log", false));
+        assert!(ig.is_ignored("build", true));
+        assert!(!ig.is_ignored("build", false));
+        assert!(!ig.is_ignored("src/build", true));
+        assert!(ig.is_ignored("docs/a/b/c.tmp", false));
+        assert!(ig.is_ignored("sub/deep/local.txt", false));
+        assert!(!ig.is_ignored("local.txt", false));
+        assert!(ig.is_ignored(".git", true));
+    }
+
+    #[test]
+    fn nested_gitignore_negates_parent_rules() {
+        let mut ig = Ignore::default();
+        ig.add_lines("", Path::new(".git/info/exclude"), ["*.tmp"].into_iter());
+        ig.add_lines(
+            "",
+            Path::new(".gitignore"),
+            ["*.log", "vendor/", "!*.tmp"].into_iter(),
+        );
+        ig.add_lines(
+            "app",
+            Path::new("app/.gitignore"),
+            ["!keep.log", "generated/"].into_iter(),
+        );
+        // The root .gitignore outranks info/exclude.
+        assert!(!ig.is_ignored("a.tmp", false));
+        assert!(ig.is_ignored("app/debug.log", false));
+        assert!(!ig.is_ignored("app/keep.log", false));
+        assert!(ig.is_ignored("keep.log", false));
+        assert!(ig.is_ignored("app/generated", true));
+        assert!(!ig.is_ignored("generated", true));
+        assert!(ig.is_ignored("vendor", true));
+        assert!(!ig.is_ignored("vendor", false));
+
+        // Re-reading the same file replaces its rules.
+        ig.add_lines(
+            "app",
+            Path::new("app/.gitignore"),
+            ["!debug.log"].into_iter(),
+        );
+        assert!(!ig.is_ignored("app/debug.log", false));
+        assert!(!ig.is_ignored("app/generated", true));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn watches_new_directories_and_reports_overflow() {
+        let dir = std::env::temp_dir().join(format!("relay-tree-watch-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("a/b/c")).unwrap();
+        std::fs::create_dir_all(dir.join("a/skip")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "skip/\n").unwrap();
+        std::fs::write(dir.join("a/b/x.txt"), "x").unwrap();
+
+        // Rules above the start directory apply; depth counts from the start.
+        let (mut w, tree) = TreeWatch::new(&dir, "a", 1, 100, true).unwrap();
+        assert_eq!(w.watched_dirs(), 2, "{:?}", tree.dirs);
+        assert_eq!(tree.files.len(), 1);
+        assert!(!w.is_truncated());
+
+        std::fs::create_dir_all(dir.join("a/new/inner")).unwrap();
+        std::fs::write(dir.join("a/new/n.txt"), "n").unwrap();
+        std::fs::create_dir(dir.join("a/new2")).unwrap();
+        let mut entries = Vec::new();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while entries.len() < 2 && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            for ev in w.read().unwrap() {
+                if let TreeEvent::Entry { path, found, .. } = ev {
+                    entries.push((path, found));
+                }
+            }
+        }
+        let new = entries.iter().find(|(p, _)| p == "a/new").unwrap();
+        // `a/new/inner` is past the depth; the file written before the watch existed is found.
+        assert_eq!(new.1, ["a/new/n.txt"]);
+        assert_eq!(w.watched_dirs(), 4);
+
+        std::fs::remove_dir_all(dir.join("a/new")).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        w.read().unwrap();
+        assert_eq!(w.watched_dirs(), 3);
+
+        let (mut small, _) = TreeWatch::new(&dir, "", usize::MAX, 2, false).unwrap();
+        assert!(small.is_truncated());
+        // Overrun the kernel queue with distinct events (identical ones are merged).
+        let queue: usize = std::fs::read_to_string("/proc/sys/fs/inotify/max_queued_events")
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(16_384);
+        for i in 0..=queue {
+            std::fs::File::create(dir.join(format!("a/{i}"))).unwrap();
+        }
+        let mut overflow = false;
+        loop {
+            let events = small.read().unwrap();
+            if events.is_empty() {
+                break;
+            }
+            overflow |= events.iter().any(|e| matches!(e, TreeEvent::Overflow));
+        }
+        assert!(overflow);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
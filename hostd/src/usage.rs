@@ -0,0 +1,111 @@
+//! Token usage extraction for `run.usage` events.
+//!
+//! Structured runners report usage in their own shapes; each is normalized to one [`Usage`] per
+//! model step. Counts are per step, never cumulative, so the server can simply sum them.
+
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Usage {
+    /// Prompt tokens not served from the provider cache.
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub reasoning_tokens: i64,
+    pub cache_read_tokens: i64,
+    pub cache_write_tokens: i64,
+    /// Only set when the agent reports a cost; never estimated here.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+fn int(v: &JsonValue, key: &str) -> i64 {
+    v.get(key).and_then(|v| v.as_i64()).unwrap_or(0).max(0)
+}
+
+impl Usage {
+    fn is_empty(&self) -> bool {
+        self.input_tokens == 0
+            && self.output_tokens == 0
+            && self.reasoning_tokens == 0
+            && self.cache_read_tokens == 0
+            && self.cache_write_tokens == 0
+            && self.cost_usd.is_none_or(|c| c == 0.0)
+    }
+
+    /// opencode `step-finish` part (`opencode run --format json` `step_finish` events and
+    /// `opencode serve` message parts): `{ tokens: { input, output, reasoning, cache: { read, write } }, cost }`.
+    pub fn from_opencode_step(part: &JsonValue, model: Option<&str>) -> Option<Self> {
+        let tokens = part.get("tokens")?;
+        let cache = tokens.get("cache").unwrap_or(&JsonValue::Null);
+        let usage = Self {
+            input_tokens: int(tokens, "input"),
+            output_tokens: int(tokens, "output"),
+            reasoning_tokens: int(tokens, "reasoning"),
+            cache_read_tokens: int(cache, "read"),
+            cache_write_tokens: int(cache, "write"),
+            cost_usd: part.get("cost").and_then(|v| v.as_f64()),
+            model: model.map(|s| s.to_string()),
+        };
+        (!usage.is_empty()).then_some(usage)
+    }
+
+    /// codex `token_count` event: `{ info: { last_token_usage: { input_tokens, cached_input_tokens,
+    /// output_tokens, reasoning_output_tokens } } }`. Codex counts cached tokens inside
+    /// `input_tokens`, so they are split out here.
+    pub fn from_codex_token_count(msg: &JsonValue, model: Option<&str>) -> Option<Self> {
+        let last = msg.get("info")?.get("last_token_usage")?;
+        let cached = int(last, "cached_input_tokens");
+        let usage = Self {
+            input_tokens: (int(last, "input_tokens") - cached).max(0),
+            output_tokens: int(last, "output_tokens"),
+            reasoning_tokens: int(last, "reasoning_output_tokens"),
+            cache_read_tokens: cached,
+            cache_write_tokens: 0,
+            cost_usd: None,
+            model: model.map(|s| s.to_string()),
+        };
+        (!usage.is_empty()).then_some(usage)
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        serde_json::to_value(self).unwrap_or(JsonValue::Null)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn normalizes_opencode_and_codex_usage() {
+        let step = json!({
+            "type": "step-finish",
+            "tokens": { "input": 120, "output": 30, "reasoning": 5, "cache": { "read": 800, "write": 0 } },
+            "cost": 0.0042
+        });
+        let u = Usage::from_opencode_step(&step, Some("anthropic/claude")).unwrap();
+        assert_eq!(u.input_tokens, 120);
+        assert_eq!(u.cache_read_tokens, 800);
+        assert_eq!(u.cost_usd, Some(0.0042));
+        assert_eq!(u.to_json()["model"], "anthropic/claude");
+
+        let empty = json!({ "tokens": { "input": 0, "output": 0 }, "cost": 0 });
+        assert!(Usage::from_opencode_step(&empty, None).is_none());
+
+        let msg = json!({
+            "type": "token_count",
+            "info": { "last_token_usage": {
+                "input_tokens": 1000, "cached_input_tokens": 600, "output_tokens": 50,
+                "reasoning_output_tokens": 10, "total_tokens": 1050
+            } }
+        });
+        let u = Usage::from_codex_token_count(&msg, None).unwrap();
+        assert_eq!((u.input_tokens, u.cache_read_tokens), (400, 600));
+        assert!(u.to_json().get("cost_usd").is_none());
+        assert!(Usage::from_codex_token_count(&json!({ "info": null }), None).is_none());
+    }
+}
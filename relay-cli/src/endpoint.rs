@@ -0,0 +1,159 @@
+//! Transport to hostd's local API: its unix socket, or its optional TCP/TLS listener.
+//!
+//! An endpoint (`--sock` / `RELAY_HOSTD_SOCK`) is a socket path, `http://host:port` or
+//! `https://host:port`. Unix endpoints send the user token hostd writes to `<socket>.token`; TCP
+//! endpoints send `RELAY_HOSTD_TOKEN` as a bearer token; for `https`,
+//! `RELAY_HOSTD_TLS_CA` replaces the public roots and `RELAY_HOSTD_TLS_CERT` +
+//! `RELAY_HOSTD_TLS_KEY` present a client certificate (mTLS).
+
+use anyhow::Context;
+use hyper_util::rt::TokioIo;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+enum Target<'a> {
+    Unix(&'a str),
+    Tcp { host_port: &'a str, tls: bool },
+}
+
+fn parse(endpoint: &str) -> Target<'_> {
+    if let Some(rest) = endpoint.strip_prefix("http://") {
+        Target::Tcp {
+            host_port: rest.trim_end_matches('/'),
+            tls: false,
+        }
+    } else if let Some(rest) = endpoint.strip_prefix("https://") {
+        Target::Tcp {
+            host_port: rest.trim_end_matches('/'),
+            tls: true,
+        }
+    } else {
+        Target::Unix(endpoint)
+    }
+}
+
+fn env_nonempty(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+pub async fn connect(endpoint: &str) -> anyhow::Result<TokioIo<Box<dyn Io>>> {
+    let io: Box<dyn Io> = match parse(endpoint) {
+        Target::Unix(path) => Box::new(
+            tokio::net::UnixStream::connect(path)
+                .await
+                .with_context(|| format!("connect unix socket: {path}"))?,
+        ),
+        Target::Tcp { host_port, tls } => {
+            let stream = tokio::net::TcpStream::connect(host_port)
+                .await
+                .with_context(|| format!("connect {host_port}"))?;
+            if tls {
+                let host = match host_port.rsplit_once(':') {
+                    Some((host, _)) => host,
+                    None => host_port,
+                };
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                let name = rustls::pki_types::ServerName::try_from(host.to_string())
+                    .with_context(|| format!("bad tls server name: {host}"))?;
+                let connector = tokio_rustls::TlsConnector::from(Arc::new(tls_config()?));
+                Box::new(
+                    connector
+                        .connect(name, stream)
+                        .await
+                        .with_context(|| format!("tls handshake with {host_port}"))?,
+                )
+            } else {
+                Box::new(stream)
+            }
+        }
+    };
+    Ok(TokioIo::new(io))
+}
+
+fn tls_config() -> anyhow::Result<rustls::ClientConfig> {
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+
+    let mut roots = rustls::RootCertStore::empty();
+    match env_nonempty("RELAY_HOSTD_TLS_CA") {
+        Some(ca) => {
+            for c in CertificateDer::pem_file_iter(&ca)
+                .with_context(|| format!("read RELAY_HOSTD_TLS_CA {ca}"))?
+            {
+                roots.add(c.context("parse RELAY_HOSTD_TLS_CA")?)?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_root_certificates(roots);
+    let config = match (
+        env_nonempty("RELAY_HOSTD_TLS_CERT"),
+        env_nonempty("RELAY_HOSTD_TLS_KEY"),
+    ) {
+        (Some(cert), Some(key)) => {
+            let certs = CertificateDer::pem_file_iter(&cert)
+                .and_then(|it| it.collect::<Result<Vec<_>, _>>())
+                .with_context(|| format!("read RELAY_HOSTD_TLS_CERT {cert}"))?;
+            let key = PrivateKeyDer::from_pem_file(&key)
+                .with_context(|| format!("read RELAY_HOSTD_TLS_KEY {key}"))?;
+            builder.with_client_auth_cert(certs, key)?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    Ok(config)
+}
+
+/// A request builder with the credentials for `endpoint`: `RELAY_RUN_TOKEN` (set by hostd inside
+/// runs, so hostd knows which run is calling), the user token over the unix socket (required while
+/// runs are active) and, over TCP, the bearer token.
+pub fn request(endpoint: &str) -> hyper::http::request::Builder {
+    let mut req = hyper::Request::builder();
+    if let Some(token) = env_nonempty("RELAY_RUN_TOKEN") {
+        req = req.header("x-relay-run-token", token);
+    }
+    if let Target::Unix(path) = parse(endpoint)
+        && let Ok(token) = std::fs::read_to_string(format!("{path}.token"))
+        && !token.trim().is_empty()
+    {
+        req = req.header("x-relay-user-token", token.trim());
+    }
+    if matches!(parse(endpoint), Target::Tcp { .. })
+        && let Some(token) = env_nonempty("RELAY_HOSTD_TOKEN")
+    {
+        req = req.header("authorization", format!("Bearer {token}"));
+    }
+    req
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_endpoints() {
+        assert!(matches!(parse("/tmp/h.sock"), Target::Unix("/tmp/h.sock")));
+        assert!(matches!(
+            parse("http://127.0.0.1:8788/"),
+            Target::Tcp {
+                host_port: "127.0.0.1:8788",
+                tls: false
+            }
+        ));
+        assert!(matches!(
+            parse("https://[::1]:8788"),
+            Target::Tcp {
+                host_port: "[::1]:8788",
+                tls: true
+            }
+        ));
+    }
+}
@@ -1,9 +1,10 @@
+mod endpoint;
+
 use anyhow::Context;
 use bytes::Bytes;
 use http_body_util::{BodyExt, Full};
+use hyper::StatusCode;
 use hyper::body::{Body as HttpBody, Frame};
-use hyper::{Request, StatusCode};
-use hyper_util::rt::TokioIo;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use std::io::IsTerminal;
@@ -18,15 +19,27 @@ fn usage() -> ! {
         r#"relay (packaged-friendly)
 
 Usage:
-  relay opencode [--sock /path/to/relay-hostd.sock] [--cmd "opencode ..."] [--cwd /path/to/project] [--attach|--no-attach]
+  relay <tool> [--sock /path/to/relay-hostd.sock] [--cmd "<tool> ..."] [--cwd /path/to/project] [--attach|--no-attach]
+
+  relay runners [--sock /path/to/relay-hostd.sock]
+
+  relay approve [--sock ...] [<run_id> [<request_id> [--session [--allow-tool <tool>]...]]]
+  relay deny [--sock ...] <run_id> <request_id>
 
   relay mcp [--root /path/to/project]
 
 Notes:
+  - <tool> is any runner registered in hostd (`relay runners` lists them; built-ins: codex, opencode,
+    claude, aider, gemini; more via ~/.relay/runners.json).
   - If --cmd is omitted, it defaults to the subcommand name (e.g. `opencode`).
   - If --cwd is omitted, it defaults to the current working directory.
+  - --sock / RELAY_HOSTD_SOCK may also be http://host:port or https://host:port for hostd's TCP listener
+    (RELAY_HOSTD_TOKEN as bearer; RELAY_HOSTD_TLS_CA / RELAY_HOSTD_TLS_CERT / RELAY_HOSTD_TLS_KEY for TLS).
   - If --sock is omitted, it tries RELAY_HOSTD_SOCK, ~/.relay/hostd.json (local_unix_socket), ~/.relay/relay-hostd.sock, then ~/.relay/daemon.state.json.
   - In a terminal (TTY), `relay <tool>` attaches by default (proxies stdin/stdout). Use `--no-attach` to only print the run id.
+  - `relay approve` without a request id lists pending permission requests (all runs, or one run);
+    with one it answers through hostd directly, which works while the server is unreachable.
+    `--session` approves the tool (or the `--allow-tool`s) for the rest of the run.
   - `--cmd` supports simple argv forms (e.g. `opencode --help`). For shell pipelines/quotes, prefer using hostd directly.
 "#
     );
@@ -56,6 +69,37 @@ struct StartRunResponse {
     run_id: String,
 }
 
+#[derive(Deserialize)]
+struct RunnerInfo {
+    tool: String,
+    #[serde(default)]
+    bin: String,
+    #[serde(default)]
+    ok: bool,
+    #[serde(default)]
+    structured: bool,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// Runners registered in hostd. Older daemons without `GET /runners` only know `opencode`.
+async fn fetch_runners(sock: &str) -> anyhow::Result<Vec<RunnerInfo>> {
+    let (status, body) = get_unix(sock, "/runners").await?;
+    if status == StatusCode::NOT_FOUND {
+        return Ok(vec![RunnerInfo {
+            tool: "opencode".to_string(),
+            bin: String::new(),
+            ok: true,
+            structured: true,
+            description: None,
+        }]);
+    }
+    if status != StatusCode::OK {
+        return Err(anyhow::anyhow!("hostd returned {status}: {body}"));
+    }
+    serde_json::from_str(&body).context("decode runners json")
+}
+
 async fn post_json_unix<TReq: Serialize>(
     sock_path: &str,
     path: &str,
@@ -82,10 +126,7 @@ async fn request_unix<TReq: Serialize>(
     content_type: Option<&str>,
     body: Option<&TReq>,
 ) -> anyhow::Result<(StatusCode, String)> {
-    let stream = tokio::net::UnixStream::connect(sock_path)
-        .await
-        .with_context(|| format!("connect unix socket: {sock_path}"))?;
-    let io = TokioIo::new(stream);
+    let io = endpoint::connect(sock_path).await?;
     let (mut sender, conn) = hyper::client::conn::http1::handshake(io)
         .await
         .context("http1 handshake")?;
@@ -93,7 +134,7 @@ async fn request_unix<TReq: Serialize>(
         let _ = conn.await;
     });
 
-    let req = Request::builder()
+    let req = endpoint::request(sock_path)
         .method(method)
         .uri(format!("http://localhost{path}"))
         .header("content-type", content_type.unwrap_or("application/json"))
@@ -300,10 +341,7 @@ async fn attach_tty(sock_path: &str, run_id: &str) -> anyhow::Result<()> {
     let sock_for_stdin = sock_path.to_string();
     let run_for_stdin = run_id.to_string();
     let stdin_task = tokio::spawn(async move {
-        let stream = tokio::net::UnixStream::connect(&sock_for_stdin)
-            .await
-            .with_context(|| format!("connect unix socket: {sock_for_stdin}"))?;
-        let io = TokioIo::new(stream);
+        let io = endpoint::connect(&sock_for_stdin).await?;
         let (mut sender, conn) = hyper::client::conn::http1::handshake(io)
             .await
             .context("http1 handshake (stdin)")?;
@@ -311,7 +349,7 @@ async fn attach_tty(sock_path: &str, run_id: &str) -> anyhow::Result<()> {
             let _ = conn.await;
         });
 
-        let req = Request::builder()
+        let req = endpoint::request(&sock_for_stdin)
             .method("POST")
             .uri(format!(
                 "http://localhost/runs/{}/stdin",
@@ -356,10 +394,7 @@ async fn attach_tty(sock_path: &str, run_id: &str) -> anyhow::Result<()> {
     drop(tx);
 
     // hostd -> stdout (streaming GET)
-    let stream = tokio::net::UnixStream::connect(sock_path)
-        .await
-        .with_context(|| format!("connect unix socket: {sock_path}"))?;
-    let io = TokioIo::new(stream);
+    let io = endpoint::connect(sock_path).await?;
     let (mut sender, conn) = hyper::client::conn::http1::handshake(io)
         .await
         .context("http1 handshake (stdout)")?;
@@ -367,7 +402,7 @@ async fn attach_tty(sock_path: &str, run_id: &str) -> anyhow::Result<()> {
         let _ = conn.await;
     });
 
-    let req = Request::builder()
+    let req = endpoint::request(sock_path)
         .method("GET")
         .uri(format!(
             "http://localhost/runs/{}/stdout",
@@ -544,7 +579,7 @@ fn normalize_mcp_tool_name(raw_name: &str) -> &str {
 
 #[cfg(test)]
 mod tests {
-    use super::normalize_mcp_tool_name;
+    use super::{normalize_mcp_tool_name, positionals};
 
     #[test]
     fn normalizes_common_mcp_tool_name_prefixes() {
@@ -557,6 +592,26 @@ mod tests {
             "git_status"
         );
     }
+
+    #[test]
+    fn positionals_skip_flag_values() {
+        let args = [
+            "relay",
+            "approve",
+            "--sock",
+            "/tmp/h.sock",
+            "run-1",
+            "--session",
+        ]
+        .iter()
+        .chain(&["--allow-tool", "bash", "req-1"])
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            positionals(&args, &["--sock", "--allow-tool"]),
+            vec!["run-1", "req-1"]
+        );
+    }
 }
 
 #[derive(Clone)]
@@ -1332,6 +1387,107 @@ async fn run_mcp(root: std::path::PathBuf) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Positional arguments after the subcommand, skipping `--flag value` pairs of `value_flags`.
+fn positionals<'a>(args: &'a [String], value_flags: &[&str]) -> Vec<&'a str> {
+    let mut out = Vec::new();
+    let mut it = args.iter().skip(2);
+    while let Some(a) = it.next() {
+        if value_flags.contains(&a.as_str()) {
+            it.next();
+        } else if !a.starts_with("--") {
+            out.push(a.as_str());
+        }
+    }
+    out
+}
+
+#[derive(Deserialize)]
+struct RunListItem {
+    run_id: String,
+}
+
+#[derive(Deserialize)]
+struct PendingPermissions {
+    #[serde(default)]
+    pending: Vec<JsonValue>,
+}
+
+/// `relay approve` / `relay deny`: list or answer pending permission requests via hostd.
+async fn run_approve(sock: &str, args: &[String], approve: bool) -> anyhow::Result<()> {
+    let pos = positionals(args, &["--sock", "--allow-tool"]);
+    match pos.as_slice() {
+        [run_id, request_id] => {
+            let allow_tools = args
+                .iter()
+                .enumerate()
+                .filter(|(_, a)| *a == "--allow-tool")
+                .filter_map(|(i, _)| args.get(i + 1).cloned())
+                .collect::<Vec<_>>();
+            let decision = match (approve, has_flag(args, "--session")) {
+                (false, _) => "deny",
+                (true, false) => "approve",
+                (true, true) => "approve_for_session",
+            };
+            let path = format!(
+                "/runs/{}/permissions/{}",
+                percent_encode_query_value(run_id),
+                percent_encode_query_value(request_id)
+            );
+            let body = serde_json::json!({
+                "decision": decision,
+                "allow_tools": allow_tools,
+                "actor": "relay-cli",
+            });
+            let (status, body) = post_json_unix(sock, &path, &body).await?;
+            if !status.is_success() {
+                return Err(anyhow::anyhow!("hostd returned {status}: {body}"));
+            }
+            eprintln!("{decision}: {run_id} {request_id}");
+            Ok(())
+        }
+        [run_id] if approve => print_pending(sock, &[run_id.to_string()]).await,
+        [] if approve => {
+            let (status, body) = get_unix(sock, "/runs").await?;
+            if status != StatusCode::OK {
+                return Err(anyhow::anyhow!("hostd returned {status}: {body}"));
+            }
+            let runs: Vec<RunListItem> = serde_json::from_str(&body).context("decode runs")?;
+            let ids = runs.into_iter().map(|r| r.run_id).collect::<Vec<_>>();
+            print_pending(sock, &ids).await
+        }
+        _ => usage(),
+    }
+}
+
+async fn print_pending(sock: &str, run_ids: &[String]) -> anyhow::Result<()> {
+    let mut any = false;
+    for run_id in run_ids {
+        let path = format!("/runs/{}/permissions", percent_encode_query_value(run_id));
+        let (status, body) = get_unix(sock, &path).await?;
+        if status != StatusCode::OK {
+            return Err(anyhow::anyhow!("hostd returned {status}: {body}"));
+        }
+        let parsed: PendingPermissions =
+            serde_json::from_str(&body).context("decode permissions")?;
+        for p in parsed.pending {
+            any = true;
+            let field = |k: &str| p.get(k).and_then(|v| v.as_str()).unwrap_or("").to_string();
+            println!(
+                "{run_id} {} {}",
+                field("request_id"),
+                match field("prompt") {
+                    prompt if prompt.is_empty() => field("op_tool"),
+                    prompt => prompt,
+                }
+            );
+        }
+    }
+    if !any {
+        eprintln!("no pending permission requests");
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = std::env::args().collect::<Vec<_>>();
@@ -1346,12 +1502,46 @@ async fn main() -> anyhow::Result<()> {
         return run_mcp(root).await;
     }
 
-    let tool = match cmd {
-        "opencode" => cmd,
-        _ => usage(),
-    };
+    if cmd.starts_with('-') {
+        usage();
+    }
 
     let sock = pick_sock(get_arg(&args, "--sock")).await?;
+    if cmd == "approve" || cmd == "deny" {
+        return run_approve(&sock, &args, cmd == "approve").await;
+    }
+    let runners = fetch_runners(&sock).await?;
+
+    if cmd == "runners" {
+        for r in &runners {
+            println!(
+                "{:<12} {:<4} {:<10} {}{}",
+                r.tool,
+                if r.ok { "ok" } else { "--" },
+                if r.structured { "structured" } else { "pty" },
+                r.bin,
+                r.description
+                    .as_deref()
+                    .map(|d| format!("  ({d})"))
+                    .unwrap_or_default()
+            );
+        }
+        return Ok(());
+    }
+
+    let Some(tool) = runners
+        .iter()
+        .find(|r| r.tool == cmd)
+        .map(|r| r.tool.as_str())
+    else {
+        let known = runners
+            .iter()
+            .map(|r| r.tool.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        eprintln!("relay: unknown tool `{cmd}` (registered in hostd: {known})\n");
+        usage();
+    };
     let cwd = get_arg(&args, "--cwd")
         .filter(|s| !s.trim().is_empty())
         .or_else(|| {
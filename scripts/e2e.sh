@@ -22,7 +22,15 @@ need bun
 need node
 
 curl_local() {
-  curl --silent --show-error --fail-with-body --noproxy "*" "$@"
+  # hostd wants its user token (written next to the socket) from callers outside a run while runs
+  # are active.
+  local args=("$@") extra=() i
+  for ((i = 0; i < ${#args[@]} - 1; i++)); do
+    if [[ "${args[i]}" == "--unix-socket" && -f "${args[i + 1]}.token" ]]; then
+      extra=(-H "x-relay-user-token: $(cat "${args[i + 1]}.token")")
+    fi
+  done
+  curl --silent --show-error --fail-with-body --noproxy "*" "${extra[@]}" "$@"
 }
 
 ROOT="$(cd -- "$(dirname -- "${BASH_SOURCE[0]}")/.." && pwd)"
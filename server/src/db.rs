@@ -82,7 +82,17 @@ CREATE TABLE IF NOT EXISTS runs (
   pending_op_tool TEXT,
   pending_op_args_summary TEXT,
   ended_at TEXT,
-  exit_code INTEGER
+  exit_code INTEGER,
+  usage_input_tokens INTEGER,
+  usage_output_tokens INTEGER,
+  usage_reasoning_tokens INTEGER,
+  usage_cache_read_tokens INTEGER,
+  usage_cache_write_tokens INTEGER,
+  usage_cost_usd REAL,
+  usage_model TEXT,
+  todos_json TEXT,
+  todos_seq INTEGER,
+  todos_updated_at TEXT
 );
 "#,
     )
@@ -110,6 +120,77 @@ CREATE TABLE IF NOT EXISTS events (
     .execute(pool)
     .await?;
 
+    // One row per `run.usage` event, so reports can group by the event's model and day.
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS usage_events (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  run_id TEXT NOT NULL,
+  ts TEXT NOT NULL,
+  model TEXT,
+  input_tokens INTEGER NOT NULL,
+  output_tokens INTEGER NOT NULL,
+  reasoning_tokens INTEGER NOT NULL,
+  cache_read_tokens INTEGER NOT NULL,
+  cache_write_tokens INTEGER NOT NULL,
+  cost_usd REAL NOT NULL
+);
+"#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS profiles (
+  name TEXT PRIMARY KEY NOT NULL,
+  data_json TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
+"#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS jobs (
+  id TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  schedule TEXT NOT NULL,
+  timezone TEXT NOT NULL,
+  missed_policy TEXT NOT NULL,
+  enabled INTEGER NOT NULL,
+  run_json TEXT NOT NULL,
+  next_run_at TEXT,
+  last_fired_at TEXT,
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
+"#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS job_firings (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  job_id TEXT NOT NULL,
+  scheduled_for TEXT NOT NULL,
+  fired_at TEXT,
+  status TEXT NOT NULL,
+  request_id TEXT,
+  host_id TEXT,
+  run_id TEXT,
+  error TEXT
+);
+"#,
+    )
+    .execute(pool)
+    .await?;
+
     // Best-effort schema upgrades for dev (ignore if already exists).
     let _ = sqlx::query("ALTER TABLE events ADD COLUMN input_id TEXT;")
         .execute(pool)
@@ -138,6 +219,34 @@ CREATE TABLE IF NOT EXISTS events (
     let _ = sqlx::query("ALTER TABLE runs ADD COLUMN pending_op_args_summary TEXT;")
         .execute(pool)
         .await;
+    let _ = sqlx::query("ALTER TABLE runs ADD COLUMN job_id TEXT;")
+        .execute(pool)
+        .await;
+    for col in [
+        "usage_input_tokens INTEGER",
+        "usage_output_tokens INTEGER",
+        "usage_reasoning_tokens INTEGER",
+        "usage_cache_read_tokens INTEGER",
+        "usage_cache_write_tokens INTEGER",
+        "usage_cost_usd REAL",
+        "usage_model TEXT",
+        "todos_json TEXT",
+        "todos_seq INTEGER",
+        "todos_updated_at TEXT",
+    ] {
+        let _ = sqlx::query(&format!("ALTER TABLE runs ADD COLUMN {col};"))
+            .execute(pool)
+            .await;
+    }
+    let _ =
+        sqlx::query("CREATE INDEX IF NOT EXISTS job_firings_job_id_id ON job_firings(job_id, id);")
+            .execute(pool)
+            .await;
+    let _ = sqlx::query(
+        "CREATE INDEX IF NOT EXISTS job_firings_request_id ON job_firings(request_id);",
+    )
+    .execute(pool)
+    .await;
     let _ = sqlx::query(
         "CREATE UNIQUE INDEX IF NOT EXISTS events_run_seq_uq ON events(run_id, seq) WHERE seq IS NOT NULL;",
     )
@@ -155,6 +264,24 @@ CREATE TABLE IF NOT EXISTS events (
         sqlx::query("UPDATE runs SET last_active_at = started_at WHERE last_active_at IS NULL;")
             .execute(pool)
             .await;
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS usage_events_run_id ON usage_events(run_id);")
+        .execute(pool)
+        .await;
+    // Runs totalled before `usage_events` existed: one row at the run's start.
+    let _ = sqlx::query(
+        r#"
+INSERT INTO usage_events (run_id, ts, model, input_tokens, output_tokens, reasoning_tokens,
+  cache_read_tokens, cache_write_tokens, cost_usd)
+SELECT id, started_at, usage_model, usage_input_tokens, COALESCE(usage_output_tokens, 0),
+  COALESCE(usage_reasoning_tokens, 0), COALESCE(usage_cache_read_tokens, 0),
+  COALESCE(usage_cache_write_tokens, 0), COALESCE(usage_cost_usd, 0.0)
+FROM runs r
+WHERE usage_input_tokens IS NOT NULL
+  AND NOT EXISTS (SELECT 1 FROM usage_events u WHERE u.run_id = r.id);
+"#,
+    )
+    .execute(pool)
+    .await;
 
     Ok(())
 }
@@ -172,8 +299,8 @@ pub async fn insert_event(
     text_redacted: Option<&str>,
     text_sha256: Option<&str>,
     data_json: Option<&str>,
-) -> anyhow::Result<()> {
-    sqlx::query(
+) -> anyhow::Result<bool> {
+    let res = sqlx::query(
         r#"
 INSERT OR IGNORE INTO events (run_id, seq, ts, type, stream, actor, input_id, text, text_redacted, text_sha256, data_json)
 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
@@ -192,7 +319,8 @@ VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
     .bind(data_json)
     .execute(pool)
     .await?;
-    Ok(())
+    // `false` when (run_id, seq) was already stored, e.g. a spool replay after reconnect.
+    Ok(res.rows_affected() > 0)
 }
 
 pub async fn upsert_run_started(
@@ -502,6 +630,14 @@ pub struct RunRow {
     pub pending_op_args_summary: Option<String>,
     pub ended_at: Option<String>,
     pub exit_code: Option<i64>,
+    pub job_id: Option<String>,
+    pub usage_input_tokens: Option<i64>,
+    pub usage_output_tokens: Option<i64>,
+    pub usage_reasoning_tokens: Option<i64>,
+    pub usage_cache_read_tokens: Option<i64>,
+    pub usage_cache_write_tokens: Option<i64>,
+    pub usage_cost_usd: Option<f64>,
+    pub usage_model: Option<String>,
 }
 
 pub async fn list_runs(pool: &Db) -> anyhow::Result<Vec<RunRow>> {
@@ -522,7 +658,15 @@ SELECT
   pending_op_tool,
   pending_op_args_summary,
   ended_at,
-  exit_code
+  exit_code,
+  job_id,
+  usage_input_tokens,
+  usage_output_tokens,
+  usage_reasoning_tokens,
+  usage_cache_read_tokens,
+  usage_cache_write_tokens,
+  usage_cost_usd,
+  usage_model
 FROM runs
 ORDER BY COALESCE(last_active_at, started_at) DESC
 LIMIT 200
@@ -551,7 +695,15 @@ SELECT
   pending_op_tool,
   pending_op_args_summary,
   ended_at,
-  exit_code
+  exit_code,
+  job_id,
+  usage_input_tokens,
+  usage_output_tokens,
+  usage_reasoning_tokens,
+  usage_cache_read_tokens,
+  usage_cache_write_tokens,
+  usage_cost_usd,
+  usage_model
 FROM runs
 WHERE id = ?1
 LIMIT 1
@@ -582,7 +734,15 @@ SELECT
   pending_op_tool,
   pending_op_args_summary,
   ended_at,
-  exit_code
+  exit_code,
+  job_id,
+  usage_input_tokens,
+  usage_output_tokens,
+  usage_reasoning_tokens,
+  usage_cache_read_tokens,
+  usage_cache_write_tokens,
+  usage_cost_usd,
+  usage_model
 FROM runs
 ORDER BY COALESCE(last_active_at, started_at) DESC
 LIMIT ?1
@@ -594,6 +754,186 @@ LIMIT ?1
     Ok(rows)
 }
 
+/// Records one `run.usage` event (emitted at `ts`) and adds it to the run's totals.
+pub async fn add_run_usage(
+    pool: &Db,
+    run_id: &str,
+    ts: DateTime<Utc>,
+    u: &RunUsage<'_>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+INSERT INTO usage_events (run_id, ts, model, input_tokens, output_tokens, reasoning_tokens,
+  cache_read_tokens, cache_write_tokens, cost_usd)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+"#,
+    )
+    .bind(run_id)
+    .bind(ts.to_rfc3339())
+    .bind(u.model)
+    .bind(u.input_tokens)
+    .bind(u.output_tokens)
+    .bind(u.reasoning_tokens)
+    .bind(u.cache_read_tokens)
+    .bind(u.cache_write_tokens)
+    .bind(u.cost_usd)
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        r#"
+UPDATE runs SET
+  usage_input_tokens = COALESCE(usage_input_tokens, 0) + ?2,
+  usage_output_tokens = COALESCE(usage_output_tokens, 0) + ?3,
+  usage_reasoning_tokens = COALESCE(usage_reasoning_tokens, 0) + ?4,
+  usage_cache_read_tokens = COALESCE(usage_cache_read_tokens, 0) + ?5,
+  usage_cache_write_tokens = COALESCE(usage_cache_write_tokens, 0) + ?6,
+  usage_cost_usd = COALESCE(usage_cost_usd, 0) + ?7,
+  usage_model = COALESCE(?8, usage_model)
+WHERE id=?1
+"#,
+    )
+    .bind(run_id)
+    .bind(u.input_tokens)
+    .bind(u.output_tokens)
+    .bind(u.reasoning_tokens)
+    .bind(u.cache_read_tokens)
+    .bind(u.cache_write_tokens)
+    .bind(u.cost_usd)
+    .bind(u.model)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Stores a `run.todos` snapshot unless a newer one (higher `seq`) is already stored.
+pub async fn set_run_todos(
+    pool: &Db,
+    run_id: &str,
+    seq: Option<i64>,
+    todos_json: &str,
+    ts: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+UPDATE runs
+SET todos_json = ?2, todos_seq = ?3, todos_updated_at = ?4
+WHERE id=?1 AND (todos_seq IS NULL OR ?3 IS NULL OR todos_seq <= ?3)
+"#,
+    )
+    .bind(run_id)
+    .bind(todos_json)
+    .bind(seq)
+    .bind(ts.to_rfc3339())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct RunTodosRow {
+    pub todos_json: Option<String>,
+    pub todos_updated_at: Option<String>,
+}
+
+pub async fn get_run_todos(pool: &Db, run_id: &str) -> anyhow::Result<Option<RunTodosRow>> {
+    let row = sqlx::query_as::<_, RunTodosRow>(
+        "SELECT todos_json, todos_updated_at FROM runs WHERE id=?1",
+    )
+    .bind(run_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub struct RunUsage<'a> {
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub reasoning_tokens: i64,
+    pub cache_read_tokens: i64,
+    pub cache_write_tokens: i64,
+    pub cost_usd: f64,
+    pub model: Option<&'a str>,
+}
+
+impl<'a> RunUsage<'a> {
+    /// Reads a `run.usage` payload; missing counters are zero.
+    pub fn from_event(data: &'a serde_json::Value) -> Self {
+        let int = |k: &str| data.get(k).and_then(|v| v.as_i64()).unwrap_or(0).max(0);
+        Self {
+            input_tokens: int("input_tokens"),
+            output_tokens: int("output_tokens"),
+            reasoning_tokens: int("reasoning_tokens"),
+            cache_read_tokens: int("cache_read_tokens"),
+            cache_write_tokens: int("cache_write_tokens"),
+            cost_usd: data
+                .get("cost_usd")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(0.0)
+                .max(0.0),
+            model: data
+                .get("model")
+                .and_then(|v| v.as_str())
+                .filter(|s| !s.trim().is_empty()),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct UsageGroupRow {
+    pub key: String,
+    pub runs: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub reasoning_tokens: i64,
+    pub cache_read_tokens: i64,
+    pub cache_write_tokens: i64,
+    pub cost_usd: f64,
+}
+
+/// Usage totals grouped by `host | tool | model | day`, largest cost first. `model` and `day`
+/// (UTC date) come from each `run.usage` event, so a run that switches model or spans midnight
+/// is split accordingly; `runs` counts the distinct runs in each group.
+pub async fn usage_by(pool: &Db, group_by: &str) -> anyhow::Result<Vec<UsageGroupRow>> {
+    let key = match group_by {
+        "host" => "r.host_id",
+        "tool" => "r.tool",
+        "model" => "COALESCE(u.model, 'unknown')",
+        "day" => "substr(u.ts, 1, 10)",
+        other => anyhow::bail!("unsupported group_by `{other}` (expected host|tool|model|day)"),
+    };
+    let rows = sqlx::query_as::<_, UsageGroupRow>(&format!(
+        r#"
+SELECT
+  {key} AS key,
+  COUNT(DISTINCT u.run_id) AS runs,
+  COALESCE(SUM(u.input_tokens), 0) AS input_tokens,
+  COALESCE(SUM(u.output_tokens), 0) AS output_tokens,
+  COALESCE(SUM(u.reasoning_tokens), 0) AS reasoning_tokens,
+  COALESCE(SUM(u.cache_read_tokens), 0) AS cache_read_tokens,
+  COALESCE(SUM(u.cache_write_tokens), 0) AS cache_write_tokens,
+  COALESCE(SUM(u.cost_usd), 0.0) AS cost_usd
+FROM usage_events u
+JOIN runs r ON r.id = u.run_id
+GROUP BY 1
+ORDER BY cost_usd DESC, input_tokens + output_tokens DESC
+"#
+    ))
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Distinct runs with any usage. Not the sum of `usage_by` groups: with `model` or `day` one run
+/// can fall into several groups.
+pub async fn usage_runs(pool: &Db) -> anyhow::Result<i64> {
+    let runs = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(DISTINCT u.run_id) FROM usage_events u JOIN runs r ON r.id = u.run_id",
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(runs)
+}
+
 #[derive(sqlx::FromRow, serde::Serialize)]
 pub struct HostRow {
     pub id: String,
@@ -665,3 +1005,306 @@ LIMIT ?4
     .await?;
     Ok(rows)
 }
+
+#[derive(sqlx::FromRow)]
+pub struct ProfileRow {
+    pub name: String,
+    pub data_json: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub async fn list_profiles(pool: &Db) -> anyhow::Result<Vec<ProfileRow>> {
+    let rows = sqlx::query_as::<_, ProfileRow>(
+        r#"
+SELECT name, data_json, created_at, updated_at
+FROM profiles
+ORDER BY name ASC
+"#,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn get_profile(pool: &Db, name: &str) -> anyhow::Result<Option<ProfileRow>> {
+    let row = sqlx::query_as::<_, ProfileRow>(
+        r#"
+SELECT name, data_json, created_at, updated_at
+FROM profiles
+WHERE name = ?1
+LIMIT 1
+"#,
+    )
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+/// Inserts or replaces a profile. Returns `true` when the profile did not exist before.
+pub async fn upsert_profile(
+    pool: &Db,
+    name: &str,
+    data_json: &str,
+    ts: DateTime<Utc>,
+) -> anyhow::Result<bool> {
+    let existed = get_profile(pool, name).await?.is_some();
+    sqlx::query(
+        r#"
+INSERT INTO profiles (name, data_json, created_at, updated_at)
+VALUES (?1, ?2, ?3, ?3)
+ON CONFLICT(name) DO UPDATE SET
+  data_json=excluded.data_json,
+  updated_at=excluded.updated_at
+"#,
+    )
+    .bind(name)
+    .bind(data_json)
+    .bind(ts.to_rfc3339())
+    .execute(pool)
+    .await?;
+    Ok(!existed)
+}
+
+pub async fn delete_profile(pool: &Db, name: &str) -> anyhow::Result<bool> {
+    let res = sqlx::query("DELETE FROM profiles WHERE name = ?1")
+        .bind(name)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+#[derive(sqlx::FromRow, Clone)]
+pub struct JobRow {
+    pub id: String,
+    pub name: String,
+    pub schedule: String,
+    pub timezone: String,
+    pub missed_policy: String,
+    pub enabled: bool,
+    pub run_json: String,
+    pub next_run_at: Option<String>,
+    pub last_fired_at: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub async fn list_jobs(pool: &Db) -> anyhow::Result<Vec<JobRow>> {
+    let rows = sqlx::query_as::<_, JobRow>(
+        r#"
+SELECT id, name, schedule, timezone, missed_policy, enabled, run_json, next_run_at, last_fired_at, created_at, updated_at
+FROM jobs
+ORDER BY name ASC, id ASC
+"#,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn get_job(pool: &Db, job_id: &str) -> anyhow::Result<Option<JobRow>> {
+    let row = sqlx::query_as::<_, JobRow>(
+        r#"
+SELECT id, name, schedule, timezone, missed_policy, enabled, run_json, next_run_at, last_fired_at, created_at, updated_at
+FROM jobs
+WHERE id = ?1
+LIMIT 1
+"#,
+    )
+    .bind(job_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+/// Inserts or replaces a job definition (keeps `created_at` and `last_fired_at` on update).
+pub async fn upsert_job(pool: &Db, job: &JobRow) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+INSERT INTO jobs (id, name, schedule, timezone, missed_policy, enabled, run_json, next_run_at, last_fired_at, created_at, updated_at)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+ON CONFLICT(id) DO UPDATE SET
+  name=excluded.name,
+  schedule=excluded.schedule,
+  timezone=excluded.timezone,
+  missed_policy=excluded.missed_policy,
+  enabled=excluded.enabled,
+  run_json=excluded.run_json,
+  next_run_at=excluded.next_run_at,
+  updated_at=excluded.updated_at
+"#,
+    )
+    .bind(&job.id)
+    .bind(&job.name)
+    .bind(&job.schedule)
+    .bind(&job.timezone)
+    .bind(&job.missed_policy)
+    .bind(job.enabled)
+    .bind(&job.run_json)
+    .bind(&job.next_run_at)
+    .bind(&job.last_fired_at)
+    .bind(&job.created_at)
+    .bind(&job.updated_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_job(pool: &Db, job_id: &str) -> anyhow::Result<bool> {
+    let res = sqlx::query("DELETE FROM jobs WHERE id = ?1")
+        .bind(job_id)
+        .execute(pool)
+        .await?;
+    let _ = sqlx::query("DELETE FROM job_firings WHERE job_id = ?1")
+        .bind(job_id)
+        .execute(pool)
+        .await;
+    Ok(res.rows_affected() > 0)
+}
+
+pub async fn set_job_next_run(
+    pool: &Db,
+    job_id: &str,
+    next_run_at: Option<DateTime<Utc>>,
+    last_fired_at: Option<DateTime<Utc>>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+UPDATE jobs
+SET next_run_at=?2,
+    last_fired_at=COALESCE(?3, last_fired_at)
+WHERE id=?1
+"#,
+    )
+    .bind(job_id)
+    .bind(next_run_at.map(|t| t.to_rfc3339()))
+    .bind(last_fired_at.map(|t| t.to_rfc3339()))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub struct NewJobFiring<'a> {
+    pub job_id: &'a str,
+    pub scheduled_for: DateTime<Utc>,
+    pub fired_at: Option<DateTime<Utc>>,
+    /// `dispatched | started | failed | skipped`
+    pub status: &'a str,
+    pub request_id: Option<&'a str>,
+    pub host_id: Option<&'a str>,
+    pub error: Option<&'a str>,
+}
+
+pub async fn insert_job_firing(pool: &Db, f: NewJobFiring<'_>) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+INSERT INTO job_firings (job_id, scheduled_for, fired_at, status, request_id, host_id, error)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+"#,
+    )
+    .bind(f.job_id)
+    .bind(f.scheduled_for.to_rfc3339())
+    .bind(f.fired_at.map(|t| t.to_rfc3339()))
+    .bind(f.status)
+    .bind(f.request_id)
+    .bind(f.host_id)
+    .bind(f.error)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Records the host's answer to a scheduler-issued `rpc.run.start` and links the new run to its
+/// job. Returns the job id when `request_id` belongs to a job firing.
+pub async fn complete_job_firing(
+    pool: &Db,
+    request_id: &str,
+    run_id: Option<&str>,
+    error: Option<&str>,
+) -> anyhow::Result<Option<String>> {
+    let status = if error.is_some() { "failed" } else { "started" };
+    let job_id = sqlx::query_scalar::<_, String>(
+        r#"
+UPDATE job_firings
+SET status=?2, run_id=COALESCE(?3, run_id), error=?4
+WHERE request_id=?1
+RETURNING job_id
+"#,
+    )
+    .bind(request_id)
+    .bind(status)
+    .bind(run_id)
+    .bind(error)
+    .fetch_optional(pool)
+    .await?;
+    if let (Some(_), Some(run_id)) = (job_id.as_deref(), run_id) {
+        link_run_to_job(pool, run_id).await?;
+    }
+    Ok(job_id)
+}
+
+/// Copies the job id of the firing that started `run_id` onto the run row (whichever of
+/// `run.started` / `rpc.response` arrives last makes the link).
+pub async fn link_run_to_job(pool: &Db, run_id: &str) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+UPDATE runs
+SET job_id=(SELECT job_id FROM job_firings WHERE run_id=?1 LIMIT 1)
+WHERE id=?1 AND job_id IS NULL
+"#,
+    )
+    .bind(run_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct JobFiringRow {
+    pub id: i64,
+    pub job_id: String,
+    pub scheduled_for: String,
+    pub fired_at: Option<String>,
+    pub status: String,
+    pub request_id: Option<String>,
+    pub host_id: Option<String>,
+    pub run_id: Option<String>,
+    pub error: Option<String>,
+    pub run_status: Option<String>,
+    pub run_exit_code: Option<i64>,
+}
+
+pub async fn list_job_firings(
+    pool: &Db,
+    job_id: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<JobFiringRow>> {
+    let limit = limit.clamp(1, 500);
+    let rows = sqlx::query_as::<_, JobFiringRow>(
+        r#"
+SELECT
+  f.id,
+  f.job_id,
+  f.scheduled_for,
+  f.fired_at,
+  f.status,
+  f.request_id,
+  f.host_id,
+  f.run_id,
+  f.error,
+  r.status AS run_status,
+  r.exit_code AS run_exit_code
+FROM job_firings f
+LEFT JOIN runs r ON r.id = f.run_id
+WHERE f.job_id=?1
+ORDER BY f.id DESC
+LIMIT ?2
+"#,
+    )
+    .bind(job_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
@@ -0,0 +1,687 @@
+//! HTTP file transfer for large files (`GET`/`PUT /runs/:run_id/files`).
+//!
+//! Both endpoints drive the host's chunked `rpc.fs.download` / `rpc.fs.upload` RPCs, so they are
+//! scoped to the run cwd and permission-gated exactly like the WS-RPC variants: the first chunk
+//! waits for approval, the rest of the transfer (same `transfer_id`) reuses it.
+
+use crate::{AppState, bearer_token, resolve_host_id_for_run, validate_jwt};
+use axum::body::{Body, Bytes};
+use axum::extract::ws::Message;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use futures_util::StreamExt;
+use relay_protocol::WsEnvelope;
+use serde::Deserialize;
+use serde_json::{Value as JsonValue, json};
+use sha2::{Digest, Sha256};
+use std::time::Duration as StdDuration;
+
+/// Bytes moved per RPC (the host's maximum chunk size).
+const CHUNK: usize = 1024 * 1024;
+/// Largest file the host accepts.
+const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+/// The first chunk may sit behind a permission request.
+const FIRST_CHUNK_TIMEOUT: StdDuration = StdDuration::from_secs(600);
+const CHUNK_TIMEOUT: StdDuration = StdDuration::from_secs(60);
+
+type HttpError = (StatusCode, String);
+
+/// Sends one RPC to `host_id` and waits for its `rpc.response`. The response is handed to the
+/// caller only: it is neither persisted nor fanned out to apps.
+pub async fn host_rpc(
+    state: &AppState,
+    host_id: &str,
+    run_id: &str,
+    rpc_type: &str,
+    mut data: JsonValue,
+    timeout: StdDuration,
+) -> Result<JsonValue, HttpError> {
+    let request_id = uuid::Uuid::new_v4().to_string();
+    data["request_id"] = JsonValue::String(request_id.clone());
+    let mut cmd = WsEnvelope::new(rpc_type, data);
+    cmd.host_id = Some(host_id.to_string());
+    cmd.run_id = Some(run_id.to_string());
+    let payload = serde_json::to_string(&cmd)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let tx = {
+        let hosts = state.hosts_tx.read().await;
+        hosts.get(host_id).cloned()
+    };
+    let Some(tx) = tx else {
+        return Err((StatusCode::BAD_GATEWAY, "host offline".into()));
+    };
+    let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+    state
+        .rpc_waiters
+        .lock()
+        .await
+        .insert(request_id.clone(), resp_tx);
+    if tx.send(Message::Text(payload)).await.is_err() {
+        state.rpc_waiters.lock().await.remove(&request_id);
+        return Err((StatusCode::BAD_GATEWAY, "host offline".into()));
+    }
+    let resp = tokio::time::timeout(timeout, resp_rx).await;
+    state.rpc_waiters.lock().await.remove(&request_id);
+
+    let resp = match resp {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(_)) => return Err((StatusCode::BAD_GATEWAY, "host offline".into())),
+        Err(_) => return Err((StatusCode::GATEWAY_TIMEOUT, format!("{rpc_type} timed out"))),
+    };
+    if resp.get("ok").and_then(|v| v.as_bool()) == Some(true) {
+        return Ok(resp.get("result").cloned().unwrap_or(JsonValue::Null));
+    }
+    let error = resp
+        .get("error")
+        .and_then(|v| v.as_str())
+        .unwrap_or("rpc failed")
+        .to_string();
+    let status = if error == "denied" {
+        StatusCode::FORBIDDEN
+    } else if error.starts_with("offset mismatch") {
+        StatusCode::CONFLICT
+    } else if error.starts_with("sha256 mismatch") {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    Err((status, error))
+}
+
+async fn authorize(
+    state: &AppState,
+    headers: &HeaderMap,
+    run_id: &str,
+) -> Result<String, HttpError> {
+    let Some(token) = bearer_token(headers) else {
+        return Err((StatusCode::UNAUTHORIZED, "missing bearer token".into()));
+    };
+    if validate_jwt(state, &token).is_err() {
+        return Err((StatusCode::UNAUTHORIZED, "invalid token".into()));
+    }
+    resolve_host_id_for_run(state, run_id)
+        .await
+        .ok_or((StatusCode::NOT_FOUND, "unknown run_id".into()))
+}
+
+#[derive(Deserialize)]
+pub struct FileQuery {
+    path: String,
+    #[serde(default)]
+    offset: Option<u64>,
+}
+
+/// `Range: bytes=N-` (open-ended ranges only).
+fn range_start(headers: &HeaderMap) -> Option<u64> {
+    let v = headers.get(header::RANGE)?.to_str().ok()?;
+    v.strip_prefix("bytes=")?
+        .strip_suffix('-')?
+        .trim()
+        .parse()
+        .ok()
+}
+
+struct Download {
+    state: AppState,
+    host_id: String,
+    run_id: String,
+    path: String,
+    transfer_id: String,
+    offset: u64,
+    /// Only a transfer that starts at 0 can be checked against the host's whole-file sha256.
+    hasher: Option<Sha256>,
+    pending: Option<JsonValue>,
+    done: bool,
+}
+
+fn decode_chunk(result: &JsonValue) -> Result<Vec<u8>, HttpError> {
+    base64::engine::general_purpose::STANDARD
+        .decode(result.get("data").and_then(|v| v.as_str()).unwrap_or(""))
+        .map_err(|e| {
+            (
+                StatusCode::BAD_GATEWAY,
+                format!("invalid chunk from host: {e}"),
+            )
+        })
+}
+
+impl Download {
+    async fn fetch(&self, timeout: StdDuration) -> Result<JsonValue, HttpError> {
+        host_rpc(
+            &self.state,
+            &self.host_id,
+            &self.run_id,
+            "rpc.fs.download",
+            json!({
+                "path": self.path,
+                "offset": self.offset,
+                "length": CHUNK,
+                "transfer_id": self.transfer_id,
+                "actor": "web",
+            }),
+            timeout,
+        )
+        .await
+    }
+
+    /// Next body chunk; `None` once the host reported `eof`.
+    async fn next_chunk(&mut self) -> Option<Result<Bytes, std::io::Error>> {
+        if self.done {
+            return None;
+        }
+        let result = match self.pending.take() {
+            Some(r) => Ok(r),
+            None => self.fetch(CHUNK_TIMEOUT).await,
+        };
+        let chunk = result.and_then(|r| decode_chunk(&r).map(|bytes| (r, bytes)));
+        let (result, bytes) = match chunk {
+            Ok(v) => v,
+            Err((_, msg)) => {
+                self.done = true;
+                return Some(Err(std::io::Error::other(msg)));
+            }
+        };
+        self.offset += bytes.len() as u64;
+        if let Some(h) = self.hasher.as_mut() {
+            h.update(&bytes);
+        }
+        let eof = result.get("eof").and_then(|v| v.as_bool()).unwrap_or(true);
+        if eof {
+            self.done = true;
+            let expected = result.get("sha256").and_then(|v| v.as_str());
+            if let (Some(h), Some(expected)) = (self.hasher.take(), expected)
+                && format!("{:x}", h.finalize()) != expected
+            {
+                return Some(Err(std::io::Error::other(
+                    "sha256 mismatch (file changed during download)",
+                )));
+            }
+        } else if bytes.is_empty() {
+            self.done = true;
+            return Some(Err(std::io::Error::other("host returned an empty chunk")));
+        }
+        Some(Ok(Bytes::from(bytes)))
+    }
+}
+
+/// `GET /runs/:run_id/files?path=...` streams a file from the run cwd. Resume with `offset` or
+/// `Range: bytes=N-`.
+pub async fn http_download(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(run_id): Path<String>,
+    Query(q): Query<FileQuery>,
+) -> Response {
+    let host_id = match authorize(&state, &headers, &run_id).await {
+        Ok(h) => h,
+        Err(err) => return err.into_response(),
+    };
+    let ranged = range_start(&headers);
+    let offset = ranged.or(q.offset).unwrap_or(0);
+    let mut dl = Download {
+        state,
+        host_id,
+        run_id,
+        path: q.path,
+        transfer_id: uuid::Uuid::new_v4().to_string(),
+        offset,
+        hasher: (offset == 0).then(Sha256::new),
+        pending: None,
+        done: false,
+    };
+    let first = match dl.fetch(FIRST_CHUNK_TIMEOUT).await {
+        Ok(r) => r,
+        Err(err) => return err.into_response(),
+    };
+    let size = first.get("size").and_then(|v| v.as_u64()).unwrap_or(0);
+    let sha256 = first
+        .get("sha256")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    dl.pending = Some(first);
+
+    let name = std::path::Path::new(&dl.path)
+        .file_name()
+        .map(|n| n.to_string_lossy().replace('"', ""))
+        .unwrap_or_else(|| "download".into());
+    let stream = futures_util::stream::unfold(dl, |mut dl| async move {
+        dl.next_chunk().await.map(|item| (item, dl))
+    });
+    let mut resp = Body::from_stream(stream).into_response();
+    let status = if ranged.is_some() {
+        StatusCode::PARTIAL_CONTENT
+    } else {
+        StatusCode::OK
+    };
+    *resp.status_mut() = status;
+    let h = resp.headers_mut();
+    h.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    h.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Ok(v) = HeaderValue::from_str(&(size - offset.min(size)).to_string()) {
+        h.insert(header::CONTENT_LENGTH, v);
+    }
+    if let Ok(v) = HeaderValue::from_str(&format!("attachment; filename=\"{name}\"")) {
+        h.insert(header::CONTENT_DISPOSITION, v);
+    }
+    if ranged.is_some()
+        && let Ok(v) =
+            HeaderValue::from_str(&format!("bytes {offset}-{}/{size}", size.saturating_sub(1)))
+    {
+        h.insert(header::CONTENT_RANGE, v);
+    }
+    // Small files fit in one chunk, so their hash is known up front.
+    if let Some(sha) = sha256.and_then(|s| HeaderValue::from_str(&s).ok()) {
+        h.insert("x-relay-sha256", sha);
+    }
+    resp
+}
+
+async fn upload_chunk(
+    state: &AppState,
+    host_id: &str,
+    run_id: &str,
+    data: JsonValue,
+    first: bool,
+) -> Result<JsonValue, HttpError> {
+    let timeout = if first {
+        FIRST_CHUNK_TIMEOUT
+    } else {
+        CHUNK_TIMEOUT
+    };
+    host_rpc(state, host_id, run_id, "rpc.fs.upload", data, timeout).await
+}
+
+/// `PUT /runs/:run_id/files?path=...[&offset=N]` uploads the raw request body into the run cwd.
+/// `X-Relay-Sha256` (hex sha256 of the whole file) is required when resuming at `offset > 0`;
+/// otherwise it is optional and checked against the body. Files over [`MAX_FILE_SIZE`] are refused.
+pub async fn http_upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(run_id): Path<String>,
+    Query(q): Query<FileQuery>,
+    body: Body,
+) -> Response {
+    let host_id = match authorize(&state, &headers, &run_id).await {
+        Ok(h) => h,
+        Err(err) => return err.into_response(),
+    };
+    let offset = q.offset.unwrap_or(0);
+    let expected = headers
+        .get("x-relay-sha256")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.trim().to_ascii_lowercase());
+    if offset > 0 && expected.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            "X-Relay-Sha256 is required when resuming an upload",
+        )
+            .into_response();
+    }
+    let too_large = || {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("file exceeds {MAX_FILE_SIZE} bytes"),
+        )
+            .into_response()
+    };
+    let declared = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.is_some_and(|len| offset.saturating_add(len) > MAX_FILE_SIZE) {
+        return too_large();
+    }
+
+    let transfer_id = uuid::Uuid::new_v4().to_string();
+    let mut hasher = (offset == 0).then(Sha256::new);
+    let mut pos = offset;
+    let mut buf: Vec<u8> = Vec::with_capacity(CHUNK);
+    let mut first = true;
+    let mut stream = body.into_data_stream();
+    let b64 = base64::engine::general_purpose::STANDARD;
+    loop {
+        match stream.next().await {
+            Some(Ok(bytes)) => buf.extend_from_slice(&bytes),
+            Some(Err(err)) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+            None => break,
+        }
+        if pos + buf.len() as u64 > MAX_FILE_SIZE {
+            return too_large();
+        }
+        // Anything up to one chunk is held back: the last chunk carries the sha256.
+        while buf.len() > CHUNK {
+            let chunk: Vec<u8> = buf.drain(..CHUNK).collect();
+            if let Some(h) = hasher.as_mut() {
+                h.update(&chunk);
+            }
+            let data = json!({
+                "path": q.path,
+                "offset": pos,
+                "data": b64.encode(&chunk),
+                "transfer_id": transfer_id,
+                "actor": "web",
+            });
+            if let Err(err) = upload_chunk(&state, &host_id, &run_id, data, first).await {
+                return err.into_response();
+            }
+            first = false;
+            pos += chunk.len() as u64;
+        }
+    }
+
+    if let Some(h) = hasher.as_mut() {
+        h.update(&buf);
+    }
+    let computed = hasher.map(|h| format!("{:x}", h.finalize()));
+    if let (Some(computed), Some(expected)) = (&computed, &expected)
+        && computed != expected
+    {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("sha256 mismatch: body hashes to {computed}"),
+        )
+            .into_response();
+    }
+    let Some(sha256) = expected.or(computed) else {
+        return (StatusCode::BAD_REQUEST, "missing sha256").into_response();
+    };
+    let data = json!({
+        "path": q.path,
+        "offset": pos,
+        "data": b64.encode(&buf),
+        "sha256": sha256,
+        "transfer_id": transfer_id,
+        "actor": "web",
+    });
+    match upload_chunk(&state, &host_id, &run_id, data, first).await {
+        Ok(result) => axum::Json(json!({
+            "path": q.path,
+            "bytes": result.get("received").cloned().unwrap_or(JsonValue::Null),
+            "sha256": sha256,
+        }))
+        .into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Stands in for hostd: answers `rpc.fs.download`/`rpc.fs.upload` from memory and records
+    /// every request it saw.
+    #[derive(Default)]
+    struct FakeHost {
+        files: Mutex<HashMap<String, Vec<u8>>>,
+        staged: Mutex<HashMap<String, Vec<u8>>>,
+        requests: Mutex<Vec<JsonValue>>,
+        bad_sha: bool,
+    }
+
+    impl FakeHost {
+        fn answer(&self, rpc_type: &str, data: &JsonValue) -> JsonValue {
+            let b64 = base64::engine::general_purpose::STANDARD;
+            let path = data["path"].as_str().unwrap().to_string();
+            let offset = data["offset"].as_u64().unwrap_or(0) as usize;
+            let mut seen = data.clone();
+            seen["data"] = JsonValue::Null;
+            self.requests.lock().unwrap().push(seen);
+            match rpc_type {
+                "rpc.fs.download" => {
+                    let files = self.files.lock().unwrap();
+                    let file = files.get(&path).unwrap();
+                    let end = (offset + data["length"].as_u64().unwrap() as usize).min(file.len());
+                    let eof = end == file.len();
+                    let sha = if self.bad_sha {
+                        "00".to_string()
+                    } else {
+                        format!("{:x}", Sha256::digest(file))
+                    };
+                    json!({ "ok": true, "result": {
+                        "path": path,
+                        "offset": offset,
+                        "size": file.len(),
+                        "bytes": end - offset,
+                        "data": b64.encode(&file[offset..end]),
+                        "eof": eof,
+                        "sha256": eof.then_some(sha),
+                    }})
+                }
+                _ => {
+                    let mut staged = self.staged.lock().unwrap();
+                    let buf = staged.entry(path.clone()).or_default();
+                    if offset != buf.len() {
+                        let error = format!("offset mismatch: {} bytes received so far", buf.len());
+                        return json!({ "ok": false, "error": error });
+                    }
+                    buf.extend(b64.decode(data["data"].as_str().unwrap()).unwrap());
+                    let received = buf.len();
+                    let Some(expected) = data["sha256"].as_str() else {
+                        return json!({ "ok": true, "result": { "path": path, "received": received, "done": false } });
+                    };
+                    let buf = staged.remove(&path).unwrap();
+                    if format!("{:x}", Sha256::digest(&buf)) != expected {
+                        return json!({ "ok": false, "error": "sha256 mismatch: upload discarded" });
+                    }
+                    self.files.lock().unwrap().insert(path.clone(), buf);
+                    json!({ "ok": true, "result": { "path": path, "received": received, "done": true } })
+                }
+            }
+        }
+    }
+
+    /// An [`AppState`] with `host` connected and owning `run-1`, plus a valid bearer header.
+    async fn connect(host: Arc<FakeHost>) -> (AppState, HeaderMap) {
+        let db = crate::db::connect("sqlite::memory:").await.unwrap();
+        crate::db::init(&db).await.unwrap();
+        let secret = "test-secret";
+        let state = AppState {
+            cfg: crate::config::Config {
+                bind_addr: "127.0.0.1:0".into(),
+                database_url: "sqlite::memory:".into(),
+                jwt_secret: secret.into(),
+                admin_username: "admin".into(),
+                admin_password_hash: String::new(),
+                store_raw_input: false,
+                redaction_extra_regex: Vec::new(),
+            },
+            db,
+            app_tx: tokio::sync::broadcast::channel(16).0,
+            jwt_encoding: jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+            jwt_decoding: jsonwebtoken::DecodingKey::from_secret(secret.as_bytes()),
+            redactor: Arc::new(relay_protocol::redaction::Redactor::new(&[]).unwrap()),
+            hosts_tx: Default::default(),
+            run_to_host: Default::default(),
+            rpc_waiters: Default::default(),
+            web_dist_dir: None,
+            server_log_path: None,
+        };
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(16);
+        state.hosts_tx.write().await.insert("host-1".into(), tx);
+        state
+            .run_to_host
+            .write()
+            .await
+            .insert("run-1".into(), "host-1".into());
+        let waiters = state.rpc_waiters.clone();
+        tokio::spawn(async move {
+            while let Some(Message::Text(text)) = rx.recv().await {
+                let env: WsEnvelope = serde_json::from_str(&text).unwrap();
+                let mut resp = host.answer(&env.r#type, &env.data);
+                let request_id = env.data["request_id"].as_str().unwrap();
+                resp["request_id"] = json!(request_id);
+                if let Some(waiter) = waiters.lock().await.remove(request_id) {
+                    let _ = waiter.send(resp);
+                }
+            }
+        });
+
+        let claims = crate::Claims {
+            sub: "admin".into(),
+            exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+        };
+        let token =
+            jsonwebtoken::encode(&Default::default(), &claims, &state.jwt_encoding).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+        );
+        (state, headers)
+    }
+
+    fn query(path: &str, offset: Option<u64>) -> Query<FileQuery> {
+        Query(FileQuery {
+            path: path.into(),
+            offset,
+        })
+    }
+
+    /// A body delivered in small frames, like a client streaming a large file.
+    fn streamed(bytes: &[u8]) -> Body {
+        let frames: Vec<Result<Bytes, std::io::Error>> = bytes
+            .chunks(64 * 1024)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        Body::from_stream(futures_util::stream::iter(frames))
+    }
+
+    fn transfer_ids(host: &FakeHost) -> Vec<String> {
+        host.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| r["transfer_id"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn upload_assembles_chunks_and_download_streams_them_back() {
+        let host = Arc::new(FakeHost::default());
+        let (state, headers) = connect(host.clone()).await;
+        let file: Vec<u8> = (0..2 * CHUNK + 5).map(|i| (i % 251) as u8).collect();
+
+        let resp = http_upload(
+            State(state.clone()),
+            headers.clone(),
+            Path("run-1".into()),
+            query("a.bin", None),
+            streamed(&file),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(host.files.lock().unwrap()["a.bin"], file);
+        {
+            let requests = host.requests.lock().unwrap();
+            let offsets: Vec<u64> = requests
+                .iter()
+                .map(|r| r["offset"].as_u64().unwrap())
+                .collect();
+            assert_eq!(offsets, [0, CHUNK as u64, 2 * CHUNK as u64]);
+            // Only the final chunk carries the hash of the whole file.
+            let hashed: Vec<bool> = requests.iter().map(|r| r["sha256"].is_string()).collect();
+            assert_eq!(hashed, [false, false, true]);
+        }
+        let ids = transfer_ids(&host);
+        assert!(!ids[0].is_empty() && ids.iter().all(|id| *id == ids[0]));
+        host.requests.lock().unwrap().clear();
+
+        let resp = http_download(
+            State(state),
+            headers,
+            Path("run-1".into()),
+            query("a.bin", None),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), file.as_slice());
+        let dl_ids = transfer_ids(&host);
+        assert_eq!(dl_ids.len(), 3);
+        assert!(dl_ids.iter().all(|id| *id == dl_ids[0] && *id != ids[0]));
+    }
+
+    #[tokio::test]
+    async fn upload_refuses_sha256_mismatches_and_oversized_files() {
+        let host = Arc::new(FakeHost::default());
+        let (state, headers) = connect(host.clone()).await;
+        let upload = |headers: HeaderMap, offset: Option<u64>, body: Body| {
+            http_upload(
+                State(state.clone()),
+                headers,
+                Path("run-1".into()),
+                query("a.bin", offset),
+                body,
+            )
+        };
+
+        let mut wrong = headers.clone();
+        wrong.insert("x-relay-sha256", HeaderValue::from_static("00"));
+        let resp = upload(wrong.clone(), None, streamed(b"hello")).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(host.files.lock().unwrap().is_empty());
+
+        // A resumed upload is checked by the host against the header.
+        host.staged
+            .lock()
+            .unwrap()
+            .insert("a.bin".into(), b"hel".to_vec());
+        let resp = upload(wrong, Some(3), streamed(b"lo")).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(host.files.lock().unwrap().is_empty());
+        let resp = upload(headers.clone(), Some(3), streamed(b"lo")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let mut huge = headers.clone();
+        huge.insert(
+            header::CONTENT_LENGTH,
+            HeaderValue::from_str(&(MAX_FILE_SIZE + 1).to_string()).unwrap(),
+        );
+        let resp = upload(huge, None, Body::empty()).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let mut near_limit = headers;
+        near_limit.insert(
+            "x-relay-sha256",
+            HeaderValue::from_static("0000000000000000"),
+        );
+        let resp = upload(near_limit, Some(MAX_FILE_SIZE - 1), streamed(b"ab")).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(host.requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn download_fails_when_the_file_hash_does_not_match() {
+        let host = Arc::new(FakeHost {
+            bad_sha: true,
+            ..Default::default()
+        });
+        host.files
+            .lock()
+            .unwrap()
+            .insert("a.bin".into(), vec![7; CHUNK + 1]);
+        let (state, headers) = connect(host).await;
+        let resp = http_download(
+            State(state),
+            headers,
+            Path("run-1".into()),
+            query("a.bin", None),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let err = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("sha256 mismatch"), "{err}");
+    }
+}
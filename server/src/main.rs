@@ -1,5 +1,8 @@
 mod config;
 mod db;
+mod files;
+mod profiles;
+mod scheduler;
 
 use argon2::PasswordHasher;
 use argon2::PasswordVerifier;
@@ -55,6 +58,8 @@ struct AppState {
     redactor: Arc<Redactor>,
     hosts_tx: Arc<RwLock<HashMap<String, mpsc::Sender<Message>>>>,
     run_to_host: Arc<RwLock<HashMap<String, String>>>,
+    /// `rpc.response` waiters for RPCs the server itself sends (`files::host_rpc`), by request_id.
+    rpc_waiters: Arc<tokio::sync::Mutex<HashMap<String, tokio::sync::oneshot::Sender<JsonValue>>>>,
     web_dist_dir: Option<std::path::PathBuf>,
     server_log_path: Option<std::path::PathBuf>,
 }
@@ -230,6 +235,63 @@ async fn http_list_runs(State(state): State<AppState>, headers: HeaderMap) -> im
     }
 }
 
+#[derive(Deserialize)]
+struct UsageQuery {
+    #[serde(default)]
+    group_by: Option<String>,
+}
+
+/// Token/cost totals across runs, grouped by `host` (default), `tool`, `model` or `day`.
+async fn http_usage(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<UsageQuery>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    let group_by = q.group_by.as_deref().unwrap_or("host");
+    if !matches!(group_by, "host" | "tool" | "model" | "day") {
+        return (
+            StatusCode::BAD_REQUEST,
+            "group_by must be host|tool|model|day",
+        )
+            .into_response();
+    }
+    let usage = async {
+        let groups = db::usage_by(&state.db, group_by).await?;
+        let runs = db::usage_runs(&state.db).await?;
+        anyhow::Ok((groups, runs))
+    };
+    match usage.await {
+        Ok((groups, runs)) => {
+            let mut totals = serde_json::json!({
+                "runs": runs, "input_tokens": 0, "output_tokens": 0, "reasoning_tokens": 0,
+                "cache_read_tokens": 0, "cache_write_tokens": 0, "cost_usd": 0.0,
+            });
+            for g in &groups {
+                for (k, v) in [
+                    ("input_tokens", g.input_tokens),
+                    ("output_tokens", g.output_tokens),
+                    ("reasoning_tokens", g.reasoning_tokens),
+                    ("cache_read_tokens", g.cache_read_tokens),
+                    ("cache_write_tokens", g.cache_write_tokens),
+                ] {
+                    totals[k] = (totals[k].as_i64().unwrap_or(0) + v).into();
+                }
+                totals["cost_usd"] =
+                    (totals["cost_usd"].as_f64().unwrap_or(0.0) + g.cost_usd).into();
+            }
+            Json(serde_json::json!({ "group_by": group_by, "groups": groups, "totals": totals }))
+                .into_response()
+        }
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
 async fn http_list_sessions(
     State(state): State<AppState>,
     headers: HeaderMap,
@@ -281,6 +343,356 @@ async fn http_get_session(
     }
 }
 
+async fn http_get_session_todos(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(session_id): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    match db::get_run_todos(&state.db, &session_id).await {
+        Ok(Some(row)) => {
+            let todos = row
+                .todos_json
+                .and_then(|s| serde_json::from_str::<JsonValue>(&s).ok())
+                .unwrap_or_else(|| serde_json::json!([]));
+            Json(serde_json::json!({
+                "session_id": session_id,
+                "todos": todos,
+                "updated_at": row.todos_updated_at,
+            }))
+            .into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "unknown session_id").into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+fn profile_row_json(row: db::ProfileRow) -> Result<JsonValue, String> {
+    let mut v: JsonValue = serde_json::from_str(&row.data_json).map_err(|e| e.to_string())?;
+    if let JsonValue::Object(map) = &mut v {
+        map.insert("name".into(), JsonValue::String(row.name));
+        map.insert("created_at".into(), JsonValue::String(row.created_at));
+        map.insert("updated_at".into(), JsonValue::String(row.updated_at));
+    }
+    Ok(v)
+}
+
+async fn load_profile(state: &AppState, name: &str) -> Result<profiles::Profile, String> {
+    let row = db::get_profile(&state.db, name)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("unknown profile `{name}`"))?;
+    let mut profile: profiles::Profile =
+        serde_json::from_str(&row.data_json).map_err(|e| e.to_string())?;
+    profile.name = row.name;
+    Ok(profile)
+}
+
+async fn save_profile(
+    state: &AppState,
+    mut profile: profiles::Profile,
+    name: Option<String>,
+    create_only: bool,
+) -> axum::response::Response {
+    if let Some(name) = name {
+        profile.name = name;
+    }
+    if let Err(err) = profile.validate() {
+        return (StatusCode::BAD_REQUEST, err).into_response();
+    }
+    if create_only {
+        match db::get_profile(&state.db, &profile.name).await {
+            Ok(Some(_)) => return (StatusCode::CONFLICT, "profile already exists").into_response(),
+            Ok(None) => {}
+            Err(err) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+            }
+        }
+    }
+    let data_json = match serde_json::to_string(&profile) {
+        Ok(s) => s,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let created = match db::upsert_profile(&state.db, &profile.name, &data_json, Utc::now()).await {
+        Ok(created) => created,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    match db::get_profile(&state.db, &profile.name).await {
+        Ok(Some(row)) => match profile_row_json(row) {
+            Ok(v) => {
+                let status = if created {
+                    StatusCode::CREATED
+                } else {
+                    StatusCode::OK
+                };
+                (status, Json(v)).into_response()
+            }
+            Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err).into_response(),
+        },
+        Ok(None) => (StatusCode::INTERNAL_SERVER_ERROR, "profile vanished").into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn http_list_profiles(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    match db::list_profiles(&state.db).await {
+        Ok(rows) => {
+            let out: Result<Vec<_>, _> = rows.into_iter().map(profile_row_json).collect();
+            match out {
+                Ok(out) => Json(out).into_response(),
+                Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err).into_response(),
+            }
+        }
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn http_create_profile(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(profile): Json<profiles::Profile>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    save_profile(&state, profile, None, true).await
+}
+
+async fn http_get_profile(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    match db::get_profile(&state.db, &name).await {
+        Ok(Some(row)) => match profile_row_json(row) {
+            Ok(v) => Json(v).into_response(),
+            Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err).into_response(),
+        },
+        Ok(None) => (StatusCode::NOT_FOUND, "unknown profile").into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn http_put_profile(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(name): axum::extract::Path<String>,
+    Json(profile): Json<profiles::Profile>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    save_profile(&state, profile, Some(name), false).await
+}
+
+async fn http_delete_profile(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    match db::delete_profile(&state.db, &name).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "unknown profile").into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn http_list_jobs(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    match db::list_jobs(&state.db).await {
+        Ok(rows) => {
+            let jobs: Vec<scheduler::Job> = rows.into_iter().map(Into::into).collect();
+            Json(jobs).into_response()
+        }
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn save_job(
+    state: &AppState,
+    job_id: String,
+    spec: scheduler::JobSpec,
+    created_at: Option<String>,
+) -> axum::response::Response {
+    let row = match scheduler::job_row_from_spec(job_id, spec, created_at.clone(), Utc::now()) {
+        Ok(row) => row,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    if let Err(err) = db::upsert_job(&state.db, &row).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+    match db::get_job(&state.db, &row.id).await {
+        Ok(Some(row)) => {
+            let status = if created_at.is_none() {
+                StatusCode::CREATED
+            } else {
+                StatusCode::OK
+            };
+            (status, Json(scheduler::Job::from(row))).into_response()
+        }
+        Ok(None) => (StatusCode::INTERNAL_SERVER_ERROR, "job vanished").into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn http_create_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(spec): Json<scheduler::JobSpec>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    save_job(&state, uuid::Uuid::new_v4().to_string(), spec, None).await
+}
+
+async fn http_get_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(job_id): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    match db::get_job(&state.db, &job_id).await {
+        Ok(Some(row)) => Json(scheduler::Job::from(row)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "unknown job_id").into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn http_put_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(job_id): axum::extract::Path<String>,
+    Json(spec): Json<scheduler::JobSpec>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    let existing = match db::get_job(&state.db, &job_id).await {
+        Ok(Some(row)) => row,
+        Ok(None) => return (StatusCode::NOT_FOUND, "unknown job_id").into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    save_job(&state, job_id, spec, Some(existing.created_at)).await
+}
+
+async fn http_delete_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(job_id): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    match db::delete_job(&state.db, &job_id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "unknown job_id").into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct JobHistoryQuery {
+    #[serde(default)]
+    limit: Option<i64>,
+}
+
+async fn http_job_history(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(job_id): axum::extract::Path<String>,
+    Query(q): Query<JobHistoryQuery>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    match db::list_job_firings(&state.db, &job_id, q.limit.unwrap_or(50)).await {
+        Ok(rows) => Json(rows).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// Fires a job immediately (outside its schedule); the firing shows up in its history.
+async fn http_run_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(job_id): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    if validate_jwt(&state, &token).is_err() {
+        return (StatusCode::UNAUTHORIZED, "invalid token").into_response();
+    }
+    let job = match db::get_job(&state.db, &job_id).await {
+        Ok(Some(row)) => row,
+        Ok(None) => return (StatusCode::NOT_FOUND, "unknown job_id").into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let data = match scheduler::expand_job_run(&state, &job).await {
+        Ok(data) => data,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    match scheduler::fire(&state, &job, Utc::now(), &data).await {
+        Ok(request_id) => Json(serde_json::json!({ "request_id": request_id })).into_response(),
+        Err(err) => (StatusCode::BAD_GATEWAY, err).into_response(),
+    }
+}
+
 #[derive(Serialize)]
 struct HostInfo {
     id: String,
@@ -720,7 +1132,7 @@ async fn handle_app_socket(state: AppState, mut socket: WebSocket) {
                                 continue;
                             }
                         } else if !subscribed_runs.is_empty() {
-                            let is_high_volume = env.r#type == "tool.call" || env.r#type == "tool.result";
+                            let is_high_volume = matches!(env.r#type.as_str(), "tool.call" | "tool.output" | "tool.result" | "fs.changed");
                             if is_high_volume {
                                 let Some(run_id) = env.run_id.as_deref() else { continue; };
                                 let Some(_) = subscribed_runs.get(run_id) else {
@@ -741,7 +1153,7 @@ async fn handle_app_socket(state: AppState, mut socket: WebSocket) {
                 let Some(Ok(incoming)) = incoming else { break; };
                 match incoming {
                     Message::Text(text) => {
-                        let Ok(env) = serde_json::from_str::<WsEnvelope>(&text) else { continue; };
+                        let Ok(mut env) = serde_json::from_str::<WsEnvelope>(&text) else { continue; };
                         let is_rpc = env.r#type.starts_with("rpc.");
                         if env.r#type != "run.send_input"
                             && env.r#type != "run.send_stdin"
@@ -787,11 +1199,41 @@ async fn handle_app_socket(state: AppState, mut socket: WebSocket) {
                             continue;
                         }
 
+                        if env.r#type == "rpc.run.start"
+                            && let Some(name) = env.data.get("profile").and_then(|v| v.as_str()).map(|s| s.to_string())
+                        {
+                            let expanded = match load_profile(&state, &name).await {
+                                Ok(profile) => profile.expand_run_start(&env.data),
+                                Err(err) => Err(err),
+                            };
+                            match expanded {
+                                Ok(data) => env.data = data,
+                                Err(err) => {
+                                    let resp = WsEnvelope::new(
+                                        "rpc.response",
+                                        serde_json::json!({
+                                            "request_id": env.data.get("request_id").cloned().unwrap_or(JsonValue::Null),
+                                            "ok": false,
+                                            "rpc_type": env.r#type,
+                                            "error": err,
+                                        }),
+                                    );
+                                    if let Ok(text) = serde_json::to_string(&resp)
+                                        && socket.send(Message::Text(text)).await.is_err()
+                                    {
+                                        break;
+                                    }
+                                    continue;
+                                }
+                            }
+                        }
+
                         let (host_id, run_id) = if env.r#type == "rpc.run.start"
                             || env.r#type == "rpc.host.info"
                             || env.r#type == "rpc.host.doctor"
                             || env.r#type == "rpc.host.capabilities"
                             || env.r#type == "rpc.host.logs.tail"
+                            || env.r#type.starts_with("rpc.opencode.")
                         {
                             let host_id =
                                 env.data.get("host_id").and_then(|v| v.as_str()).map(|s| s.to_string());
@@ -937,6 +1379,15 @@ ON CONFLICT(id) DO UPDATE SET last_seen_at=excluded.last_seen_at
                         .await;
                     last_seen_written_at = now_inst;
                 }
+                if env.r#type == "rpc.response"
+                    && let Some(request_id) = env.data.get("request_id").and_then(|v| v.as_str())
+                {
+                    let waiter = state.rpc_waiters.lock().await.remove(request_id);
+                    if let Some(waiter) = waiter {
+                        let _ = waiter.send(env.data);
+                        continue;
+                    }
+                }
                 let run_id = env.run_id.clone().unwrap_or_else(|| "unknown".into());
                 let seq = env.seq;
 
@@ -970,6 +1421,7 @@ ON CONFLICT(id) DO UPDATE SET last_seen_at=excluded.last_seen_at
                         env.ts,
                     )
                     .await;
+                    let _ = db::link_run_to_job(&state.db, &run_id).await;
                 } else if env.r#type == "run.awaiting_input" {
                     let has_request_id = env
                         .data
@@ -1030,9 +1482,14 @@ ON CONFLICT(id) DO UPDATE SET last_seen_at=excluded.last_seen_at
                         )
                         .await;
                     }
-                } else if env.r#type == "rpc.response" && run_id != "unknown" {
-                    let mut map = state.run_to_host.write().await;
-                    map.insert(run_id.clone(), host_id.clone());
+                } else if env.r#type == "rpc.response" {
+                    if run_id != "unknown" {
+                        let mut map = state.run_to_host.write().await;
+                        map.insert(run_id.clone(), host_id.clone());
+                    }
+                    if env.data.get("rpc_type").and_then(|v| v.as_str()) == Some("rpc.run.start") {
+                        scheduler::on_run_start_response(&state, &env).await;
+                    }
                 }
 
                 if let Some(opencode_session_id) = env
@@ -1066,8 +1523,20 @@ ON CONFLICT(id) DO UPDATE SET last_seen_at=excluded.last_seen_at
                 // the DB with an "unknown" run_id.
                 let should_persist = !(env.run_id.is_none() && env.r#type == "rpc.response");
                 if should_persist {
-                    let data_json = serde_json::to_string(&env.data).ok();
-                    let _ = db::insert_event(
+                    // Downloaded file chunks are for the requester only, not the event log.
+                    let data_json = if env.data.get("rpc_type").and_then(|v| v.as_str())
+                        == Some("rpc.fs.download")
+                    {
+                        let mut data = env.data.clone();
+                        if let Some(result) = data.get_mut("result").and_then(|v| v.as_object_mut())
+                        {
+                            result.remove("data");
+                        }
+                        serde_json::to_string(&data).ok()
+                    } else {
+                        serde_json::to_string(&env.data).ok()
+                    };
+                    let inserted = db::insert_event(
                         &state.db,
                         &run_id,
                         seq,
@@ -1082,6 +1551,19 @@ ON CONFLICT(id) DO UPDATE SET last_seen_at=excluded.last_seen_at
                         data_json.as_deref(),
                     )
                     .await;
+                    // Count each usage event once, even when the host replays its spool.
+                    if env.r#type == "run.usage" && matches!(inserted, Ok(true)) {
+                        let usage = db::RunUsage::from_event(&env.data);
+                        let _ = db::add_run_usage(&state.db, &run_id, env.ts, &usage).await;
+                    }
+                    if env.r#type == "run.todos"
+                        && matches!(inserted, Ok(true))
+                        && let Some(todos) = env.data.get("todos").filter(|v| v.is_array())
+                    {
+                        let _ =
+                            db::set_run_todos(&state.db, &run_id, seq, &todos.to_string(), env.ts)
+                                .await;
+                    }
                 }
 
                 // Ack to host for spool replay.
@@ -1340,6 +1822,7 @@ async fn main() -> anyhow::Result<()> {
         redactor,
         hosts_tx: Arc::new(RwLock::new(HashMap::new())),
         run_to_host: Arc::new(RwLock::new(HashMap::new())),
+        rpc_waiters: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
         web_dist_dir: None,
         server_log_path,
     };
@@ -1372,6 +1855,9 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
+    // Scheduled jobs (`/jobs`).
+    tokio::spawn(scheduler::run(state.clone()));
+
     let app = Router::new()
         .route("/health", get(health))
         .route("/auth/login", post(login))
@@ -1379,6 +1865,24 @@ async fn main() -> anyhow::Result<()> {
         .route("/sessions", get(http_list_sessions))
         .route("/sessions/recent", get(http_list_recent_sessions))
         .route("/hosts", get(http_list_hosts))
+        .route("/usage", get(http_usage))
+        .route(
+            "/profiles",
+            get(http_list_profiles).post(http_create_profile),
+        )
+        .route(
+            "/profiles/:name",
+            get(http_get_profile)
+                .put(http_put_profile)
+                .delete(http_delete_profile),
+        )
+        .route("/jobs", get(http_list_jobs).post(http_create_job))
+        .route(
+            "/jobs/:job_id",
+            get(http_get_job).put(http_put_job).delete(http_delete_job),
+        )
+        .route("/jobs/:job_id/history", get(http_job_history))
+        .route("/jobs/:job_id/run", post(http_run_job))
         .route("/server/logs/tail", get(http_server_logs_tail))
         .route("/runs/:run_id/messages", get(http_list_messages))
         .route("/sessions/:session_id", get(http_get_session))
@@ -1386,7 +1890,12 @@ async fn main() -> anyhow::Result<()> {
             "/sessions/:session_id/messages",
             get(http_list_session_messages),
         )
+        .route("/sessions/:session_id/todos", get(http_get_session_todos))
         .route("/runs/:run_id/input", post(http_send_input))
+        .route(
+            "/runs/:run_id/files",
+            get(files::http_download).put(files::http_upload),
+        )
         .route("/ws/app", get(ws_app))
         .route("/ws/host", get(ws_host))
         .fallback(http_static_fallback)
@@ -1432,6 +1941,163 @@ mod tests {
         assert!(err.to_string().contains("mismatch"));
     }
 
+    #[tokio::test]
+    async fn job_firing_links_run_to_job() {
+        let db = db::connect("sqlite::memory:").await.unwrap();
+        db::init(&db).await.unwrap();
+
+        let spec: scheduler::JobSpec = serde_json::from_value(serde_json::json!({
+            "name": "dependency-audit",
+            "schedule": "0 7 * * MON-FRI",
+            "run": { "host_id": "build-1", "cwd": "~/repos/api", "initial_prompt": "audit deps" },
+        }))
+        .unwrap();
+        let row = scheduler::job_row_from_spec("job-1".into(), spec, None, Utc::now()).unwrap();
+        assert!(row.next_run_at.is_some());
+        db::upsert_job(&db, &row).await.unwrap();
+
+        let ts = Utc::now();
+        db::insert_job_firing(
+            &db,
+            db::NewJobFiring {
+                job_id: "job-1",
+                scheduled_for: ts,
+                fired_at: Some(ts),
+                status: "dispatched",
+                request_id: Some("req-1"),
+                host_id: Some("build-1"),
+                error: None,
+            },
+        )
+        .await
+        .unwrap();
+        // run.started may arrive before the rpc.response that carries the run id.
+        db::upsert_run_started(&db, "run-1", "build-1", "opencode", None, "/r", ts)
+            .await
+            .unwrap();
+        db::link_run_to_job(&db, "run-1").await.unwrap();
+        let job_id = db::complete_job_firing(&db, "req-1", Some("run-1"), None)
+            .await
+            .unwrap();
+        assert_eq!(job_id.as_deref(), Some("job-1"));
+        assert_eq!(
+            db::complete_job_firing(&db, "other", None, Some("x"))
+                .await
+                .unwrap(),
+            None
+        );
+
+        let run = db::get_run(&db, "run-1").await.unwrap().unwrap();
+        assert_eq!(run.job_id.as_deref(), Some("job-1"));
+        let history = db::list_job_firings(&db, "job-1", 10).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].status, "started");
+        assert_eq!(history[0].run_status.as_deref(), Some("running"));
+    }
+
+    #[tokio::test]
+    async fn usage_events_aggregate_once_per_seq() {
+        let db = db::connect("sqlite::memory:").await.unwrap();
+        db::init(&db).await.unwrap();
+
+        let ts = Utc::now();
+        db::upsert_run_started(&db, "run-1", "host-1", "opencode", None, "/r", ts)
+            .await
+            .unwrap();
+        db::upsert_run_started(&db, "run-2", "host-2", "codex", None, "/r", ts)
+            .await
+            .unwrap();
+        let usage = serde_json::json!({
+            "input_tokens": 100, "output_tokens": 20, "cache_read_tokens": 5,
+            "cost_usd": 0.5, "model": "prov/m1"
+        });
+        // The second insert is a spool replay of the same seq and must not count again.
+        let ts = chrono::DateTime::parse_from_rfc3339("2026-01-01T23:59:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        for _ in 0..2 {
+            let inserted = db::insert_event(
+                &db,
+                "run-1",
+                Some(3),
+                ts,
+                "run.usage",
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+            if inserted {
+                db::add_run_usage(&db, "run-1", ts, &db::RunUsage::from_event(&usage))
+                    .await
+                    .unwrap();
+            }
+        }
+        // Same run, after midnight and on another model.
+        let later = ts + chrono::Duration::minutes(5);
+        let switched =
+            serde_json::json!({ "input_tokens": 10, "cost_usd": 0.25, "model": "prov/m2" });
+        db::add_run_usage(&db, "run-1", later, &db::RunUsage::from_event(&switched))
+            .await
+            .unwrap();
+
+        let run = db::get_run(&db, "run-1").await.unwrap().unwrap();
+        assert_eq!(run.usage_input_tokens, Some(110));
+        assert_eq!(run.usage_model.as_deref(), Some("prov/m2"));
+
+        let by_model = db::usage_by(&db, "model").await.unwrap();
+        assert_eq!(by_model.len(), 2);
+        assert_eq!(by_model[0].key, "prov/m1");
+        assert_eq!(by_model[0].runs, 1);
+        assert_eq!(by_model[0].input_tokens, 100);
+        assert_eq!(by_model[0].cost_usd, 0.5);
+        assert_eq!(by_model[1].key, "prov/m2");
+        assert_eq!(by_model[1].input_tokens, 10);
+        // run-1 is in both model groups but is one run in the totals.
+        assert_eq!(by_model.iter().map(|g| g.runs).sum::<i64>(), 2);
+        assert_eq!(db::usage_runs(&db).await.unwrap(), 1);
+
+        let by_day = db::usage_by(&db, "day").await.unwrap();
+        let days: Vec<&str> = by_day.iter().map(|r| r.key.as_str()).collect();
+        assert_eq!(days, ["2026-01-01", "2026-01-02"]);
+
+        let by_host = db::usage_by(&db, "host").await.unwrap();
+        assert_eq!(by_host.len(), 1);
+        assert_eq!(by_host[0].runs, 1);
+        assert_eq!(by_host[0].cost_usd, 0.75);
+        assert!(db::usage_by(&db, "nope").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn run_todos_keep_latest_snapshot() {
+        let db = db::connect("sqlite::memory:").await.unwrap();
+        db::init(&db).await.unwrap();
+
+        let ts = Utc::now();
+        db::upsert_run_started(&db, "run-1", "host-1", "opencode", None, "/r", ts)
+            .await
+            .unwrap();
+        let empty = db::get_run_todos(&db, "run-1").await.unwrap().unwrap();
+        assert!(empty.todos_json.is_none());
+
+        db::set_run_todos(&db, "run-1", Some(7), r#"[{"content":"b"}]"#, ts)
+            .await
+            .unwrap();
+        // An older snapshot arriving late (e.g. from a spool replay) must not win.
+        db::set_run_todos(&db, "run-1", Some(4), r#"[{"content":"a"}]"#, ts)
+            .await
+            .unwrap();
+        let row = db::get_run_todos(&db, "run-1").await.unwrap().unwrap();
+        assert_eq!(row.todos_json.as_deref(), Some(r#"[{"content":"b"}]"#));
+        assert!(row.todos_updated_at.is_some());
+        assert!(db::get_run_todos(&db, "nope").await.unwrap().is_none());
+    }
+
     #[tokio::test]
     async fn list_message_events_can_exclude_run_output() {
         let db = db::connect("sqlite::memory:").await.unwrap();
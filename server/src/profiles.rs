@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+
+/// A named, server-side launch template for `rpc.run.start`.
+///
+/// Every field except `name` is optional; fields present in the `rpc.run.start` request override
+/// the profile (env maps are merged, request keys win).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_prompt: Option<String>,
+    #[serde(default)]
+    pub permission_policy: PermissionPolicy,
+    /// Same shape as `rpc.run.start.sandbox` (`bool` or `{ network, hide_home }`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<JsonValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isolation: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PermissionPolicy {
+    /// Tools pre-approved for the run's session (e.g. `bash`, `edit`).
+    #[serde(default)]
+    pub allow_tools: Vec<String>,
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+fn valid_env_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Rejected by hostd as well: relay's own vars, loader hooks and anything that swaps the agent's
+/// config (and its permission policy), shell init or binaries.
+fn reserved_env_name(name: &str) -> bool {
+    const RESERVED: &[&str] = &[
+        "OPENCODE_PERMISSION",
+        "OPENCODE_CONFIG",
+        "OPENCODE_CONFIG_DIR",
+        "OPENCODE_CONFIG_CONTENT",
+        "CODEX_HOME",
+        "HOME",
+        "XDG_CONFIG_HOME",
+        "PATH",
+        "BASH_ENV",
+        "ENV",
+        "SHELLOPTS",
+        "BASHOPTS",
+        "PROMPT_COMMAND",
+    ];
+    name.starts_with("RELAY_")
+        || name.starts_with("LD_")
+        || name.starts_with("DYLD_")
+        || RESERVED.contains(&name)
+}
+
+fn non_empty(v: &Option<String>) -> bool {
+    v.as_deref().is_some_and(|s| !s.trim().is_empty())
+}
+
+impl Profile {
+    pub fn validate(&self) -> Result<(), String> {
+        if !valid_name(&self.name) {
+            return Err("profile name must be 1-64 chars of [A-Za-z0-9._-]".into());
+        }
+        for key in self.env.keys() {
+            if !valid_env_name(key) {
+                return Err(format!("invalid env var name `{key}`"));
+            }
+            if reserved_env_name(key) {
+                return Err(format!("env var `{key}` is reserved and cannot be set"));
+            }
+        }
+        if let Some(isolation) = self.isolation.as_deref()
+            && !matches!(isolation.trim(), "" | "none" | "worktree")
+        {
+            return Err(format!(
+                "invalid isolation `{isolation}` (expected none|worktree)"
+            ));
+        }
+        match &self.sandbox {
+            None | Some(JsonValue::Null) | Some(JsonValue::Bool(_)) => {}
+            Some(JsonValue::Object(obj)) => {
+                for (k, v) in obj {
+                    if !matches!(k.as_str(), "network" | "hide_home") {
+                        return Err(format!("unknown sandbox field `{k}`"));
+                    }
+                    if !v.is_boolean() {
+                        return Err(format!("sandbox.{k} must be a boolean"));
+                    }
+                }
+            }
+            Some(_) => return Err("sandbox must be a boolean or an object".into()),
+        }
+        if self
+            .permission_policy
+            .allow_tools
+            .iter()
+            .any(|t| t.trim().is_empty())
+        {
+            return Err("permission_policy.allow_tools must not contain empty names".into());
+        }
+        Ok(())
+    }
+
+    /// Expands the profile into the `rpc.run.start` data that is forwarded to hostd. The
+    /// `profile` key is dropped; request fields override the profile's.
+    pub fn expand_run_start(&self, request: &JsonValue) -> Result<JsonValue, String> {
+        let mut out = serde_json::Map::new();
+        let mut set_str = |key: &str, v: &Option<String>| {
+            if non_empty(v) {
+                out.insert(key.to_string(), JsonValue::from(v.clone()));
+            }
+        };
+        set_str("host_id", &self.host_id);
+        set_str("cwd", &self.cwd);
+        set_str("tool", &self.tool);
+        set_str("cmd", &self.cmd);
+        set_str("model", &self.model);
+        set_str("initial_prompt", &self.initial_prompt);
+        set_str("isolation", &self.isolation);
+        if let Some(sandbox) = self.sandbox.clone().filter(|v| !v.is_null()) {
+            out.insert("sandbox".into(), sandbox);
+        }
+        if !self.permission_policy.allow_tools.is_empty() {
+            out.insert(
+                "allow_tools".into(),
+                JsonValue::from(self.permission_policy.allow_tools.clone()),
+            );
+        }
+
+        let mut env: serde_json::Map<String, JsonValue> = self
+            .env
+            .iter()
+            .map(|(k, v)| (k.clone(), JsonValue::from(v.clone())))
+            .collect();
+        let Some(req) = request.as_object() else {
+            return Err("rpc.run.start data must be an object".into());
+        };
+        for (k, v) in req {
+            match k.as_str() {
+                "profile" => {}
+                "env" => match v {
+                    JsonValue::Null => {}
+                    JsonValue::Object(extra) => {
+                        for (ek, ev) in extra {
+                            if !valid_env_name(ek) {
+                                return Err(format!("invalid env var name `{ek}`"));
+                            }
+                            if !ev.is_string() {
+                                return Err(format!("env.{ek} must be a string"));
+                            }
+                            env.insert(ek.clone(), ev.clone());
+                        }
+                    }
+                    _ => return Err("env must be an object of strings".into()),
+                },
+                _ if v.is_null() => {}
+                _ => {
+                    out.insert(k.clone(), v.clone());
+                }
+            }
+        }
+        if !env.is_empty() {
+            out.insert("env".into(), JsonValue::Object(env));
+        }
+
+        let host_id = out.get("host_id").and_then(|v| v.as_str()).unwrap_or("");
+        if host_id.trim().is_empty() {
+            if self.name.is_empty() {
+                return Err("rpc.run.start requires host_id".into());
+            }
+            return Err(format!(
+                "profile `{}` has no host_id and the request did not provide one",
+                self.name
+            ));
+        }
+        if !out.contains_key("tool") {
+            out.insert("tool".into(), JsonValue::from("opencode"));
+        }
+        Ok(JsonValue::Object(out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn expand_merges_profile_and_request() {
+        let profile: Profile = serde_json::from_value(json!({
+            "name": "nightly-refactor",
+            "host_id": "host-1",
+            "cwd": "/work/repo",
+            "tool": "codex",
+            "model": "m1",
+            "env": { "RUST_LOG": "info", "CI": "1" },
+            "initial_prompt": "refactor the parser",
+            "permission_policy": { "allow_tools": ["bash"] },
+            "isolation": "worktree",
+        }))
+        .unwrap();
+        profile.validate().unwrap();
+
+        let data = profile
+            .expand_run_start(&json!({
+                "request_id": "r1",
+                "profile": "nightly-refactor",
+                "model": "m2",
+                "env": { "CI": "0" },
+            }))
+            .unwrap();
+        assert_eq!(data["host_id"], "host-1");
+        assert_eq!(data["tool"], "codex");
+        assert_eq!(data["model"], "m2");
+        assert_eq!(data["request_id"], "r1");
+        assert_eq!(data["env"], json!({ "RUST_LOG": "info", "CI": "0" }));
+        assert_eq!(data["allow_tools"], json!(["bash"]));
+        assert_eq!(data["isolation"], "worktree");
+        assert!(data.get("profile").is_none());
+
+        let no_host = Profile {
+            name: "p".into(),
+            ..Default::default()
+        };
+        assert!(no_host.expand_run_start(&json!({})).is_err());
+        assert!(serde_json::from_value::<Profile>(json!({ "name": "p", "bogus": 1 })).is_err());
+        let bad = Profile {
+            name: "p".into(),
+            isolation: Some("vm".into()),
+            ..Default::default()
+        };
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_reserved_env() {
+        for key in [
+            "OPENCODE_PERMISSION",
+            "OPENCODE_CONFIG",
+            "BASH_ENV",
+            "PATH",
+            "LD_PRELOAD",
+            "RELAY_RUN_TOKEN",
+        ] {
+            let profile = Profile {
+                name: "p".into(),
+                env: BTreeMap::from([(key.to_string(), "x".to_string())]),
+                ..Default::default()
+            };
+            assert!(profile.validate().is_err(), "{key} should be rejected");
+        }
+    }
+}
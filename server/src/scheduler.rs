@@ -0,0 +1,535 @@
+use crate::{AppState, db, profiles};
+use axum::extract::ws::Message;
+use chrono::{DateTime, Duration, Utc};
+use relay_protocol::WsEnvelope;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::time::Duration as StdDuration;
+
+/// How often due jobs are checked.
+const TICK: StdDuration = StdDuration::from_secs(15);
+/// Occurrences older than this when the scheduler gets to them count as missed.
+const MISFIRE_GRACE_SECS: i64 = 120;
+/// Upper bound of occurrences examined (and of `run_all` catch-up firings) per job and tick.
+const MAX_CATCH_UP: usize = 20;
+
+/// What to do with occurrences that passed while relay-server was down or the target host was
+/// offline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissedPolicy {
+    /// Record them as `skipped` and wait for the next occurrence.
+    Skip,
+    /// Fire once for all of them (the most recent one), skip the rest.
+    #[default]
+    RunOnce,
+    /// Fire each of them (up to `MAX_CATCH_UP`).
+    RunAll,
+}
+
+impl MissedPolicy {
+    pub fn parse(s: Option<&str>) -> Result<Self, String> {
+        match s.map(str::trim).unwrap_or("") {
+            "" | "run_once" => Ok(Self::RunOnce),
+            "skip" => Ok(Self::Skip),
+            "run_all" => Ok(Self::RunAll),
+            other => Err(format!(
+                "invalid missed_policy `{other}` (expected skip|run_once|run_all)"
+            )),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Skip => "skip",
+            Self::RunOnce => "run_once",
+            Self::RunAll => "run_all",
+        }
+    }
+}
+
+/// Request body of `POST /jobs` / `PUT /jobs/:id`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobSpec {
+    pub name: String,
+    /// Standard 5-field cron expression (`min hour dom month dow`), e.g. `0 7 * * MON-FRI`.
+    pub schedule: String,
+    /// IANA time zone the schedule is evaluated in (default `UTC`).
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub missed_policy: Option<String>,
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// `rpc.run.start` data (may reference a saved launch profile via `profile`).
+    pub run: JsonValue,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Job {
+    pub id: String,
+    pub name: String,
+    pub schedule: String,
+    pub timezone: String,
+    pub missed_policy: String,
+    pub enabled: bool,
+    pub run: JsonValue,
+    pub next_run_at: Option<String>,
+    pub last_fired_at: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl From<db::JobRow> for Job {
+    fn from(row: db::JobRow) -> Self {
+        Self {
+            run: serde_json::from_str(&row.run_json).unwrap_or(JsonValue::Null),
+            id: row.id,
+            name: row.name,
+            schedule: row.schedule,
+            timezone: row.timezone,
+            missed_policy: row.missed_policy,
+            enabled: row.enabled,
+            next_run_at: row.next_run_at,
+            last_fired_at: row.last_fired_at,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+pub struct Schedule {
+    cron: croner::Cron,
+    tz: chrono_tz::Tz,
+}
+
+impl Schedule {
+    pub fn parse(expr: &str, timezone: &str) -> Result<Self, String> {
+        let cron = croner::Cron::new(expr.trim())
+            .parse()
+            .map_err(|e| format!("invalid schedule `{expr}`: {e}"))?;
+        let tz = timezone
+            .trim()
+            .parse::<chrono_tz::Tz>()
+            .map_err(|_| format!("unknown timezone `{timezone}`"))?;
+        Ok(Self { cron, tz })
+    }
+
+    /// First occurrence strictly after `t`.
+    pub fn next_after(&self, t: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.cron
+            .find_next_occurrence(&t.with_timezone(&self.tz), false)
+            .ok()
+            .map(|t| t.with_timezone(&Utc))
+    }
+}
+
+/// Outcome of looking at a due job at `now`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub fire: Vec<DateTime<Utc>>,
+    pub skip: Vec<DateTime<Utc>>,
+    /// Missed occurrences older than the last `MAX_CATCH_UP`, neither fired nor listed in `skip`.
+    pub dropped: usize,
+    pub next_run_at: Option<DateTime<Utc>>,
+}
+
+/// Splits the occurrences in `[due, now]` into ones to fire and ones to skip according to the
+/// missed-run policy, and computes the next occurrence after `now`.
+pub fn plan(
+    schedule: &Schedule,
+    due: DateTime<Utc>,
+    now: DateTime<Utc>,
+    policy: MissedPolicy,
+) -> Plan {
+    // Only the most recent `MAX_CATCH_UP` occurrences are kept; a long outage with a frequent
+    // schedule must not turn into an unbounded walk either.
+    let mut occurrences = std::collections::VecDeque::new();
+    let mut dropped = 0;
+    let mut t = Some(due);
+    let mut steps = 0;
+    while let Some(cur) = t
+        && cur <= now
+        && steps < 100_000
+    {
+        occurrences.push_back(cur);
+        if occurrences.len() > MAX_CATCH_UP {
+            occurrences.pop_front();
+            dropped += 1;
+        }
+        t = schedule.next_after(cur);
+        steps += 1;
+    }
+
+    let grace = Duration::seconds(MISFIRE_GRACE_SECS);
+    let (on_time, missed): (Vec<_>, Vec<_>) =
+        occurrences.into_iter().partition(|t| now - *t <= grace);
+
+    let mut out = Plan {
+        dropped,
+        next_run_at: schedule.next_after(now),
+        ..Default::default()
+    };
+    match policy {
+        MissedPolicy::Skip => out.skip = missed,
+        MissedPolicy::RunAll => out.fire = missed,
+        MissedPolicy::RunOnce => {
+            if on_time.is_empty() {
+                let mut missed = missed;
+                if let Some(last) = missed.pop() {
+                    out.fire.push(last);
+                }
+                out.skip = missed;
+            } else {
+                out.skip = missed;
+            }
+        }
+    }
+    out.fire.extend(on_time);
+    out
+}
+
+/// Validates a job spec and builds the row to store; `next_run_at` is computed from now.
+pub fn job_row_from_spec(
+    id: String,
+    spec: JobSpec,
+    created_at: Option<String>,
+    now: DateTime<Utc>,
+) -> Result<db::JobRow, String> {
+    let name = spec.name.trim().to_string();
+    if name.is_empty() {
+        return Err("name is required".into());
+    }
+    let timezone = spec
+        .timezone
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "UTC".to_string());
+    let schedule = Schedule::parse(&spec.schedule, &timezone)?;
+    let missed_policy = MissedPolicy::parse(spec.missed_policy.as_deref())?;
+    if !spec.run.is_object() {
+        return Err("run must be an object with rpc.run.start data".into());
+    }
+    // Catch obviously broken run data early; profiles are re-expanded at firing time.
+    if spec.run.get("profile").and_then(|v| v.as_str()).is_none() {
+        profiles::Profile::default().expand_run_start(&spec.run)?;
+    }
+    let enabled = spec.enabled.unwrap_or(true);
+    let now_s = now.to_rfc3339();
+    Ok(db::JobRow {
+        id,
+        name,
+        schedule: spec.schedule.trim().to_string(),
+        timezone,
+        missed_policy: missed_policy.as_str().to_string(),
+        enabled,
+        run_json: serde_json::to_string(&spec.run).map_err(|e| e.to_string())?,
+        next_run_at: enabled
+            .then(|| schedule.next_after(now))
+            .flatten()
+            .map(|t| t.to_rfc3339()),
+        last_fired_at: None,
+        created_at: created_at.unwrap_or_else(|| now_s.clone()),
+        updated_at: now_s,
+    })
+}
+
+/// Expands the job's `rpc.run.start` data (including a referenced profile).
+pub async fn expand_job_run(state: &AppState, job: &db::JobRow) -> Result<JsonValue, String> {
+    let run: JsonValue = serde_json::from_str(&job.run_json).map_err(|e| e.to_string())?;
+    match run.get("profile").and_then(|v| v.as_str()) {
+        Some(name) => crate::load_profile(state, name)
+            .await?
+            .expand_run_start(&run),
+        None => profiles::Profile::default().expand_run_start(&run),
+    }
+}
+
+fn parse_ts(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Sends `rpc.run.start` for one occurrence of `job` to its host. The firing is recorded as
+/// `dispatched` and completed once the host answers (see [`on_run_start_response`]), or recorded
+/// as `failed` right away when the host is offline.
+pub async fn fire(
+    state: &AppState,
+    job: &db::JobRow,
+    scheduled_for: DateTime<Utc>,
+    data: &JsonValue,
+) -> Result<String, String> {
+    let host_id = data
+        .get("host_id")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let tx = {
+        let hosts = state.hosts_tx.read().await;
+        hosts.get(&host_id).cloned()
+    };
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let mut data = data.clone();
+    if let JsonValue::Object(map) = &mut data {
+        map.insert("request_id".into(), JsonValue::String(request_id.clone()));
+    }
+    let mut cmd = WsEnvelope::new("rpc.run.start", data);
+    cmd.host_id = Some(host_id.clone());
+    let payload = serde_json::to_string(&cmd).map_err(|e| e.to_string())?;
+
+    db::insert_job_firing(
+        &state.db,
+        db::NewJobFiring {
+            job_id: &job.id,
+            scheduled_for,
+            fired_at: Some(Utc::now()),
+            status: if tx.is_some() { "dispatched" } else { "failed" },
+            request_id: Some(&request_id),
+            host_id: Some(&host_id),
+            error: tx.is_none().then_some("host offline"),
+        },
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    let sent = match tx {
+        Some(tx) => tx.send(Message::Text(payload)).await.is_ok(),
+        None => return Err("host offline".into()),
+    };
+    if !sent {
+        let _ = db::complete_job_firing(&state.db, &request_id, None, Some("host offline")).await;
+        return Err("host offline".into());
+    }
+    tracing::info!(job_id = %job.id, %host_id, %request_id, "scheduled run dispatched");
+    Ok(request_id)
+}
+
+async fn tick_job(state: &AppState, job: &db::JobRow, now: DateTime<Utc>) -> anyhow::Result<()> {
+    let Some(due) = job.next_run_at.as_deref().and_then(parse_ts) else {
+        return Ok(());
+    };
+    if due > now {
+        return Ok(());
+    }
+    let schedule = match Schedule::parse(&job.schedule, &job.timezone) {
+        Ok(s) => s,
+        Err(err) => {
+            db::insert_job_firing(
+                &state.db,
+                db::NewJobFiring {
+                    job_id: &job.id,
+                    scheduled_for: due,
+                    fired_at: None,
+                    status: "failed",
+                    request_id: None,
+                    host_id: None,
+                    error: Some(&err),
+                },
+            )
+            .await?;
+            db::set_job_next_run(&state.db, &job.id, None, None).await?;
+            return Ok(());
+        }
+    };
+    let policy = MissedPolicy::parse(Some(&job.missed_policy)).unwrap_or_default();
+
+    let data = match expand_job_run(state, job).await {
+        Ok(data) => data,
+        Err(err) => {
+            let next = schedule.next_after(now);
+            db::insert_job_firing(
+                &state.db,
+                db::NewJobFiring {
+                    job_id: &job.id,
+                    scheduled_for: due,
+                    fired_at: None,
+                    status: "failed",
+                    request_id: None,
+                    host_id: None,
+                    error: Some(&err),
+                },
+            )
+            .await?;
+            db::set_job_next_run(&state.db, &job.id, next, None).await?;
+            return Ok(());
+        }
+    };
+    let host_id = data.get("host_id").and_then(|v| v.as_str()).unwrap_or("");
+    let online = state.hosts_tx.read().await.contains_key(host_id);
+    if !online {
+        // Keep `next_run_at` in the past: once the host reconnects the missed policy decides.
+        return Ok(());
+    }
+
+    let plan = plan(&schedule, due, now, policy);
+    if plan.dropped > 0 {
+        // One history entry for the occurrences too old to catch up on, starting at `due`.
+        let note = format!(
+            "{} missed occurrences were dropped (only the last {MAX_CATCH_UP} are caught up on)",
+            plan.dropped
+        );
+        tracing::warn!(job_id = %job.id, dropped = plan.dropped, "scheduler: {note}");
+        db::insert_job_firing(
+            &state.db,
+            db::NewJobFiring {
+                job_id: &job.id,
+                scheduled_for: due,
+                fired_at: None,
+                status: "skipped",
+                request_id: None,
+                host_id: None,
+                error: Some(&note),
+            },
+        )
+        .await?;
+    }
+    for t in &plan.skip {
+        db::insert_job_firing(
+            &state.db,
+            db::NewJobFiring {
+                job_id: &job.id,
+                scheduled_for: *t,
+                fired_at: None,
+                status: "skipped",
+                request_id: None,
+                host_id: None,
+                error: None,
+            },
+        )
+        .await?;
+    }
+    let mut last_fired = None;
+    for t in &plan.fire {
+        match fire(state, job, *t, &data).await {
+            Ok(_) => last_fired = Some(now),
+            Err(err) => tracing::warn!(job_id = %job.id, "scheduler: dispatch failed: {err}"),
+        }
+    }
+    db::set_job_next_run(&state.db, &job.id, plan.next_run_at, last_fired).await?;
+    Ok(())
+}
+
+/// Scheduler loop: fires due jobs every [`TICK`].
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(TICK);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let jobs = match db::list_jobs(&state.db).await {
+            Ok(jobs) => jobs,
+            Err(err) => {
+                tracing::warn!("scheduler: list jobs failed: {err}");
+                continue;
+            }
+        };
+        let now = Utc::now();
+        for job in jobs.iter().filter(|j| j.enabled) {
+            if let Err(err) = tick_job(&state, job, now).await {
+                tracing::warn!(job_id = %job.id, "scheduler: tick failed: {err}");
+            }
+        }
+    }
+}
+
+/// Completes the job firing behind a host `rpc.response` to `rpc.run.start`, if any.
+pub async fn on_run_start_response(state: &AppState, env: &WsEnvelope) {
+    let Some(request_id) = env.data.get("request_id").and_then(|v| v.as_str()) else {
+        return;
+    };
+    let ok = env
+        .data
+        .get("ok")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let run_id = env.run_id.clone().or_else(|| {
+        env.data
+            .get("result")
+            .and_then(|r| r.get("run_id"))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+    });
+    let error = (!ok).then(|| {
+        env.data
+            .get("error")
+            .and_then(|v| v.as_str())
+            .unwrap_or("rpc.run.start failed")
+            .to_string()
+    });
+    if let Err(err) =
+        db::complete_job_firing(&state.db, request_id, run_id.as_deref(), error.as_deref()).await
+    {
+        tracing::warn!(%request_id, "scheduler: record firing failed: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> DateTime<Utc> {
+        parse_ts(s).unwrap()
+    }
+
+    #[test]
+    fn plan_applies_missed_policy() {
+        // Weekdays at 07:00 in Berlin (05:00 UTC in summer).
+        let schedule = Schedule::parse("0 7 * * MON-FRI", "Europe/Berlin").unwrap();
+        assert_eq!(
+            schedule.next_after(ts("2026-06-05T06:00:00Z")), // Friday
+            Some(ts("2026-06-08T05:00:00Z"))                 // Monday
+        );
+
+        // On time: fire exactly the due occurrence.
+        let p = plan(
+            &schedule,
+            ts("2026-06-08T05:00:00Z"),
+            ts("2026-06-08T05:00:10Z"),
+            MissedPolicy::Skip,
+        );
+        assert_eq!(p.fire, vec![ts("2026-06-08T05:00:00Z")]);
+        assert!(p.skip.is_empty());
+        assert_eq!(p.next_run_at, Some(ts("2026-06-09T05:00:00Z")));
+
+        // Down Mon..Wed morning, back Wednesday noon: three missed occurrences.
+        let due = ts("2026-06-08T05:00:00Z");
+        let now = ts("2026-06-10T12:00:00Z");
+        let p = plan(&schedule, due, now, MissedPolicy::Skip);
+        assert!(p.fire.is_empty());
+        assert_eq!(p.skip.len(), 3);
+        let p = plan(&schedule, due, now, MissedPolicy::RunOnce);
+        assert_eq!(p.fire, vec![ts("2026-06-10T05:00:00Z")]);
+        assert_eq!(p.skip.len(), 2);
+        let p = plan(&schedule, due, now, MissedPolicy::RunAll);
+        assert_eq!(p.fire.len(), 3);
+        assert_eq!(p.next_run_at, Some(ts("2026-06-11T05:00:00Z")));
+
+        assert_eq!(p.dropped, 0);
+
+        assert!(Schedule::parse("not cron", "UTC").is_err());
+        assert!(Schedule::parse("0 7 * * *", "Mars/Olympus").is_err());
+        assert!(MissedPolicy::parse(Some("later")).is_err());
+    }
+
+    #[test]
+    fn plan_counts_occurrences_beyond_the_catch_up_limit() {
+        let schedule = Schedule::parse("* * * * *", "UTC").unwrap();
+        // 31 missed minutely occurrences: only the last 20 are kept.
+        let due = ts("2026-06-08T05:00:00Z");
+        let now = ts("2026-06-08T05:30:30Z");
+        let p = plan(&schedule, due, now, MissedPolicy::RunAll);
+        assert_eq!(p.dropped, 11);
+        assert_eq!(p.fire.len(), MAX_CATCH_UP);
+        assert_eq!(p.fire.first(), Some(&ts("2026-06-08T05:11:00Z")));
+        assert_eq!(p.fire.last(), Some(&ts("2026-06-08T05:30:00Z")));
+
+        let p = plan(&schedule, due, now, MissedPolicy::Skip);
+        // 05:29 and 05:30 are within the misfire grace and still fire.
+        assert_eq!((p.skip.len(), p.dropped), (MAX_CATCH_UP - 2, 11));
+        assert_eq!(
+            p.fire,
+            vec![ts("2026-06-08T05:29:00Z"), ts("2026-06-08T05:30:00Z")]
+        );
+        assert_eq!(p.next_run_at, Some(ts("2026-06-08T05:31:00Z")));
+    }
+}
@@ -347,6 +347,12 @@ class RelayStore {
           this.#runsUpdateInPlace(nextRuns, msg.run_id, (cur) => ({ ...cur, last_active_at, status: "running" }));
           if (!awaitingChanged) { nextAwaiting = { ...nextAwaiting }; awaitingChanged = true; }
           nextAwaiting[msg.run_id] = undefined;
+        } else if (msg.type === "run.permission_decided") {
+          // Answered elsewhere (e.g. `relay approve` on the host).
+          if (nextAwaiting[msg.run_id]?.request_id === dataString(msg, "request_id")) {
+            if (!awaitingChanged) { nextAwaiting = { ...nextAwaiting }; awaitingChanged = true; }
+            nextAwaiting[msg.run_id] = undefined;
+          }
         }
         if (opencodeSessionId) this.#runsUpdateInPlace(nextRuns, msg.run_id, (cur) => ({ ...cur, opencode_session_id: opencodeSessionId }));
       }
@@ -823,10 +829,11 @@ class RelayStore {
     }
   };
   riskForOpTool(name?: string | null): any {
-    const t = (name ?? "").trim().toLowerCase();
+    const t = (name ?? "").trim().toLowerCase().replace(/^rpc\./, "");
     if (!t) return null;
     if (t.startsWith("fs.read")) return { kind: "read", label: "read" };
-    if (t.startsWith("fs.write")) return { kind: "write", label: "write" };
+    if (t.startsWith("fs.write") || t.startsWith("fs.apply_patch")) return { kind: "write", label: "write" };
+    if (t.startsWith("git.")) return { kind: "write", label: "write" };
     if (t === "bash" || t.endsWith(".bash")) return { kind: "exec", label: "exec" };
     return { kind: "other", label: "other" };
   }